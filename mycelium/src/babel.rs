@@ -11,7 +11,10 @@ use log::trace;
 use tokio_util::codec::{Decoder, Encoder};
 
 pub use self::{
-    hello::Hello, ihu::Ihu, route_request::RouteRequest, seqno_request::SeqNoRequest,
+    hello::Hello,
+    ihu::{Ihu, IhuTimestamp},
+    route_request::RouteRequest,
+    seqno_request::SeqNoRequest,
     update::Update,
 };
 
@@ -43,6 +46,17 @@ const TLV_TYPE_ROUTE_REQUEST: u8 = 9;
 /// TLV type for the [`SeqNoRequest`] tlv
 const TLV_TYPE_SEQNO_REQUEST: u8 = 10;
 
+/// Sub-TLV type for a single byte of padding.
+const SUB_TLV_TYPE_PAD1: u8 = 0;
+/// Sub-TLV type for multiple bytes of padding.
+const SUB_TLV_TYPE_PADN: u8 = 1;
+/// Sub-TLV type for timestamps, as defined in the [RTT
+/// extension](https://datatracker.ietf.org/doc/html/rfc9616#name-timestamp-sub-tlv-in-hello-t).
+const SUB_TLV_TYPE_TIMESTAMP: u8 = 3;
+/// Bit in the sub-TLV type indicating the whole TLV must be ignored if the sub-TLV is not
+/// understood.
+const SUB_TLV_MANDATORY: u8 = 0x80;
+
 /// Wildcard address, the value is empty (0 bytes length).
 const AE_WILDCARD: u8 = 0;
 /// IPv4 address, the value is _at most_ 4 bytes long.
@@ -52,6 +66,50 @@ const AE_IPV6: u8 = 2;
 /// Link-local IPv6 address, the value is 8 bytes long. This implies a `fe80::/64` prefix.
 const AE_IPV6_LL: u8 = 3;
 
+/// Read the [sub-TLV's](https://datatracker.ietf.org/doc/html/rfc8966#name-sub-tlv-format) in
+/// the next `len` bytes of `src`. Exactly `len` bytes are consumed from `src`.
+///
+/// The `handler` is called with the type and body of every sub-TLV which is not padding, and
+/// returns if the sub-TLV is understood. If a mandatory sub-TLV is not understood, or the
+/// sub-TLV's are malformed, this returns false, and the TLV containing them must be ignored.
+fn read_sub_tlvs(
+    src: &mut bytes::BytesMut,
+    len: usize,
+    mut handler: impl FnMut(u8, &[u8]) -> bool,
+) -> bool {
+    if src.remaining() < len {
+        src.advance(src.remaining());
+        return false;
+    }
+    let mut sub_tlvs = src.split_to(len);
+
+    while sub_tlvs.has_remaining() {
+        let sub_tlv_type = sub_tlvs.get_u8();
+        if sub_tlv_type == SUB_TLV_TYPE_PAD1 {
+            continue;
+        }
+        if !sub_tlvs.has_remaining() {
+            trace!("Sub-TLV without length");
+            return false;
+        }
+        let sub_tlv_len = sub_tlvs.get_u8() as usize;
+        if sub_tlvs.remaining() < sub_tlv_len {
+            trace!("Sub-TLV length exceeds TLV length");
+            return false;
+        }
+        let body = sub_tlvs.split_to(sub_tlv_len);
+        if sub_tlv_type == SUB_TLV_TYPE_PADN {
+            continue;
+        }
+        if !handler(sub_tlv_type, &body) && sub_tlv_type & SUB_TLV_MANDATORY != 0 {
+            trace!("Mandatory sub-TLV {sub_tlv_type} not understood");
+            return false;
+        }
+    }
+
+    true
+}
+
 /// A codec which can send and receive whole babel packets on the wire.
 #[derive(Debug, Clone)]
 pub struct Codec {
@@ -125,8 +183,7 @@ impl Decoder for Codec {
             return Ok(None);
         }
 
-        // at this point we have a whole body loaded in the buffer. We currently don't support sub
-        // TLV's
+        // at this point we have a whole body loaded in the buffer.
 
         trace!("Read babel TLV body");
 
@@ -137,7 +194,7 @@ impl Decoder for Codec {
         let body_len = src.get_u8();
         // TLV payload
         let tlv = match tlv_type {
-            TLV_TYPE_HELLO => Some(Hello::from_bytes(src).into()),
+            TLV_TYPE_HELLO => Hello::from_bytes(src, body_len).map(From::from),
             TLV_TYPE_IHU => Ihu::from_bytes(src, body_len).map(From::from),
             TLV_TYPE_UPDATE => Update::from_bytes(src, body_len).map(From::from),
             TLV_TYPE_ROUTE_REQUEST => RouteRequest::from_bytes(src, body_len).map(From::from),
@@ -231,6 +288,83 @@ mod tests {
         assert_eq!(super::Tlv::from(ihu), recv_ihu);
     }
 
+    #[tokio::test]
+    async fn codec_hello_timestamp() {
+        let (tx, rx) = tokio::io::duplex(1024);
+        let mut sender = Framed::new(tx, super::Codec::new());
+        let mut receiver = Framed::new(rx, super::Codec::new());
+
+        let mut hello = super::Hello::new_unicast(15.into(), 400);
+        hello.set_timestamp(1_234_567);
+
+        sender
+            .send(hello.clone().into())
+            .await
+            .expect("Send on a non-networked buffer can never fail; qed");
+        let recv_hello = receiver
+            .next()
+            .await
+            .expect("Buffer isn't closed so this is always `Some`; qed")
+            .expect("Can decode the previously encoded value");
+        assert_eq!(super::Tlv::from(hello), recv_hello);
+    }
+
+    #[tokio::test]
+    async fn codec_ihu_timestamp() {
+        let (tx, rx) = tokio::io::duplex(1024);
+        let mut sender = Framed::new(tx, super::Codec::new());
+        let mut receiver = Framed::new(rx, super::Codec::new());
+
+        let mut ihu = super::Ihu::new(27.into(), 400, Some(Ipv6Addr::LOCALHOST.into()));
+        ihu.set_timestamp(super::IhuTimestamp {
+            origin: 1,
+            receive: u32::MAX,
+        });
+
+        sender
+            .send(ihu.clone().into())
+            .await
+            .expect("Send on a non-networked buffer can never fail; qed");
+        let recv_ihu = receiver
+            .next()
+            .await
+            .expect("Buffer isn't closed so this is always `Some`; qed")
+            .expect("Can decode the previously encoded value");
+        assert_eq!(super::Tlv::from(ihu), recv_ihu);
+    }
+
+    #[test]
+    fn sub_tlvs_skip_padding() {
+        let mut buf = bytes::BytesMut::from(&[0, 1, 2, 0, 0, 3, 1, 42, 0, 99][..]);
+
+        let mut seen = vec![];
+        assert!(super::read_sub_tlvs(&mut buf, 9, |t, body| {
+            seen.push((t, body.to_vec()));
+            true
+        }));
+        assert_eq!(seen, vec![(3, vec![42])]);
+        assert_eq!(buf[..], [99]);
+    }
+
+    #[test]
+    fn sub_tlvs_unknown_mandatory() {
+        let mut buf = bytes::BytesMut::from(&[4, 0, 0x84, 1, 0, 99][..]);
+
+        assert!(!super::read_sub_tlvs(&mut buf, 5, |_, _| false));
+        assert_eq!(buf[..], [99]);
+
+        let mut buf = bytes::BytesMut::from(&[4, 0][..]);
+        assert!(super::read_sub_tlvs(&mut buf, 2, |_, _| false));
+    }
+
+    #[test]
+    fn sub_tlvs_malformed() {
+        let mut buf = bytes::BytesMut::from(&[3, 4, 0, 0, 99][..]);
+
+        assert!(!super::read_sub_tlvs(&mut buf, 4, |_, _| true));
+        assert_eq!(buf[..], [99]);
+    }
+
     #[tokio::test]
     async fn codec_update() {
         let (tx, rx) = tokio::io::duplex(1024);
@@ -1,25 +1,35 @@
 //! The babel [Hello TLV](https://datatracker.ietf.org/doc/html/rfc8966#section-4.6.5).
 
+use std::time::Duration;
+
 use bytes::{Buf, BufMut};
 use log::trace;
 
 use crate::sequence_number::SeqNo;
 
+use super::SUB_TLV_TYPE_TIMESTAMP;
+
 /// Flag bit indicating a [`Hello`] is sent as unicast hello.
 const HELLO_FLAG_UNICAST: u16 = 0x8000;
 
 /// Mask to apply to [`Hello`] flags, leaving only valid flags.
 const FLAG_MASK: u16 = 0b10000000_00000000;
 
-/// Wire size of a [`Hello`] TLV without TLV header.
+/// Wire size of a [`Hello`] TLV without TLV header and sub-TLV's.
 const HELLO_WIRE_SIZE: u8 = 6;
 
+/// Size of the body of a timestamp sub-TLV in a [`Hello`].
+const TIMESTAMP_SIZE: u8 = 4;
+
 /// Hello TLV body as defined in https://datatracker.ietf.org/doc/html/rfc8966#section-4.6.5.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Hello {
     flags: u16,
     seqno: SeqNo,
     interval: u16,
+    /// Transmit timestamp of the packet, as defined in the [RTT
+    /// extension](https://datatracker.ietf.org/doc/html/rfc9616#name-timestamp-sub-tlv-in-hello-t).
+    timestamp: Option<u32>,
 }
 
 impl Hello {
@@ -29,12 +39,40 @@ impl Hello {
             flags: HELLO_FLAG_UNICAST,
             seqno,
             interval,
+            timestamp: None,
         }
     }
 
+    /// Get the [`SeqNo`] of this `Hello`.
+    pub fn seqno(&self) -> SeqNo {
+        self.seqno
+    }
+
+    /// Get the interval after which the sender of this `Hello` will send the next one. If the
+    /// sender does not send Hellos periodically, this is 0.
+    pub fn interval(&self) -> Duration {
+        // Interval is expressed in centiseconds on the wire.
+        Duration::from_millis(self.interval as u64 * 10)
+    }
+
+    /// Get the transmit timestamp of this `Hello`, if one is set.
+    pub fn timestamp(&self) -> Option<u32> {
+        self.timestamp
+    }
+
+    /// Set the transmit timestamp of this `Hello`, in microseconds.
+    pub fn set_timestamp(&mut self, timestamp: u32) {
+        self.timestamp = Some(timestamp);
+    }
+
     /// Calculates the size on the wire of this `Hello`.
     pub fn wire_size(&self) -> u8 {
         HELLO_WIRE_SIZE
+            + match self.timestamp {
+                // sub-TLV header and body
+                Some(_) => 2 + TIMESTAMP_SIZE,
+                None => 0,
+            }
     }
 
     /// Construct a `Hello` from wire bytes.
@@ -43,18 +81,36 @@ impl Hello {
     ///
     /// This function will panic if there are insufficient bytes present in the provided buffer to
     /// decode a complete `Hello`.
-    pub fn from_bytes(src: &mut bytes::BytesMut) -> Self {
+    pub fn from_bytes(src: &mut bytes::BytesMut, len: u8) -> Option<Self> {
         let flags = src.get_u16() & FLAG_MASK;
         let seqno = src.get_u16().into();
         let interval = src.get_u16();
 
+        let mut timestamp = None;
+        let sub_tlvs_valid = super::read_sub_tlvs(
+            src,
+            len.saturating_sub(HELLO_WIRE_SIZE) as usize,
+            |sub_tlv_type, body| match sub_tlv_type {
+                SUB_TLV_TYPE_TIMESTAMP if body.len() == TIMESTAMP_SIZE as usize => {
+                    timestamp = Some(u32::from_be_bytes([body[0], body[1], body[2], body[3]]));
+                    true
+                }
+                _ => false,
+            },
+        );
+        if !sub_tlvs_valid {
+            trace!("Dropping hello tlv with invalid sub tlvs");
+            return None;
+        }
+
         trace!("Read hello tlv body");
 
-        Self {
+        Some(Self {
             flags,
             seqno,
             interval,
-        }
+            timestamp,
+        })
     }
 
     /// Encode this `Hello` tlv as part of a packet.
@@ -62,6 +118,11 @@ impl Hello {
         dst.put_u16(self.flags);
         dst.put_u16(self.seqno.into());
         dst.put_u16(self.interval);
+        if let Some(timestamp) = self.timestamp {
+            dst.put_u8(SUB_TLV_TYPE_TIMESTAMP);
+            dst.put_u8(TIMESTAMP_SIZE);
+            dst.put_u32(timestamp);
+        }
     }
 }
 
@@ -77,6 +138,7 @@ mod tests {
             flags: 0,
             seqno: 25.into(),
             interval: 400,
+            timestamp: None,
         };
 
         hello.write_bytes(&mut buf);
@@ -90,6 +152,7 @@ mod tests {
             flags: super::HELLO_FLAG_UNICAST,
             seqno: 16.into(),
             interval: 4000,
+            timestamp: None,
         };
 
         hello.write_bytes(&mut buf);
@@ -106,9 +169,10 @@ mod tests {
             flags: super::HELLO_FLAG_UNICAST,
             seqno: 19.into(),
             interval: 513,
+            timestamp: None,
         };
 
-        assert_eq!(super::Hello::from_bytes(&mut buf), hello);
+        assert_eq!(super::Hello::from_bytes(&mut buf, 6), Some(hello));
         assert_eq!(buf.remaining(), 0);
 
         let mut buf = bytes::BytesMut::from(&[0b00000000u8, 0b00000000, 1, 19, 200, 100][..]);
@@ -117,9 +181,10 @@ mod tests {
             flags: 0,
             seqno: 275.into(),
             interval: 51300,
+            timestamp: None,
         };
 
-        assert_eq!(super::Hello::from_bytes(&mut buf), hello);
+        assert_eq!(super::Hello::from_bytes(&mut buf, 6), Some(hello));
         assert_eq!(buf.remaining(), 0);
     }
 
@@ -131,9 +196,10 @@ mod tests {
             flags: super::HELLO_FLAG_UNICAST,
             seqno: 100.into(),
             interval: 400,
+            timestamp: None,
         };
 
-        assert_eq!(super::Hello::from_bytes(&mut buf), hello);
+        assert_eq!(super::Hello::from_bytes(&mut buf, 6), Some(hello));
         assert_eq!(buf.remaining(), 0);
 
         let mut buf = bytes::BytesMut::from(&[0b00001001u8, 0b00000000, 0, 100, 1, 144][..]);
@@ -142,9 +208,10 @@ mod tests {
             flags: 0,
             seqno: 100.into(),
             interval: 400,
+            timestamp: None,
         };
 
-        assert_eq!(super::Hello::from_bytes(&mut buf), hello);
+        assert_eq!(super::Hello::from_bytes(&mut buf, 6), Some(hello));
         assert_eq!(buf.remaining(), 0);
     }
 
@@ -154,9 +221,46 @@ mod tests {
 
         let hello_src = super::Hello::new_unicast(16.into(), 400);
         hello_src.write_bytes(&mut buf);
-        let decoded = super::Hello::from_bytes(&mut buf);
+        let decoded = super::Hello::from_bytes(&mut buf, hello_src.wire_size());
+
+        assert_eq!(Some(hello_src), decoded);
+        assert_eq!(buf.remaining(), 0);
+    }
+
+    #[test]
+    fn timestamp_encoding() {
+        let mut buf = bytes::BytesMut::new();
 
-        assert_eq!(hello_src, decoded);
+        let mut hello = super::Hello::new_unicast(16.into(), 4000);
+        hello.set_timestamp(0x01020304);
+
+        hello.write_bytes(&mut buf);
+
+        assert_eq!(hello.wire_size(), 12);
+        assert_eq!(buf.len(), 12);
+        assert_eq!(buf[..12], [128, 0, 0, 16, 15, 160, 3, 4, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn timestamp_decoding() {
+        let mut buf = bytes::BytesMut::from(
+            &[
+                128u8, 0, 0, 16, 15, 160, 0, 1, 1, 0, 3, 4, 1, 2, 3, 4, 7, 1, 0,
+            ][..],
+        );
+
+        let decoded = super::Hello::from_bytes(&mut buf, 19).expect("Hello is valid");
+
+        assert_eq!(decoded.seqno(), 16.into());
+        assert_eq!(decoded.timestamp(), Some(0x01020304));
         assert_eq!(buf.remaining(), 0);
     }
+
+    #[test]
+    fn unknown_mandatory_sub_tlv() {
+        let mut buf = bytes::BytesMut::from(&[128u8, 0, 0, 16, 15, 160, 0x87, 1, 0, 20][..]);
+
+        assert_eq!(super::Hello::from_bytes(&mut buf, 9), None);
+        assert_eq!(buf[..], [20]);
+    }
 }
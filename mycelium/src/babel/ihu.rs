@@ -7,17 +7,34 @@ use log::trace;
 
 use crate::metric::Metric;
 
-use super::{AE_IPV4, AE_IPV6, AE_IPV6_LL, AE_WILDCARD};
+use super::{AE_IPV4, AE_IPV6, AE_IPV6_LL, AE_WILDCARD, SUB_TLV_TYPE_TIMESTAMP};
 
 /// Base wire size of an [`Ihu`] without variable length address encoding.
 const IHU_BASE_WIRE_SIZE: u8 = 6;
 
+/// Size of the body of a timestamp sub-TLV in an [`Ihu`].
+const TIMESTAMP_SIZE: u8 = 8;
+
 /// IHU TLV body as defined in https://datatracker.ietf.org/doc/html/rfc8966#name-ihu.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Ihu {
     rx_cost: Metric,
     interval: u16,
     address: Option<IpAddr>,
+    timestamp: Option<IhuTimestamp>,
+}
+
+/// Timestamps in an [`Ihu`], as defined in the [RTT
+/// extension](https://datatracker.ietf.org/doc/html/rfc9616#name-timestamp-sub-tlv-in-ihu-tlv).
+///
+/// All timestamps are expressed in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IhuTimestamp {
+    /// Transmit timestamp of the last [`Hello`](super::Hello) received from the destination of
+    /// the IHU.
+    pub origin: u32,
+    /// Time at which that [`Hello`](super::Hello) was received by the sender of the IHU.
+    pub receive: u32,
 }
 
 impl Ihu {
@@ -32,9 +49,25 @@ impl Ihu {
             rx_cost,
             interval,
             address,
+            timestamp: None,
         }
     }
 
+    /// Get the rx cost announced in this `Ihu`.
+    pub fn rx_cost(&self) -> Metric {
+        self.rx_cost
+    }
+
+    /// Get the timestamps of this `Ihu`, if they are set.
+    pub fn timestamp(&self) -> Option<IhuTimestamp> {
+        self.timestamp
+    }
+
+    /// Set the timestamps of this `Ihu`.
+    pub fn set_timestamp(&mut self, timestamp: IhuTimestamp) {
+        self.timestamp = Some(timestamp);
+    }
+
     /// Calculates the size on the wire of this `Ihu`.
     pub fn wire_size(&self) -> u8 {
         IHU_BASE_WIRE_SIZE
@@ -44,6 +77,11 @@ impl Ihu {
                 // TODO: link local should be encoded differently
                 Some(IpAddr::V6(_)) => 16,
             }
+            + match self.timestamp {
+                // sub-TLV header and body
+                Some(_) => 2 + TIMESTAMP_SIZE,
+                None => 0,
+            }
     }
 
     /// Construct a `Ihu` from wire bytes.
@@ -58,19 +96,19 @@ impl Ihu {
         let _ = src.get_u8();
         let rx_cost = src.get_u16().into();
         let interval = src.get_u16();
-        let address = match ae {
-            AE_WILDCARD => None,
+        let (address, address_len) = match ae {
+            AE_WILDCARD => (None, 0),
             AE_IPV4 => {
                 let mut raw_ip = [0; 4];
                 raw_ip.copy_from_slice(&src[..4]);
                 src.advance(4);
-                Some(Ipv4Addr::from(raw_ip).into())
+                (Some(Ipv4Addr::from(raw_ip).into()), 4)
             }
             AE_IPV6 => {
                 let mut raw_ip = [0; 16];
                 raw_ip.copy_from_slice(&src[..16]);
                 src.advance(16);
-                Some(Ipv6Addr::from(raw_ip).into())
+                (Some(Ipv6Addr::from(raw_ip).into()), 16)
             }
             AE_IPV6_LL => {
                 let mut raw_ip = [0; 16];
@@ -78,7 +116,7 @@ impl Ihu {
                 raw_ip[1] = 0x80;
                 raw_ip[8..].copy_from_slice(&src[..8]);
                 src.advance(8);
-                Some(Ipv6Addr::from(raw_ip).into())
+                (Some(Ipv6Addr::from(raw_ip).into()), 8)
             }
             _ => {
                 // Invalid AE type, skip reamining data and ignore
@@ -88,12 +126,33 @@ impl Ihu {
             }
         };
 
+        let mut timestamp = None;
+        let sub_tlvs_valid = super::read_sub_tlvs(
+            src,
+            len.saturating_sub(IHU_BASE_WIRE_SIZE + address_len) as usize,
+            |sub_tlv_type, body| match sub_tlv_type {
+                SUB_TLV_TYPE_TIMESTAMP if body.len() == TIMESTAMP_SIZE as usize => {
+                    timestamp = Some(IhuTimestamp {
+                        origin: u32::from_be_bytes([body[0], body[1], body[2], body[3]]),
+                        receive: u32::from_be_bytes([body[4], body[5], body[6], body[7]]),
+                    });
+                    true
+                }
+                _ => false,
+            },
+        );
+        if !sub_tlvs_valid {
+            trace!("Dropping ihu tlv with invalid sub tlvs");
+            return None;
+        }
+
         trace!("Read ihu tlv body");
 
         Some(Self {
             rx_cost,
             interval,
             address,
+            timestamp,
         })
     }
 
@@ -113,6 +172,12 @@ impl Ihu {
             Some(IpAddr::V4(ip)) => dst.put_slice(&ip.octets()),
             Some(IpAddr::V6(ip)) => dst.put_slice(&ip.octets()),
         }
+        if let Some(timestamp) = self.timestamp {
+            dst.put_u8(SUB_TLV_TYPE_TIMESTAMP);
+            dst.put_u8(TIMESTAMP_SIZE);
+            dst.put_u32(timestamp.origin);
+            dst.put_u32(timestamp.receive);
+        }
     }
 }
 
@@ -130,6 +195,7 @@ mod tests {
             rx_cost: 25.into(),
             interval: 400,
             address: Some(Ipv4Addr::new(1, 1, 1, 1).into()),
+            timestamp: None,
         };
 
         ihu.write_bytes(&mut buf);
@@ -143,6 +209,7 @@ mod tests {
             rx_cost: 100.into(),
             interval: 4000,
             address: Some(Ipv6Addr::new(2, 0, 1234, 2345, 3456, 4567, 5678, 1).into()),
+            timestamp: None,
         };
 
         ihu.write_bytes(&mut buf);
@@ -162,6 +229,7 @@ mod tests {
             rx_cost: 1.into(),
             interval: 300,
             address: None,
+            timestamp: None,
         };
 
         let buf_len = buf.len();
@@ -174,6 +242,7 @@ mod tests {
             rx_cost: 2.into(),
             interval: 44,
             address: Some(Ipv4Addr::new(3, 4, 5, 6).into()),
+            timestamp: None,
         };
 
         let buf_len = buf.len();
@@ -190,6 +259,7 @@ mod tests {
             rx_cost: 2.into(),
             interval: 44,
             address: Some(Ipv6Addr::new(0x400, 0, 5, 6, 0x708, 0x90a, 0xb0c, 0xd0e).into()),
+            timestamp: None,
         };
 
         let buf_len = buf.len();
@@ -202,6 +272,7 @@ mod tests {
             rx_cost: 258.into(),
             interval: 42,
             address: Some(Ipv6Addr::new(0xfe80, 0, 0, 0, 0x708, 0x90a, 0xb0c, 0xd0e).into()),
+            timestamp: None,
         };
 
         let buf_len = buf.len();
@@ -243,4 +314,63 @@ mod tests {
         assert_eq!(Some(hello_src), decoded);
         assert_eq!(buf.remaining(), 0);
     }
+
+    #[test]
+    fn timestamp_encoding() {
+        let mut buf = bytes::BytesMut::new();
+
+        let mut ihu = super::Ihu::new(25.into(), 400, Some(Ipv4Addr::new(1, 1, 1, 1).into()));
+        ihu.set_timestamp(super::IhuTimestamp {
+            origin: 1,
+            receive: 0x01020304,
+        });
+
+        ihu.write_bytes(&mut buf);
+
+        assert_eq!(ihu.wire_size(), 20);
+        assert_eq!(buf.len(), 20);
+        assert_eq!(
+            buf[..20],
+            [1, 0, 0, 25, 1, 144, 1, 1, 1, 1, 3, 8, 0, 0, 0, 1, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn timestamp_decoding() {
+        let mut buf = bytes::BytesMut::from(&[0, 0, 0, 1, 1, 44, 3, 8, 0, 0, 0, 1, 0, 0, 0, 2][..]);
+
+        let buf_len = buf.len();
+        let ihu = super::Ihu::from_bytes(&mut buf, buf_len as u8).expect("Ihu is valid");
+
+        assert_eq!(
+            ihu.timestamp(),
+            Some(super::IhuTimestamp {
+                origin: 1,
+                receive: 2,
+            })
+        );
+        assert_eq!(buf.remaining(), 0);
+    }
+
+    #[test]
+    fn ignores_invalid_timestamp() {
+        let mut buf = bytes::BytesMut::from(&[0, 0, 0, 1, 1, 44, 3, 2, 0, 1][..]);
+
+        let buf_len = buf.len();
+        let ihu = super::Ihu::from_bytes(&mut buf, buf_len as u8).expect("Ihu is valid");
+
+        assert_eq!(ihu.timestamp(), None);
+        assert_eq!(buf.remaining(), 0);
+
+        // A timestamp with trailing data does not follow the RFC either.
+        let mut buf = bytes::BytesMut::from(
+            &[0, 0, 0, 1, 1, 44, 3, 12, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3][..],
+        );
+
+        let buf_len = buf.len();
+        let ihu = super::Ihu::from_bytes(&mut buf, buf_len as u8).expect("Ihu is valid");
+
+        assert_eq!(ihu.timestamp(), None);
+        assert_eq!(buf.remaining(), 0);
+    }
 }
@@ -75,6 +75,11 @@ impl SeqNoRequest {
             .expect("Decrementing a hop count of 1 is not allowed");
     }
 
+    /// Lower the hop count of this `SeqNoRequest` to `limit`, if it is currently higher.
+    pub fn limit_hop_count(&mut self, limit: NonZeroU8) {
+        self.hop_count = self.hop_count.min(limit);
+    }
+
     /// Calculates the size on the wire of this `Update`.
     pub fn wire_size(&self) -> u8 {
         SEQNO_REQUEST_BASE_WIRE_SIZE + (self.prefix.prefix_len() + 7) / 8
@@ -333,6 +338,23 @@ mod tests {
         assert_eq!(buf.remaining(), 0);
     }
 
+    #[test]
+    fn limit_hop_count() {
+        let mut snr = super::SeqNoRequest::new(
+            1.into(),
+            RouterId::from([1; RouterId::BYTE_SIZE]),
+            Subnet::new(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0).into(), 64)
+                .expect("64 is a valid IPv6 prefix size; qed"),
+        );
+        assert_eq!(snr.hop_count(), 64);
+
+        snr.limit_hop_count(NonZeroU8::new(8).unwrap());
+        assert_eq!(snr.hop_count(), 8);
+        // A higher limit does not raise the hop count again.
+        snr.limit_hop_count(NonZeroU8::new(16).unwrap());
+        assert_eq!(snr.hop_count(), 8);
+    }
+
     #[test]
     fn roundtrip() {
         let mut buf = bytes::BytesMut::new();
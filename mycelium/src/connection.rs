@@ -1,12 +1,21 @@
 use std::{io, net::SocketAddr, pin::Pin};
 
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
+    io::{AsyncRead, AsyncWrite, DuplexStream},
     net::TcpStream,
 };
+use tokio_rustls::TlsStream;
 
+mod handshake;
+mod proxy;
+mod secure;
 mod tracked;
+mod websocket;
+pub use handshake::{handshake, HandshakeError};
+pub(crate) use proxy::http_connect;
+pub use secure::Secure;
 pub use tracked::Tracked;
+pub use websocket::WebSocket;
 
 /// Cost to add to the peer_link_cost for "local processing", when peers are connected over IPv6.
 ///
@@ -28,6 +37,23 @@ const PACKET_PROCESSING_COST_IP6_QUIC: u16 = 7;
 // TODO
 const PACKET_PROCESSING_COST_IP4_QUIC: u16 = 12;
 
+/// Cost to add to the peer_link_cost for "local processing", when peers are connected over a
+/// Unix domain socket. Both peers run on the same host, so this is lower than any network
+/// connection.
+#[cfg(target_family = "unix")]
+const PACKET_PROCESSING_COST_UNIX: u16 = 5;
+
+/// Cost to add to the peer_link_cost for "local processing", when peers are connected with a
+/// WebSocket over IPv6.
+///
+/// This is slightly higher than [`PACKET_PROCESSING_COST_IP6_TCP`] because of the added framing,
+/// so plain Tcp connections are preferred if both are available.
+const PACKET_PROCESSING_COST_IP6_WS: u16 = 12;
+
+/// Cost to add to the peer_link_cost for "local processing", when peers are connected with a
+/// WebSocket over IPv4.
+const PACKET_PROCESSING_COST_IP4_WS: u16 = 17;
+
 pub trait Connection: AsyncRead + AsyncWrite {
     /// Get an identifier for this connection, which shows details about the remote
     fn identifier(&self) -> Result<String, io::Error>;
@@ -70,6 +96,22 @@ impl Connection for TcpStream {
     }
 }
 
+impl Connection for TlsStream<TcpStream> {
+    fn identifier(&self) -> Result<String, io::Error> {
+        let stream = self.get_ref().0;
+        Ok(format!(
+            "TLS {} <-> {}",
+            stream.local_addr()?,
+            stream.peer_addr()?
+        ))
+    }
+
+    fn static_link_cost(&self) -> Result<u16, io::Error> {
+        // Apart from the encryption, this is the same as a plain Tcp connection.
+        self.get_ref().0.static_link_cost()
+    }
+}
+
 impl AsyncRead for Quic {
     #[inline]
     fn poll_read(
@@ -138,10 +180,21 @@ impl Connection for Quic {
     }
 }
 
-#[cfg(test)]
-use tokio::io::DuplexStream;
+#[cfg(target_family = "unix")]
+impl Connection for tokio::net::UnixStream {
+    fn identifier(&self) -> Result<String, io::Error> {
+        Ok(format!(
+            "Unix {:?} <-> {:?}",
+            self.local_addr()?,
+            self.peer_addr()?
+        ))
+    }
+
+    fn static_link_cost(&self) -> Result<u16, io::Error> {
+        Ok(PACKET_PROCESSING_COST_UNIX)
+    }
+}
 
-#[cfg(test)]
 impl Connection for DuplexStream {
     fn identifier(&self) -> Result<String, io::Error> {
         Ok("Memory pipe".to_string())
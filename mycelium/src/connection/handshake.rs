@@ -0,0 +1,437 @@
+//! Authenticated handshake, performed on every new connection before it is used by a
+//! [`Peer`](crate::peer::Peer).
+//!
+//! Both sides start by sending their [`PublicKey`] together with a random nonce. Afterwards,
+//! every side proves it holds the [`SecretKey`] of the [`PublicKey`] it announced, by sending a
+//! MAC over the exchanged values. This MAC is keyed with the [`SharedSecret`](crate::crypto::SharedSecret)
+//! derived from our own [`SecretKey`] and the remote [`PublicKey`]. Only a node which holds the
+//! secret key of the announced public key can derive the same shared secret, and thus produce a
+//! valid proof. Since the proof covers the nonce chosen by the verifying side, a proof can't be
+//! replayed on a different connection.
+//!
+//! The proof on its own does not protect the connection: a machine in the middle could relay the
+//! handshake between two honest nodes, and inject its own traffic afterwards. Therefore both
+//! sides also derive a key per direction from the shared secret and both nonces, and the
+//! connection is wrapped in a [`Secure`] connection, which authenticates and encrypts every
+//! record with these keys. Only the nodes which completed the handshake know the keys, so any
+//! traffic injected by a relay is rejected.
+//!
+//! If the connection runs over TLS or Quic, the certificate presented by the server is included in
+//! the proofs as a channel binding. Remotes don't verify this certificate on its own, but since
+//! both sides must agree on it, the handshake fails if a machine in the middle terminates the TLS
+//! connection with a certificate of its own. The certificate is thus pinned to the key of the
+//! node which presented it.
+//!
+//! Both sides announce the highest handshake version they support, and the lowest of both is used.
+//! This way, a future version of the handshake can fall back to an older version when connecting to
+//! nodes which were not upgraded yet. Nodes which predate the handshake don't send it at all, and
+//! can't connect to nodes running the handshake.
+
+use std::{fmt, io};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::Secure;
+use crate::crypto::{PublicKey, SecretKey};
+
+/// Magic bytes identifying the start of a handshake.
+const HANDSHAKE_MAGIC: &[u8; 8] = b"mycelium";
+/// Highest version of the handshake protocol we support.
+const HANDSHAKE_VERSION: u8 = 1;
+/// Lowest version of the handshake protocol we still support.
+const MIN_HANDSHAKE_VERSION: u8 = 1;
+/// Size of the random nonce sent by both sides.
+const NONCE_SIZE: usize = 32;
+/// Size of the initial handshake message: magic, version, public key and nonce.
+const HELLO_SIZE: usize = HANDSHAKE_MAGIC.len() + 1 + 32 + NONCE_SIZE;
+/// Size of the key ownership proof.
+const PROOF_SIZE: usize = 32;
+/// Domain separation for the key ownership proof.
+const PROOF_CONTEXT: &[u8] = b"mycelium handshake proof v1";
+/// Domain separation for the session keys.
+const SESSION_KEY_CONTEXT: &[u8] = b"mycelium session key v1";
+
+/// Error returned if a handshake can't be completed.
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// An IO error happened on the underlying connection.
+    Io(io::Error),
+    /// The remote did not start with the expected handshake magic. This is most likely a node
+    /// running a version which predates the handshake.
+    InvalidMagic,
+    /// The remote only supports handshake versions which are too old for us.
+    UnsupportedVersion(u8),
+    /// The remote announced our own public key.
+    OwnKey,
+    /// The remote could not prove it owns the public key it announced.
+    InvalidProof,
+}
+
+/// Perform the handshake on the given connection.
+///
+/// `channel_binding` identifies the channel the connection runs over, i.e. the DER encoded
+/// certificate presented by the server if the connection is secured with TLS, or is empty
+/// otherwise. Both sides must pass the same value, or the handshake fails.
+///
+/// On success, the connection is returned as a [`Secure`] connection, together with the
+/// [`PublicKey`] of the remote. At this point the remote has proven that it owns the associated
+/// [`SecretKey`].
+pub async fn handshake<C>(
+    con: C,
+    node_secret_key: &SecretKey,
+    node_public_key: PublicKey,
+    channel_binding: &[u8],
+) -> Result<(Secure<C>, PublicKey), HandshakeError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    versioned_handshake(
+        con,
+        node_secret_key,
+        node_public_key,
+        channel_binding,
+        HANDSHAKE_VERSION,
+    )
+    .await
+}
+
+/// Perform the handshake, announcing `local_version` as the highest version we support.
+async fn versioned_handshake<C>(
+    mut con: C,
+    node_secret_key: &SecretKey,
+    node_public_key: PublicKey,
+    channel_binding: &[u8],
+    local_version: u8,
+) -> Result<(Secure<C>, PublicKey), HandshakeError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let local_nonce: [u8; NONCE_SIZE] = rand::random();
+
+    let mut hello = [0; HELLO_SIZE];
+    hello[..8].copy_from_slice(HANDSHAKE_MAGIC);
+    hello[8] = local_version;
+    hello[9..41].copy_from_slice(node_public_key.as_bytes());
+    hello[41..].copy_from_slice(&local_nonce);
+    con.write_all(&hello).await?;
+    con.flush().await?;
+
+    // Check the magic first, so we fail early if the remote does not perform a handshake at all,
+    // instead of waiting for a full hello which might never come.
+    let mut remote_hello = [0; HELLO_SIZE];
+    con.read_exact(&mut remote_hello[..HANDSHAKE_MAGIC.len()])
+        .await?;
+    if &remote_hello[..8] != HANDSHAKE_MAGIC {
+        return Err(HandshakeError::InvalidMagic);
+    }
+    con.read_exact(&mut remote_hello[HANDSHAKE_MAGIC.len()..])
+        .await?;
+    // Both sides pick the same version, since they both know the version of the other side.
+    let version = local_version.min(remote_hello[8]);
+    if version < MIN_HANDSHAKE_VERSION {
+        return Err(HandshakeError::UnsupportedVersion(remote_hello[8]));
+    }
+    let remote_public_key = PublicKey::from(
+        <[u8; 32]>::try_from(&remote_hello[9..41]).expect("Slice size is valid for a public key"),
+    );
+    if remote_public_key == node_public_key {
+        return Err(HandshakeError::OwnKey);
+    }
+    let remote_nonce = &remote_hello[41..];
+
+    // Bind all values derived from the shared secret to the negotiated version and the channel,
+    // so neither can be changed by a machine in the middle.
+    let shared_secret = node_secret_key.shared_secret(&remote_public_key);
+    let transcript_key = blake3::Hasher::new_keyed(&shared_secret)
+        .update(&[version])
+        .update(channel_binding)
+        .finalize();
+
+    let proof = transcript_hash(
+        PROOF_CONTEXT,
+        transcript_key.as_bytes(),
+        &node_public_key,
+        &remote_public_key,
+        &local_nonce,
+        remote_nonce,
+    );
+    con.write_all(proof.as_bytes()).await?;
+    con.flush().await?;
+
+    let mut remote_proof = [0; PROOF_SIZE];
+    con.read_exact(&mut remote_proof).await?;
+    let expected_proof = transcript_hash(
+        PROOF_CONTEXT,
+        transcript_key.as_bytes(),
+        &remote_public_key,
+        &node_public_key,
+        remote_nonce,
+        &local_nonce,
+    );
+    // Comparison between hashes is constant time.
+    if blake3::Hash::from(remote_proof) != expected_proof {
+        return Err(HandshakeError::InvalidProof);
+    }
+
+    let tx_key = transcript_hash(
+        SESSION_KEY_CONTEXT,
+        transcript_key.as_bytes(),
+        &node_public_key,
+        &remote_public_key,
+        &local_nonce,
+        remote_nonce,
+    );
+    let rx_key = transcript_hash(
+        SESSION_KEY_CONTEXT,
+        transcript_key.as_bytes(),
+        &remote_public_key,
+        &node_public_key,
+        remote_nonce,
+        &local_nonce,
+    );
+
+    Ok((
+        Secure::new(con, *tx_key.as_bytes(), *rx_key.as_bytes()),
+        remote_public_key,
+    ))
+}
+
+/// Hash the values exchanged during the handshake, keyed with the transcript key. Depending on the
+/// `context`, this is either the proof that the `sender` owns its [`PublicKey`], or the key used
+/// to encrypt data sent by the `sender`. The order of the arguments matters, so a proof can't be
+/// reflected back to the side which sent it, and both directions use a different key.
+fn transcript_hash(
+    context: &[u8],
+    transcript_key: &[u8; 32],
+    sender: &PublicKey,
+    receiver: &PublicKey,
+    sender_nonce: &[u8],
+    receiver_nonce: &[u8],
+) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new_keyed(transcript_key);
+    hasher.update(context);
+    hasher.update(sender.as_bytes());
+    hasher.update(receiver.as_bytes());
+    hasher.update(sender_nonce);
+    hasher.update(receiver_nonce);
+    hasher.finalize()
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::Io(e) => write!(f, "IO error during handshake: {e}"),
+            HandshakeError::InvalidMagic => f.write_str(
+                "Remote did not send a valid handshake, it might run an older version of mycelium",
+            ),
+            HandshakeError::UnsupportedVersion(v) => {
+                write!(f, "Remote only supports outdated handshake version {v}")
+            }
+            HandshakeError::OwnKey => f.write_str("Remote announced our own public key"),
+            HandshakeError::InvalidProof => {
+                f.write_str("Remote could not prove ownership of its public key")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HandshakeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(value: io::Error) -> Self {
+        HandshakeError::Io(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{handshake, versioned_handshake, HandshakeError, HANDSHAKE_VERSION};
+    use crate::crypto::{PublicKey, SecretKey};
+
+    #[tokio::test]
+    async fn handshake_succeeds() {
+        let sk1 = SecretKey::new();
+        let pk1 = PublicKey::from(&sk1);
+        let sk2 = SecretKey::new();
+        let pk2 = PublicKey::from(&sk2);
+
+        let (con1, con2) = tokio::io::duplex(1500);
+
+        let (res1, res2) = tokio::join!(
+            handshake(con1, &sk1, pk1, &[]),
+            handshake(con2, &sk2, pk2, &[])
+        );
+
+        let (mut con1, remote1) = res1.expect("Handshake succeeds");
+        let (mut con2, remote2) = res2.expect("Handshake succeeds");
+        assert_eq!(remote1, pk2);
+        assert_eq!(remote2, pk1);
+
+        // The session keys of both sides match.
+        con1.write_all(b"ping").await.expect("Can write data");
+        con1.flush().await.expect("Can flush data");
+        let mut buf = [0; 4];
+        con2.read_exact(&mut buf).await.expect("Can read data");
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn relayed_handshake_rejects_injected_data() {
+        let sk1 = SecretKey::new();
+        let pk1 = PublicKey::from(&sk1);
+        let sk2 = SecretKey::new();
+        let pk2 = PublicKey::from(&sk2);
+
+        // A relay sits between both nodes, and forwards the handshake unmodified.
+        let (con1, mut relay1) = tokio::io::duplex(1500);
+        let (con2, mut relay2) = tokio::io::duplex(1500);
+        let relay = tokio::spawn(async move {
+            let mut hello1 = [0; super::HELLO_SIZE];
+            let mut hello2 = [0; super::HELLO_SIZE];
+            relay1.read_exact(&mut hello1).await.unwrap();
+            relay2.read_exact(&mut hello2).await.unwrap();
+            relay2.write_all(&hello1).await.unwrap();
+            relay1.write_all(&hello2).await.unwrap();
+            let mut proof1 = [0; super::PROOF_SIZE];
+            let mut proof2 = [0; super::PROOF_SIZE];
+            relay1.read_exact(&mut proof1).await.unwrap();
+            relay2.read_exact(&mut proof2).await.unwrap();
+            relay2.write_all(&proof1).await.unwrap();
+            relay1.write_all(&proof2).await.unwrap();
+            // Now inject some traffic of our own, framed as a record.
+            let mut record = vec![0, 20];
+            record.extend_from_slice(&[0xAA; 20]);
+            relay2.write_all(&record).await.unwrap();
+            (relay1, relay2)
+        });
+
+        let (res1, res2) = tokio::join!(
+            handshake(con1, &sk1, pk1, &[]),
+            handshake(con2, &sk2, pk2, &[])
+        );
+        let _relay = relay.await.expect("Relay finishes");
+
+        let _ = res1.expect("Relayed handshake succeeds");
+        let (mut con2, _) = res2.expect("Relayed handshake succeeds");
+        let mut buf = [0; 4];
+        assert!(con2.read_exact(&mut buf).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_unowned_key() {
+        let sk1 = SecretKey::new();
+        let pk1 = PublicKey::from(&sk1);
+        let sk2 = SecretKey::new();
+        // Claim to be some other node, without having its secret key.
+        let stolen_pk = PublicKey::from(&SecretKey::new());
+
+        let (con1, con2) = tokio::io::duplex(1500);
+
+        let (res1, _) = tokio::join!(
+            handshake(con1, &sk1, pk1, &[]),
+            handshake(con2, &sk2, stolen_pk, &[])
+        );
+
+        assert!(matches!(res1, Err(HandshakeError::InvalidProof)));
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_own_key() {
+        let sk = SecretKey::new();
+        let pk = PublicKey::from(&sk);
+
+        let (con1, con2) = tokio::io::duplex(1500);
+
+        let (res1, res2) =
+            tokio::join!(handshake(con1, &sk, pk, &[]), handshake(con2, &sk, pk, &[]));
+
+        assert!(matches!(res1, Err(HandshakeError::OwnKey)));
+        assert!(matches!(res2, Err(HandshakeError::OwnKey)));
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_invalid_magic() {
+        let sk = SecretKey::new();
+        let pk = PublicKey::from(&sk);
+
+        let (con1, mut con2) = tokio::io::duplex(1500);
+
+        con2.write_all(&[0; super::HELLO_SIZE])
+            .await
+            .expect("Can write to duplex stream");
+
+        let res = handshake(con1, &sk, pk, &[]).await;
+
+        assert!(matches!(res, Err(HandshakeError::InvalidMagic)));
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_different_channel_binding() {
+        let sk1 = SecretKey::new();
+        let pk1 = PublicKey::from(&sk1);
+        let sk2 = SecretKey::new();
+        let pk2 = PublicKey::from(&sk2);
+
+        let (con1, con2) = tokio::io::duplex(1500);
+
+        // Both sides see a different certificate, e.g. because a machine in the middle terminates
+        // TLS.
+        let (res1, res2) = tokio::join!(
+            handshake(con1, &sk1, pk1, b"certificate"),
+            handshake(con2, &sk2, pk2, b"other certificate")
+        );
+
+        assert!(matches!(res1, Err(HandshakeError::InvalidProof)));
+        assert!(matches!(res2, Err(HandshakeError::InvalidProof)));
+    }
+
+    #[tokio::test]
+    async fn handshake_falls_back_to_lowest_version() {
+        let sk1 = SecretKey::new();
+        let pk1 = PublicKey::from(&sk1);
+        let sk2 = SecretKey::new();
+        let pk2 = PublicKey::from(&sk2);
+
+        let (con1, con2) = tokio::io::duplex(1500);
+
+        let (res1, res2) = tokio::join!(
+            handshake(con1, &sk1, pk1, &[]),
+            versioned_handshake(con2, &sk2, pk2, &[], HANDSHAKE_VERSION + 1)
+        );
+
+        let (mut con1, _) = res1.expect("Handshake succeeds");
+        let (mut con2, _) = res2.expect("Handshake succeeds");
+        con1.write_all(b"ping").await.expect("Can write data");
+        con1.flush().await.expect("Can flush data");
+        let mut buf = [0; 4];
+        con2.read_exact(&mut buf).await.expect("Can read data");
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_outdated_version() {
+        let sk1 = SecretKey::new();
+        let pk1 = PublicKey::from(&sk1);
+        let sk2 = SecretKey::new();
+        let pk2 = PublicKey::from(&sk2);
+
+        let (con1, con2) = tokio::io::duplex(1500);
+
+        let (res1, res2) = tokio::join!(
+            handshake(con1, &sk1, pk1, &[]),
+            versioned_handshake(con2, &sk2, pk2, &[], 0)
+        );
+
+        assert!(matches!(res1, Err(HandshakeError::UnsupportedVersion(0))));
+        assert!(matches!(res2, Err(HandshakeError::UnsupportedVersion(_))));
+    }
+}
@@ -0,0 +1,98 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Maximum size of the response of a proxy to a CONNECT request.
+const MAX_RESPONSE_SIZE: usize = 8 * 1024;
+
+/// Open a tunnel to `target`, which is a `host:port` pair, through an HTTP proxy, by sending a
+/// CONNECT request on a connection to the proxy. Once this returns, all data on the connection is
+/// forwarded to and from the target.
+pub async fn http_connect<S>(stream: &mut S, target: &str) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await?;
+    stream.flush().await?;
+
+    // Read the response one byte at a time, so no data from the target is consumed after the
+    // end of the headers.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "proxy response is too large",
+            ));
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let status_line = response
+        .split(|b| *b == b'\r')
+        .next()
+        .map(String::from_utf8_lossy)
+        .unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') && status.len() == 3 => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("proxy refused tunnel: {status_line}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::http_connect;
+
+    const REQUEST: &[u8] = b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n";
+
+    #[tokio::test]
+    async fn opens_tunnel() {
+        let (mut client, mut proxy) = tokio::io::duplex(1500);
+        let proxy = tokio::spawn(async move {
+            let mut request = vec![0; REQUEST.len()];
+            proxy
+                .read_exact(&mut request)
+                .await
+                .expect("Can read request");
+            assert_eq!(request, REQUEST);
+            proxy
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\ndata")
+                .await
+                .expect("Can write response");
+        });
+
+        http_connect(&mut client, "example.com:443")
+            .await
+            .expect("Tunnel is opened");
+        // Data sent after the response is not consumed.
+        let mut data = [0; 4];
+        client.read_exact(&mut data).await.expect("Can read data");
+        assert_eq!(&data, b"data");
+        proxy.await.expect("Proxy finishes");
+    }
+
+    #[tokio::test]
+    async fn refused_tunnel() {
+        let (mut client, mut proxy) = tokio::io::duplex(1500);
+        tokio::spawn(async move {
+            let mut request = vec![0; REQUEST.len()];
+            proxy
+                .read_exact(&mut request)
+                .await
+                .expect("Can read request");
+            proxy
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .expect("Can write response");
+        });
+
+        assert!(http_connect(&mut client, "example.com:443").await.is_err());
+    }
+}
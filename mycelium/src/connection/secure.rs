@@ -0,0 +1,309 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use aes_gcm::{AeadInPlace, Aes256Gcm, Key, KeyInit};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::Connection;
+
+/// Size of the length prefix of a record.
+const RECORD_HEADER_SIZE: usize = 2;
+/// Size of the authentication tag of a record.
+const RECORD_TAG_SIZE: usize = 16;
+/// Maximum amount of plaintext in a single record.
+const MAX_RECORD_DATA: usize = u16::MAX as usize - RECORD_TAG_SIZE;
+/// Size of the buffer used to read from the underlying connection.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// A connection of which every byte is encrypted and authenticated with the session keys derived
+/// during the [`handshake`](super::handshake).
+///
+/// Data is sent in records, consisting of a 2 byte big endian length, followed by the encrypted
+/// data and its authentication tag. Every direction has its own key, and records are numbered,
+/// with the number being used as nonce. As a result, records can't be modified, injected,
+/// reordered or replayed by anyone who does not know the session keys. If a record fails to
+/// authenticate, reading from the connection returns an error.
+pub struct Secure<C> {
+    con: C,
+    tx_cipher: Aes256Gcm,
+    tx_counter: u64,
+    rx_cipher: Aes256Gcm,
+    rx_counter: u64,
+    /// Encrypted records which are not yet written to the underlying connection.
+    write_buf: Vec<u8>,
+    /// Amount of bytes from write_buf which are already written.
+    write_pos: usize,
+    /// Bytes read from the underlying connection which don't form a full record yet.
+    read_buf: Vec<u8>,
+    /// Decrypted data which is not yet returned to the reader.
+    plain: Vec<u8>,
+    /// Amount of bytes from plain which are already returned.
+    plain_pos: usize,
+}
+
+impl<C> Secure<C> {
+    /// Wrap a connection, using `tx_key` to encrypt outgoing data, and `rx_key` to decrypt
+    /// incoming data.
+    pub(super) fn new(con: C, tx_key: [u8; 32], rx_key: [u8; 32]) -> Self {
+        let tx_key: Key<Aes256Gcm> = tx_key.into();
+        let rx_key: Key<Aes256Gcm> = rx_key.into();
+        Self {
+            con,
+            tx_cipher: Aes256Gcm::new(&tx_key),
+            tx_counter: 0,
+            rx_cipher: Aes256Gcm::new(&rx_key),
+            rx_counter: 0,
+            write_buf: Vec::new(),
+            write_pos: 0,
+            read_buf: Vec::new(),
+            plain: Vec::new(),
+            plain_pos: 0,
+        }
+    }
+
+    /// Encrypt data as a new record, and append it to the write buffer.
+    fn seal(&mut self, data: &[u8]) -> io::Result<()> {
+        let nonce = record_nonce(&mut self.tx_counter)?;
+        let header = ((data.len() + RECORD_TAG_SIZE) as u16).to_be_bytes();
+
+        let start = self.write_buf.len();
+        self.write_buf.extend_from_slice(&header);
+        self.write_buf.extend_from_slice(data);
+        let tag = self
+            .tx_cipher
+            .encrypt_in_place_detached(
+                nonce[..].into(),
+                &header,
+                &mut self.write_buf[start + RECORD_HEADER_SIZE..],
+            )
+            .expect("Encryption can't fail; qed.");
+        self.write_buf.extend_from_slice(tag.as_slice());
+
+        Ok(())
+    }
+
+    /// Decrypt the first record in the read buffer, if it is complete. Returns true if a record
+    /// was decrypted.
+    fn open(&mut self) -> io::Result<bool> {
+        if self.read_buf.len() < RECORD_HEADER_SIZE {
+            return Ok(false);
+        }
+        let header = [self.read_buf[0], self.read_buf[1]];
+        let record_len = u16::from_be_bytes(header) as usize;
+        if record_len < RECORD_TAG_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "record is too short",
+            ));
+        }
+        if self.read_buf.len() < RECORD_HEADER_SIZE + record_len {
+            return Ok(false);
+        }
+
+        let nonce = record_nonce(&mut self.rx_counter)?;
+        let mut data = self.read_buf[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + record_len].to_vec();
+        self.read_buf.drain(..RECORD_HEADER_SIZE + record_len);
+        let tag = data.split_off(record_len - RECORD_TAG_SIZE);
+        self.rx_cipher
+            .decrypt_in_place_detached(nonce[..].into(), &header, &mut data, tag[..].into())
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "record failed to authenticate")
+            })?;
+
+        self.plain = data;
+        self.plain_pos = 0;
+
+        Ok(true)
+    }
+}
+
+impl<C> Secure<C>
+where
+    C: AsyncWrite + Unpin,
+{
+    /// Write all buffered records to the underlying connection.
+    fn poll_write_buffered(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_buf.len() {
+            let n =
+                ready!(Pin::new(&mut self.con).poll_write(cx, &self.write_buf[self.write_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += n;
+        }
+        self.write_buf.clear();
+        self.write_pos = 0;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Get the nonce for the next record, and advance the record counter.
+fn record_nonce(counter: &mut u64) -> io::Result<[u8; 12]> {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    *counter = counter.checked_add(1).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Other, "record counter of session exhausted")
+    })?;
+    Ok(nonce)
+}
+
+impl<C> Connection for Secure<C>
+where
+    C: Connection + Unpin,
+{
+    #[inline]
+    fn identifier(&self) -> Result<String, io::Error> {
+        self.con.identifier()
+    }
+
+    #[inline]
+    fn static_link_cost(&self) -> Result<u16, io::Error> {
+        self.con.static_link_cost()
+    }
+}
+
+impl<C> AsyncRead for Secure<C>
+where
+    C: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.plain_pos < this.plain.len() {
+                let n = buf.remaining().min(this.plain.len() - this.plain_pos);
+                buf.put_slice(&this.plain[this.plain_pos..this.plain_pos + n]);
+                this.plain_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.open()? {
+                continue;
+            }
+
+            let mut chunk = [0; READ_CHUNK_SIZE];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.con).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                // EOF, which is only fine in between records.
+                return if this.read_buf.is_empty() {
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()))
+                };
+            }
+            this.read_buf.extend_from_slice(chunk_buf.filled());
+        }
+    }
+}
+
+impl<C> AsyncWrite for Secure<C>
+where
+    C: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let this = &mut *self;
+        // Only accept new data once the previous records are written, so the buffer stays
+        // bounded.
+        ready!(this.poll_write_buffered(cx))?;
+
+        let n = buf.len().min(MAX_RECORD_DATA);
+        this.seal(&buf[..n])?;
+        // Try to write the record right away. If this is not possible, it is written on the next
+        // write or flush.
+        if let Poll::Ready(Err(e)) = this.poll_write_buffered(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        ready!(self.poll_write_buffered(cx))?;
+        Pin::new(&mut self.con).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        ready!(self.poll_write_buffered(cx))?;
+        Pin::new(&mut self.con).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::Secure;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let (con1, con2) = tokio::io::duplex(1500);
+        let mut con1 = Secure::new(con1, [1; 32], [2; 32]);
+        let mut con2 = Secure::new(con2, [2; 32], [1; 32]);
+
+        let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        let expected = data.clone();
+        let writer = tokio::spawn(async move {
+            con1.write_all(&data).await.expect("Can write data");
+            con1.shutdown().await.expect("Can shut down connection");
+        });
+
+        let mut received = Vec::new();
+        con2.read_to_end(&mut received)
+            .await
+            .expect("Can read data");
+        writer.await.expect("Writer finishes");
+
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn rejects_modified_records() {
+        let (con1, mut con2) = tokio::io::duplex(1500);
+        let mut con1 = Secure::new(con1, [1; 32], [2; 32]);
+
+        con1.write_all(b"hello").await.expect("Can write data");
+        con1.flush().await.expect("Can flush data");
+
+        let mut record = [0; 2 + 5 + 16];
+        con2.read_exact(&mut record).await.expect("Can read record");
+        record[3] ^= 1;
+
+        let (mut con3, con4) = tokio::io::duplex(1500);
+        let mut con4 = Secure::new(con4, [2; 32], [1; 32]);
+        con3.write_all(&record).await.expect("Can write record");
+
+        let mut buf = [0; 5];
+        assert!(con4.read_exact(&mut buf).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_key() {
+        let (con1, con2) = tokio::io::duplex(1500);
+        let mut con1 = Secure::new(con1, [1; 32], [2; 32]);
+        let mut con2 = Secure::new(con2, [2; 32], [3; 32]);
+
+        con1.write_all(b"hello").await.expect("Can write data");
+        con1.flush().await.expect("Can flush data");
+
+        let mut buf = [0; 5];
+        assert!(con2.read_exact(&mut buf).await.is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use futures::{Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{tungstenite, WebSocketStream};
+
+use super::{Connection, PACKET_PROCESSING_COST_IP4_WS, PACKET_PROCESSING_COST_IP6_WS};
+
+/// A wrapper around a [`WebSocketStream`], implementing the [`Connection`] trait.
+///
+/// Data written to the connection is sent in binary messages, and the payload of received binary
+/// messages is returned when reading. Other messages are ignored.
+pub struct WebSocket<S> {
+    stream: WebSocketStream<S>,
+    remote: SocketAddr,
+    secure: bool,
+    /// Data of a received message which was not read yet.
+    read_buf: Bytes,
+}
+
+impl<S> WebSocket<S> {
+    /// Create a new wrapper around a [`WebSocketStream`]. `secure` indicates if the stream runs
+    /// over TLS.
+    pub fn new(stream: WebSocketStream<S>, remote: SocketAddr, secure: bool) -> Self {
+        Self {
+            stream,
+            remote,
+            secure,
+            read_buf: Bytes::new(),
+        }
+    }
+}
+
+impl<S> AsyncRead for WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.read_buf.is_empty() {
+            match ready!(Pin::new(&mut self.stream).poll_next(cx)) {
+                Some(Ok(tungstenite::Message::Binary(data))) => self.read_buf = data.into(),
+                // Control frames are handled by the stream itself, and we never send text.
+                Some(Ok(tungstenite::Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Poll::Ready(Err(to_io_error(e))),
+            }
+        }
+
+        let amount = self.read_buf.len().min(buf.remaining());
+        buf.put_slice(&self.read_buf[..amount]);
+        self.read_buf.advance(amount);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        ready!(Pin::new(&mut self.stream).poll_ready(cx)).map_err(to_io_error)?;
+        Pin::new(&mut self.stream)
+            .start_send(tungstenite::Message::Binary(buf.to_vec()))
+            .map_err(to_io_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.stream)
+            .poll_flush(cx)
+            .map_err(to_io_error)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.stream)
+            .poll_close(cx)
+            .map_err(to_io_error)
+    }
+}
+
+impl<S> Connection for WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn identifier(&self) -> Result<String, io::Error> {
+        Ok(format!(
+            "{} -> {}",
+            if self.secure { "WSS" } else { "WS" },
+            self.remote
+        ))
+    }
+
+    fn static_link_cost(&self) -> Result<u16, io::Error> {
+        Ok(match self.remote {
+            SocketAddr::V4(_) => PACKET_PROCESSING_COST_IP4_WS,
+            SocketAddr::V6(ip) if ip.ip().to_ipv4_mapped().is_some() => {
+                PACKET_PROCESSING_COST_IP4_WS
+            }
+            SocketAddr::V6(_) => PACKET_PROCESSING_COST_IP6_WS,
+        })
+    }
+}
+
+/// Convert a WebSocket error to an [`io::Error`], keeping the original IO error if there is one.
+fn to_io_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(e) => e,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::ErrorKind::BrokenPipe.into()
+        }
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
@@ -4,13 +4,16 @@ use core::fmt;
 use std::{
     error::Error,
     fmt::Display,
-    net::Ipv6Addr,
+    net::{IpAddr, Ipv6Addr},
     ops::{Deref, DerefMut},
+    str::FromStr,
 };
 
 use aes_gcm::{aead::OsRng, AeadCore, AeadInPlace, Aes256Gcm, Key, KeyInit};
 use serde::{de::Visitor, Deserialize, Serialize};
 
+use crate::subnet::Subnet;
+
 /// Default MTU for a packet. Ideally this would not be needed and the [`PacketBuffer`] takes a
 /// const generic argument which is then expanded with the needed extra space for the buffer,
 /// however as it stands const generics can only be used standalone and not in a constant
@@ -23,6 +26,16 @@ const AES_TAG_SIZE: usize = 16;
 /// Size of an AES_GCM nonce in bytes.
 const AES_NONCE_SIZE: usize = 12;
 
+/// Maximum amount of subnets which can be delegated to a single [`PublicKey`], in addition to
+/// the subnet containing its [`address`](PublicKey::address).
+pub const MAX_DELEGATED_SUBNETS: u8 = 16;
+
+/// Prefix length of a subnet delegated to a [`PublicKey`].
+const DELEGATED_SUBNET_PREFIX_LEN: u8 = 64;
+
+/// Context used when deriving delegated subnets, so they are independent of the regular address.
+const DELEGATED_SUBNET_CONTEXT: &[u8] = b"mycelium delegated subnet";
+
 /// Size of user defined data header. This header will be part of the encrypted data.
 const DATA_HEADER_SIZE: usize = 4;
 
@@ -123,6 +136,41 @@ impl PublicKey {
         Ipv6Addr::from(buf)
     }
 
+    /// Generates the delegated [`Subnet`] with the given index from a `PublicKey`.
+    ///
+    /// Every key owns [`MAX_DELEGATED_SUBNETS`] of these /64 subnets, which can be announced in
+    /// addition to the subnet of its [`address`](Self::address), e.g. to route traffic to
+    /// containers behind a node. Like the address, the subnet is guaranteed to be part of the
+    /// `400::/7` range. Returns [`None`] if the index is not lower than
+    /// [`MAX_DELEGATED_SUBNETS`].
+    pub fn delegated_subnet(&self, index: u8) -> Option<Subnet> {
+        if index >= MAX_DELEGATED_SUBNETS {
+            return None;
+        }
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(DELEGATED_SUBNET_CONTEXT);
+        hasher.update(self.as_bytes());
+        hasher.update(&[index]);
+        let mut buf = [0; 16];
+        hasher.finalize_xof().fill(&mut buf[..8]);
+        // Same mangling as for the regular address.
+        let lsb = buf[0].count_ones() as u8 % 2;
+        buf[0] = 0x04 | lsb;
+        Some(
+            Subnet::new(IpAddr::V6(Ipv6Addr::from(buf)), DELEGATED_SUBNET_PREFIX_LEN)
+                .expect("Static prefix length is valid for IPv6; qed"),
+        )
+    }
+
+    /// Checks if the given [`Subnet`] is one of the subnets delegated to this `PublicKey`, as
+    /// generated by [`delegated_subnet`](Self::delegated_subnet).
+    pub fn owns_delegated_subnet(&self, subnet: &Subnet) -> bool {
+        subnet.prefix_len() == DELEGATED_SUBNET_PREFIX_LEN
+            && (0..MAX_DELEGATED_SUBNETS)
+                .filter_map(|index| self.delegated_subnet(index))
+                .any(|delegated| delegated.network() == subnet.network())
+    }
+
     /// Convert this `PublicKey` to a byte array.
     pub fn to_bytes(self) -> [u8; 32] {
         self.0.to_bytes()
@@ -314,6 +362,14 @@ impl TryFrom<&str> for PublicKey {
     }
 }
 
+impl FromStr for PublicKey {
+    type Err = faster_hex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PublicKey::try_from(s)
+    }
+}
+
 impl From<&SecretKey> for PublicKey {
     fn from(value: &SecretKey) -> Self {
         PublicKey(x25519_dalek::PublicKey::from(&value.0))
@@ -369,7 +425,11 @@ impl fmt::Debug for PacketBuffer {
 
 #[cfg(test)]
 mod tests {
-    use super::{PacketBuffer, SecretKey, AES_NONCE_SIZE, AES_TAG_SIZE, DATA_HEADER_SIZE};
+    use super::{
+        PacketBuffer, PublicKey, SecretKey, AES_NONCE_SIZE, AES_TAG_SIZE, DATA_HEADER_SIZE,
+        MAX_DELEGATED_SUBNETS,
+    };
+    use crate::subnet::Subnet;
 
     #[test]
     /// Test if encryption works in general. We just create some random value and encrypt it.
@@ -447,4 +507,31 @@ mod tests {
         assert_eq!(pb.buffer().len(), super::PACKET_SIZE);
         assert_eq!(pb.buffer_mut().len(), super::PACKET_SIZE);
     }
+
+    #[test]
+    /// Delegated subnets are deterministic, distinct /64's in the overlay range, which don't
+    /// overlap with the subnet of the regular address.
+    fn delegated_subnets() {
+        let pk = PublicKey::from(&SecretKey::new());
+        let global = Subnet::new("400::".parse().unwrap(), 7).unwrap();
+        let own = Subnet::new(pk.address().into(), 64).unwrap();
+
+        let subnets = (0..MAX_DELEGATED_SUBNETS)
+            .map(|index| pk.delegated_subnet(index).expect("Index is in range"))
+            .collect::<Vec<_>>();
+
+        for (i, subnet) in subnets.iter().enumerate() {
+            assert_eq!(subnet.prefix_len(), 64);
+            assert!(global.contains_subnet(subnet));
+            assert!(!subnet.contains_subnet(&own));
+            assert!(pk.owns_delegated_subnet(subnet));
+            assert!(!subnets[i + 1..].contains(subnet));
+            assert_eq!(pk.delegated_subnet(i as u8), Some(*subnet));
+        }
+
+        assert_eq!(pk.delegated_subnet(MAX_DELEGATED_SUBNETS), None);
+        assert!(!pk.owns_delegated_subnet(&own));
+        let other = PublicKey::from(&SecretKey::new());
+        assert!(!other.owns_delegated_subnet(&subnets[0]));
+    }
 }
@@ -1,9 +1,12 @@
-use std::net::{IpAddr, Ipv6Addr};
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+};
 
 use etherparse::{icmpv6::DestUnreachableCode, Icmpv6Type, PacketBuilder};
 use futures::{Sink, SinkExt, Stream, StreamExt};
 use log::{debug, error, trace, warn};
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::{sync::mpsc::UnboundedReceiver, task::JoinHandle};
 
 use crate::{crypto::PacketBuffer, packet::DataPacket, router::Router};
 
@@ -51,6 +54,8 @@ const MESSAGE_HOP_LIMIT: u8 = 64;
 #[derive(Clone)]
 pub struct DataPlane {
     router: Router,
+    /// The tasks moving packets between the host and the router.
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
 }
 
 impl DataPlane {
@@ -72,21 +77,39 @@ impl DataPlane {
         U: Sink<(PacketBuffer, IpAddr, IpAddr)> + Send + Unpin + 'static,
         U::Error: std::fmt::Display,
     {
-        let dp = Self { router };
-
-        tokio::spawn(
-            dp.clone()
-                .inject_l3_packet_loop(l3_packet_stream, l3_packet_sink.clone()),
-        );
-        tokio::spawn(dp.clone().extract_packet_loop(
-            l3_packet_sink,
-            message_packet_sink,
-            host_packet_source,
-        ));
+        let dp = Self {
+            router,
+            tasks: Arc::new(Mutex::new(Vec::new())),
+        };
+
+        *dp.tasks.lock().unwrap() = vec![
+            tokio::spawn(
+                dp.clone()
+                    .inject_l3_packet_loop(l3_packet_stream, l3_packet_sink.clone()),
+            ),
+            tokio::spawn(dp.clone().extract_packet_loop(
+                l3_packet_sink,
+                message_packet_sink,
+                host_packet_source,
+            )),
+        ];
 
         dp
     }
 
+    /// Stop moving packets between the host and the router. This drops the l3 packet stream and
+    /// sink, which closes the TUN interface if one is used.
+    pub async fn shutdown(&self) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in &tasks {
+            task.abort();
+        }
+        for task in tasks {
+            // The only possible error is the cancellation we just requested.
+            let _ = task.await;
+        }
+    }
+
     /// Get a reference to the [`Router`] used.
     pub fn router(&self) -> &Router {
         &self.router
@@ -220,6 +243,7 @@ impl DataPlane {
                     "No entry found for destination address {}, dropping packet",
                     dst_ip
                 );
+                self.router.data_counters().no_route();
 
                 let mut pb = PacketBuffer::new();
                 // From self to self
@@ -278,6 +302,7 @@ impl DataPlane {
                 Ok(data) => data,
                 Err(_) => {
                     log::debug!("Dropping data packet with invalid encrypted content");
+                    self.router.data_counters().decryption_failure();
                     continue;
                 }
             };
@@ -375,6 +400,7 @@ impl DataPlane {
                         Ok(pb) => pb,
                         Err(e) => {
                             warn!("Failed to decrypt ICMP data body {e}");
+                            self.router.data_counters().decryption_failure();
                             continue;
                         }
                     };
@@ -1,6 +1,6 @@
 use std::{
     fmt,
-    net::{AddrParseError, SocketAddr},
+    net::{AddrParseError, IpAddr, Ipv6Addr, SocketAddr},
     str::FromStr,
 };
 
@@ -13,6 +13,13 @@ pub enum EndpointParseError {
     MissingProtocol,
     /// An endpoint was specified using a protocol we (currently) do not understand.
     UnknownProtocol,
+    /// A path was specified for a protocol which does not support it.
+    UnexpectedPath,
+    /// No path was specified for a protocol which requires it.
+    MissingPath,
+    /// A host name was specified for a protocol which does not support it, or the host name or
+    /// port are not valid.
+    InvalidHost,
     /// Error while parsing the specific address.
     Address(AddrParseError),
 }
@@ -25,20 +32,71 @@ pub enum Protocol {
     Tcp,
     /// Quic protocol (over UDP).
     Quic,
+    /// Tcp, secured with TLS. The certificate of the remote is not verified, since the remote is
+    /// authenticated by the handshake on the connection.
+    Tls,
+    /// WebSocket protocol (over plain text Tcp).
+    Ws,
+    /// WebSocket protocol over TLS. The certificate of the remote is not verified, since the
+    /// remote is authenticated by the handshake on the connection.
+    Wss,
+    /// Unix domain socket. The path of the endpoint is the path of the socket.
+    Unix,
+    /// In memory connection, set up in the same process. These can't be parsed, and the remote
+    /// can't be reconnected once the connection is lost.
+    Memory,
 }
 
+/// The address used by endpoints which don't have a socket address.
+const UNSPECIFIED_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0);
+
 /// An endpoint defines a address and a protocol to use when communicating with it.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Endpoint {
     proto: Protocol,
     socket_addr: SocketAddr,
+    /// Host name of a WebSocket endpoint, which is resolved when connecting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    /// Path of the request used to set up a WebSocket connection, or the path of a Unix domain
+    /// socket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
 }
 
 impl Endpoint {
     /// Create a new `Endpoint` with given [`Protocol`] and address.
     pub fn new(proto: Protocol, socket_addr: SocketAddr) -> Self {
-        Self { proto, socket_addr }
+        Self {
+            proto,
+            socket_addr,
+            host: None,
+            path: None,
+        }
+    }
+
+    /// Create a new `Endpoint` with given [`Protocol`], address, and path. A path is only used by
+    /// the WebSocket protocols, see [`Endpoint::path`].
+    pub fn with_path(proto: Protocol, socket_addr: SocketAddr, path: String) -> Self {
+        Self {
+            proto,
+            socket_addr,
+            host: None,
+            path: Some(path),
+        }
+    }
+
+    /// Create a new `Endpoint` for a WebSocket protocol, identified by a host name and port. The
+    /// host name is resolved every time a connection is made, and is also sent to the remote, so
+    /// it can be used behind proxies and load balancers serving multiple hosts.
+    pub fn with_host(proto: Protocol, host: String, port: u16, path: Option<String>) -> Self {
+        Self {
+            proto,
+            socket_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+            host: Some(host),
+            path,
+        }
     }
 
     /// Get the [`Protocol`] used by this `Endpoint`.
@@ -46,10 +104,44 @@ impl Endpoint {
         self.proto
     }
 
-    /// Get the [`SocketAddr`] used by this `Endpoint`.
+    /// Create a new `Endpoint` for a Unix domain socket at the given path.
+    pub fn unix(path: String) -> Self {
+        Self::with_path(Protocol::Unix, UNSPECIFIED_ADDRESS, path)
+    }
+
+    /// Create a new `Endpoint` for an in memory connection. The identifier must be unique for
+    /// every connection.
+    pub fn memory(identifier: String) -> Self {
+        Self::with_path(Protocol::Memory, UNSPECIFIED_ADDRESS, identifier)
+    }
+
+    /// Get the [`SocketAddr`] used by this `Endpoint`. For Unix domain sockets and in memory
+    /// connections, this is the unspecified IPv6 address with port 0. For endpoints with a
+    /// [host name](Endpoint::host), this is the unspecified IPv6 address with the port of the
+    /// endpoint.
     pub fn address(&self) -> SocketAddr {
         self.socket_addr
     }
+
+    /// Get the host name of this `Endpoint`, if it is identified by one instead of an address.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Get the authority to connect to, i.e. the host name or IP address, followed by the port.
+    pub fn authority(&self) -> String {
+        match self.host {
+            Some(ref host) => format!("{host}:{}", self.socket_addr.port()),
+            None => self.socket_addr.to_string(),
+        }
+    }
+
+    /// Get the path of the request used to set up a WebSocket connection, if one is set. If there
+    /// is no path, `/` is used. For Unix domain sockets, this is the path of the socket, and for
+    /// in memory connections this is the identifier of the connection.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
 }
 
 impl FromStr for Endpoint {
@@ -62,10 +154,37 @@ impl FromStr for Endpoint {
                 let proto = match proto.to_lowercase().as_str() {
                     "tcp" => Protocol::Tcp,
                     "quic" => Protocol::Quic,
+                    "tls" => Protocol::Tls,
+                    "ws" => Protocol::Ws,
+                    "wss" => Protocol::Wss,
+                    "unix" if socket.is_empty() => return Err(EndpointParseError::MissingPath),
+                    "unix" => return Ok(Endpoint::unix(socket.to_string())),
                     _ => return Err(EndpointParseError::UnknownProtocol),
                 };
-                let socket_addr = SocketAddr::from_str(socket)?;
-                Ok(Endpoint { proto, socket_addr })
+                // A path starts at the first `/`, which can't be part of the address.
+                let (socket, path) = match socket.find('/') {
+                    Some(idx) => (&socket[..idx], Some(&socket[idx..])),
+                    None => (socket, None),
+                };
+                let websocket = matches!(proto, Protocol::Ws | Protocol::Wss);
+                if path.is_some() && !websocket {
+                    return Err(EndpointParseError::UnexpectedPath);
+                }
+                let path = path.map(str::to_string);
+                match SocketAddr::from_str(socket) {
+                    Ok(socket_addr) => Ok(Endpoint {
+                        proto,
+                        socket_addr,
+                        host: None,
+                        path,
+                    }),
+                    // WebSocket endpoints can also use a host name.
+                    Err(_) if websocket => {
+                        let (host, port) = parse_host(socket)?;
+                        Ok(Endpoint::with_host(proto, host.to_string(), port, path))
+                    }
+                    Err(e) => Err(e.into()),
+                }
             }
         }
     }
@@ -73,8 +192,38 @@ impl FromStr for Endpoint {
 
 impl fmt::Display for Endpoint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_fmt(format_args!("{} {}", self.proto, self.socket_addr))
+        match self.proto {
+            Protocol::Unix | Protocol::Memory => f.write_fmt(format_args!(
+                "{} {}",
+                self.proto,
+                self.path().unwrap_or_default()
+            )),
+            _ => f.write_fmt(format_args!(
+                "{} {}{}",
+                self.proto,
+                self.authority(),
+                self.path().unwrap_or_default()
+            )),
+        }
+    }
+}
+
+/// Parse a `host:port` pair. The host must be a valid DNS name.
+fn parse_host(s: &str) -> Result<(&str, u16), EndpointParseError> {
+    let (host, port) = s.rsplit_once(':').ok_or(EndpointParseError::InvalidHost)?;
+    let port = port.parse().map_err(|_| EndpointParseError::InvalidHost)?;
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if host.len() > 253 || !host.split('.').all(valid_label) {
+        return Err(EndpointParseError::InvalidHost);
     }
+
+    Ok((host, port))
 }
 
 impl fmt::Display for Protocol {
@@ -82,6 +231,11 @@ impl fmt::Display for Protocol {
         f.write_str(match self {
             Self::Tcp => "Tcp",
             Self::Quic => "Quic",
+            Self::Tls => "Tls",
+            Self::Ws => "Ws",
+            Self::Wss => "Wss",
+            Self::Unix => "Unix",
+            Self::Memory => "Memory",
         })
     }
 }
@@ -91,6 +245,9 @@ impl fmt::Display for EndpointParseError {
         match self {
             Self::MissingProtocol => f.write_str("missing leading protocol identifier"),
             Self::UnknownProtocol => f.write_str("protocol for endpoint is not supported"),
+            Self::UnexpectedPath => f.write_str("protocol for endpoint does not support a path"),
+            Self::MissingPath => f.write_str("protocol for endpoint requires a path"),
+            Self::InvalidHost => f.write_str("invalid host name or port"),
             Self::Address(e) => f.write_fmt(format_args!("failed to parse address: {}", e)),
         }
     }
@@ -110,3 +267,82 @@ impl From<AddrParseError> for EndpointParseError {
         Self::Address(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{Endpoint, EndpointParseError, Protocol};
+
+    #[test]
+    fn parse_endpoints() {
+        let ep = Endpoint::from_str("tcp://[::1]:9651").expect("Valid endpoint");
+        assert_eq!(ep.proto(), Protocol::Tcp);
+        assert_eq!(ep.path(), None);
+
+        let ep = Endpoint::from_str("tls://192.0.2.6:9653").expect("Valid endpoint");
+        assert_eq!(ep.proto(), Protocol::Tls);
+
+        let ep = Endpoint::from_str("ws://192.0.2.6:80").expect("Valid endpoint");
+        assert_eq!(ep.proto(), Protocol::Ws);
+        assert_eq!(ep.path(), None);
+
+        let ep = Endpoint::from_str("wss://[2001:db8::1]:443/mycelium/ws").expect("Valid endpoint");
+        assert_eq!(ep.proto(), Protocol::Wss);
+        assert_eq!(ep.address(), "[2001:db8::1]:443".parse().unwrap());
+        assert_eq!(ep.path(), Some("/mycelium/ws"));
+        assert_eq!(ep.to_string(), "Wss [2001:db8::1]:443/mycelium/ws");
+        assert_eq!(ep.host(), None);
+
+        let ep = Endpoint::from_str("wss://mycelium.example.com:443/ws").expect("Valid endpoint");
+        assert_eq!(ep.proto(), Protocol::Wss);
+        assert_eq!(ep.host(), Some("mycelium.example.com"));
+        assert_eq!(ep.address().port(), 443);
+        assert_eq!(ep.authority(), "mycelium.example.com:443");
+        assert_eq!(ep.path(), Some("/ws"));
+        assert_eq!(ep.to_string(), "Wss mycelium.example.com:443/ws");
+
+        let ep = Endpoint::from_str("ws://localhost:8080").expect("Valid endpoint");
+        assert_eq!(ep.host(), Some("localhost"));
+        assert_eq!(ep.path(), None);
+
+        for invalid in [
+            "ws://example.com",
+            "ws://example.com:99999",
+            "ws://-example.com:80",
+            "ws://exa_mple.com:80",
+            "ws://:80",
+        ] {
+            assert_eq!(
+                Endpoint::from_str(invalid),
+                Err(EndpointParseError::InvalidHost)
+            );
+        }
+        assert!(matches!(
+            Endpoint::from_str("tcp://example.com:9651"),
+            Err(EndpointParseError::Address(_))
+        ));
+
+        let ep = Endpoint::from_str("unix:///run/mycelium.sock").expect("Valid endpoint");
+        assert_eq!(ep.proto(), Protocol::Unix);
+        assert_eq!(ep.path(), Some("/run/mycelium.sock"));
+        assert_eq!(ep.to_string(), "Unix /run/mycelium.sock");
+        assert_eq!(
+            Endpoint::from_str("unix://"),
+            Err(EndpointParseError::MissingPath)
+        );
+        assert_eq!(
+            Endpoint::from_str("memory://1"),
+            Err(EndpointParseError::UnknownProtocol)
+        );
+
+        assert_eq!(
+            Endpoint::from_str("tcp://192.0.2.6:9651/path"),
+            Err(EndpointParseError::UnexpectedPath)
+        );
+        assert_eq!(
+            Endpoint::from_str("http://192.0.2.6:80"),
+            Err(EndpointParseError::UnknownProtocol)
+        );
+    }
+}
@@ -1,5 +1,10 @@
 use crate::{babel, subnet::Subnet};
 
+mod configured;
+
+pub(crate) use configured::FilterChain;
+pub use configured::{FilterConfig, FilterStats};
+
 /// This trait is used to filter incoming updates from peers. Only updates which pass all
 /// configured filters on the local [`Router`](crate::router::Router) will actually be forwarded
 /// to the [`Router`](crate::router::Router) for processing.
@@ -51,3 +56,39 @@ impl RouteUpdateFilter for RouterIdOwnsSubnet {
                 .contains_ip(update.router_id().to_pubkey().address().into())
     }
 }
+
+/// Limit the announced subnets to those which are delegated to the `RouterId`, as derived by
+/// [`PublicKey::delegated_subnet`](crate::crypto::PublicKey::delegated_subnet).
+///
+/// Since retractions can be sent by any node to indicate they don't have a route for the subnet,
+/// these are also allowed.
+pub struct RouterIdDelegatedSubnet;
+
+impl RouteUpdateFilter for RouterIdDelegatedSubnet {
+    fn allow(&self, update: &babel::Update) -> bool {
+        update.metric().is_infinite()
+            || update
+                .router_id()
+                .to_pubkey()
+                .owns_delegated_subnet(&update.subnet())
+    }
+}
+
+/// Combine multiple filters, allowing an update if at least one of them allows it.
+pub struct AnyOf {
+    filters: Vec<Box<dyn RouteUpdateFilter + Send + Sync>>,
+}
+
+impl AnyOf {
+    /// Create a new `AnyOf` filter from the given filters. If no filters are given, all updates
+    /// are rejected.
+    pub fn new(filters: Vec<Box<dyn RouteUpdateFilter + Send + Sync>>) -> Self {
+        Self { filters }
+    }
+}
+
+impl RouteUpdateFilter for AnyOf {
+    fn allow(&self, update: &babel::Update) -> bool {
+        self.filters.iter().any(|filter| filter.allow(update))
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{babel, crypto::PublicKey, subnet::Subnet};
+
+/// Rate limit state of peers which did not send an update for this long is discarded.
+const RATE_LIMIT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A filter for incoming updates which can be configured by the operator, and changed at
+/// runtime.
+///
+/// Apart from the rate limit, retractions are never rejected, since these only remove routes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum FilterConfig {
+    /// Reject updates for routes originated by routers using one of these public keys.
+    DenyRouters { keys: Vec<PublicKey> },
+    /// Reject updates which announce a metric higher than this value.
+    MaxMetric { metric: u16 },
+    /// Limit the amount of updates accepted from a single peer. Every peer can send up to
+    /// `burst` updates at once, after which updates are only accepted at the given rate.
+    RateLimit { updates_per_second: u32, burst: u32 },
+    /// Reject updates for subnets which are part of one of these subnets.
+    DenySubnets { subnets: Vec<Subnet> },
+}
+
+/// A configured [`FilterConfig`], together with the amount of updates it rejected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilterStats {
+    /// The filter.
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+    /// Amount of updates rejected by the filter since it was configured.
+    pub rejected: u64,
+}
+
+/// A chain of [`FilterConfig`]s. An update is only accepted if all filters in the chain accept
+/// it.
+pub(crate) struct FilterChain {
+    filters: Vec<ConfiguredFilter>,
+}
+
+/// A filter in a [`FilterChain`].
+struct ConfiguredFilter {
+    config: FilterConfig,
+    rejected: AtomicU64,
+    /// Token buckets per peer, only used for [`FilterConfig::RateLimit`].
+    rate_limiter: Mutex<RateLimiter>,
+}
+
+/// Token buckets for all peers which recently sent an update.
+#[derive(Default)]
+struct RateLimiter {
+    buckets: HashMap<String, TokenBucket>,
+    last_cleanup: Option<Instant>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl FilterChain {
+    /// Create a new `FilterChain` from the given filters.
+    pub fn new(filters: Vec<FilterConfig>) -> Self {
+        Self {
+            filters: filters.into_iter().map(ConfiguredFilter::new).collect(),
+        }
+    }
+
+    /// Replace the filters in the chain. The counters of filters which were already configured
+    /// are kept.
+    pub fn replace(&mut self, filters: Vec<FilterConfig>) {
+        let mut old = std::mem::take(&mut self.filters);
+        self.filters = filters
+            .into_iter()
+            .map(|config| match old.iter().position(|f| f.config == config) {
+                Some(pos) => old.swap_remove(pos),
+                None => ConfiguredFilter::new(config),
+            })
+            .collect();
+    }
+
+    /// Judge an incoming update received from the peer with the given identifier.
+    pub fn allow(&self, update: &babel::Update, peer: &str, now: Instant) -> bool {
+        for filter in &self.filters {
+            if !filter.allow(update, peer, now) {
+                filter.rejected.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Get the configured filters, with the amount of updates they rejected.
+    pub fn stats(&self) -> Vec<FilterStats> {
+        self.filters
+            .iter()
+            .map(|filter| FilterStats {
+                filter: filter.config.clone(),
+                rejected: filter.rejected.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+impl ConfiguredFilter {
+    fn new(config: FilterConfig) -> Self {
+        Self {
+            config,
+            rejected: AtomicU64::new(0),
+            rate_limiter: Mutex::new(RateLimiter::default()),
+        }
+    }
+
+    fn allow(&self, update: &babel::Update, peer: &str, now: Instant) -> bool {
+        match &self.config {
+            FilterConfig::DenyRouters { keys } => {
+                update.metric().is_infinite() || !keys.contains(&update.router_id().to_pubkey())
+            }
+            FilterConfig::MaxMetric { metric } => {
+                update.metric().is_infinite() || u16::from(update.metric()) <= *metric
+            }
+            FilterConfig::RateLimit {
+                updates_per_second,
+                burst,
+            } => self
+                .rate_limiter
+                .lock()
+                .unwrap()
+                .allow(peer, *updates_per_second, *burst, now),
+            FilterConfig::DenySubnets { subnets } => {
+                update.metric().is_infinite()
+                    || !subnets.iter().any(|s| s.contains_subnet(&update.subnet()))
+            }
+        }
+    }
+}
+
+impl RateLimiter {
+    fn allow(&mut self, peer: &str, rate: u32, burst: u32, now: Instant) -> bool {
+        if self
+            .last_cleanup
+            .map(|last| now.duration_since(last) >= RATE_LIMIT_IDLE_TIMEOUT)
+            .unwrap_or(true)
+        {
+            self.buckets.retain(|_, bucket| {
+                now.duration_since(bucket.last_update) < RATE_LIMIT_IDLE_TIMEOUT
+            });
+            self.last_cleanup = Some(now);
+        }
+
+        let bucket = self
+            .buckets
+            .entry(peer.to_string())
+            .or_insert_with(|| TokenBucket {
+                tokens: burst as f64,
+                last_update: now,
+            });
+
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.last_update).as_secs_f64() * rate as f64)
+            .min(burst as f64);
+        bucket.last_update = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv6Addr,
+        time::{Duration, Instant},
+    };
+
+    use super::{FilterChain, FilterConfig};
+    use crate::{
+        babel,
+        crypto::{PublicKey, SecretKey},
+        metric::Metric,
+        router_id::RouterId,
+        subnet::Subnet,
+    };
+
+    fn update(key: PublicKey, metric: Metric, subnet: Subnet) -> babel::Update {
+        babel::Update::new(
+            Duration::from_secs(60),
+            0.into(),
+            metric,
+            subnet,
+            RouterId::new(key),
+        )
+    }
+
+    fn subnet(segment: u16) -> Subnet {
+        Subnet::new(Ipv6Addr::new(0x400, segment, 0, 0, 0, 0, 0, 0).into(), 64)
+            .expect("Valid subnet definition")
+    }
+
+    #[test]
+    fn deny_routers_and_subnets() {
+        let denied = PublicKey::from(&SecretKey::new());
+        let allowed = PublicKey::from(&SecretKey::new());
+        let chain = FilterChain::new(vec![
+            FilterConfig::DenyRouters { keys: vec![denied] },
+            FilterConfig::DenySubnets {
+                subnets: vec![
+                    Subnet::new(Ipv6Addr::new(0x400, 1, 0, 0, 0, 0, 0, 0).into(), 32).unwrap(),
+                ],
+            },
+        ]);
+        let now = Instant::now();
+
+        assert!(chain.allow(&update(allowed, 10.into(), subnet(2)), "peer", now));
+        assert!(!chain.allow(&update(denied, 10.into(), subnet(2)), "peer", now));
+        assert!(!chain.allow(&update(allowed, 10.into(), subnet(1)), "peer", now));
+        // Retractions are allowed.
+        assert!(chain.allow(&update(denied, Metric::infinite(), subnet(1)), "peer", now));
+
+        let stats = chain.stats();
+        assert_eq!(stats[0].rejected, 1);
+        assert_eq!(stats[1].rejected, 1);
+    }
+
+    #[test]
+    fn max_metric() {
+        let key = PublicKey::from(&SecretKey::new());
+        let chain = FilterChain::new(vec![FilterConfig::MaxMetric { metric: 100 }]);
+        let now = Instant::now();
+
+        assert!(chain.allow(&update(key, 100.into(), subnet(1)), "peer", now));
+        assert!(!chain.allow(&update(key, 101.into(), subnet(1)), "peer", now));
+        assert!(chain.allow(&update(key, Metric::infinite(), subnet(1)), "peer", now));
+    }
+
+    #[test]
+    fn rate_limit_per_peer() {
+        let key = PublicKey::from(&SecretKey::new());
+        let chain = FilterChain::new(vec![FilterConfig::RateLimit {
+            updates_per_second: 2,
+            burst: 2,
+        }]);
+        let now = Instant::now();
+        let u = update(key, 10.into(), subnet(1));
+
+        assert!(chain.allow(&u, "peer1", now));
+        assert!(chain.allow(&u, "peer1", now));
+        assert!(!chain.allow(&u, "peer1", now));
+        // Other peers have their own budget.
+        assert!(chain.allow(&u, "peer2", now));
+        // Tokens are refilled over time.
+        assert!(chain.allow(&u, "peer1", now + Duration::from_millis(500)));
+        assert!(!chain.allow(&u, "peer1", now + Duration::from_millis(500)));
+
+        assert_eq!(chain.stats()[0].rejected, 2);
+    }
+
+    #[test]
+    fn replace_keeps_counters() {
+        let key = PublicKey::from(&SecretKey::new());
+        let mut chain = FilterChain::new(vec![FilterConfig::MaxMetric { metric: 100 }]);
+
+        assert!(!chain.allow(&update(key, 101.into(), subnet(1)), "peer", Instant::now()));
+
+        chain.replace(vec![
+            FilterConfig::DenySubnets { subnets: vec![] },
+            FilterConfig::MaxMetric { metric: 100 },
+        ]);
+
+        let stats = chain.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].rejected, 0);
+        assert_eq!(stats[1].filter, FilterConfig::MaxMetric { metric: 100 });
+        assert_eq!(stats[1].rejected, 1);
+    }
+}
@@ -1,6 +1,8 @@
 use std::{
+    fmt,
     future::Future,
     net::{IpAddr, Ipv6Addr},
+    path::PathBuf,
     time::Duration,
 };
 
@@ -10,8 +12,10 @@ use endpoint::Endpoint;
 use log::{error, info, warn};
 #[cfg(feature = "message")]
 use message::MessageStack;
-use message::{MessageId, MessageInfo, MessagePushResponse, PushMessageError, ReceivedMessage};
-use peer_manager::{PeerExists, PeerNotFound, PeerStats};
+use message::{
+    MessageId, MessageInfo, MessagePushResponse, MessageReader, PushMessageError, ReceivedMessage,
+};
+use peer_manager::{PeerAcl, PeerAclStats, PeerExists, PeerNotFound, PeerStats};
 use routing_table::RouteEntry;
 use subnet::Subnet;
 
@@ -25,15 +29,23 @@ mod interval;
 #[cfg(feature = "message")]
 pub mod message;
 mod metric;
+pub mod metrics;
 pub mod packet;
 mod peer;
 pub mod peer_manager;
+pub mod route_history;
 pub mod router;
 mod router_id;
 mod routing_table;
+mod seqno_cache;
 mod sequence_number;
+#[cfg(any(test, feature = "test-support"))]
+pub mod sim;
+mod snapshot;
 mod source_table;
 pub mod subnet;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 mod tun;
 
 /// The prefix of the global subnet used.
@@ -41,6 +53,12 @@ pub const GLOBAL_SUBNET_ADDRESS: IpAddr = IpAddr::V6(Ipv6Addr::new(0x400, 0, 0,
 /// The prefix length of the global subnet used.
 pub const GLOBAL_SUBNET_PREFIX_LEN: u8 = 7;
 
+/// Name of the file in the state directory holding the routing state snapshot.
+const ROUTER_SNAPSHOT_FILE: &str = "router_state.bin";
+/// Name of the directory in the state directory holding persisted messages.
+#[cfg(feature = "message")]
+const MESSAGE_STORE_DIR: &str = "messages";
+
 /// Config for a mycelium [`Node`].
 pub struct Config {
     /// The secret key of the node.
@@ -53,16 +71,50 @@ pub struct Config {
     pub tcp_listen_port: u16,
     /// Listen port for Quic connections.
     pub quic_listen_port: u16,
+    /// Listen port for TLS connections. If this is not set, no TLS listener is started.
+    pub tls_listen_port: Option<u16>,
+    /// Listen port for WebSocket connections. If this is not set, no WebSocket listener is
+    /// started.
+    pub ws_listen_port: Option<u16>,
+    /// Listen port for WebSocket connections secured with TLS. If this is not set, no secure
+    /// WebSocket listener is started.
+    pub wss_listen_port: Option<u16>,
+    /// HTTP proxy, as `host:port`, used to tunnel outbound WebSocket connections with a CONNECT
+    /// request. If this is not set, WebSocket peers are connected directly.
+    pub ws_proxy: Option<String>,
+    /// Path of a Unix domain socket to accept connections on. If this is not set, no Unix domain
+    /// socket listener is started.
+    pub unix_listen_path: Option<PathBuf>,
     /// Udp port for peer discovery.
     pub peer_discovery_port: Option<u16>,
     /// Name for the TUN device.
     pub tun_name: String,
+    /// Access control rules for inbound and discovered peers.
+    pub peer_acl: PeerAcl,
+    /// Directory to persist state in across restarts. If this is not set, no state is persisted.
+    pub state_dir: Option<PathBuf>,
+    /// Keep received messages which have not been read yet, and messages which are still being
+    /// sent, in the state directory. After a restart, these messages are available again and
+    /// sending is resumed. This has no effect if `state_dir` is not set.
+    pub persist_messages: bool,
+    /// Limits on the received messages which are kept until they are read.
+    #[cfg(feature = "message")]
+    pub inbox_limits: message::InboxLimits,
+    /// Extra subnets to announce in addition to the node subnet, e.g. to route traffic for hosts
+    /// behind this node. Every subnet must be delegated to the node key, see
+    /// [`PublicKey::delegated_subnet`](crypto::PublicKey::delegated_subnet).
+    pub extra_subnets: Vec<Subnet>,
+    /// Extra filters for route updates received from peers. These can be changed at runtime.
+    pub update_filters: Vec<filters::FilterConfig>,
+    /// Timers used by the router.
+    pub router_config: router::RouterConfig,
 }
 
 /// The Node is the main structure in mycelium. It governs the entire data flow.
 pub struct Node {
     router: router::Router,
     peer_manager: peer_manager::PeerManager,
+    data_plane: DataPlane,
     #[cfg(feature = "message")]
     message_stack: message::MessageStack,
 }
@@ -71,11 +123,43 @@ pub struct Node {
 pub struct NodeInfo {
     /// The overlay subnet in use by the node.
     pub node_subnet: Subnet,
+    /// Extra subnets announced by the node.
+    pub extra_subnets: Vec<Subnet>,
+}
+
+/// Error returned when an extra subnet in the [`Config`] is not delegated to the node key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubnetNotDelegated(pub Subnet);
+
+impl fmt::Display for SubnetNotDelegated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subnet {} is not delegated to the node key", self.0)
+    }
+}
+
+impl std::error::Error for SubnetNotDelegated {}
+
+/// The filters for route updates which are always applied by a [`Node`], regardless of its
+/// configuration.
+pub(crate) fn builtin_update_filters() -> Vec<Box<dyn filters::RouteUpdateFilter + Send + Sync>> {
+    vec![
+        Box::new(filters::AllowedSubnet::new(
+            Subnet::new(GLOBAL_SUBNET_ADDRESS, GLOBAL_SUBNET_PREFIX_LEN)
+                .expect("Global subnet is properly defined; qed"),
+        )),
+        Box::new(filters::MaxSubnetSize::<64>),
+        Box::new(filters::AnyOf::new(vec![
+            Box::new(filters::RouterIdOwnsSubnet),
+            Box::new(filters::RouterIdDelegatedSubnet),
+        ])),
+    ]
 }
 
 impl Node {
     /// Setup a new `Node` with the provided [`Config`].
     pub async fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
+        config.router_config.validate()?;
+
         let node_pub_key = crypto::PublicKey::from(&config.node_key);
         let node_addr = node_pub_key.address();
         let (tun_tx, tun_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -90,20 +174,46 @@ impl Node {
         )
         .expect("64 is a valid IPv6 prefix size; qed");
 
+        let mut static_routes = vec![node_subnet];
+        for subnet in config.extra_subnets {
+            if !node_pub_key.owns_delegated_subnet(&subnet) {
+                return Err(SubnetNotDelegated(subnet).into());
+            }
+            if !static_routes.contains(&subnet) {
+                info!("Announcing extra subnet {subnet}");
+                static_routes.push(subnet);
+            }
+        }
+
+        let snapshot_path = if let Some(state_dir) = &config.state_dir {
+            tokio::fs::create_dir_all(state_dir).await?;
+            Some(state_dir.join(ROUTER_SNAPSHOT_FILE))
+        } else {
+            None
+        };
+        if config.persist_messages && config.state_dir.is_none() {
+            warn!("Messages can only be persisted if a state directory is set");
+        }
+        #[cfg(feature = "message")]
+        let message_store_dir = match config.state_dir {
+            Some(ref state_dir) if config.persist_messages => {
+                Some(state_dir.join(MESSAGE_STORE_DIR))
+            }
+            _ => None,
+        };
+
         // Creating a new Router instance
         let router = match router::Router::new(
             tun_tx,
             node_subnet,
-            vec![node_subnet],
-            (config.node_key, node_pub_key),
-            vec![
-                Box::new(filters::AllowedSubnet::new(
-                    Subnet::new(GLOBAL_SUBNET_ADDRESS, GLOBAL_SUBNET_PREFIX_LEN)
-                        .expect("Global subnet is properly defined; qed"),
-                )),
-                Box::new(filters::MaxSubnetSize::<64>),
-                Box::new(filters::RouterIdOwnsSubnet),
-            ],
+            static_routes,
+            (config.node_key.clone(), node_pub_key),
+            config.router_config,
+            router::UpdateFilters {
+                fixed: builtin_update_filters(),
+                configured: config.update_filters,
+            },
+            snapshot_path,
         ) {
             Ok(router) => {
                 info!(
@@ -121,15 +231,19 @@ impl Node {
         // Creating a new PeerManager instance
         let pm = peer_manager::PeerManager::new(
             router.clone(),
-            config.peers,
-            config.tcp_listen_port,
-            config.quic_listen_port,
-            if let Some(port) = config.peer_discovery_port {
-                port
-            } else {
-                0
+            (config.node_key, node_pub_key),
+            peer_manager::PeerManagerConfig {
+                peers: config.peers,
+                tcp_listen_port: config.tcp_listen_port,
+                quic_listen_port: config.quic_listen_port,
+                tls_listen_port: config.tls_listen_port,
+                ws_listen_port: config.ws_listen_port,
+                wss_listen_port: config.wss_listen_port,
+                ws_proxy: config.ws_proxy,
+                unix_listen_path: config.unix_listen_path,
+                peer_discovery_port: config.peer_discovery_port,
+                peer_acl: config.peer_acl,
             },
-            config.peer_discovery_port.is_none(),
         )?;
         info!("Started peer manager");
 
@@ -142,7 +256,7 @@ impl Node {
         #[cfg(not(feature = "message"))]
         let msg_sender = futures::sink::drain();
 
-        let _data_plane = if config.no_tun {
+        let data_plane = if config.no_tun {
             warn!("Starting data plane without TUN interface, L3 functionality disabled");
             DataPlane::new(
                 router.clone(),
@@ -174,20 +288,49 @@ impl Node {
         };
 
         #[cfg(feature = "message")]
-        let ms = MessageStack::new(_data_plane, msg_receiver);
+        let ms = MessageStack::new(
+            data_plane.clone(),
+            msg_receiver,
+            message_store_dir,
+            config.inbox_limits,
+        );
 
         Ok(Node {
             router,
             peer_manager: pm,
+            data_plane,
             #[cfg(feature = "message")]
             message_stack: ms,
         })
     }
 
+    /// Shut down the `Node`.
+    ///
+    /// No new connections are accepted or set up, and the routes announced by the node are
+    /// retracted on all peers, so they can immediately select a different route, rather than
+    /// waiting until they notice the node is gone. Afterwards, the connections to all peers are
+    /// closed, the TUN interface is removed and all background tasks are stopped. The `Node`
+    /// should not be used anymore after this.
+    pub async fn shutdown(&self) {
+        info!("Shutting down node");
+        self.peer_manager.shutdown().await;
+        self.router.shutdown().await;
+        #[cfg(feature = "message")]
+        self.message_stack.shutdown().await;
+        self.data_plane.shutdown().await;
+    }
+
     /// Get information about the running `Node`
     pub fn info(&self) -> NodeInfo {
         NodeInfo {
             node_subnet: self.router.node_tun_subnet(),
+            extra_subnets: self
+                .router
+                .static_routes()
+                .iter()
+                .filter(|sr| **sr != self.router.node_tun_subnet())
+                .copied()
+                .collect(),
         }
     }
 
@@ -196,6 +339,19 @@ impl Node {
         self.peer_manager.peers()
     }
 
+    /// Add a new peer over an in memory connection. The other side of the connection must be
+    /// added to a different `Node`. This allows connecting nodes running in the same process,
+    /// without using any sockets, e.g.
+    ///
+    /// ```ignore
+    /// let (con1, con2) = tokio::io::duplex(1500);
+    /// node1.add_memory_peer(con1);
+    /// node2.add_memory_peer(con2);
+    /// ```
+    pub fn add_memory_peer(&self, con: tokio::io::DuplexStream) {
+        self.peer_manager.add_memory_peer(con)
+    }
+
     /// Add a new peer to the system identified by an [`Endpoint`].
     pub fn add_peer(&self, endpoint: Endpoint) -> Result<(), PeerExists> {
         self.peer_manager.add_peer(endpoint)
@@ -206,6 +362,59 @@ impl Node {
         self.peer_manager.delete_peer(&endpoint)
     }
 
+    /// Set the cost which is added to the link cost of the peer identified by an [`Endpoint`], or
+    /// remove it by passing [`None`]. The cost is kept when the peer reconnects.
+    pub fn set_peer_cost(
+        &self,
+        endpoint: &Endpoint,
+        cost: Option<u16>,
+    ) -> Result<(), PeerNotFound> {
+        self.peer_manager.set_peer_cost(endpoint, cost)
+    }
+
+    /// Get the current [`PeerAcl`] of the system.
+    pub fn peer_acl(&self) -> PeerAcl {
+        self.peer_manager.acl()
+    }
+
+    /// Replace the [`PeerAcl`] of the system. Connected inbound and discovered peers which are
+    /// no longer allowed are disconnected.
+    pub fn set_peer_acl(&self, acl: PeerAcl) {
+        self.peer_manager.set_acl(acl)
+    }
+
+    /// Get the amount of remotes which have been rejected by the [`PeerAcl`].
+    pub fn peer_acl_stats(&self) -> PeerAclStats {
+        self.peer_manager.acl_stats()
+    }
+
+    /// Get the configured filters for route updates, with the amount of updates they rejected.
+    pub fn update_filters(&self) -> Vec<filters::FilterStats> {
+        self.router.update_filters()
+    }
+
+    /// Replace the configured filters for route updates.
+    pub fn set_update_filters(&self, filters: Vec<filters::FilterConfig>) {
+        self.router.set_update_filters(filters)
+    }
+
+    /// Get the amount of data packets handled since the node started.
+    pub fn data_plane_metrics(&self) -> metrics::DataPlaneMetrics {
+        self.router.data_plane_metrics()
+    }
+
+    /// Get the amount of messages handled since the node started.
+    #[cfg(feature = "message")]
+    pub fn message_metrics(&self) -> metrics::MessageMetrics {
+        self.message_stack.metrics()
+    }
+
+    /// Get the recorded changes of selected routes, optionally only for the given [`Subnet`],
+    /// ordered from oldest to newest.
+    pub fn route_history(&self, subnet: Option<Subnet>) -> Vec<route_history::RouteChange> {
+        self.router.route_history(subnet)
+    }
+
     /// List all selected [`routes`](RouteEntry) in the system.
     pub fn selected_routes(&self) -> Vec<RouteEntry> {
         self.router.load_selected_routes()
@@ -219,6 +428,14 @@ impl Node {
 
 #[cfg(feature = "message")]
 impl Node {
+    /// Get a handle to the message stack of the node.
+    ///
+    /// The handle can be used to send and receive messages from other tasks, without keeping
+    /// access to the node while waiting for a message.
+    pub fn message_stack(&self) -> MessageStack {
+        self.message_stack.clone()
+    }
+
     /// Wait for a messsage to arrive in the message stack.
     ///
     /// An the optional `topic` is provided, only messages which have exactly the same value in
@@ -240,6 +457,25 @@ impl Node {
         async move { ms.message(pop, topic).await }
     }
 
+    /// Wait for a message to arrive in the message stack, and read its data as it comes in.
+    ///
+    /// An the optional `topic` is provided, only messages which have exactly the same value in
+    /// `topic` will be returned. Messages which are already fully received are returned first,
+    /// and are removed from the internal queue. Otherwise, the next new message is returned as
+    /// soon as the sender starts sending it. Large messages should be read this way, as they
+    /// don't need to be kept in memory as a whole.
+    ///
+    /// Like [`Node::get_message`], the returned future waits indefinitely until a message is
+    /// received.
+    pub fn get_message_stream(
+        &self,
+        topic: Option<Vec<u8>>,
+    ) -> impl Future<Output = MessageReader> + '_ {
+        // See get_message for why the future is constructed manually.
+        let ms = &self.message_stack;
+        async move { ms.message_stream(topic).await }
+    }
+
     /// Push a new message to the message stack.
     ///
     /// The system will attempt to transmit the message for `try_duration`. A message is considered
@@ -269,6 +505,32 @@ impl Node {
         )
     }
 
+    /// Push a new message to the message stack, of which the data is read from `reader` while
+    /// the message is being sent. The reader must provide exactly `len` bytes.
+    ///
+    /// Only a limited part of the message is read ahead of what the receiver acknowledged, so
+    /// large messages don't need to be kept in memory as a whole. If the reader fails, or does
+    /// not provide enough data, the message is aborted.
+    pub fn push_message_stream<R>(
+        &self,
+        dst: IpAddr,
+        reader: R,
+        len: u64,
+        topic: Option<Vec<u8>>,
+        try_duration: Duration,
+    ) -> Result<MessageId, PushMessageError>
+    where
+        R: tokio::io::AsyncRead + Send + Unpin + 'static,
+    {
+        self.message_stack.new_message_stream(
+            dst,
+            reader,
+            len,
+            topic.unwrap_or_default(),
+            try_duration,
+        )
+    }
+
     /// Get the status of a message sent previously.
     ///
     /// Returns [`Option::None`] if no message is found with the given id. Message info is only
@@ -278,6 +540,11 @@ impl Node {
         self.message_stack.message_info(id)
     }
 
+    /// Get the current usage of the inbox of received messages.
+    pub fn inbox_stats(&self) -> message::InboxStats {
+        self.message_stack.inbox_stats()
+    }
+
     /// Send a reply to a previously received message.
     pub fn reply_message(
         &self,
@@ -289,4 +556,128 @@ impl Node {
         self.message_stack
             .reply_message(id, dst, data, try_duration)
     }
+
+    /// Send a reply to a previously received message, of which the data is read from `reader`
+    /// while it is being sent. See [`Node::push_message_stream`].
+    pub fn reply_message_stream<R>(
+        &self,
+        id: MessageId,
+        dst: IpAddr,
+        reader: R,
+        len: u64,
+        try_duration: Duration,
+    ) -> MessageId
+    where
+        R: tokio::io::AsyncRead + Send + Unpin + 'static,
+    {
+        self.message_stack
+            .reply_message_stream(id, dst, reader, len, try_duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tokio_util::codec::Framed;
+
+    use crate::{
+        crypto::{PublicKey, SecretKey},
+        packet::{ControlPacket, Packet},
+        peer_manager::ConnectionState,
+        test_support::{connect, node},
+    };
+
+    #[tokio::test]
+    async fn memory_peers() {
+        let (key1, key2) = (SecretKey::new(), SecretKey::new());
+        let (pk1, pk2) = (PublicKey::from(&key1), PublicKey::from(&key2));
+        let (node1, node2) = (node(key1).await, node(key2).await);
+
+        connect(&node1, &node2);
+
+        // The handshake happens in the background.
+        for _ in 0..50 {
+            if !node1.peer_info().is_empty() && !node2.peer_info().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let (peers1, peers2) = (node1.peer_info(), node2.peer_info());
+        assert_eq!(peers1.len(), 1);
+        assert_eq!(peers2.len(), 1);
+        assert_eq!(peers1[0].connection_state, ConnectionState::Alive);
+        assert_eq!(peers1[0].remote_key, Some(pk2));
+        assert_eq!(peers2[0].remote_key, Some(pk1));
+    }
+
+    #[tokio::test]
+    async fn peer_cost() {
+        let (node1, node2) = (node(SecretKey::new()).await, node(SecretKey::new()).await);
+
+        connect(&node1, &node2);
+        for _ in 0..50 {
+            if !node1.peer_info().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let endpoint = node1.peer_info()[0].endpoint.clone();
+        assert_eq!(node1.peer_info()[0].cost, None);
+        node1
+            .set_peer_cost(&endpoint, Some(100))
+            .expect("Peer is known");
+        assert_eq!(node1.peer_info()[0].cost, Some(100));
+        node1.set_peer_cost(&endpoint, None).expect("Peer is known");
+        assert_eq!(node1.peer_info()[0].cost, None);
+
+        assert!(node2
+            .set_peer_cost(&"tcp://[::1]:9651".parse().unwrap(), Some(100))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_retracts_routes() {
+        let node1 = node(SecretKey::new()).await;
+        let subnet = node1.info().node_subnet;
+
+        // Act as the remote peer on the raw connection, so the link stays up until node1 closes
+        // it.
+        let (con1, con2) = tokio::io::duplex(1500);
+        node1.add_memory_peer(con1);
+        let sk = SecretKey::new();
+        let (con2, _) = crate::connection::handshake(con2, &sk, PublicKey::from(&sk), &[])
+            .await
+            .expect("Handshake succeeds");
+        let mut framed = Framed::new(con2, crate::packet::Codec::new());
+
+        // Wait for the route to node1 to be announced.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(packet)) = framed.next().await {
+                if let Packet::ControlPacket(ControlPacket::Update(update)) = packet {
+                    if update.subnet() == subnet && !update.metric().is_infinite() {
+                        return;
+                    }
+                }
+            }
+            panic!("Connection closed before the route was announced");
+        })
+        .await
+        .expect("Route is announced");
+
+        // Every packet received before the connection drops is collected while node1 shuts down.
+        let ((), retracted) = tokio::join!(node1.shutdown(), async {
+            let mut retracted = false;
+            while let Some(Ok(packet)) = framed.next().await {
+                if let Packet::ControlPacket(ControlPacket::Update(update)) = packet {
+                    retracted |= update.subnet() == subnet && update.metric().is_infinite();
+                }
+            }
+            retracted
+        });
+        assert!(retracted);
+    }
 }
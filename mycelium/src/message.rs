@@ -11,6 +11,7 @@ use std::{
     marker::PhantomData,
     net::IpAddr,
     ops::{Deref, DerefMut},
+    path::PathBuf,
     sync::{Arc, Mutex},
     time::{self, Duration},
 };
@@ -19,17 +20,32 @@ use futures::{Stream, StreamExt};
 use log::{debug, error, trace, warn};
 use rand::Fill;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
-use tokio::sync::watch;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::{broadcast, mpsc, oneshot, watch},
+    task::JoinHandle,
+};
 
 use crate::{
     crypto::{PacketBuffer, PublicKey},
     data::DataPlane,
-    message::{chunk::MessageChunk, done::MessageDone, init::MessageInit},
+    message::{
+        chunk::MessageChunk,
+        done::MessageDone,
+        init::MessageInit,
+        store::{MessageStore, StoreOp, StoredOutboundMessage},
+        stream::{InboundStream, OutboundStream, STREAM_SEND_WINDOW},
+    },
+    metrics::{MessageCounters, MessageMetrics},
 };
 
 mod chunk;
 mod done;
 mod init;
+mod store;
+mod stream;
+
+pub use stream::MessageReader;
 
 /// The amount of time to try and send messages before we give up.
 const MESSAGE_SEND_WINDOW: Duration = Duration::from_secs(60 * 5);
@@ -40,6 +56,18 @@ const RETRANSMISSION_DELAY: Duration = Duration::from_secs(1);
 /// Amount of time between sweeps of the subscriber list to clear orphaned subscribers.
 const REPLY_SUBSCRIBER_CLEAR_DELAY: Duration = Duration::from_secs(60);
 
+/// Amount of time between saving the progress of outbound messages, if messages are persisted.
+const MESSAGE_PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default maximum total size of received messages, in bytes.
+const DEFAULT_INBOX_MAX_BYTES: usize = 256 * 1024 * 1024;
+/// Default maximum total size of received messages from a single sender, in bytes.
+const DEFAULT_INBOX_MAX_BYTES_PER_SENDER: usize = 64 * 1024 * 1024;
+/// Default maximum amount of received messages with the same topic.
+const DEFAULT_INBOX_MAX_TOPIC_MESSAGES: usize = 10_000;
+/// Default maximum amount of messages which are being received at the same time.
+const DEFAULT_INBOX_MAX_PENDING_MESSAGES: usize = 256;
+
 /// The average size of a single chunk. This is mainly intended to preallocate the chunk array on
 /// the receiver size. This value should allow reasonable overhead for standard MTU.
 const AVERAGE_CHUNK_SIZE: usize = 1_300;
@@ -61,7 +89,8 @@ const FLAG_MESSAGE_INIT: u16 = 0b1000_0000_0000_0000;
 // Flag indicating the message with the given ID is done, i.e. it has been fully transmitted.
 const FLAG_MESSAGE_DONE: u16 = 0b0100_0000_0000_0000;
 /// Indicates the message with this ID is aborted by the sender and the receiver should discard it.
-/// The receiver can ignore this if it fully received the message.
+/// The receiver can ignore this if it fully received the message. If this is set on the ACK of an
+/// INIT, the receiver refuses the message, and the sender should stop sending it.
 const FLAG_MESSAGE_ABORTED: u16 = 0b0010_0000_0000_0000;
 /// Flag indicating we are transferring a data chunk.
 const FLAG_MESSAGE_CHUNK: u16 = 0b0001_0000_0000_0000;
@@ -81,6 +110,10 @@ const MESSAGE_CHECKSUM_LENGTH: usize = 32;
 /// Checksum of a message used to verify received message integrity.
 pub type Checksum = [u8; MESSAGE_CHECKSUM_LENGTH];
 
+/// Amount of events which are kept for a subscriber of received messages or state changes of
+/// sent messages, if it does not keep up.
+const SUBSCRIPTION_BUFFER: usize = 256;
+
 /// Response type when pushing a message.
 pub type MessagePushResponse = (MessageId, Option<watch::Receiver<Option<ReceivedMessage>>>);
 
@@ -97,6 +130,19 @@ pub struct MessageStack {
     /// This takes an Option as value to avoid the hassle of constructing a dummy value when
     /// creating the watch channel.
     reply_subscribers: Arc<Mutex<HashMap<MessageId, watch::Sender<Option<ReceivedMessage>>>>>,
+    /// Counters of handled messages.
+    counters: Arc<MessageCounters>,
+    /// Subscribers for messages which are added to the inbox.
+    received: broadcast::Sender<ReceivedMessage>,
+    /// Subscribers for state changes of sent messages.
+    state_changes: broadcast::Sender<MessageStateChange>,
+    /// Background tasks of the message stack, which are stopped when it is shut down.
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    /// Channel to the task which persists messages, if messages are persisted.
+    store: Option<mpsc::UnboundedSender<StoreOp>>,
+    /// The task which persists messages. This is stopped after all other tasks, so the latest
+    /// state is saved.
+    store_task: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 struct MessageOutbox {
@@ -111,18 +157,40 @@ struct MessageInbox {
     complete_msges: VecDeque<ReceivedMessage>,
     /// Notification sender used to allert subscribed listeners.
     notify: watch::Sender<()>,
+    /// Limits on the messages kept in the inbox.
+    limits: InboxLimits,
+    /// Total size of the messages in the inbox, including the announced size of pending messages.
+    bytes: usize,
+    /// Size of the messages in the inbox per sender, including pending messages. Senders are
+    /// identified by their key, since a sender can use any IP in its subnet.
+    sender_bytes: HashMap<PublicKey, usize>,
+    /// Amount of messages in the inbox per topic, including pending messages.
+    topic_messages: HashMap<Vec<u8>, usize>,
+    /// Readers waiting for a new message, in the order they started waiting.
+    stream_claims: VecDeque<StreamClaim>,
+}
+
+/// A reader waiting for a new message, optionally with a specific topic.
+struct StreamClaim {
+    topic: Option<Vec<u8>>,
+    reader: oneshot::Sender<MessageReader>,
 }
 
 struct ReceivedMessageInfo {
     id: MessageId,
     is_reply: bool,
     src: IpAddr,
+    /// The public key of the sender.
+    src_pk: PublicKey,
     dst: IpAddr,
     /// Length of the finished message.
     len: u64,
     /// Optional topic of the message.
     topic: Vec<u8>,
     chunks: Vec<Option<Chunk>>,
+    /// Set if the message is passed to a [`MessageReader`] while it is being received. Such
+    /// messages don't count towards the [`InboxLimits`].
+    stream: Option<InboundStream>,
 }
 
 #[derive(Clone)]
@@ -162,6 +230,9 @@ struct ChunkState {
     chunk_size: usize,
     /// Transmit state of the chunk.
     chunk_transmit_state: ChunkTransmitState,
+    /// Data of the chunk, if the message is read from a source while it is sent. This is only set
+    /// once the chunk is read, until it is acknowledged.
+    data: Option<Vec<u8>>,
 }
 
 /// Transmission state of an individual chunk
@@ -187,6 +258,8 @@ enum TransmissionState {
     Read,
     /// Transmission aborted by us. We indicated this by sending an abort flag to the receiver.
     Aborted,
+    /// Remote refused the message, because it has no room for it.
+    Rejected,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -195,12 +268,246 @@ pub enum PushMessageError {
     TopicTooLarge,
 }
 
+/// Limits on the received messages kept by the [`MessageStack`]. Messages count towards these
+/// limits from the moment the sender announces them, until they are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboxLimits {
+    /// Maximum total size of received messages, in bytes.
+    pub max_bytes: usize,
+    /// Maximum total size of received messages from a single sender, in bytes.
+    pub max_bytes_per_sender: usize,
+    /// Maximum amount of received messages with the same topic.
+    pub max_topic_messages: usize,
+    /// Maximum amount of messages which are being received at the same time. New messages over
+    /// this limit are always rejected, regardless of the [`OverflowPolicy`].
+    pub max_pending_messages: usize,
+    /// What to do with a new message if it exceeds one of the other limits.
+    pub overflow: OverflowPolicy,
+}
+
+/// Action taken when a new message does not fit in the inbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// Refuse the new message. The sender is informed, and stops sending the message.
+    #[default]
+    Reject,
+    /// Remove the oldest unread messages which count towards the exceeded limit, until the new
+    /// message fits. If the space is taken by messages which are still being received, the new
+    /// message is refused.
+    DropOldest,
+}
+
+/// Error returned when parsing an unknown [`OverflowPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidOverflowPolicy;
+
+impl Default for InboxLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_INBOX_MAX_BYTES,
+            max_bytes_per_sender: DEFAULT_INBOX_MAX_BYTES_PER_SENDER,
+            max_topic_messages: DEFAULT_INBOX_MAX_TOPIC_MESSAGES,
+            max_pending_messages: DEFAULT_INBOX_MAX_PENDING_MESSAGES,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = InvalidOverflowPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "drop-oldest" => Ok(Self::DropOldest),
+            _ => Err(InvalidOverflowPolicy),
+        }
+    }
+}
+
+impl fmt::Display for InvalidOverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("overflow policy must be \"reject\" or \"drop-oldest\"")
+    }
+}
+
+impl std::error::Error for InvalidOverflowPolicy {}
+
 impl MessageInbox {
-    fn new(notify: watch::Sender<()>) -> Self {
+    fn new(notify: watch::Sender<()>, limits: InboxLimits) -> Self {
         Self {
             pending_msges: HashMap::new(),
             complete_msges: VecDeque::new(),
             notify,
+            limits,
+            bytes: 0,
+            sender_bytes: HashMap::new(),
+            topic_messages: HashMap::new(),
+            stream_claims: VecDeque::new(),
+        }
+    }
+
+    /// Count a message towards the inbox limits.
+    fn account(&mut self, src: PublicKey, len: usize, topic: &[u8]) {
+        self.bytes += len;
+        *self.sender_bytes.entry(src).or_default() += len;
+        *self.topic_messages.entry(topic.to_vec()).or_default() += 1;
+    }
+
+    /// Stop counting a message towards the inbox limits.
+    fn unaccount(&mut self, src: PublicKey, len: usize, topic: &[u8]) {
+        self.bytes -= len;
+        if let Some(bytes) = self.sender_bytes.get_mut(&src) {
+            *bytes -= len;
+            if *bytes == 0 {
+                self.sender_bytes.remove(&src);
+            }
+        }
+        if let Some(count) = self.topic_messages.get_mut(topic) {
+            *count -= 1;
+            if *count == 0 {
+                self.topic_messages.remove(topic);
+            }
+        }
+    }
+
+    /// Start tracking a message which is being received. There must not be a pending message with
+    /// the same id.
+    fn insert_pending(&mut self, message: ReceivedMessageInfo) {
+        if message.stream.is_none() {
+            self.account(message.src_pk, message.len as usize, &message.topic);
+        }
+        self.pending_msges.insert(message.id, message);
+    }
+
+    /// Stop tracking a message which is being received.
+    fn remove_pending(&mut self, id: &MessageId) -> Option<ReceivedMessageInfo> {
+        let message = self.pending_msges.remove(id)?;
+        if message.stream.is_none() {
+            self.unaccount(message.src_pk, message.len as usize, &message.topic);
+        }
+        Some(message)
+    }
+
+    /// Take the oldest reader waiting for a message with the given topic. Readers which stopped
+    /// waiting are removed.
+    fn take_stream_claim(&mut self, topic: &[u8]) -> Option<oneshot::Sender<MessageReader>> {
+        self.stream_claims.retain(|claim| !claim.reader.is_closed());
+        let idx = self
+            .stream_claims
+            .iter()
+            .position(|claim| claim.topic.as_ref().map(|t| t == topic).unwrap_or(true))?;
+        self.stream_claims.remove(idx).map(|claim| claim.reader)
+    }
+
+    /// Add a fully received message to the back of the inbox.
+    fn push_complete(&mut self, message: ReceivedMessage) {
+        self.account(message.src_pk, message.data.len(), &message.topic);
+        self.complete_msges.push_back(message);
+    }
+
+    /// Remove the fully received message at the given position in the inbox.
+    fn remove_complete(&mut self, idx: usize) -> Option<ReceivedMessage> {
+        let message = self.complete_msges.remove(idx)?;
+        self.unaccount(message.src_pk, message.data.len(), &message.topic);
+        Some(message)
+    }
+
+    /// Check if a new message with the given sender, length and topic can be received. If the
+    /// overflow policy allows it, unread messages are removed to make room. These are returned.
+    /// If the message can't be received, [`Option::None`] is returned, and the inbox is left
+    /// unchanged.
+    fn make_room(
+        &mut self,
+        src: PublicKey,
+        len: u64,
+        topic: &[u8],
+    ) -> Option<Vec<ReceivedMessage>> {
+        if self.pending_msges.len() >= self.limits.max_pending_messages
+            || len > self.limits.max_bytes as u64
+            || len > self.limits.max_bytes_per_sender as u64
+            || self.limits.max_topic_messages == 0
+        {
+            return None;
+        }
+        let len = len as usize;
+
+        // First find all messages to remove, so nothing is removed if the message does not fit
+        // in the end.
+        let mut bytes = self.bytes;
+        let mut sender_bytes = self.sender_bytes.get(&src).copied().unwrap_or_default();
+        let mut topic_messages = self.topic_messages.get(topic).copied().unwrap_or_default();
+        let mut evict = Vec::new();
+        loop {
+            let topic_full = topic_messages >= self.limits.max_topic_messages;
+            let sender_full = sender_bytes + len > self.limits.max_bytes_per_sender;
+            if !topic_full && !sender_full && bytes + len <= self.limits.max_bytes {
+                break;
+            }
+            if self.limits.overflow == OverflowPolicy::Reject {
+                return None;
+            }
+            // Messages are added at the back, so the first match is the oldest.
+            let (idx, message) = self.complete_msges.iter().enumerate().find(|(idx, m)| {
+                !evict.contains(idx)
+                    && if topic_full {
+                        m.topic == topic
+                    } else if sender_full {
+                        m.src_pk == src
+                    } else {
+                        true
+                    }
+            })?;
+            bytes -= message.data.len();
+            if message.src_pk == src {
+                sender_bytes -= message.data.len();
+            }
+            if message.topic == topic {
+                topic_messages -= 1;
+            }
+            evict.push(idx);
+        }
+
+        // Remove from the back, so the indices of the other messages don't change.
+        evict.sort_unstable();
+        Some(
+            evict
+                .into_iter()
+                .rev()
+                .filter_map(|idx| self.remove_complete(idx))
+                .collect(),
+        )
+    }
+
+    /// Get the current usage of the inbox.
+    fn stats(&self) -> InboxStats {
+        let mut senders = self
+            .sender_bytes
+            .iter()
+            .map(|(&pk, &bytes)| SenderUsage {
+                ip: pk.address().into(),
+                pk,
+                bytes,
+            })
+            .collect::<Vec<_>>();
+        senders.sort_by_key(|usage| usage.ip);
+        let mut topics = self
+            .topic_messages
+            .iter()
+            .map(|(topic, &messages)| TopicUsage {
+                topic: topic.clone(),
+                messages,
+            })
+            .collect::<Vec<_>>();
+        topics.sort_by(|a, b| a.topic.cmp(&b.topic));
+
+        InboxStats {
+            messages: self.complete_msges.len(),
+            pending_messages: self.pending_msges.len(),
+            bytes: self.bytes,
+            senders,
+            topics,
         }
     }
 }
@@ -223,28 +530,82 @@ impl MessageStack {
     /// Create a new `MessageStack`. This uses the provided [`DataPlane`] to inject message
     /// packets. Received packets must be injected into the `MessageStack` through the provided
     /// [`Stream`].
-    pub fn new<S>(data_plane: DataPlane, message_packet_stream: S) -> Self
+    ///
+    /// If a store directory is set, unread received messages and messages which are still being
+    /// sent are saved in it. Messages saved by a previous `MessageStack` are loaded, and sending
+    /// is resumed if their deadline did not pass yet.
+    ///
+    /// Received messages are kept until they are read, within the given [`InboxLimits`].
+    pub fn new<S>(
+        data_plane: DataPlane,
+        message_packet_stream: S,
+        store_dir: Option<PathBuf>,
+        inbox_limits: InboxLimits,
+    ) -> Self
     where
         S: Stream<Item = (PacketBuffer, IpAddr, IpAddr)> + Send + Unpin + 'static,
     {
+        let mut store = store_dir.and_then(|dir| match MessageStore::open(&dir) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                error!("Failed to open message store in {dir:?}, messages won't be persisted: {e}");
+                None
+            }
+        });
+        let (inbox_messages, outbox_messages) = match store.as_mut() {
+            Some(store) => (
+                store.load_inbox().unwrap_or_else(|e| {
+                    error!("Failed to load saved received messages: {e}");
+                    vec![]
+                }),
+                store.load_outbox().unwrap_or_else(|e| {
+                    error!("Failed to load saved outbound messages: {e}");
+                    vec![]
+                }),
+            ),
+            None => (vec![], vec![]),
+        };
+        let (store_tx, store_task) = match store {
+            Some(store) => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                (Some(tx), Some(tokio::spawn(store.run(rx))))
+            }
+            None => (None, None),
+        };
+
         let (notify, subscriber) = watch::channel(());
+        let mut inbox = MessageInbox::new(notify, inbox_limits);
+        // Saved messages are always loaded, even if they exceed the limits.
+        for message in inbox_messages {
+            inbox.push_complete(message);
+        }
         let ms = Self {
             data_plane: Arc::new(Mutex::new(data_plane)),
-            inbox: Arc::new(Mutex::new(MessageInbox::new(notify))),
+            inbox: Arc::new(Mutex::new(inbox)),
             outbox: Arc::new(Mutex::new(MessageOutbox::new())),
             subscriber,
             reply_subscribers: Arc::new(Mutex::new(HashMap::new())),
+            counters: Arc::new(MessageCounters::default()),
+            received: broadcast::channel(SUBSCRIPTION_BUFFER).0,
+            state_changes: broadcast::channel(SUBSCRIPTION_BUFFER).0,
+            tasks: Arc::new(Mutex::new(Vec::new())),
+            store: store_tx,
+            store_task: Arc::new(Mutex::new(store_task)),
         };
 
-        tokio::task::spawn(
+        for msg in outbox_messages {
+            ms.resume_message(msg);
+        }
+
+        ms.track_task(tokio::task::spawn(
             ms.clone()
                 .handle_incoming_message_packets(message_packet_stream),
-        );
+        ));
 
         // task to periodically clear leftover reply subscribers
         {
             let ms = ms.clone();
-            tokio::task::spawn(async move {
+            let task = tokio::task::spawn(async move {
                 loop {
                     tokio::time::sleep(REPLY_SUBSCRIBER_CLEAR_DELAY).await;
 
@@ -262,10 +623,159 @@ impl MessageStack {
                     })
                 }
             });
+            ms.track_task(task);
+        }
+
+        if ms.store.is_some() {
+            let ms = ms.clone();
+            let task = tokio::task::spawn(async move {
+                loop {
+                    tokio::time::sleep(MESSAGE_PROGRESS_SAVE_INTERVAL).await;
+                    ms.save_progress();
+                }
+            });
+            ms.track_task(task);
         }
+
         ms
     }
 
+    /// Keep track of a background task, so it can be stopped when the message stack is shut
+    /// down. Tasks which already finished are forgotten.
+    fn track_task(&self, task: JoinHandle<()>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Stop all background tasks of the message stack. Messages which are still being sent are
+    /// abandoned, unless messages are persisted, in which case their progress is saved.
+    pub async fn shutdown(&self) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in &tasks {
+            task.abort();
+        }
+        for task in tasks {
+            // The only possible error is the cancellation we just requested.
+            let _ = task.await;
+        }
+
+        self.save_progress();
+        self.store_op(StoreOp::Close);
+        let store_task = self.store_task.lock().unwrap().take();
+        if let Some(store_task) = store_task {
+            if let Err(e) = store_task.await {
+                error!("Message store task failed: {e}");
+            }
+        }
+    }
+
+    /// Send an operation to the message store, if messages are persisted.
+    fn store_op(&self, op: StoreOp) {
+        if let Some(ref store) = self.store {
+            // This only fails if the store is closed, in which case we are shutting down.
+            let _ = store.send(op);
+        }
+    }
+
+    /// Inform subscribers a message was added to the inbox.
+    fn publish_received(&self, message: &ReceivedMessage) {
+        if self.received.receiver_count() > 0 {
+            // This only fails if all subscribers are gone in the meantime.
+            let _ = self.received.send(message.clone());
+        }
+    }
+
+    /// Inform subscribers the state of a sent message changed.
+    fn publish_state(&self, id: MessageId, message: &OutboundMessageInfo) {
+        if self.state_changes.receiver_count() > 0 {
+            // This only fails if all subscribers are gone in the meantime.
+            let _ = self.state_changes.send(MessageStateChange {
+                id,
+                info: message.info(),
+            });
+        }
+    }
+
+    /// Save the progress of outbound messages which changed since the last save, if messages are
+    /// persisted.
+    fn save_progress(&self) {
+        if self.store.is_none() {
+            return;
+        }
+        let mut outbox = self.outbox.lock().unwrap();
+        for (id, msg) in outbox.msges.iter_mut() {
+            // Messages read from a source can't be resumed, so they are never saved.
+            if msg.progress_changed
+                && msg.state == TransmissionState::InProgress
+                && msg.stream.is_none()
+            {
+                self.store_op(StoreOp::SaveProgress(
+                    *id,
+                    msg.chunks
+                        .iter()
+                        .map(|chunk| {
+                            matches!(chunk.chunk_transmit_state, ChunkTransmitState::Acked)
+                        })
+                        .collect(),
+                ));
+            }
+            msg.progress_changed = false;
+        }
+    }
+
+    /// Resume sending a message loaded from the message store. If the message expired in the
+    /// meantime, it is removed from the store instead.
+    fn resume_message(&self, stored: StoredOutboundMessage) {
+        // Like new messages, resumed messages are not sent for longer than the send window.
+        let window = stored
+            .deadline
+            .min(stored.created + MESSAGE_SEND_WINDOW)
+            .duration_since(time::SystemTime::now())
+            .unwrap_or_default();
+        if window.is_zero() {
+            debug!("Dropping expired saved message {}", stored.id.as_hex());
+            self.store_op(StoreOp::RemoveOutbound(stored.id));
+            return;
+        }
+
+        // The receiver might have restarted as well, and lost the chunks it received, so the
+        // message is always sent again from the INIT. The chunks acknowledged before are only kept
+        // as a hint, see `handle_message_reply`.
+        let len = stored.data.len();
+        let mut chunks = chunk_states(len);
+        match stored.acked_chunks {
+            Some(acked_chunks) if acked_chunks.len() == chunks.len() => {
+                for (chunk, acked) in chunks.iter_mut().zip(acked_chunks) {
+                    if acked {
+                        chunk.chunk_transmit_state = ChunkTransmitState::Acked;
+                    }
+                }
+            }
+            _ => chunks = vec![],
+        }
+        debug!("Resuming saved message {}", stored.id.as_hex());
+
+        self.outbox.lock().unwrap().insert(OutboundMessageInfo {
+            state: TransmissionState::Init,
+            created: stored.created,
+            deadline: stored.deadline,
+            len,
+            reply: stored.reply,
+            progress_changed: false,
+            msg: Message {
+                id: stored.id,
+                src: stored.src,
+                dst: stored.dst,
+                topic: stored.topic,
+                data: stored.data,
+            },
+            chunks,
+            stream: None,
+        });
+        self.spawn_send_task(stored.id, stored.reply, len, window);
+    }
+
     /// Handle incoming messages from the [`DataPlane`].
     async fn handle_incoming_message_packets<S>(self, mut message_packet_stream: S)
     where
@@ -301,21 +811,28 @@ impl MessageStack {
                     debug!("Dropping INIT ACK for message not in init state");
                     return;
                 }
+                if flags.aborted() {
+                    debug!("Receiver refused message {}", message_id.as_hex());
+                    message.state = TransmissionState::Rejected;
+                    self.counters.rejected();
+                    self.store_op(StoreOp::RemoveOutbound(message_id));
+                    self.publish_state(message_id, message);
+                    message.wake_stream();
+                    return;
+                }
                 message.state = TransmissionState::InProgress;
-                // Transform message into chunks.
-                let mut chunks =
-                    Vec::with_capacity((message.len + AVERAGE_CHUNK_SIZE - 1) / AVERAGE_CHUNK_SIZE);
-                for (chunk_idx, data_chunk) in
-                    message.msg.data.chunks(AVERAGE_CHUNK_SIZE).enumerate()
-                {
-                    chunks.push(ChunkState {
-                        chunk_idx,
-                        chunk_offset: chunk_idx * AVERAGE_CHUNK_SIZE,
-                        chunk_size: data_chunk.len(),
-                        chunk_transmit_state: ChunkTransmitState::Started,
-                    })
+                // Transform message into chunks. The receiver starts over after an INIT, so all
+                // chunks are sent, but chunks of a resumed message which were acknowledged before
+                // the restart are only sent with the retransmissions.
+                let hint = std::mem::replace(&mut message.chunks, chunk_states(message.len));
+                for (chunk, hinted) in message.chunks.iter_mut().zip(hint) {
+                    if matches!(hinted.chunk_transmit_state, ChunkTransmitState::Acked) {
+                        chunk.chunk_transmit_state = ChunkTransmitState::Sent(time::Instant::now());
+                    }
                 }
-                message.chunks = chunks;
+                message.progress_changed = true;
+                self.publish_state(message_id, message);
+                message.wake_stream();
             }
         } else if flags.chunk() {
             // ACK for a chunk, mark chunk as received so it is not retried again.
@@ -336,8 +853,16 @@ impl MessageStack {
                 // ACKs the right chunk. Additionally a malicious node could return a crafted input
                 // here anyway.
 
-                message.chunks[mc.chunk_idx() as usize].chunk_transmit_state =
-                    ChunkTransmitState::Acked;
+                let chunk = &mut message.chunks[mc.chunk_idx() as usize];
+                chunk.chunk_transmit_state = ChunkTransmitState::Acked;
+                message.progress_changed = true;
+                // Data read from a source is no longer needed, which makes room to read more.
+                if chunk.data.take().is_some() {
+                    if let Some(ref mut stream) = message.stream {
+                        stream.buffered -= 1;
+                    }
+                    message.wake_stream();
+                }
             }
         } else if flags.done() {
             // ACK for full message.
@@ -348,6 +873,9 @@ impl MessageStack {
                     return;
                 }
                 message.state = TransmissionState::Received;
+                self.counters.delivered();
+                self.store_op(StoreOp::RemoveOutbound(message_id));
+                self.publish_state(message_id, message);
             }
         } else if flags.read() {
             // Ack for a read flag. Since the original read flag is sent by the receiver, this
@@ -376,26 +904,78 @@ impl MessageStack {
                 debug!("Dropping INIT message as we already have a complete message with this ID");
                 return;
             }
+            // A streamed message is already passed to a reader, so it can't be reset. The INIT is
+            // sent again if our ACK got lost.
+            if matches!(inbox.pending_msges.get(&message_id), Some(m) if m.stream.is_some()) {
+                debug!("Acknowledging repeated INIT for streamed message");
+                drop(inbox);
+                self.reply(MessageInit::new(mp).into_reply().into_inner(), src, dst);
+                return;
+            }
             // Otherwise unilaterally reset the state. The message id space is large enough to
             // avoid accidental collisions.
+            if inbox.remove_pending(&message_id).is_some() {
+                debug!("Dropped current pending message because we received a new message with INIT flag set for the same ID");
+            }
+            // Limits are applied per sender key, so a sender can't get around them by using
+            // multiple IPs in its subnet.
+            let Some(src_pk) = self.data_plane.lock().unwrap().router().get_pubkey(src) else {
+                warn!("No public key entry for IP we just received a message INIT from");
+                return;
+            };
             let mi = MessageInit::new(mp);
             let expected_chunks =
                 (mi.length() as usize + AVERAGE_CHUNK_SIZE - 1) / AVERAGE_CHUNK_SIZE;
-            let chunks = vec![None; expected_chunks];
-            let message = ReceivedMessageInfo {
+            let mut message = ReceivedMessageInfo {
                 id: message_id,
                 is_reply,
                 src,
+                src_pk,
                 dst,
                 len: mi.length(),
                 topic: mi.topic().into(),
-                chunks,
+                chunks: vec![],
+                stream: None,
             };
-
-            if inbox.pending_msges.insert(message_id, message).is_some() {
-                debug!("Dropped current pending message because we received a new message with INIT flag set for the same ID");
+            // If a reader is waiting for this message, pass the message to it while it is being
+            // received, instead of keeping it in the inbox.
+            message.stream = self.claim_stream(&mut inbox, &message, expected_chunks);
+            // Otherwise only allocate space for the message if it fits in the inbox.
+            if message.stream.is_some() {
+                debug!(
+                    "Streaming message {} of {} bytes from {src} to a waiting reader",
+                    message_id.as_hex(),
+                    mi.length()
+                );
+            } else if let Some(dropped) = inbox.make_room(src_pk, mi.length(), mi.topic()) {
+                for unread in dropped {
+                    debug!(
+                        "Dropping unread message {} to make room for message {}",
+                        unread.id.as_hex(),
+                        message_id.as_hex()
+                    );
+                    self.counters.inbox_dropped();
+                    self.store_op(StoreOp::RemoveInbound(unread.id));
+                }
+            } else {
+                debug!(
+                    "Rejecting message {} of {} bytes from {src}, inbox limit reached",
+                    message_id.as_hex(),
+                    mi.length()
+                );
+                self.counters.inbox_rejected();
+                let mut reply = mi.into_reply().into_inner();
+                reply.header_mut().flags_mut().set_aborted();
+                self.reply(reply, src, dst);
+                return;
+            }
+            // Chunks of streamed messages are kept by the stream until they are read.
+            if message.stream.is_none() {
+                message.chunks = vec![None; expected_chunks];
             }
 
+            inbox.insert_pending(message);
+
             Some(mi.into_reply().into_inner())
         } else if flags.chunk() {
             // A chunk can only be received for incomplete messages. We don't have to check the
@@ -430,17 +1010,40 @@ impl MessageStack {
                     );
                     return;
                 }
-                // Finally check if we have sufficient space for our chunks.
-                if message.chunks.len() as u64 <= mc.chunk_idx() {
-                    // TODO: optimize
-                    let chunks =
-                        vec![None; (mc.chunk_idx() + 1 - message.chunks.len() as u64) as usize];
-                    message.chunks.extend_from_slice(&chunks);
+                // Chunks of streamed messages are passed to the reader as soon as possible.
+                if let Some(ref mut stream) = message.stream {
+                    let chunk = Chunk {
+                        data: mc.data().to_vec(),
+                    };
+                    if !stream.insert(mc.chunk_idx() as usize, chunk) {
+                        // Not acknowledging the chunk makes the sender retry it later.
+                        debug!(
+                            "Not accepting CHUNK {} yet, reader is too far behind",
+                            mc.chunk_idx()
+                        );
+                        return;
+                    }
+                    if stream.forward().is_err() {
+                        debug!(
+                            "Reader of message {} is gone, dropping message",
+                            message_id.as_hex()
+                        );
+                        inbox.remove_pending(&message_id);
+                        return;
+                    }
+                } else {
+                    // Finally check if we have sufficient space for our chunks.
+                    if message.chunks.len() as u64 <= mc.chunk_idx() {
+                        // TODO: optimize
+                        let chunks =
+                            vec![None; (mc.chunk_idx() + 1 - message.chunks.len() as u64) as usize];
+                        message.chunks.extend_from_slice(&chunks);
+                    }
+                    // Now insert the chunk. Overwrite any previous chunk.
+                    message.chunks[mc.chunk_idx() as usize] = Some(Chunk {
+                        data: mc.data().to_vec(),
+                    });
                 }
-                // Now insert the chunk. Overwrite any previous chunk.
-                message.chunks[mc.chunk_idx() as usize] = Some(Chunk {
-                    data: mc.data().to_vec(),
-                });
 
                 Some(mc.into_reply().into_inner())
             } else {
@@ -449,8 +1052,12 @@ impl MessageStack {
         } else if flags.done() {
             let mut inbox = self.inbox.lock().unwrap();
             let md = MessageDone::new(mp);
-            // At this point, we should have all message chunks. Verify length and reassemble them.
-            if let Some(inbound_message) = inbox.pending_msges.get_mut(&message_id) {
+            // At this point, we should have all message chunks. Streamed messages only need to be
+            // verified, others are reassembled first.
+            if matches!(inbox.pending_msges.get(&message_id), Some(m) if m.stream.is_some()) {
+                self.finish_stream(&mut inbox, message_id, &md)
+                    .then(|| md.into_reply().into_inner())
+            } else if let Some(inbound_message) = inbox.pending_msges.get_mut(&message_id) {
                 // Check if we have sufficient chunks
                 if md.chunk_count() != inbound_message.chunks.len() as u64 {
                     // TODO: report error to sender
@@ -498,22 +1105,14 @@ impl MessageStack {
                     return;
                 }
 
-                // Convert the IP's to PublicKeys.
-                let dp = self.data_plane.lock().unwrap();
-                let src_pubkey = if let Some(pk) = dp.router().get_pubkey(message.src) {
-                    pk
-                } else {
-                    warn!("No public key entry for IP we just received a message chunk from");
-                    return;
-                };
                 // This always is our own key as we are receiving.
-                let dst_pubkey = dp.router().node_public_key();
+                let dst_pubkey = self.data_plane.lock().unwrap().router().node_public_key();
 
                 let message = ReceivedMessage {
                     id: message.id,
                     is_reply: inbound_message.is_reply,
                     src_ip: message.src,
-                    src_pk: src_pubkey,
+                    src_pk: inbound_message.src_pk,
                     dst_ip: message.dst,
                     dst_pk: dst_pubkey,
                     topic: message.topic,
@@ -521,6 +1120,7 @@ impl MessageStack {
                 };
 
                 debug!("Message {} reception complete", message.id.as_hex());
+                self.counters.received();
 
                 // Check if we have any listeners and try to send the message to those first.
                 let mut subscribers = self.reply_subscribers.lock().unwrap();
@@ -530,19 +1130,26 @@ impl MessageStack {
                     if let Err(e) = sub.send(Some(message)) {
                         debug!("Subscriber quit before we could send the reply");
                         // Move message to be read if there were no subscribers.
-                        inbox.complete_msges.push_back(e.0.unwrap());
+                        let message = e.0.unwrap();
+                        self.store_op(StoreOp::SaveInbound(message.clone()));
+                        inbox.remove_pending(&message_id);
+                        self.publish_received(&message);
+                        inbox.push_complete(message);
                         // Notify subscribers we have a new message.
                         inbox.notify.send_replace(());
                     } else {
                         debug!("Informed subscriber of message reply");
+                        inbox.remove_pending(&message_id);
                     }
                 } else {
                     // Move message to be read if there were no subscribers.
-                    inbox.complete_msges.push_back(message);
+                    self.store_op(StoreOp::SaveInbound(message.clone()));
+                    inbox.remove_pending(&message_id);
+                    self.publish_received(&message);
+                    inbox.push_complete(message);
                     // Notify subscribers we have a new message.
                     inbox.notify.send_replace(());
                 }
-                inbox.pending_msges.remove(&message_id);
 
                 Some(md.into_reply().into_inner())
             } else {
@@ -557,14 +1164,19 @@ impl MessageStack {
                 }
                 debug!("Receiver confirmed READ of message {}", message_id.as_hex());
                 message.state = TransmissionState::Read;
+                self.counters.read();
+                self.publish_state(message_id, message);
             }
             None
         } else if flags.aborted() {
             // If the message is not finished yet, discard it completely.
             // But if it is finished, ignore this, i.e, nothing to do.
             let mut inbox = self.inbox.lock().unwrap();
-            if inbox.pending_msges.remove(&message_id).is_some() {
+            if let Some(message) = inbox.remove_pending(&message_id) {
                 debug!("Dropping pending message because we received an ABORT");
+                if let Some(stream) = message.stream {
+                    stream.fail("message aborted by the sender");
+                }
             }
             None
         } else {
@@ -572,18 +1184,130 @@ impl MessageStack {
             None
         };
         if let Some(reply) = reply {
-            // This is a reply, so SRC -> DST and DST -> SRC
-            // FIXME: this can be fixed once the dataplane accepts generic IpAddr addresses.
-            match (src, dst) {
-                (IpAddr::V6(src), IpAddr::V6(dst)) => {
-                    self.data_plane.lock().unwrap().inject_message_packet(
-                        dst,
-                        src,
-                        reply.into_inner(),
-                    );
-                }
-                _ => debug!("can only reply to message fragments if both src and dst are IPv6"),
+            self.reply(reply, src, dst);
+        }
+    }
+
+    /// Pass a new message to the oldest reader waiting for a message with its topic, if any.
+    /// Replies for which a subscriber is waiting are never passed to a reader. Streamed messages
+    /// are not kept in the inbox, but the limit on pending messages still applies.
+    fn claim_stream(
+        &self,
+        inbox: &mut MessageInbox,
+        message: &ReceivedMessageInfo,
+        expected_chunks: usize,
+    ) -> Option<InboundStream> {
+        if inbox.stream_claims.is_empty()
+            || inbox.pending_msges.len() >= inbox.limits.max_pending_messages
+            || (message.is_reply
+                && self
+                    .reply_subscribers
+                    .lock()
+                    .unwrap()
+                    .contains_key(&message.id))
+        {
+            return None;
+        }
+        let claim = inbox.take_stream_claim(&message.topic)?;
+        // This always is our own key as we are receiving.
+        let dst_pk = self.data_plane.lock().unwrap().router().node_public_key();
+        let (reader, mut stream) = MessageReader::new(message, dst_pk, expected_chunks);
+        // Subscribers get the full message once it is received, so keep a copy of it for them.
+        if self.received.receiver_count() > 0 {
+            stream.keep_data();
+        }
+        // If the reader stopped waiting in the meantime, the message is kept in the inbox instead.
+        claim.send(reader).ok()?;
+
+        Some(stream)
+    }
+
+    /// Pass the remaining chunks of a streamed message to its reader after the sender indicated
+    /// it sent all of them, and verify the message. Returns `true` if the message is complete and
+    /// the DONE packet can be acknowledged.
+    fn finish_stream(&self, inbox: &mut MessageInbox, id: MessageId, md: &MessageDone) -> bool {
+        let Some(message) = inbox.pending_msges.get_mut(&id) else {
+            return false;
+        };
+        let Some(ref mut stream) = message.stream else {
+            return false;
+        };
+        if md.chunk_count() != stream.chunk_count as u64 {
+            debug!("Message has invalid amount of chunks");
+            return false;
+        }
+        if stream.forward().is_err() {
+            debug!(
+                "Reader of message {} is gone, dropping message",
+                id.as_hex()
+            );
+            inbox.remove_pending(&id);
+            return false;
+        }
+        if stream.next_chunk < stream.chunk_count {
+            // Either chunks are missing, or the reader has no room for them yet. Not
+            // acknowledging the DONE packet makes the sender retry it later.
+            debug!("DONE received for message which is not fully passed to the reader");
+            return false;
+        }
+
+        let failure = if stream.forwarded != message.len {
+            debug!("Message has invalid size");
+            Some("message has invalid size")
+        } else if stream.checksum() != md.checksum() {
+            debug!(
+                "Message has wrong checksum, got {} expected {}",
+                md.checksum().to_hex(),
+                stream.checksum().to_hex()
+            );
+            Some("message has wrong checksum")
+        } else {
+            None
+        };
+        let message = inbox
+            .remove_pending(&id)
+            .expect("Pending message exists as we just checked it; qed");
+        let stream = message
+            .stream
+            .expect("Message is streamed as we just checked it; qed");
+        if let Some(reason) = failure {
+            stream.fail(reason);
+            return false;
+        }
+
+        debug!("Message {} reception complete", id.as_hex());
+        let data = stream.finish();
+        self.counters.received();
+        if let Some(data) = data {
+            self.publish_received(&ReceivedMessage {
+                id,
+                is_reply: message.is_reply,
+                src_ip: message.src,
+                src_pk: message.src_pk,
+                dst_ip: message.dst,
+                // This always is our own key as we are receiving.
+                dst_pk: self.data_plane.lock().unwrap().router().node_public_key(),
+                topic: message.topic,
+                data,
+            });
+        }
+        self.notify_read(id, message.src, message.dst);
+
+        true
+    }
+
+    /// Send a reply to a message packet received from `src` for `dst`.
+    fn reply(&self, reply: MessagePacket, src: IpAddr, dst: IpAddr) {
+        // This is a reply, so SRC -> DST and DST -> SRC
+        // FIXME: this can be fixed once the dataplane accepts generic IpAddr addresses.
+        match (src, dst) {
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                self.data_plane
+                    .lock()
+                    .unwrap()
+                    .inject_message_packet(dst, src, reply.into_inner());
             }
+            _ => debug!("can only reply to message fragments if both src and dst are IPv6"),
         }
     }
 }
@@ -615,6 +1339,45 @@ impl MessageStack {
             .0
     }
 
+    /// Push a new message of `len` bytes, which are read from `reader` while the message is sent.
+    /// Only the part of the message which is not acknowledged by the receiver yet is kept in
+    /// memory. The message is tried for the given duration, but at least for a second, after which
+    /// it is aborted. A [message id](MessageId) will be randomly generated, and returned.
+    ///
+    /// If the reader fails, or ends before `len` bytes are read, the message is aborted. Streamed
+    /// messages are never persisted.
+    pub fn new_message_stream<R>(
+        &self,
+        dst: IpAddr,
+        reader: R,
+        len: u64,
+        topic: Vec<u8>,
+        try_duration: Duration,
+    ) -> Result<MessageId, PushMessageError>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        self.push_message_stream(None, dst, reader, len, topic, try_duration)
+    }
+
+    /// Push a new message which is a reply to the message with [the provided id](MessageId), of
+    /// which the data is read from `reader` while it is sent. See
+    /// [`MessageStack::new_message_stream`].
+    pub fn reply_message_stream<R>(
+        &self,
+        reply_to: MessageId,
+        dst: IpAddr,
+        reader: R,
+        len: u64,
+        try_duration: Duration,
+    ) -> MessageId
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        self.push_message_stream(Some(reply_to), dst, reader, len, vec![], try_duration)
+            .expect("Empty topic is never too large")
+    }
+
     /// Subscribe to a new message with the given ID. In practice, this will be a reply.
     pub fn subscribe_id(&self, id: MessageId) -> watch::Receiver<Option<ReceivedMessage>> {
         let mut subscribers = self.reply_subscribers.lock().unwrap();
@@ -675,10 +1438,27 @@ impl MessageStack {
             created,
             deadline,
             len,
+            reply,
+            progress_changed: false,
             msg,
             chunks: vec![], // leave Vec empty at start
+            stream: None,
         };
 
+        if self.store.is_some() {
+            self.store_op(StoreOp::SaveOutbound(StoredOutboundMessage {
+                id,
+                reply,
+                src: obmi.msg.src,
+                dst: obmi.msg.dst,
+                created,
+                deadline,
+                topic: obmi.msg.topic.clone(),
+                data: obmi.msg.data.clone(),
+                acked_chunks: None,
+            }));
+        }
+
         let subscription = if subscribe {
             Some(self.subscribe_id(id))
         } else {
@@ -696,10 +1476,12 @@ impl MessageStack {
         mi.set_length(len as u64);
         mi.set_topic(&obmi.msg.topic);
 
+        self.publish_state(id, &obmi);
         self.outbox
             .lock()
             .expect("Outbox lock isn't poisoned; qed")
             .insert(obmi);
+        self.counters.sent();
 
         // Actually send the init packet
         match (src, dst) {
@@ -713,10 +1495,208 @@ impl MessageStack {
             _ => debug!("Can only send messages between two IPv6 addresses"),
         }
 
+        self.spawn_send_task(id, reply, len, MESSAGE_SEND_WINDOW);
+
+        Ok((id, subscription))
+    }
+
+    /// Push a new message which is read from a source while it is sent. If id is set, it is
+    /// considered a reply to that id. If not, a new id is generated.
+    fn push_message_stream<R>(
+        &self,
+        id: Option<MessageId>,
+        dst: IpAddr,
+        reader: R,
+        len: u64,
+        topic: Vec<u8>,
+        try_duration: Duration,
+    ) -> Result<MessageId, PushMessageError>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        if topic.len() > 255 {
+            return Err(PushMessageError::TopicTooLarge);
+        }
+
+        let src = self
+            .data_plane
+            .lock()
+            .unwrap()
+            .router()
+            .node_public_key()
+            .address()
+            .into();
+
+        let (id, reply) = if let Some(id) = id {
+            (id, true)
+        } else {
+            (MessageId::new(), false)
+        };
+
+        let len = len as usize;
+        // The message is sent at least once, this also keeps the send window of the send task
+        // from being 0.
+        let try_duration = try_duration.max(RETRANSMISSION_DELAY);
+        let created = std::time::SystemTime::now();
+        let obmi = OutboundMessageInfo {
+            state: TransmissionState::Init,
+            created,
+            deadline: created + try_duration,
+            len,
+            reply,
+            progress_changed: false,
+            msg: Message {
+                id,
+                src,
+                dst,
+                topic,
+                // The data is kept in the chunks while they are being sent.
+                data: vec![],
+            },
+            chunks: vec![],
+            stream: Some(OutboundStream::new()),
+        };
+
+        // The init packet is sent by the send task.
+        self.publish_state(id, &obmi);
+        self.outbox
+            .lock()
+            .expect("Outbox lock isn't poisoned; qed")
+            .insert(obmi);
+        self.counters.sent();
+
+        self.track_task(tokio::task::spawn(
+            self.clone().read_message_source(id, reader, len),
+        ));
+        // Large messages can take longer than the regular send window, so they are tried for the
+        // full duration.
+        self.spawn_send_task(id, reply, len, try_duration);
+
+        Ok(id)
+    }
+
+    /// Read the data of the message with the given id from its source, as chunks are
+    /// acknowledged by the receiver.
+    async fn read_message_source<R>(self, id: MessageId, reader: R, len: usize)
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let mut reader = reader.take(len as u64);
+        let mut hasher = blake3::Hasher::new();
+        let chunk_count = (len + AVERAGE_CHUNK_SIZE - 1) / AVERAGE_CHUNK_SIZE;
+        let Some(window) = self
+            .outbox
+            .lock()
+            .unwrap()
+            .msges
+            .get(&id)
+            .and_then(|msg| msg.stream.as_ref())
+            .map(|stream| stream.window.clone())
+        else {
+            return;
+        };
+
+        for chunk_idx in 0..chunk_count {
+            // Wait until the remote accepted the message, and there is room in the send window.
+            // The notification is created before checking, so a wakeup in between is not missed.
+            loop {
+                let notified = window.notified();
+                match self.source_readable(id) {
+                    Some(true) => break,
+                    Some(false) => notified.await,
+                    None => return,
+                }
+            }
+
+            let chunk_size = AVERAGE_CHUNK_SIZE.min(len - chunk_idx * AVERAGE_CHUNK_SIZE);
+            let mut data = vec![0; chunk_size];
+            if let Err(e) = reader.read_exact(&mut data).await {
+                error!("Failed to read data of message {}: {e}", id.as_hex());
+                self.abort_message(id);
+                return;
+            }
+            hasher.update(&data);
+
+            let mut outbox = self.outbox.lock().unwrap();
+            let Some(msg) = outbox.msges.get_mut(&id) else {
+                return;
+            };
+            // The chunks are only set once the remote accepted the message.
+            msg.chunks[chunk_idx].data = Some(data);
+            if let Some(ref mut stream) = msg.stream {
+                stream.buffered += 1;
+                if chunk_idx + 1 == chunk_count {
+                    stream.checksum = Some(hasher.finalize());
+                }
+            }
+        }
+
+        // An empty message has no chunks, so the checksum is set as soon as it is accepted.
+        if chunk_count == 0 {
+            if let Some(ref mut stream) = self
+                .outbox
+                .lock()
+                .unwrap()
+                .msges
+                .get_mut(&id)
+                .and_then(|msg| msg.stream.as_mut())
+            {
+                stream.checksum = Some(hasher.finalize());
+            }
+        }
+    }
+
+    /// Check if the next chunk of the message with the given id can be read from its source. This
+    /// returns [`Option::None`] if the message is no longer being sent.
+    fn source_readable(&self, id: MessageId) -> Option<bool> {
+        let outbox = self.outbox.lock().unwrap();
+        let msg = outbox.msges.get(&id)?;
+        let stream = msg.stream.as_ref()?;
+        match msg.state {
+            TransmissionState::Init => Some(false),
+            TransmissionState::InProgress => Some(stream.buffered < STREAM_SEND_WINDOW),
+            _ => None,
+        }
+    }
+
+    /// Abort sending the message with the given id, and inform the receiver.
+    fn abort_message(&self, id: MessageId) {
+        let mut outbox = self.outbox.lock().unwrap();
+        let Some(msg) = outbox.msges.get_mut(&id) else {
+            return;
+        };
+        if !matches!(
+            msg.state,
+            TransmissionState::Init | TransmissionState::InProgress
+        ) {
+            return;
+        }
+        msg.state = TransmissionState::Aborted;
+        self.counters.aborted();
+        self.publish_state(id, msg);
+        msg.wake_stream();
+
+        let mut mp = MessagePacket::new(PacketBuffer::new());
+        mp.header_mut().set_message_id(id);
+        mp.header_mut().flags_mut().set_aborted();
+        match (msg.msg.src, msg.msg.dst) {
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                self.data_plane
+                    .lock()
+                    .unwrap()
+                    .inject_message_packet(src, dst, mp.into_inner());
+            }
+            _ => debug!("Can only send messages between two IPv6 addresses"),
+        }
+    }
+
+    /// Spawn a task which sends the message with the given id from the outbox, until the remote
+    /// acknowledged it or the send window elapsed.
+    fn spawn_send_task(&self, id: MessageId, reply: bool, len: usize, send_window: Duration) {
         // Clone message stack so it can be injected in the task.
         let message_stack = self.clone();
-        tokio::task::spawn(async move {
-            let mut deadline = tokio::time::interval(MESSAGE_SEND_WINDOW);
+        let task = tokio::task::spawn(async move {
+            let mut deadline = tokio::time::interval(send_window);
             let mut interval = tokio::time::interval(RETRANSMISSION_DELAY);
             // Avoid a send burst if the system is slow.
             interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
@@ -769,6 +1749,11 @@ impl MessageStack {
                                             all_acked = false;
                                         }
                                         match chunk.chunk_transmit_state {
+                                            ChunkTransmitState::Started
+                                                if msg.stream.is_some() && chunk.data.is_none() =>
+                                            {
+                                                // The chunk is not read from the source yet.
+                                            }
                                             ChunkTransmitState::Started => {
                                                 // Generate and send chunk, move chunk to state sent
                                                 let mut mp = MessagePacket::new(PacketBuffer::new());
@@ -778,8 +1763,10 @@ impl MessageStack {
                                                 mc.set_chunk_idx(chunk.chunk_idx as u64);
                                                 mc.set_chunk_offset(chunk.chunk_offset as u64);
                                                 if let Err(e) = mc.set_chunk_data(
-                                                    &msg.msg.data[chunk.chunk_offset
-                                                        ..chunk.chunk_offset + chunk.chunk_size],
+                                                    chunk.data.as_deref().unwrap_or_else(|| {
+                                                        &msg.msg.data[chunk.chunk_offset
+                                                            ..chunk.chunk_offset + chunk.chunk_size]
+                                                    }),
                                                 ) {
                                                     error!("Failed to generate and send chunk: {e}");
                                                 };
@@ -813,8 +1800,10 @@ impl MessageStack {
                                                     mc.set_chunk_idx(chunk.chunk_idx as u64);
                                                     mc.set_chunk_offset(chunk.chunk_offset as u64);
                                                     if let Err(e) = mc.set_chunk_data(
-                                                        &msg.msg.data[chunk.chunk_offset
-                                                            ..chunk.chunk_offset + chunk.chunk_size],
+                                                        chunk.data.as_deref().unwrap_or_else(|| {
+                                                            &msg.msg.data[chunk.chunk_offset
+                                                                ..chunk.chunk_offset + chunk.chunk_size]
+                                                        }),
                                                     ) {
                                                         error!("Failed to generate and send chunk: {e}");
                                                     };
@@ -845,14 +1834,21 @@ impl MessageStack {
                                         }
                                     }
 
-                                    // If every chunk is acked, send the done packet.
-                                    if all_acked {
+                                    // If every chunk is acked, send the done packet. The checksum of
+                                    // a message read from a source is only known once the source is
+                                    // fully read.
+                                    let checksum = match msg.stream {
+                                        _ if !all_acked => None,
+                                        Some(ref stream) => stream.checksum,
+                                        None => Some(msg.msg.checksum()),
+                                    };
+                                    if let Some(checksum) = checksum {
                                         let mut mp = MessagePacket::new(PacketBuffer::new());
                                         mp.header_mut().set_message_id(id);
 
                                         let mut md = MessageDone::new(mp);
                                         md.set_chunk_count(msg.chunks.len() as u64);
-                                        md.set_checksum(msg.msg.checksum());
+                                        md.set_checksum(checksum);
 
                                         match (msg.msg.src, msg.msg.dst) {
                                             (IpAddr::V6(src), IpAddr::V6(dst)) => {
@@ -881,6 +1877,9 @@ impl MessageStack {
                                 TransmissionState::Aborted => {
                                     // Nothing to do if we aborted the message.
                                 }
+                                TransmissionState::Rejected => {
+                                    // Nothing to do if the remote refused the message.
+                                }
                             };
                         } else {
                             // If the message is gone, just exit
@@ -896,6 +1895,10 @@ impl MessageStack {
                             if let Some(msg) = message_stack.outbox.lock().unwrap().msges.get_mut(&id) {
                                 if matches!(msg.state, TransmissionState::Init | TransmissionState::InProgress) {
                                     msg.state = TransmissionState::Aborted;
+                                    message_stack.counters.aborted();
+                                    message_stack.store_op(StoreOp::RemoveOutbound(id));
+                                    message_stack.publish_state(id, msg);
+                                    msg.wake_stream();
 
                                     // Inform receiver of message abortion.
                                     let mut mp = MessagePacket::new(PacketBuffer::new());
@@ -925,57 +1928,65 @@ impl MessageStack {
                         }
 
                         // Second tick, clean up.
-                        message_stack.outbox.lock().unwrap().msges.remove(&id);
+                        if let Some(msg) = message_stack.outbox.lock().unwrap().msges.remove(&id) {
+                            msg.wake_stream();
+                        }
                         return
                     }
                 }
             }
         });
+        self.track_task(task);
+    }
 
-        Ok((id, subscription))
+    /// Get the amount of messages handled since the message stack was created.
+    pub fn metrics(&self) -> MessageMetrics {
+        self.counters.metrics()
+    }
+
+    /// Get the current usage of the inbox.
+    pub fn inbox_stats(&self) -> InboxStats {
+        self.inbox.lock().unwrap().stats()
     }
 
     /// Get information about the status of an outbound message.
     pub fn message_info(&self, id: MessageId) -> Option<MessageInfo> {
         let outbox = self.outbox.lock().unwrap();
-        outbox.msges.get(&id).map(|mi| MessageInfo {
-            dst: mi.msg.dst,
-            state: match mi.state {
-                TransmissionState::Init => TransmissionProgress::Pending,
-                TransmissionState::InProgress => {
-                    let (pending, sent, acked) = mi.chunks.iter().fold(
-                        (0, 0, 0),
-                        |(mut pending, mut sent, mut acked), chunk| {
-                            match chunk.chunk_transmit_state {
-                                ChunkTransmitState::Started => pending += 1,
-                                ChunkTransmitState::Sent(_) => sent += 1,
-                                ChunkTransmitState::Acked => acked += 1,
-                            };
-                            (pending, sent, acked)
-                        },
-                    );
-                    TransmissionProgress::Sending {
-                        pending,
-                        sent,
-                        acked,
-                    }
-                }
-                TransmissionState::Received => TransmissionProgress::Received,
-                TransmissionState::Read => TransmissionProgress::Read,
-                TransmissionState::Aborted => TransmissionProgress::Aborted,
-            },
-            created: mi
-                .created
-                .duration_since(time::UNIX_EPOCH)
-                .expect("Message was created after the epoch")
-                .as_secs() as i64,
-            deadline: mi
-                .deadline
-                .duration_since(time::UNIX_EPOCH)
-                .expect("Message expires after the epoch")
-                .as_secs() as i64,
-            msg_len: mi.len,
-        })
+        outbox.msges.get(&id).map(OutboundMessageInfo::info)
+    }
+
+    /// Subscribe to messages which are added to the inbox. Every subscriber gets all messages,
+    /// which are not removed from the inbox. A subscriber which does not keep up misses the
+    /// oldest messages.
+    ///
+    /// Messages which are passed to a reader of [`MessageStack::message_stream`] while they are
+    /// received are passed to subscribers once they are complete. To do so, such messages are
+    /// kept in memory as a whole if there are subscribers when they start.
+    pub fn subscribe_received(&self) -> broadcast::Receiver<ReceivedMessage> {
+        self.received.subscribe()
+    }
+
+    /// Subscribe to state changes of sent messages. A subscriber which does not keep up misses
+    /// the oldest changes.
+    pub fn subscribe_state_changes(&self) -> broadcast::Receiver<MessageStateChange> {
+        self.state_changes.subscribe()
+    }
+
+    /// Remove the message with the given id from the inbox, if it is still there.
+    pub fn take_message(&self, id: MessageId) -> Option<ReceivedMessage> {
+        let msg = self.discard_message(id)?;
+        self.notify_read(msg.id, msg.src_ip, msg.dst_ip);
+        Some(msg)
+    }
+
+    /// Remove the message with the given id from the inbox, if it is still there, without
+    /// informing the sender that it was read.
+    pub fn discard_message(&self, id: MessageId) -> Option<ReceivedMessage> {
+        let mut inbox = self.inbox.lock().unwrap();
+        let idx = inbox.complete_msges.iter().position(|m| m.id == id)?;
+        let msg = inbox.remove_complete(idx)?;
+        self.store_op(StoreOp::RemoveInbound(msg.id));
+        Some(msg)
     }
 
     /// A future which eventually resolves to a new (inbound message)[`ReceivedMessage`], if new messages come in.
@@ -983,6 +1994,22 @@ impl MessageStack {
     /// If pop is false, the message is not removed and the next call of this method will return
     /// the same message.
     pub async fn message(&self, pop: bool, topic: Option<Vec<u8>>) -> ReceivedMessage {
+        let msg = self.next_message(pop, topic).await;
+        self.notify_read(msg.id, msg.src_ip, msg.dst_ip);
+        msg
+    }
+
+    /// A future which eventually resolves to the first message in the inbox, optionally with the
+    /// given topic. Unlike [`MessageStack::message`], the sender is not informed that the message
+    /// was read, and the message is kept in the inbox until it is taken with
+    /// [`MessageStack::take_message`] or [`MessageStack::discard_message`].
+    pub async fn peek_message(&self, topic: Option<Vec<u8>>) -> ReceivedMessage {
+        self.next_message(false, topic).await
+    }
+
+    /// Wait for the first message in the inbox, optionally with the given topic, and remove it
+    /// if `pop` is set.
+    async fn next_message(&self, pop: bool, topic: Option<Vec<u8>>) -> ReceivedMessage {
         // Copy the subscriber since we need mutable access to it.
         let mut subscriber = self.subscriber.clone();
 
@@ -992,24 +2019,20 @@ impl MessageStack {
             'check: {
                 let mut inbox = self.inbox.lock().unwrap();
                 // If a filter is set only check for those messages.
-                if let Some(ref topic) = topic {
-                    if let Some((idx, _)) = inbox
-                        .complete_msges
-                        .iter()
-                        .enumerate()
-                        .find(|(_, v)| &v.topic == topic)
-                    {
-                        return inbox.complete_msges.remove(idx).unwrap();
-                    } else {
-                        break 'check;
-                    }
-                }
+                let Some(idx) = inbox.complete_msges.iter().position(|m| match topic {
+                    Some(ref topic) => &m.topic == topic,
+                    None => true,
+                }) else {
+                    break 'check;
+                };
                 if let Some(msg) = if pop {
-                    inbox.complete_msges.pop_front()
+                    inbox.remove_complete(idx)
                 } else {
-                    inbox.complete_msges.front().cloned()
+                    inbox.complete_msges.get(idx).cloned()
                 } {
-                    self.notify_read(&msg);
+                    if pop {
+                        self.store_op(StoreOp::RemoveInbound(msg.id));
+                    }
                     return msg;
                 };
             }
@@ -1020,16 +2043,67 @@ impl MessageStack {
         }
     }
 
+    /// A future which eventually resolves to a [`MessageReader`] for a new inbound message,
+    /// optionally with the given topic.
+    ///
+    /// Messages which are already fully received are returned first, and removed from the inbox.
+    /// Otherwise, the next new message is passed to the reader while it is being received, so it
+    /// does not need to be kept in memory as a whole.
+    pub async fn message_stream(&self, topic: Option<Vec<u8>>) -> MessageReader {
+        // Copy the subscriber since we need mutable access to it.
+        let mut subscriber = self.subscriber.clone();
+
+        loop {
+            subscriber.borrow_and_update();
+            let mut claim = {
+                let mut inbox = self.inbox.lock().unwrap();
+                let idx = inbox.complete_msges.iter().position(|m| match topic {
+                    Some(ref topic) => &m.topic == topic,
+                    None => true,
+                });
+                if let Some(msg) = idx.and_then(|idx| inbox.remove_complete(idx)) {
+                    self.store_op(StoreOp::RemoveInbound(msg.id));
+                    self.notify_read(msg.id, msg.src_ip, msg.dst_ip);
+                    return MessageReader::from_message(msg);
+                }
+                let (tx, rx) = oneshot::channel();
+                inbox.stream_claims.push_back(StreamClaim {
+                    topic: topic.clone(),
+                    reader: tx,
+                });
+                rx
+            };
+
+            // If a complete message comes in before a new message is passed to us, check the
+            // inbox again. The claim is dropped in that case, so no message is passed to it.
+            tokio::select! {
+                reader = &mut claim => {
+                    if let Ok(reader) = reader {
+                        return reader;
+                    }
+                }
+                // Sender can never be dropped since we hold a reference to self which contains
+                // the inbox.
+                _ = subscriber.changed() => {
+                    // A message might have been passed to us in the meantime.
+                    if let Ok(reader) = claim.try_recv() {
+                        return reader;
+                    }
+                }
+            }
+        }
+    }
+
     /// Notify the sender of a message that it has been read.
-    fn notify_read(&self, msg: &ReceivedMessage) {
+    fn notify_read(&self, id: MessageId, src_ip: IpAddr, dst_ip: IpAddr) {
         let mut mp = MessagePacket::new(PacketBuffer::new());
         let mut header = mp.header_mut();
-        header.set_message_id(msg.id);
+        header.set_message_id(id);
         header.flags_mut().set_read();
 
-        debug!("Notify sender we read message {}", msg.id.as_hex());
+        debug!("Notify sender we read message {}", id.as_hex());
 
-        match (msg.src_ip, msg.dst_ip) {
+        match (src_ip, dst_ip) {
             (IpAddr::V6(src), IpAddr::V6(dst)) => {
                 self.data_plane
                     .lock()
@@ -1045,7 +2119,7 @@ impl MessageStack {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageInfo {
     /// The receiver of this message.
@@ -1060,7 +2134,7 @@ pub struct MessageInfo {
     pub msg_len: usize,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum TransmissionProgress {
     /// Pending transmission, the remote has not yet acknowledged our init message.
@@ -1081,9 +2155,54 @@ pub enum TransmissionProgress {
     /// We aborted sending this message, the remote __might__ have a full message and process it,
     /// but that generally won't be the case.
     Aborted,
+    /// The remote refused the message, because it has no room for it.
+    Rejected,
+}
+
+/// A change of the state of a sent message.
+#[derive(Debug, Clone)]
+pub struct MessageStateChange {
+    /// Id of the message.
+    pub id: MessageId,
+    /// Info of the message after the change.
+    pub info: MessageInfo,
+}
+
+/// Usage of the inbox of the [`MessageStack`]. Messages which are still being received count
+/// with their announced size.
+pub struct InboxStats {
+    /// Messages which are fully received, but not read yet.
+    pub messages: usize,
+    /// Messages which are still being received.
+    pub pending_messages: usize,
+    /// Total size of the messages, in bytes.
+    pub bytes: usize,
+    /// Size of the messages per sender.
+    pub senders: Vec<SenderUsage>,
+    /// Amount of messages per topic.
+    pub topics: Vec<TopicUsage>,
+}
+
+/// Size of the messages in the inbox from a single sender.
+pub struct SenderUsage {
+    /// The overlay ip of the sender. This is the address of the sender's key, the sender can also
+    /// send from other IPs in its subnet.
+    pub ip: IpAddr,
+    /// The public key of the sender.
+    pub pk: PublicKey,
+    /// Total size of the messages, in bytes.
+    pub bytes: usize,
+}
+
+/// Amount of messages in the inbox with a single topic.
+pub struct TopicUsage {
+    /// The topic, which is empty for messages without topic.
+    pub topic: Vec<u8>,
+    /// Amount of messages.
+    pub messages: usize,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct MessageId([u8; MESSAGE_ID_SIZE]);
 
 impl MessageId {
@@ -1427,10 +2546,85 @@ pub struct OutboundMessageInfo {
     deadline: time::SystemTime,
     /// Length of the message.
     len: usize,
+    /// Is this message a reply to a received message.
+    reply: bool,
+    /// Did the transmission progress change since it was last saved in the message store.
+    progress_changed: bool,
     /// The message to send.
     msg: Message,
     /// Chunks of the message.
     chunks: Vec<ChunkState>,
+    /// Set if the data of the message is read from a source while it is sent, instead of being
+    /// kept in `msg`.
+    stream: Option<OutboundStream>,
+}
+
+impl OutboundMessageInfo {
+    /// Get the public info of the message.
+    fn info(&self) -> MessageInfo {
+        MessageInfo {
+            dst: self.msg.dst,
+            state: match self.state {
+                TransmissionState::Init => TransmissionProgress::Pending,
+                TransmissionState::InProgress => {
+                    let (pending, sent, acked) = self.chunks.iter().fold(
+                        (0, 0, 0),
+                        |(mut pending, mut sent, mut acked), chunk| {
+                            match chunk.chunk_transmit_state {
+                                ChunkTransmitState::Started => pending += 1,
+                                ChunkTransmitState::Sent(_) => sent += 1,
+                                ChunkTransmitState::Acked => acked += 1,
+                            };
+                            (pending, sent, acked)
+                        },
+                    );
+                    TransmissionProgress::Sending {
+                        pending,
+                        sent,
+                        acked,
+                    }
+                }
+                TransmissionState::Received => TransmissionProgress::Received,
+                TransmissionState::Read => TransmissionProgress::Read,
+                TransmissionState::Aborted => TransmissionProgress::Aborted,
+                TransmissionState::Rejected => TransmissionProgress::Rejected,
+            },
+            created: self
+                .created
+                .duration_since(time::UNIX_EPOCH)
+                .expect("Message was created after the epoch")
+                .as_secs() as i64,
+            deadline: self
+                .deadline
+                .duration_since(time::UNIX_EPOCH)
+                .expect("Message expires after the epoch")
+                .as_secs() as i64,
+            msg_len: self.len,
+        }
+    }
+
+    /// Wake up the task reading the source of the message, if there is one.
+    fn wake_stream(&self) {
+        if let Some(ref stream) = self.stream {
+            stream.window.notify_waiters();
+        }
+    }
+}
+
+/// Split a message of the given length in chunks, none of which has been sent yet.
+fn chunk_states(len: usize) -> Vec<ChunkState> {
+    (0..(len + AVERAGE_CHUNK_SIZE - 1) / AVERAGE_CHUNK_SIZE)
+        .map(|chunk_idx| {
+            let chunk_offset = chunk_idx * AVERAGE_CHUNK_SIZE;
+            ChunkState {
+                chunk_idx,
+                chunk_offset,
+                chunk_size: AVERAGE_CHUNK_SIZE.min(len - chunk_offset),
+                chunk_transmit_state: ChunkTransmitState::Started,
+                data: None,
+            }
+        })
+        .collect()
 }
 
 /// A message checksum. In practice this is a 32 byte blake3 digest of the entire message.
@@ -1457,8 +2651,193 @@ impl std::error::Error for PushMessageError {}
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        io::Cursor,
+        net::{IpAddr, Ipv6Addr},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use tokio::{
+        io::AsyncReadExt,
+        sync::{
+            broadcast::{self, error::TryRecvError},
+            watch,
+        },
+        task::JoinHandle,
+    };
+    use tokio_util::io::InspectReader;
+
+    use super::{
+        stream::STREAM_SEND_WINDOW, InboxLimits, MessageId, MessageInbox, MessageInit,
+        MessagePacket, MessagePacketHeaderMut, MessageReader, MessageStack, MessageStateChange,
+        OverflowPolicy, PacketBuffer, ReceivedMessage, ReceivedMessageInfo, TransmissionProgress,
+        AVERAGE_CHUNK_SIZE, MESSAGE_HEADER_SIZE,
+    };
+    use crate::{
+        crypto::{PublicKey, SecretKey},
+        test_support::{connected_nodes, received_message},
+        Node,
+    };
+
+    fn inbox(overflow: OverflowPolicy) -> MessageInbox {
+        MessageInbox::new(
+            watch::channel(()).0,
+            InboxLimits {
+                max_bytes: 1_000,
+                max_bytes_per_sender: 600,
+                max_topic_messages: 3,
+                max_pending_messages: 2,
+                overflow,
+            },
+        )
+    }
+
+    fn sender(n: u8) -> PublicKey {
+        PublicKey::from([n; 32])
+    }
+
+    fn received(src: PublicKey, len: usize, topic: &[u8]) -> ReceivedMessage {
+        ReceivedMessage {
+            src_ip: src.address().into(),
+            src_pk: src,
+            ..received_message(topic, &vec![0; len])
+        }
+    }
+
+    fn pending(src: PublicKey, len: u64) -> ReceivedMessageInfo {
+        ReceivedMessageInfo {
+            id: MessageId::new(),
+            is_reply: false,
+            src: src.address().into(),
+            src_pk: src,
+            dst: sender(0).address().into(),
+            len,
+            topic: vec![],
+            chunks: vec![],
+            stream: None,
+        }
+    }
+
+    #[test]
+    fn inbox_rejects_over_limits() {
+        let mut inbox = inbox(OverflowPolicy::Reject);
+        inbox.push_complete(received(sender(1), 500, b"a"));
+
+        // Per sender limit.
+        assert!(inbox.make_room(sender(1), 200, b"b").is_none());
+        assert!(inbox.make_room(sender(2), 200, b"b").is_some());
+        // Total limit.
+        inbox.push_complete(received(sender(2), 400, b"a"));
+        assert!(inbox.make_room(sender(3), 200, b"b").is_none());
+        assert!(inbox.make_room(sender(3), 100, b"b").is_some());
+        // Topic limit.
+        inbox.push_complete(received(sender(3), 0, b"a"));
+        assert!(inbox.make_room(sender(4), 0, b"a").is_none());
+        assert!(inbox.make_room(sender(4), 0, b"b").is_some());
+        // Messages which never fit.
+        assert!(inbox.make_room(sender(4), 601, b"b").is_none());
+
+        assert_eq!(inbox.complete_msges.len(), 3);
+        assert_eq!(inbox.bytes, 900);
+    }
+
+    #[test]
+    fn inbox_limits_pending_messages() {
+        let mut inbox = inbox(OverflowPolicy::DropOldest);
+        inbox.insert_pending(pending(sender(1), 100));
+        let second = pending(sender(2), 100);
+        let second_id = second.id;
+        inbox.insert_pending(second);
+        assert_eq!(inbox.bytes, 200);
+
+        assert!(inbox.make_room(sender(3), 100, b"").is_none());
+        assert!(inbox.remove_pending(&second_id).is_some());
+        assert!(inbox.make_room(sender(3), 100, b"").is_some());
+        assert_eq!(inbox.bytes, 100);
+        assert_eq!(inbox.sender_bytes.get(&sender(2)), None);
+    }
+
+    #[test]
+    fn inbox_drops_oldest() {
+        let mut inbox = inbox(OverflowPolicy::DropOldest);
+        let first = received(sender(1), 300, b"a");
+        let first_id = first.id;
+        inbox.push_complete(first);
+        inbox.push_complete(received(sender(2), 300, b"a"));
+        inbox.push_complete(received(sender(1), 300, b"b"));
+
+        // Sender 1 is at its limit, so its oldest message is removed.
+        let dropped = inbox.make_room(sender(1), 100, b"c").unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].id, first_id);
+        assert_eq!(inbox.bytes, 600);
+
+        // Total limit, any message can be removed.
+        inbox.push_complete(received(sender(3), 400, b"c"));
+        let dropped = inbox.make_room(sender(4), 300, b"c").unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].src_pk, sender(2));
+        assert_eq!(inbox.bytes, 700);
+
+        // Topic limit, only messages with the same topic are removed.
+        inbox.push_complete(received(sender(4), 0, b"c"));
+        inbox.push_complete(received(sender(4), 0, b"c"));
+        let dropped = inbox.make_room(sender(4), 0, b"c").unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].src_pk, sender(3));
+        assert_eq!(inbox.topic_messages.get(&b"c"[..]), Some(&2));
+
+        // Space taken by pending messages can't be freed.
+        let mut inbox = self::inbox(OverflowPolicy::DropOldest);
+        inbox.insert_pending(pending(sender(1), 600));
+        assert!(inbox.make_room(sender(1), 100, b"").is_none());
+    }
+
+    #[tokio::test]
+    async fn inbox_limits_sender_key() {
+        let (node1, node2) = connected_nodes().await;
+        let receiver = node2.message_stack();
+        receiver.inbox.lock().unwrap().limits.max_bytes_per_sender = 100;
 
-    use super::{MessagePacketHeaderMut, MESSAGE_HEADER_SIZE};
+        // Node 1 can send from any IP in its subnet, these all count towards the same limit.
+        let src1 = address(&node1);
+        let IpAddr::V6(ip) = src1 else {
+            panic!("Overlay addresses are IPv6");
+        };
+        let mut segments = ip.segments();
+        segments[7] ^= 1;
+        let src2 = IpAddr::V6(Ipv6Addr::from(segments));
+        for src in [src1, src2] {
+            let mut mi = MessageInit::new(MessagePacket::new(PacketBuffer::new()));
+            mi.set_length(80);
+            mi.set_topic(b"");
+            receiver.handle_message(mi.into_inner(), src, address(&node2));
+        }
+
+        let inbox = receiver.inbox.lock().unwrap();
+        assert_eq!(inbox.pending_msges.len(), 1);
+        assert_eq!(
+            inbox.sender_bytes.get(&node1.router.node_public_key()),
+            Some(&80)
+        );
+    }
+
+    #[test]
+    fn parse_overflow_policy() {
+        assert_eq!(
+            "reject".parse::<OverflowPolicy>(),
+            Ok(OverflowPolicy::Reject)
+        );
+        assert_eq!(
+            "drop-oldest".parse::<OverflowPolicy>(),
+            Ok(OverflowPolicy::DropOldest)
+        );
+        assert!("dropOldest".parse::<OverflowPolicy>().is_err());
+    }
 
     #[test]
     fn set_init_flag() {
@@ -1540,4 +2919,258 @@ mod tests {
         assert!(buf_mut.flags().ack() && buf_mut.flags().init());
         assert_eq!(buf_mut.header[8], 0b1000_0001);
     }
+
+    /// The overlay address of a node.
+    fn address(node: &Node) -> IpAddr {
+        node.router.node_public_key().address().into()
+    }
+
+    /// Start waiting for a streamed message with the given topic, and wait until the claim for
+    /// the next message is registered.
+    async fn claim_stream(ms: &MessageStack, topic: &[u8]) -> JoinHandle<MessageReader> {
+        let claim = tokio::spawn({
+            let ms = ms.clone();
+            let topic = topic.to_vec();
+            async move { ms.message_stream(Some(topic)).await }
+        });
+        while ms.inbox.lock().unwrap().stream_claims.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        claim
+    }
+
+    /// Wait until the message with the given id is no longer being sent, and get its final state.
+    async fn final_state(ms: &MessageStack, id: MessageId) -> TransmissionProgress {
+        loop {
+            match ms.message_info(id).expect("Message is known").state {
+                TransmissionProgress::Pending | TransmissionProgress::Sending { .. } => {
+                    tokio::time::sleep(Duration::from_millis(100)).await
+                }
+                state => return state,
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_message() {
+        let (node1, node2) = connected_nodes().await;
+        let (sender, receiver) = (node1.message_stack(), node2.message_stack());
+
+        // The message is larger than what the receiver accepts before it is read, and what is
+        // read from the source ahead of the acknowledgements.
+        let len = 6 * STREAM_SEND_WINDOW * AVERAGE_CHUNK_SIZE + 100;
+        let data = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+        let read = Arc::new(AtomicUsize::new(0));
+        let source = InspectReader::new(Cursor::new(data.clone()), {
+            let read = read.clone();
+            move |bytes: &[u8]| {
+                read.fetch_add(bytes.len(), Ordering::Relaxed);
+            }
+        });
+
+        let claim = claim_stream(&receiver, b"stream").await;
+        let id = sender
+            .new_message_stream(
+                address(&node2),
+                source,
+                len as u64,
+                b"stream".to_vec(),
+                Duration::from_secs(60),
+            )
+            .expect("Can push message");
+        let reader = claim.await.expect("Claim is resolved");
+        assert_eq!(reader.id, id);
+        assert_eq!(reader.len, len as u64);
+        assert_eq!(reader.topic, b"stream");
+
+        // While the message is not read, the source is only read up to the send window.
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(read.load(Ordering::Relaxed) < len);
+        {
+            let outbox = sender.outbox.lock().unwrap();
+            let stream = outbox.msges[&id]
+                .stream
+                .as_ref()
+                .expect("Message is streamed");
+            assert!(stream.buffered <= STREAM_SEND_WINDOW);
+            assert!(stream.checksum.is_none());
+        }
+
+        // The reader only ends without error if the checksum in the DONE packet is valid.
+        let mut received = Vec::new();
+        reader
+            .into_async_read()
+            .read_to_end(&mut received)
+            .await
+            .expect("Message is received");
+        assert_eq!(received, data);
+        assert_eq!(read.load(Ordering::Relaxed), len);
+        assert!(matches!(
+            final_state(&sender, id).await,
+            TransmissionProgress::Received | TransmissionProgress::Read
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_message_short_source() {
+        let (node1, node2) = connected_nodes().await;
+        let (sender, receiver) = (node1.message_stack(), node2.message_stack());
+
+        // The source ends halfway through the message.
+        let len = 10 * AVERAGE_CHUNK_SIZE;
+        let claim = claim_stream(&receiver, b"short").await;
+        let id = sender
+            .new_message_stream(
+                address(&node2),
+                Cursor::new(vec![0; len / 2]),
+                len as u64,
+                b"short".to_vec(),
+                Duration::from_secs(60),
+            )
+            .expect("Can push message");
+        let reader = claim.await.expect("Claim is resolved");
+        assert_eq!(reader.id, id);
+
+        let mut received = Vec::new();
+        assert!(reader
+            .into_async_read()
+            .read_to_end(&mut received)
+            .await
+            .is_err());
+        assert!(received.len() < len);
+        assert!(matches!(
+            final_state(&sender, id).await,
+            TransmissionProgress::Aborted
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_message_without_try_duration() {
+        let (node1, _node2) = connected_nodes().await;
+        let sender = node1.message_stack();
+
+        // Nobody receives the message, so it is aborted once the minimum try duration passed.
+        let unreachable = PublicKey::from(&SecretKey::new()).address().into();
+        let id = sender
+            .new_message_stream(
+                unreachable,
+                Cursor::new(vec![0; 100]),
+                100,
+                vec![],
+                Duration::ZERO,
+            )
+            .expect("Can push message");
+        let state = tokio::time::timeout(Duration::from_secs(60), final_state(&sender, id))
+            .await
+            .expect("Message is aborted");
+        assert!(matches!(state, TransmissionProgress::Aborted));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn subscribe_received() {
+        let (node1, node2) = connected_nodes().await;
+        let (sender, receiver) = (node1.message_stack(), node2.message_stack());
+        let mut received = receiver.subscribe_received();
+
+        let (id, _) = sender
+            .new_message(
+                address(&node2),
+                b"data".to_vec(),
+                b"topic".to_vec(),
+                Duration::from_secs(60),
+                false,
+            )
+            .expect("Can push message");
+        let msg = tokio::time::timeout(Duration::from_secs(60), received.recv())
+            .await
+            .expect("Message is received")
+            .expect("Subscriber keeps up");
+        assert_eq!(msg.id, id);
+        assert_eq!(msg.data, b"data");
+        assert_eq!(msg.topic, b"topic");
+        // Subscribers don't take the message from the inbox.
+        assert!(receiver.take_message(id).is_some());
+
+        // Messages which are passed to a reader while they are received are published once they
+        // are complete.
+        let claim = claim_stream(&receiver, b"stream").await;
+        let id = sender
+            .new_message_stream(
+                address(&node2),
+                Cursor::new(b"data".to_vec()),
+                4,
+                b"stream".to_vec(),
+                Duration::from_secs(60),
+            )
+            .expect("Can push message");
+        let mut data = Vec::new();
+        claim
+            .await
+            .expect("Claim is resolved")
+            .into_async_read()
+            .read_to_end(&mut data)
+            .await
+            .expect("Message is received");
+        assert_eq!(data, b"data");
+        let msg = tokio::time::timeout(Duration::from_secs(60), received.recv())
+            .await
+            .expect("Message is published")
+            .expect("Subscriber keeps up");
+        assert_eq!(msg.id, id);
+        assert_eq!(msg.data, b"data");
+        assert_eq!(msg.topic, b"stream");
+        assert_eq!(msg.src_pk, node1.router.node_public_key());
+        // Streamed messages never enter the inbox.
+        assert!(receiver.take_message(id).is_none());
+        assert!(matches!(received.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    /// Wait for the next state change, which must be of the message with the given id.
+    async fn next_state(
+        changes: &mut broadcast::Receiver<MessageStateChange>,
+        id: MessageId,
+    ) -> TransmissionProgress {
+        let change = tokio::time::timeout(Duration::from_secs(60), changes.recv())
+            .await
+            .expect("State changes")
+            .expect("Subscriber keeps up");
+        assert_eq!(change.id, id);
+        change.info.state
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn subscribe_state_changes() {
+        let (node1, node2) = connected_nodes().await;
+        let (sender, receiver) = (node1.message_stack(), node2.message_stack());
+        let mut changes = sender.subscribe_state_changes();
+
+        let (id, _) = sender
+            .new_message(
+                address(&node2),
+                b"data".to_vec(),
+                b"topic".to_vec(),
+                Duration::from_secs(60),
+                false,
+            )
+            .expect("Can push message");
+        assert!(matches!(
+            next_state(&mut changes, id).await,
+            TransmissionProgress::Pending
+        ));
+        assert!(matches!(
+            next_state(&mut changes, id).await,
+            TransmissionProgress::Sending { .. }
+        ));
+        assert!(matches!(
+            next_state(&mut changes, id).await,
+            TransmissionProgress::Received
+        ));
+
+        // Reading the message on the receiver informs the sender.
+        receiver.message(true, None).await;
+        assert!(matches!(
+            next_state(&mut changes, id).await,
+            TransmissionProgress::Read
+        ));
+    }
 }
@@ -0,0 +1,601 @@
+//! Optional on-disk store for messages, which allows them to survive a restart.
+//!
+//! Completed inbound messages are kept until they are read, and outbound messages are kept until
+//! the receiver acknowledged them or they are aborted. Every message is saved in its own file,
+//! named after the hex encoded [`MessageId`]. The transmission progress of an outbound message is
+//! saved in a separate file, so the (possibly large) message itself is only written once.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{debug, error, warn};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+use super::{MessageId, ReceivedMessage, MESSAGE_ID_SIZE};
+use crate::crypto::PublicKey;
+
+/// Name of the directory holding completed inbound messages.
+const INBOX_DIR: &str = "inbox";
+/// Name of the directory holding outbound messages.
+const OUTBOX_DIR: &str = "outbox";
+/// Extension of a file holding a message.
+const MESSAGE_EXTENSION: &str = "msg";
+/// Extension of a file holding the transmission progress of an outbound message.
+const PROGRESS_EXTENSION: &str = "progress";
+/// Magic bytes at the start of an inbound message file.
+const INBOUND_MAGIC: &[u8; 4] = b"mymi";
+/// Magic bytes at the start of an outbound message file.
+const OUTBOUND_MAGIC: &[u8; 4] = b"mymo";
+/// Magic bytes at the start of a progress file.
+const PROGRESS_MAGIC: &[u8; 4] = b"mymp";
+/// Version of the file formats.
+const STORE_VERSION: u8 = 1;
+/// Address family marker of an IPv4 address.
+const FAMILY_IPV4: u8 = 4;
+/// Address family marker of an IPv6 address.
+const FAMILY_IPV6: u8 = 6;
+
+/// An outbound message as it is saved in the [`MessageStore`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredOutboundMessage {
+    /// Id of the message.
+    pub id: MessageId,
+    /// Is this message a reply to a received message.
+    pub reply: bool,
+    /// Overlay ip of the sender, i.e. us.
+    pub src: IpAddr,
+    /// Overlay ip of the receiver.
+    pub dst: IpAddr,
+    /// Time the message was created.
+    pub created: SystemTime,
+    /// Time at which we stop trying to send the message.
+    pub deadline: SystemTime,
+    /// Topic of the message.
+    pub topic: Vec<u8>,
+    /// Data of the message.
+    pub data: Vec<u8>,
+    /// For every chunk, whether the receiver acknowledged it. This is `None` if the receiver did
+    /// not acknowledge the start of the transmission.
+    pub acked_chunks: Option<Vec<bool>>,
+}
+
+/// An operation on the [`MessageStore`]. Operations are applied in order by
+/// [`MessageStore::run`].
+pub enum StoreOp {
+    /// Save a completed inbound message which has not been read yet.
+    SaveInbound(ReceivedMessage),
+    /// Remove an inbound message, since it was read.
+    RemoveInbound(MessageId),
+    /// Save a new outbound message.
+    SaveOutbound(StoredOutboundMessage),
+    /// Save which chunks of an outbound message have been acknowledged.
+    SaveProgress(MessageId, Vec<bool>),
+    /// Remove an outbound message, since it no longer needs to be sent.
+    RemoveOutbound(MessageId),
+    /// Stop applying operations. Operations sent after this are discarded.
+    Close,
+}
+
+/// Messages saved in a directory.
+pub struct MessageStore {
+    inbox_dir: PathBuf,
+    outbox_dir: PathBuf,
+    /// Sequence number of the next saved inbound message, used to keep the order in which
+    /// messages were received.
+    next_inbound_seqno: u64,
+}
+
+impl MessageStore {
+    /// Open a `MessageStore` in the given directory, which is created if it does not exist yet.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let inbox_dir = dir.join(INBOX_DIR);
+        let outbox_dir = dir.join(OUTBOX_DIR);
+        std::fs::create_dir_all(&inbox_dir)?;
+        std::fs::create_dir_all(&outbox_dir)?;
+
+        Ok(Self {
+            inbox_dir,
+            outbox_dir,
+            next_inbound_seqno: 0,
+        })
+    }
+
+    /// Load all saved inbound messages, in the order they were received. Files which can't be
+    /// decoded are removed.
+    pub fn load_inbox(&mut self) -> io::Result<Vec<ReceivedMessage>> {
+        let mut messages = Vec::new();
+        for path in message_files(&self.inbox_dir)? {
+            match std::fs::read(&path).and_then(|data| decode_inbound(&data)) {
+                Ok(message) => messages.push(message),
+                Err(e) => {
+                    warn!("Removing unreadable message file {path:?}: {e}");
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+        messages.sort_by_key(|(seqno, msg)| (*seqno, msg.id));
+        if let Some((seqno, _)) = messages.last() {
+            self.next_inbound_seqno = seqno + 1;
+        }
+
+        Ok(messages.into_iter().map(|(_, msg)| msg).collect())
+    }
+
+    /// Load all saved outbound messages, including their progress. Files which can't be decoded
+    /// are removed.
+    pub fn load_outbox(&self) -> io::Result<Vec<StoredOutboundMessage>> {
+        let mut messages = Vec::new();
+        for path in message_files(&self.outbox_dir)? {
+            let mut message = match std::fs::read(&path).and_then(|data| decode_outbound(&data)) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Removing unreadable message file {path:?}: {e}");
+                    let _ = std::fs::remove_file(&path);
+                    let _ = std::fs::remove_file(path.with_extension(PROGRESS_EXTENSION));
+                    continue;
+                }
+            };
+            // A missing or broken progress file means the transmission has to start over.
+            message.acked_chunks = std::fs::read(path.with_extension(PROGRESS_EXTENSION))
+                .and_then(|data| decode_progress(&data))
+                .ok();
+            messages.push(message);
+        }
+
+        Ok(messages)
+    }
+
+    /// Apply the received [`StoreOp`]s until the channel is closed or [`StoreOp::Close`] is
+    /// received.
+    pub async fn run(mut self, mut ops: mpsc::UnboundedReceiver<StoreOp>) {
+        while let Some(op) = ops.recv().await {
+            let res = match op {
+                StoreOp::SaveInbound(msg) => {
+                    let path = self.message_path(&self.inbox_dir, msg.id);
+                    let seqno = self.next_inbound_seqno;
+                    self.next_inbound_seqno += 1;
+                    write_file(&path, &encode_inbound(seqno, &msg)).await
+                }
+                StoreOp::RemoveInbound(id) => {
+                    remove_file(&self.message_path(&self.inbox_dir, id)).await
+                }
+                StoreOp::SaveOutbound(msg) => {
+                    let path = self.message_path(&self.outbox_dir, msg.id);
+                    write_file(&path, &encode_outbound(&msg)).await
+                }
+                StoreOp::SaveProgress(id, acked_chunks) => {
+                    let path = self
+                        .message_path(&self.outbox_dir, id)
+                        .with_extension(PROGRESS_EXTENSION);
+                    write_file(&path, &encode_progress(&acked_chunks)).await
+                }
+                StoreOp::RemoveOutbound(id) => {
+                    let path = self.message_path(&self.outbox_dir, id);
+                    match remove_file(&path).await {
+                        Ok(()) => remove_file(&path.with_extension(PROGRESS_EXTENSION)).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                StoreOp::Close => {
+                    debug!("Closing message store");
+                    return;
+                }
+            };
+            if let Err(e) = res {
+                error!("Failed to update message store: {e}");
+            }
+        }
+    }
+
+    /// Path of the file holding the message with the given id in the given directory.
+    fn message_path(&self, dir: &Path, id: MessageId) -> PathBuf {
+        dir.join(id.as_hex()).with_extension(MESSAGE_EXTENSION)
+    }
+}
+
+/// List all message files in a directory.
+fn message_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some(MESSAGE_EXTENSION) {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Write a file by first writing a temporary file, which then replaces the existing file. As a
+/// result, there is always a complete file at the path, even if the process is interrupted.
+async fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+/// Remove a file. A file which does not exist is not an error.
+async fn remove_file(path: &Path) -> io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Encode a received message, with the sequence number which orders it among the saved messages.
+fn encode_inbound(seqno: u64, msg: &ReceivedMessage) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(128 + msg.topic.len() + msg.data.len());
+
+    buf.put_slice(INBOUND_MAGIC);
+    buf.put_u8(STORE_VERSION);
+    buf.put_u64(seqno);
+    buf.put_slice(&msg.id.0);
+    buf.put_u8(msg.is_reply as u8);
+    put_ip(&mut buf, msg.src_ip);
+    buf.put_slice(msg.src_pk.as_bytes());
+    put_ip(&mut buf, msg.dst_ip);
+    buf.put_slice(msg.dst_pk.as_bytes());
+    put_bytes(&mut buf, &msg.topic);
+    put_bytes(&mut buf, &msg.data);
+
+    buf
+}
+
+/// Decode a received message, and its sequence number.
+fn decode_inbound(mut src: &[u8]) -> io::Result<(u64, ReceivedMessage)> {
+    read_header(&mut src, INBOUND_MAGIC)?;
+    ensure_remaining(&src, 8)?;
+    let seqno = src.get_u64();
+    let id = read_message_id(&mut src)?;
+    ensure_remaining(&src, 1)?;
+    let is_reply = src.get_u8() != 0;
+    let src_ip = read_ip(&mut src)?;
+    let src_pk = read_public_key(&mut src)?;
+    let dst_ip = read_ip(&mut src)?;
+    let dst_pk = read_public_key(&mut src)?;
+    let topic = read_bytes(&mut src)?;
+    let data = read_bytes(&mut src)?;
+
+    Ok((
+        seqno,
+        ReceivedMessage {
+            id,
+            is_reply,
+            src_ip,
+            src_pk,
+            dst_ip,
+            dst_pk,
+            topic,
+            data,
+        },
+    ))
+}
+
+/// Encode an outbound message. The progress is not included.
+fn encode_outbound(msg: &StoredOutboundMessage) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(64 + msg.topic.len() + msg.data.len());
+
+    buf.put_slice(OUTBOUND_MAGIC);
+    buf.put_u8(STORE_VERSION);
+    buf.put_slice(&msg.id.0);
+    buf.put_u8(msg.reply as u8);
+    put_ip(&mut buf, msg.src);
+    put_ip(&mut buf, msg.dst);
+    put_time(&mut buf, msg.created);
+    put_time(&mut buf, msg.deadline);
+    put_bytes(&mut buf, &msg.topic);
+    put_bytes(&mut buf, &msg.data);
+
+    buf
+}
+
+/// Decode an outbound message. The progress is not included.
+fn decode_outbound(mut src: &[u8]) -> io::Result<StoredOutboundMessage> {
+    read_header(&mut src, OUTBOUND_MAGIC)?;
+    let id = read_message_id(&mut src)?;
+    ensure_remaining(&src, 1)?;
+    let reply = src.get_u8() != 0;
+    let msg_src = read_ip(&mut src)?;
+    let dst = read_ip(&mut src)?;
+    let created = read_time(&mut src)?;
+    let deadline = read_time(&mut src)?;
+    let topic = read_bytes(&mut src)?;
+    let data = read_bytes(&mut src)?;
+
+    Ok(StoredOutboundMessage {
+        id,
+        reply,
+        src: msg_src,
+        dst,
+        created,
+        deadline,
+        topic,
+        data,
+        acked_chunks: None,
+    })
+}
+
+/// Encode which chunks of an outbound message are acknowledged, as a bitmap.
+fn encode_progress(acked_chunks: &[bool]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(9 + (acked_chunks.len() + 7) / 8);
+
+    buf.put_slice(PROGRESS_MAGIC);
+    buf.put_u8(STORE_VERSION);
+    buf.put_u32(acked_chunks.len() as u32);
+    for byte in acked_chunks.chunks(8) {
+        buf.put_u8(
+            byte.iter()
+                .enumerate()
+                .fold(0, |acc, (idx, acked)| acc | ((*acked as u8) << idx)),
+        );
+    }
+
+    buf
+}
+
+/// Decode which chunks of an outbound message are acknowledged.
+fn decode_progress(mut src: &[u8]) -> io::Result<Vec<bool>> {
+    read_header(&mut src, PROGRESS_MAGIC)?;
+    ensure_remaining(&src, 4)?;
+    let count = src.get_u32() as usize;
+    ensure_remaining(&src, (count + 7) / 8)?;
+
+    Ok((0..count)
+        .map(|idx| src[idx / 8] & (1 << (idx % 8)) != 0)
+        .collect())
+}
+
+/// Check the magic bytes and version at the start of a file.
+fn read_header(src: &mut &[u8], magic: &[u8; 4]) -> io::Result<()> {
+    if src.remaining() < magic.len() + 1 || &src[..magic.len()] != magic {
+        return Err(invalid_data("not a message store file"));
+    }
+    src.advance(magic.len());
+    if src.get_u8() != STORE_VERSION {
+        return Err(invalid_data("unsupported message store file version"));
+    }
+
+    Ok(())
+}
+
+/// Encode a point in time as the amount of milliseconds since the UNIX epoch.
+fn put_time(buf: &mut BytesMut, time: SystemTime) {
+    buf.put_u64(
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+    );
+}
+
+/// Read a point in time encoded as the amount of milliseconds since the UNIX epoch.
+fn read_time(src: &mut &[u8]) -> io::Result<SystemTime> {
+    ensure_remaining(src, 8)?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(src.get_u64()))
+}
+
+/// Encode an IP address, prefixed by its family.
+fn put_ip(buf: &mut BytesMut, ip: IpAddr) {
+    match ip {
+        IpAddr::V4(ip) => {
+            buf.put_u8(FAMILY_IPV4);
+            buf.put_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.put_u8(FAMILY_IPV6);
+            buf.put_slice(&ip.octets());
+        }
+    }
+}
+
+/// Read an IP address, prefixed by its family.
+fn read_ip(src: &mut &[u8]) -> io::Result<IpAddr> {
+    ensure_remaining(src, 1)?;
+    match src.get_u8() {
+        FAMILY_IPV4 => {
+            ensure_remaining(src, 4)?;
+            let mut raw_ip = [0; 4];
+            src.copy_to_slice(&mut raw_ip);
+            Ok(IpAddr::V4(Ipv4Addr::from(raw_ip)))
+        }
+        FAMILY_IPV6 => {
+            ensure_remaining(src, 16)?;
+            let mut raw_ip = [0; 16];
+            src.copy_to_slice(&mut raw_ip);
+            Ok(IpAddr::V6(Ipv6Addr::from(raw_ip)))
+        }
+        _ => Err(invalid_data("unknown address family in message store file")),
+    }
+}
+
+/// Encode a byte string, prefixed by its length.
+fn put_bytes(buf: &mut BytesMut, data: &[u8]) {
+    buf.put_u64(data.len() as u64);
+    buf.put_slice(data);
+}
+
+/// Read a byte string, prefixed by its length.
+fn read_bytes(src: &mut &[u8]) -> io::Result<Vec<u8>> {
+    ensure_remaining(src, 8)?;
+    let len = src.get_u64();
+    if len > src.remaining() as u64 {
+        return Err(invalid_data("message store file is truncated"));
+    }
+    let mut data = vec![0; len as usize];
+    src.copy_to_slice(&mut data);
+
+    Ok(data)
+}
+
+/// Read a [`MessageId`].
+fn read_message_id(src: &mut &[u8]) -> io::Result<MessageId> {
+    ensure_remaining(src, MESSAGE_ID_SIZE)?;
+    let mut raw = [0; MESSAGE_ID_SIZE];
+    src.copy_to_slice(&mut raw);
+    Ok(MessageId(raw))
+}
+
+/// Read a [`PublicKey`].
+fn read_public_key(src: &mut &[u8]) -> io::Result<PublicKey> {
+    ensure_remaining(src, 32)?;
+    let mut raw = [0; 32];
+    src.copy_to_slice(&mut raw);
+    Ok(PublicKey::from(raw))
+}
+
+/// Ensure at least `len` bytes are left in the buffer.
+fn ensure_remaining(src: &&[u8], len: usize) -> io::Result<()> {
+    if src.remaining() < len {
+        Err(invalid_data("message store file is truncated"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Create an [`io::Error`] indicating a malformed file.
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv6Addr},
+        time::{Duration, SystemTime},
+    };
+
+    use tokio::sync::mpsc;
+
+    use super::{
+        decode_inbound, decode_outbound, decode_progress, encode_inbound, encode_outbound,
+        encode_progress, MessageStore, StoreOp, StoredOutboundMessage,
+    };
+    use crate::{
+        message::{MessageId, ReceivedMessage},
+        test_support::received_message,
+    };
+
+    fn outbound_message() -> StoredOutboundMessage {
+        let created = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        StoredOutboundMessage {
+            id: MessageId::new(),
+            reply: false,
+            src: IpAddr::V6(Ipv6Addr::new(0x400, 1, 2, 3, 0, 0, 0, 1)),
+            dst: IpAddr::V6(Ipv6Addr::new(0x400, 4, 5, 6, 0, 0, 0, 1)),
+            created,
+            deadline: created + Duration::from_secs(60),
+            topic: vec![],
+            data: vec![7; 5000],
+            acked_chunks: None,
+        }
+    }
+
+    fn assert_same_message(left: &ReceivedMessage, right: &ReceivedMessage) {
+        assert_eq!(left.id, right.id);
+        assert_eq!(left.is_reply, right.is_reply);
+        assert_eq!(left.src_ip, right.src_ip);
+        assert_eq!(left.src_pk, right.src_pk);
+        assert_eq!(left.dst_ip, right.dst_ip);
+        assert_eq!(left.dst_pk, right.dst_pk);
+        assert_eq!(left.topic, right.topic);
+        assert_eq!(left.data, right.data);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let msg = ReceivedMessage {
+            is_reply: true,
+            ..received_message(b"topic", b"hello")
+        };
+        let (seqno, decoded) =
+            decode_inbound(&encode_inbound(42, &msg)).expect("Can decode inbound message");
+        assert_eq!(seqno, 42);
+        assert_same_message(&decoded, &msg);
+
+        let msg = outbound_message();
+        assert_eq!(
+            decode_outbound(&encode_outbound(&msg)).expect("Can decode outbound message"),
+            msg
+        );
+
+        let acked = (0..21).map(|idx| idx % 3 == 0).collect::<Vec<_>>();
+        assert_eq!(
+            decode_progress(&encode_progress(&acked)).expect("Can decode progress"),
+            acked
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_data() {
+        let encoded = encode_inbound(0, &received_message(b"topic", b"hello"));
+        assert!(decode_inbound(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_outbound(&encoded).is_err());
+
+        let encoded = encode_outbound(&outbound_message());
+        assert!(decode_outbound(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_outbound(&[]).is_err());
+
+        let encoded = encode_progress(&[true; 9]);
+        assert!(decode_progress(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!(
+            "mycelium-message-store-test-{}",
+            rand::random::<u64>()
+        ));
+        let mut store = MessageStore::open(&dir).expect("Can create message store");
+        assert!(store.load_inbox().expect("Can list inbox").is_empty());
+        assert!(store.load_outbox().expect("Can list outbox").is_empty());
+
+        let (first, second, read) = (
+            received_message(b"topic", b"first"),
+            received_message(b"topic", b"second"),
+            received_message(b"topic", b"read"),
+        );
+        let (outbound, removed) = (outbound_message(), outbound_message());
+        let acked = vec![true, false, true, false];
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        for op in [
+            StoreOp::SaveInbound(first.clone()),
+            StoreOp::SaveInbound(second.clone()),
+            StoreOp::SaveInbound(read.clone()),
+            StoreOp::RemoveInbound(read.id),
+            StoreOp::SaveOutbound(outbound.clone()),
+            StoreOp::SaveProgress(outbound.id, acked.clone()),
+            StoreOp::SaveOutbound(removed.clone()),
+            StoreOp::RemoveOutbound(removed.id),
+            StoreOp::Close,
+        ] {
+            tx.send(op).expect("Store task is running");
+        }
+        store.run(rx).await;
+
+        let mut store = MessageStore::open(&dir).expect("Can open message store");
+        let inbox = store.load_inbox().expect("Can load inbox");
+        let outbox = store.load_outbox().expect("Can load outbox");
+        std::fs::remove_dir_all(&dir).expect("Can remove message store");
+
+        assert_eq!(inbox.len(), 2);
+        assert_same_message(&inbox[0], &first);
+        assert_same_message(&inbox[1], &second);
+        assert_eq!(
+            outbox,
+            vec![StoredOutboundMessage {
+                acked_chunks: Some(acked),
+                ..outbound
+            }]
+        );
+    }
+}
@@ -0,0 +1,423 @@
+//! Streaming of message data, so large messages don't need to be kept in memory as a whole.
+
+use std::{
+    collections::VecDeque,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use tokio::{
+    io::AsyncRead,
+    sync::{mpsc, Notify},
+};
+use tokio_util::io::StreamReader;
+
+use crate::crypto::PublicKey;
+
+use super::{Chunk, MessageChecksum, MessageId, ReceivedMessage, ReceivedMessageInfo};
+
+/// Amount of chunks read from the source of a streamed message which can be waiting for an
+/// acknowledgement of the receiver.
+pub(super) const STREAM_SEND_WINDOW: usize = 64;
+/// Amount of chunks after the last chunk passed to the [`MessageReader`] which are accepted. Chunks
+/// further in the message are not acknowledged, so the sender sends them again later.
+const STREAM_RECEIVE_WINDOW: usize = 128;
+/// Amount of chunks which can be passed to a [`MessageReader`] before it reads them.
+const STREAM_READER_BUFFER: usize = 64;
+
+/// Progress of a message which is passed to a [`MessageReader`] while it is being received.
+pub(super) enum StreamEvent {
+    /// The next part of the message.
+    Data(Bytes),
+    /// The full message has been received, and the checksum is valid.
+    Done,
+    /// The message could not be received.
+    Failed(&'static str),
+}
+
+/// A received message, of which the data is read in order as it comes in.
+///
+/// The data is available as a [`Stream`] of chunks, or as an [`AsyncRead`] through
+/// [`MessageReader::into_async_read`]. The checksum of the message is only verified once all data
+/// is received, so data must not be trusted until the stream ended without error.
+pub struct MessageReader {
+    /// Id of the message.
+    pub id: MessageId,
+    /// This message is a reply to an initial message with the given id.
+    pub is_reply: bool,
+    /// The overlay ip of the sender.
+    pub src_ip: IpAddr,
+    /// The public key of the sender of the message.
+    pub src_pk: PublicKey,
+    /// The overlay ip of the receiver.
+    pub dst_ip: IpAddr,
+    /// The public key of the receiver of the message. This is always ours.
+    pub dst_pk: PublicKey,
+    /// The possible topic of the message.
+    pub topic: Vec<u8>,
+    /// Size of the message in bytes.
+    pub len: u64,
+    events: mpsc::Receiver<StreamEvent>,
+    finished: bool,
+}
+
+/// State of a message which is streamed to a [`MessageReader`] while it is being received.
+pub(super) struct InboundStream {
+    events: mpsc::Sender<StreamEvent>,
+    /// Index of the next chunk to pass to the reader.
+    pub next_chunk: usize,
+    /// Received chunks which are not passed to the reader yet, starting at `next_chunk`.
+    window: VecDeque<Option<Chunk>>,
+    /// Amount of chunks in the message, as far as known.
+    pub chunk_count: usize,
+    /// Amount of bytes passed to the reader.
+    pub forwarded: u64,
+    hasher: blake3::Hasher,
+    /// Copy of the data passed to the reader, if it is kept with [`InboundStream::keep_data`].
+    data: Option<Vec<u8>>,
+}
+
+/// State of a message which is sent while it is read from a source.
+pub(super) struct OutboundStream {
+    /// Notified when chunks are acknowledged or the message is stopped, so the task reading the
+    /// source can continue.
+    pub window: Arc<Notify>,
+    /// Amount of chunks which have been read from the source, but are not acknowledged yet.
+    pub buffered: usize,
+    /// Checksum of the message, once it is fully read from the source.
+    pub checksum: Option<MessageChecksum>,
+}
+
+/// Error returned when the [`MessageReader`] of a message is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct ReaderGone;
+
+impl MessageReader {
+    /// Create a new `MessageReader` for a message which is still being received, together with
+    /// the [`InboundStream`] to pass the message to it. `dst_pk` is our own key.
+    pub(super) fn new(
+        message: &ReceivedMessageInfo,
+        dst_pk: PublicKey,
+        chunk_count: usize,
+    ) -> (Self, InboundStream) {
+        let (tx, rx) = mpsc::channel(STREAM_READER_BUFFER);
+        (
+            Self {
+                id: message.id,
+                is_reply: message.is_reply,
+                src_ip: message.src,
+                src_pk: message.src_pk,
+                dst_ip: message.dst,
+                dst_pk,
+                topic: message.topic.clone(),
+                len: message.len,
+                events: rx,
+                finished: false,
+            },
+            InboundStream {
+                events: tx,
+                next_chunk: 0,
+                window: VecDeque::new(),
+                chunk_count,
+                forwarded: 0,
+                hasher: blake3::Hasher::new(),
+                data: None,
+            },
+        )
+    }
+
+    /// Create a `MessageReader` for a message which is already fully received.
+    pub(super) fn from_message(msg: ReceivedMessage) -> Self {
+        let (tx, rx) = mpsc::channel(2);
+        let len = msg.data.len() as u64;
+        // The channel has room for both events, and the receiver is not dropped.
+        let _ = tx.try_send(StreamEvent::Data(msg.data.into()));
+        let _ = tx.try_send(StreamEvent::Done);
+        Self {
+            id: msg.id,
+            is_reply: msg.is_reply,
+            src_ip: msg.src_ip,
+            src_pk: msg.src_pk,
+            dst_ip: msg.dst_ip,
+            dst_pk: msg.dst_pk,
+            topic: msg.topic,
+            len,
+            events: rx,
+            finished: false,
+        }
+    }
+
+    /// Read the data of the message as an [`AsyncRead`].
+    pub fn into_async_read(self) -> impl AsyncRead + Send + Unpin {
+        StreamReader::new(self)
+    }
+}
+
+impl Stream for MessageReader {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        let event = match self.events.poll_recv(cx) {
+            Poll::Ready(event) => event,
+            Poll::Pending => return Poll::Pending,
+        };
+        Poll::Ready(match event {
+            Some(StreamEvent::Data(data)) => Some(Ok(data)),
+            Some(StreamEvent::Done) => {
+                self.finished = true;
+                None
+            }
+            Some(StreamEvent::Failed(reason)) => {
+                self.finished = true;
+                Some(Err(io::Error::new(io::ErrorKind::InvalidData, reason)))
+            }
+            None => {
+                self.finished = true;
+                Some(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "message was dropped before it was fully received",
+                )))
+            }
+        })
+    }
+}
+
+impl InboundStream {
+    /// Keep a received chunk until it can be passed to the reader. Chunks which are too far ahead
+    /// of the reader are refused, in which case `false` is returned. Chunks which were already
+    /// passed to the reader are ignored.
+    pub fn insert(&mut self, chunk_idx: usize, chunk: Chunk) -> bool {
+        if chunk_idx < self.next_chunk {
+            return true;
+        }
+        let offset = chunk_idx - self.next_chunk;
+        if offset >= STREAM_RECEIVE_WINDOW {
+            return false;
+        }
+        if self.window.len() <= offset {
+            self.window.resize(offset + 1, None);
+        }
+        // Overwrite any previous chunk.
+        self.window[offset] = Some(chunk);
+        self.chunk_count = self.chunk_count.max(chunk_idx + 1);
+
+        true
+    }
+
+    /// Pass the chunks which follow the last passed chunk to the reader, as long as it has room
+    /// for them. One slot is always kept free, so the final event can always be sent.
+    pub fn forward(&mut self) -> Result<(), ReaderGone> {
+        if self.events.is_closed() {
+            return Err(ReaderGone);
+        }
+        while self.events.capacity() > 1 {
+            let Some(Some(chunk)) = self.window.front_mut().map(Option::take) else {
+                break;
+            };
+            self.window.pop_front();
+            self.hasher.update(&chunk.data);
+            self.forwarded += chunk.data.len() as u64;
+            if let Some(ref mut data) = self.data {
+                data.extend_from_slice(&chunk.data);
+            }
+            self.events
+                .try_send(StreamEvent::Data(chunk.data.into()))
+                .map_err(|_| ReaderGone)?;
+            self.next_chunk += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Checksum of the data passed to the reader so far.
+    pub fn checksum(&self) -> MessageChecksum {
+        self.hasher.finalize()
+    }
+
+    /// Keep a copy of all data passed to the reader, so the full message is available once it
+    /// is received. This must be called before any data is passed to the reader.
+    pub fn keep_data(&mut self) {
+        debug_assert_eq!(self.forwarded, 0);
+        self.data = Some(Vec::new());
+    }
+
+    /// Inform the reader the full message is received. Returns the data of the message if it is
+    /// kept.
+    pub fn finish(self) -> Option<Vec<u8>> {
+        let _ = self.events.try_send(StreamEvent::Done);
+        self.data
+    }
+
+    /// Inform the reader the message could not be received.
+    pub fn fail(self, reason: &'static str) {
+        let _ = self.events.try_send(StreamEvent::Failed(reason));
+    }
+}
+
+impl OutboundStream {
+    /// Create a new `OutboundStream` for a message which is not read yet.
+    pub fn new() -> Self {
+        Self {
+            window: Arc::new(Notify::new()),
+            buffered: 0,
+            checksum: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::{IpAddr, Ipv6Addr},
+    };
+
+    use futures::StreamExt;
+    use tokio::io::AsyncReadExt;
+
+    use super::{MessageReader, ReaderGone, STREAM_READER_BUFFER, STREAM_RECEIVE_WINDOW};
+    use crate::{
+        crypto::PublicKey,
+        message::{Chunk, MessageId, ReceivedMessageInfo},
+        test_support::received_message,
+    };
+
+    fn new_reader(len: u64, chunk_count: usize) -> (MessageReader, super::InboundStream) {
+        let ip = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        let pk = PublicKey::from([0; 32]);
+        let message = ReceivedMessageInfo {
+            id: MessageId::new(),
+            is_reply: false,
+            src: ip,
+            src_pk: pk,
+            dst: ip,
+            len,
+            topic: vec![],
+            chunks: vec![],
+            stream: None,
+        };
+        MessageReader::new(&message, pk, chunk_count)
+    }
+
+    fn chunk(data: &[u8]) -> Chunk {
+        Chunk {
+            data: data.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_chunks_in_order() {
+        let (reader, mut stream) = new_reader(6, 3);
+
+        // The first chunk is missing, so nothing can be passed yet.
+        assert!(stream.insert(1, chunk(b"cd")));
+        stream.forward().unwrap();
+        assert_eq!(stream.next_chunk, 0);
+
+        assert!(stream.insert(0, chunk(b"ab")));
+        stream.forward().unwrap();
+        assert_eq!(stream.next_chunk, 2);
+        // Chunks which are passed already are ignored.
+        assert!(stream.insert(0, chunk(b"xx")));
+        assert!(stream.insert(2, chunk(b"ef")));
+        stream.forward().unwrap();
+        assert_eq!(stream.next_chunk, stream.chunk_count);
+        assert_eq!(stream.forwarded, 6);
+        assert_eq!(stream.checksum(), blake3::hash(b"abcdef"));
+        stream.finish();
+
+        let mut data = vec![];
+        reader
+            .into_async_read()
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        assert_eq!(data, b"abcdef");
+    }
+
+    #[tokio::test]
+    async fn keeps_data() {
+        let (_reader, mut stream) = new_reader(4, 2);
+        stream.keep_data();
+        assert!(stream.insert(1, chunk(b"cd")));
+        assert!(stream.insert(0, chunk(b"ab")));
+        stream.forward().unwrap();
+        assert_eq!(stream.finish(), Some(b"abcd".to_vec()));
+
+        let (_reader, mut stream) = new_reader(2, 1);
+        assert!(stream.insert(0, chunk(b"ab")));
+        stream.forward().unwrap();
+        assert_eq!(stream.finish(), None);
+    }
+
+    #[tokio::test]
+    async fn refuses_chunks_outside_window() {
+        let (_reader, mut stream) = new_reader(1, 1);
+
+        assert!(!stream.insert(STREAM_RECEIVE_WINDOW, chunk(b"a")));
+        assert_eq!(stream.chunk_count, 1);
+        assert!(stream.insert(STREAM_RECEIVE_WINDOW - 1, chunk(b"a")));
+        assert_eq!(stream.chunk_count, STREAM_RECEIVE_WINDOW);
+    }
+
+    #[tokio::test]
+    async fn keeps_room_for_final_event() {
+        let (mut reader, mut stream) =
+            new_reader(STREAM_READER_BUFFER as u64 + 1, STREAM_READER_BUFFER + 1);
+        for idx in 0..=STREAM_READER_BUFFER {
+            assert!(stream.insert(idx, chunk(b"a")));
+        }
+
+        stream.forward().unwrap();
+        assert_eq!(stream.next_chunk, STREAM_READER_BUFFER - 1);
+
+        // Reading makes room for the other chunks.
+        for _ in 0..2 {
+            assert_eq!(&reader.next().await.unwrap().unwrap()[..], b"a");
+        }
+        stream.forward().unwrap();
+        assert_eq!(stream.next_chunk, STREAM_READER_BUFFER + 1);
+        stream.fail("bad checksum");
+
+        let mut read = 2;
+        while let Some(data) = reader.next().await {
+            match data {
+                Ok(_) => read += 1,
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            }
+        }
+        assert_eq!(read, STREAM_READER_BUFFER + 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_messages() {
+        let (mut reader, stream) = new_reader(1, 1);
+        drop(stream);
+        let err = reader.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(reader.next().await.is_none());
+
+        let (reader, mut stream) = new_reader(1, 1);
+        drop(reader);
+        stream.insert(0, chunk(b"a"));
+        assert_eq!(stream.forward(), Err(ReaderGone));
+    }
+
+    #[tokio::test]
+    async fn complete_message() {
+        let reader = MessageReader::from_message(received_message(b"topic", b"data"));
+        assert_eq!(reader.len, 4);
+
+        let data = reader.collect::<Vec<_>>().await;
+        assert_eq!(data.len(), 1);
+        assert_eq!(&data[0].as_ref().unwrap()[..], b"data");
+    }
+}
@@ -0,0 +1,154 @@
+//! Counters which can be used to monitor a running node.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// Counters of packets handled by the data plane and the [`Router`](crate::router::Router).
+#[derive(Default)]
+pub(crate) struct DataPlaneCounters {
+    packets_routed: AtomicU64,
+    no_route: AtomicU64,
+    ttl_exceeded: AtomicU64,
+    decryption_failures: AtomicU64,
+}
+
+/// Amount of packets handled by the data plane since the node started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataPlaneMetrics {
+    /// Packets which were delivered to the local TUN interface or forwarded to a peer.
+    pub packets_routed: u64,
+    /// Packets for which no route was found, and an ICMP no route to host was generated instead.
+    pub no_route: u64,
+    /// Packets which were dropped because their hop limit expired.
+    pub ttl_exceeded: u64,
+    /// Packets which could not be decrypted.
+    pub decryption_failures: u64,
+}
+
+impl DataPlaneCounters {
+    /// Count a packet which was delivered locally or forwarded to a peer.
+    pub fn packet_routed(&self) {
+        self.packets_routed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a packet for which there is no route.
+    pub fn no_route(&self) {
+        self.no_route.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a packet which was dropped because the hop limit expired.
+    pub fn ttl_exceeded(&self) {
+        self.ttl_exceeded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a packet which could not be decrypted.
+    pub fn decryption_failure(&self) {
+        self.decryption_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the current value of all counters.
+    pub fn metrics(&self) -> DataPlaneMetrics {
+        DataPlaneMetrics {
+            packets_routed: self.packets_routed.load(Ordering::Relaxed),
+            no_route: self.no_route.load(Ordering::Relaxed),
+            ttl_exceeded: self.ttl_exceeded.load(Ordering::Relaxed),
+            decryption_failures: self.decryption_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters of messages handled by the message stack.
+#[cfg(feature = "message")]
+#[derive(Default)]
+pub(crate) struct MessageCounters {
+    sent: AtomicU64,
+    received: AtomicU64,
+    delivered: AtomicU64,
+    read: AtomicU64,
+    aborted: AtomicU64,
+    rejected: AtomicU64,
+    inbox_rejected: AtomicU64,
+    inbox_dropped: AtomicU64,
+}
+
+/// Amount of messages handled by the message stack since the node started.
+#[cfg(feature = "message")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageMetrics {
+    /// Messages pushed to be sent, including replies.
+    pub sent: u64,
+    /// Messages which were fully received.
+    pub received: u64,
+    /// Sent messages which the receiver confirmed it fully received.
+    pub delivered: u64,
+    /// Sent messages which the receiver confirmed have been read.
+    pub read: u64,
+    /// Sent messages which were aborted because they weren't received in time.
+    pub aborted: u64,
+    /// Sent messages which the receiver refused because its inbox is full.
+    pub rejected: u64,
+    /// Received messages which were refused because the inbox is full.
+    pub inbox_rejected: u64,
+    /// Unread received messages which were removed to make room for new messages.
+    pub inbox_dropped: u64,
+}
+
+#[cfg(feature = "message")]
+impl MessageCounters {
+    /// Count a message which is pushed to be sent.
+    pub fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a message which was fully received.
+    pub fn received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a sent message which was fully received by the receiver.
+    pub fn delivered(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a sent message which was read by the receiver.
+    pub fn read(&self) {
+        self.read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a sent message which was aborted.
+    pub fn aborted(&self) {
+        self.aborted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a sent message which was refused by the receiver.
+    pub fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a received message which was refused.
+    pub fn inbox_rejected(&self) {
+        self.inbox_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an unread received message which was removed to make room.
+    pub fn inbox_dropped(&self) {
+        self.inbox_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the current value of all counters.
+    pub fn metrics(&self) -> MessageMetrics {
+        MessageMetrics {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            delivered: self.delivered.load(Ordering::Relaxed),
+            read: self.read.load(Ordering::Relaxed),
+            aborted: self.aborted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            inbox_rejected: self.inbox_rejected.load(Ordering::Relaxed),
+            inbox_dropped: self.inbox_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -7,21 +7,24 @@ use std::{
         atomic::{AtomicBool, AtomicU64, Ordering},
         Arc, RwLock, Weak,
     },
+    time::Duration,
 };
 use tokio::{
     select,
     sync::{mpsc, Notify},
 };
-use tokio_util::codec::Framed;
+use tokio_util::{codec::Framed, sync::CancellationToken};
 
 use crate::{
-    connection::{self, Connection},
-    packet::{self, Packet},
-};
-use crate::{
+    babel::IhuTimestamp,
+    metric::Metric,
     packet::{ControlPacket, DataPacket},
     sequence_number::SeqNo,
 };
+use crate::{
+    connection::{self, Connection},
+    packet::{self, Packet},
+};
 
 /// The maximum amount of packets to immediately send if they are ready when the first one is
 /// received.
@@ -39,6 +42,20 @@ const EXISTING_METRIC_FACTOR: u32 = 9;
 /// Divisor for smoothed metric calcuation of the combined metric
 const TOTAL_METRIC_DIVISOR: u32 = 10;
 
+/// Cost factor of a link in a single direction if no packets are lost, as used in the
+/// [ETX](https://datatracker.ietf.org/doc/html/rfc8966#name-link-cost-computation) computation.
+const ETX_NO_LOSS: u16 = 256;
+
+/// Amount of Hellos remembered to compute the reception rate of a link.
+const HELLO_HISTORY_SIZE: u16 = 16;
+
+/// Round trip times (in microseconds) larger than this are considered invalid, and are most
+/// likely caused by a peer echoing a bogus timestamp.
+const MAX_RTT_MICROS: u32 = 60_000_000;
+
+/// Maximum time to spend sending queued control packets when the connection of a peer is closed.
+const CLOSE_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone)]
 /// A peer represents a directly connected participant in the network.
 pub struct Peer {
@@ -79,6 +96,9 @@ impl Peer {
                 static_link_cost: connection.static_link_cost()?,
                 death_notifier,
                 alive: AtomicBool::new(true),
+                timestamps_supported: AtomicBool::new(false),
+                closed: CancellationToken::new(),
+                timestamp_epoch: tokio::time::Instant::now(),
             }),
         };
 
@@ -103,6 +123,7 @@ impl Peer {
                                             }
                                         }
                                         Packet::ControlPacket(packet) => {
+                                            peer.process_received_timestamps(&packet);
                                             if let Err(error) = router_control_tx.send((packet, peer.clone())) {
                                                 error!("Error sending to to_routing_control: {}", error);
                                             }
@@ -145,7 +166,8 @@ impl Peer {
                             }
                         }
 
-                        Some(packet) = from_routing_control.recv() => {
+                        Some(mut packet) = from_routing_control.recv() => {
+                            peer.set_transmit_timestamps(&mut packet);
                             // Send it over the TCP stream
                             if let Err(e) = framed.send(Packet::ControlPacket(packet)).await {
                                 error!("Error writing to stream: {}", e);
@@ -154,6 +176,24 @@ impl Peer {
                         }
 
                         _ = death_watcher.notified() => {
+                            // Send control packets which are already queued, e.g. route
+                            // retractions, before closing the connection.
+                            let flush = async {
+                                while let Ok(mut packet) = from_routing_control.try_recv() {
+                                    peer.set_transmit_timestamps(&mut packet);
+                                    framed.feed(Packet::ControlPacket(packet)).await?;
+                                }
+                                framed.close().await
+                            };
+                            match tokio::time::timeout(CLOSE_FLUSH_TIMEOUT, flush).await {
+                                Ok(Ok(())) => {}
+                                Ok(Err(e)) => {
+                                    debug!("Error closing stream to {}: {e}", peer.connection_identifier());
+                                }
+                                Err(_) => {
+                                    debug!("Timed out closing stream to {}", peer.connection_identifier());
+                                }
+                            }
                             break;
                         }
                     }
@@ -164,9 +204,12 @@ impl Peer {
                 peer.inner.alive.store(false, Ordering::Relaxed);
                 let remote_id = peer.connection_identifier().clone();
                 debug!("Notifying router peer {remote_id} is dead");
+                let closed = peer.inner.closed.clone();
                 if let Err(e) = dead_peer_sink.send(peer).await {
-                    error!("Peer {remote_id} could not notify router of termination: {e}");
+                    // The router only stops listening once it is shut down itself.
+                    debug!("Peer {remote_id} could not notify router of termination: {e}");
                 }
+                closed.cancel();
             });
         }
 
@@ -183,12 +226,56 @@ impl Peer {
         self.inner.state.write().unwrap().hello_seqno += 1;
     }
 
+    /// Time at which we last sent a Hello to this peer.
+    pub fn time_last_sent_hello(&self) -> tokio::time::Instant {
+        self.inner.state.read().unwrap().time_last_sent_hello
+    }
+
+    pub fn set_time_last_sent_hello(&self, time: tokio::time::Instant) {
+        self.inner.state.write().unwrap().time_last_sent_hello = time
+    }
+
+    /// Record that we received a Hello with the given [`SeqNo`] and interval from this peer.
+    pub fn received_hello(&self, seqno: SeqNo, interval: Duration) {
+        let mut state = self.inner.state.write().unwrap();
+        state.hello_history.record(seqno);
+        // An interval of 0 means the peer does not send Hellos periodically.
+        state.hello_interval = if interval.is_zero() {
+            None
+        } else {
+            Some(interval)
+        };
+        state.time_last_received_hello = tokio::time::Instant::now();
+    }
+
+    /// The interval at which this peer sends Hellos, as advertised in the last Hello received
+    /// from it. This is `None` if the peer did not announce an interval (yet).
+    pub fn hello_interval(&self) -> Option<Duration> {
+        self.inner.state.read().unwrap().hello_interval
+    }
+
+    /// Time at which we last received a Hello from this peer.
     pub fn time_last_received_hello(&self) -> tokio::time::Instant {
         self.inner.state.read().unwrap().time_last_received_hello
     }
 
-    pub fn set_time_last_received_hello(&self, time: tokio::time::Instant) {
-        self.inner.state.write().unwrap().time_last_received_hello = time
+    /// The cost of receiving packets from this peer, based on the amount of recently lost Hellos.
+    /// This is the value we announce to the peer in an IHU.
+    ///
+    /// A link without loss has a cost of 256. If we haven't received any recent Hello, the cost
+    /// is infinite.
+    pub fn rx_cost(&self) -> Metric {
+        match self.inner.state.read().unwrap().hello_history.rx_factor() {
+            Some(rx_factor) => Metric::new(rx_factor),
+            None => Metric::infinite(),
+        }
+    }
+
+    /// Set the cost of sending packets to this peer, as announced by the peer in an IHU.
+    pub fn set_tx_cost(&self, tx_cost: Metric) {
+        // Older nodes announce their link cost instead, which can be lower than the cost of a
+        // link without loss.
+        self.inner.state.write().unwrap().tx_cost = u16::from(tx_cost).max(ETX_NO_LOSS);
     }
 
     /// For sending data packets towards a peer instance on this node.
@@ -208,12 +295,28 @@ impl Peer {
     /// Get the cost to use the peer, i.e. the additional impact on the [`crate::metric::Metric`]
     /// for using this `Peer`.
     ///
-    /// This is a smoothed value, which is calculated over the recent history of link cost.
+    /// The base cost of the link is the smoothed round trip time plus the static cost of the
+    /// connection type. It is scaled by the expected amount of transmissions needed to get a
+    /// packet across (ETX), as derived from the loss of Hellos in both directions. Since the base
+    /// cost of a fast local link is close to 0, the ETX cost from
+    /// [appendix A.2.2 of the babel rfc](https://datatracker.ietf.org/doc/html/rfc8966#name-etx)
+    /// is added as well, minus the cost of a link without loss. This way, a link without loss only
+    /// pays its base cost.
     pub fn link_cost(&self) -> u16 {
-        self.inner.state.read().unwrap().link_cost + self.inner.static_link_cost
+        let state = self.inner.state.read().unwrap();
+        // If we haven't received a Hello yet, there is no loss information in this direction.
+        let rx_factor = state.hello_history.rx_factor().unwrap_or(ETX_NO_LOSS) as u64;
+        let etx = rx_factor * state.tx_cost as u64 / ETX_NO_LOSS as u64;
+        let base_cost = state.link_cost as u64 + self.inner.static_link_cost as u64;
+        let cost = base_cost * etx / ETX_NO_LOSS as u64
+            + etx.saturating_sub(ETX_NO_LOSS as u64)
+            + state.extra_link_cost as u64;
+        // Never return an infinite cost, routes through this peer are still valid.
+        cost.min(u16::MAX as u64 - 1) as u16
     }
 
-    /// Sets the link cost based on the provided value.
+    /// Sets the latency part of the link cost based on the provided value, typically a round
+    /// trip time in milliseconds.
     ///
     /// The link cost is not set to the given value, but rather to an average of recent values.
     /// This makes sure short-lived, hard spikes of the link cost of a peer don't influence the
@@ -227,6 +330,90 @@ impl Peer {
             / TOTAL_METRIC_DIVISOR) as u16;
     }
 
+    /// Sets an additional, configured cost for using this link. This is added on top of the
+    /// measured link cost, and can be used to make a link less (or, by resetting it to 0, no
+    /// longer less) preferable.
+    pub fn set_extra_link_cost(&self, extra_link_cost: u16) {
+        self.inner.state.write().unwrap().extra_link_cost = extra_link_cost;
+    }
+
+    /// Current local timestamp in microseconds, used in the timestamp sub-TLV's of packets
+    /// exchanged with this peer. Timestamps wrap around after a little over an hour.
+    fn timestamp(&self) -> u32 {
+        // Truncation is intended, timestamps are compared with wrapping arithmetic.
+        self.inner.timestamp_epoch.elapsed().as_micros() as u32
+    }
+
+    /// Check if the peer sends timestamps in its Hellos. If it does, it also understands the
+    /// timestamps we echo in IHU's.
+    pub fn timestamps_supported(&self) -> bool {
+        self.inner.timestamps_supported.load(Ordering::Relaxed)
+    }
+
+    /// Process the timestamps in a [`ControlPacket`] which was just received from this peer.
+    ///
+    /// For a Hello, the transmit timestamp and time of reception are remembered, so they can be
+    /// echoed in the next IHU. For an IHU, the round trip time is calculated as described in
+    /// [section 3 of the RTT extension](https://datatracker.ietf.org/doc/html/rfc9616#section-3),
+    /// and used to update the link cost.
+    fn process_received_timestamps(&self, packet: &ControlPacket) {
+        match packet {
+            ControlPacket::Hello(hello) => {
+                if let Some(transmit) = hello.timestamp() {
+                    self.inner
+                        .timestamps_supported
+                        .store(true, Ordering::Relaxed);
+                    let mut state = self.inner.state.write().unwrap();
+                    state.hello_timestamp = Some((transmit, self.timestamp()));
+                    state.last_hello_transmit = Some(transmit);
+                }
+            }
+            ControlPacket::Ihu(ihu) => {
+                let Some(timestamp) = ihu.timestamp() else {
+                    return;
+                };
+                // The RFC sends a Hello in the same packet as an IHU with timestamps. We only
+                // send a single TLV per packet, so the peer sends the Hello right before the IHU
+                // instead.
+                let Some(transmit) = self.inner.state.write().unwrap().last_hello_transmit.take()
+                else {
+                    return;
+                };
+                // Time between sending our Hello and receiving the IHU, minus the time the peer
+                // held our Hello before sending the IHU.
+                let elapsed = self.timestamp().wrapping_sub(timestamp.origin);
+                let held = transmit.wrapping_sub(timestamp.receive);
+                if elapsed > MAX_RTT_MICROS || held > elapsed {
+                    debug!(
+                        "Ignoring invalid IHU timestamps from {}",
+                        self.connection_identifier()
+                    );
+                    return;
+                }
+                let rtt_ms = (elapsed - held) / 1000;
+                self.set_link_cost(rtt_ms.min(u16::MAX as u32 - 1) as u16);
+            }
+            _ => {}
+        }
+    }
+
+    /// Set the transmit timestamps in a [`ControlPacket`] which is about to be sent to this peer.
+    ///
+    /// Every Hello carries a timestamp, since every peer which completed the handshake understands
+    /// sub-TLV's. An IHU only echoes timestamps once the peer sent a Hello with a timestamp.
+    fn set_transmit_timestamps(&self, packet: &mut ControlPacket) {
+        match packet {
+            ControlPacket::Hello(hello) => hello.set_timestamp(self.timestamp()),
+            ControlPacket::Ihu(ihu) => {
+                let hello_timestamp = self.inner.state.write().unwrap().hello_timestamp.take();
+                if let Some((origin, receive)) = hello_timestamp {
+                    ihu.set_timestamp(IhuTimestamp { origin, receive });
+                }
+            }
+            _ => {}
+        }
+    }
+
     /// Identifier for the connection to the `Peer`.
     pub fn connection_identifier(&self) -> &String {
         &self.inner.connection_identifier
@@ -250,6 +437,12 @@ impl Peer {
         self.inner.death_notifier.notify_one();
     }
 
+    /// Wait until the connection of this `Peer` is closed. This returns immediately if the
+    /// connection is already closed.
+    pub async fn closed(&self) {
+        self.inner.closed.cancelled().await
+    }
+
     /// Checks if the connection of this `Peer` is still alive.
     ///
     /// For connection types which don't have (real time) state information, this might return a
@@ -315,14 +508,36 @@ struct PeerInner {
     death_notifier: Arc<Notify>,
     /// Keep track if the connection is alive.
     alive: AtomicBool,
+    /// Set once the peer sent a Hello with a timestamp sub-TLV.
+    timestamps_supported: AtomicBool,
+    /// Cancelled once the connection is closed.
+    closed: CancellationToken,
+    /// Reference point for timestamps sent to this peer.
+    timestamp_epoch: tokio::time::Instant,
 }
 
 #[derive(Debug)]
 struct PeerState {
     hello_seqno: SeqNo,
-    time_last_received_hello: tokio::time::Instant,
+    time_last_sent_hello: tokio::time::Instant,
+    /// Smoothed round trip time to the peer.
     link_cost: u16,
+    /// History of Hellos received from the peer.
+    hello_history: HelloHistory,
+    /// Interval at which the peer sends Hellos, as advertised by the peer.
+    hello_interval: Option<Duration>,
+    time_last_received_hello: tokio::time::Instant,
+    /// Cost of sending packets to the peer, as announced by the peer.
+    tx_cost: u16,
+    /// Transmit timestamp of the last Hello received from the peer, and the local time at which
+    /// it was received. This is echoed in the next IHU sent to the peer.
+    hello_timestamp: Option<(u32, u32)>,
+    /// Transmit timestamp of the last Hello received from the peer, which is sent together with
+    /// the next IHU of the peer.
+    last_hello_transmit: Option<u32>,
     time_last_received_ihu: tokio::time::Instant,
+    /// Configured cost added to the link cost of this peer.
+    extra_link_cost: u16,
 }
 
 impl PeerState {
@@ -331,16 +546,246 @@ impl PeerState {
         // Initialize last_sent_hello_seqno to 0
         let hello_seqno = SeqNo::default();
         let link_cost = DEFAULT_LINK_COST;
-        // Initialize time_last_received_hello to now
-        let time_last_received_hello = tokio::time::Instant::now();
+        // Initialize time_last_sent_hello to now
+        let time_last_sent_hello = tokio::time::Instant::now();
         // Initialiwe time_last_send_ihu
         let time_last_received_ihu = tokio::time::Instant::now();
 
         Self {
             hello_seqno,
             link_cost,
+            hello_history: HelloHistory::default(),
+            hello_interval: None,
+            time_last_received_hello: tokio::time::Instant::now(),
+            // Assume there is no loss until the peer tells us otherwise.
+            tx_cost: ETX_NO_LOSS,
+            hello_timestamp: None,
+            last_hello_transmit: None,
             time_last_received_ihu,
-            time_last_received_hello,
+            time_last_sent_hello,
+            extra_link_cost: 0,
         }
     }
 }
+
+/// History of received Hellos, as described in
+/// [appendix A.1 of the babel rfc](https://datatracker.ietf.org/doc/html/rfc8966#name-maintaining-hello-history).
+#[derive(Debug, Default)]
+struct HelloHistory {
+    /// Bitmap of received Hellos, the least significant bit is the most recent Hello.
+    history: u16,
+    /// Amount of valid entries in `history`.
+    len: u16,
+    /// The [`SeqNo`] we expect on the next Hello. This is `None` if we did not receive a Hello
+    /// yet.
+    expected: Option<SeqNo>,
+}
+
+impl HelloHistory {
+    /// Record the reception of a Hello with the given [`SeqNo`].
+    fn record(&mut self, seqno: SeqNo) {
+        if let Some(expected) = self.expected {
+            let ahead = u16::from(seqno).wrapping_sub(expected.into());
+            let behind = u16::from(expected).wrapping_sub(seqno.into());
+            if ahead <= HELLO_HISTORY_SIZE {
+                // Hellos in between were lost.
+                for _ in 0..ahead {
+                    self.push(false);
+                }
+            } else if behind <= HELLO_HISTORY_SIZE {
+                // Peer increased its Hello interval without us noticing, forget the entries
+                // which were never sent.
+                self.history = self.history.checked_shr(behind as u32).unwrap_or(0);
+                self.len = self.len.saturating_sub(behind);
+            } else {
+                // Peer most likely restarted its Hello seqno, start over.
+                *self = Self::default();
+            }
+        }
+
+        self.push(true);
+        self.expected = Some(seqno + 1);
+    }
+
+    /// Add an entry to the history.
+    fn push(&mut self, received: bool) {
+        self.history = (self.history << 1) | received as u16;
+        self.len = (self.len + 1).min(HELLO_HISTORY_SIZE);
+    }
+
+    /// Cost factor of receiving from the peer, computed as the inverse of the fraction of
+    /// received Hellos. This is [`ETX_NO_LOSS`] if all recent Hellos are received, and `None` if
+    /// no Hellos are known or all of them are lost.
+    fn rx_factor(&self) -> Option<u16> {
+        let received = self.history.count_ones() as u16;
+        if received == 0 {
+            return None;
+        }
+        Some(ETX_NO_LOSS * self.len / received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{atomic::AtomicU64, Arc},
+        time::Duration,
+    };
+
+    use tokio::sync::mpsc;
+
+    use super::{HelloHistory, Peer, DEFAULT_LINK_COST, ETX_NO_LOSS};
+    use crate::{
+        babel::{Hello, Ihu, IhuTimestamp},
+        metric::Metric,
+        packet::ControlPacket,
+    };
+
+    /// Create a [`Peer`] on an in memory connection, which is not connected to a router.
+    fn dummy_peer() -> Peer {
+        let (router_data_tx, _) = mpsc::channel(1);
+        let (router_control_tx, _) = mpsc::unbounded_channel();
+        let (dead_peer_sink, _) = mpsc::channel(1);
+        let (con1, _) = tokio::io::duplex(1500);
+        Peer::new(
+            router_data_tx,
+            router_control_tx,
+            con1,
+            dead_peer_sink,
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(AtomicU64::new(0)),
+        )
+        .expect("Can create a dummy peer")
+    }
+
+    #[tokio::test]
+    async fn ihu_timestamps_sent_once_supported() {
+        let peer = dummy_peer();
+
+        let mut hello = ControlPacket::from(Hello::new_unicast(1.into(), 400));
+        peer.set_transmit_timestamps(&mut hello);
+        assert!(matches!(hello, ControlPacket::Hello(ref h) if h.timestamp().is_some()));
+        let mut ihu = ControlPacket::from(Ihu::new(1.into(), 400, None));
+        peer.set_transmit_timestamps(&mut ihu);
+        assert!(matches!(ihu, ControlPacket::Ihu(ref i) if i.timestamp().is_none()));
+        assert!(!peer.timestamps_supported());
+
+        let mut remote_hello = Hello::new_unicast(1.into(), 400);
+        remote_hello.set_timestamp(1000);
+        peer.process_received_timestamps(&ControlPacket::from(remote_hello));
+        assert!(peer.timestamps_supported());
+
+        let mut ihu = ControlPacket::from(Ihu::new(1.into(), 400, None));
+        peer.set_transmit_timestamps(&mut ihu);
+        assert!(matches!(
+            ihu,
+            ControlPacket::Ihu(ref i) if i.timestamp().map(|ts| ts.origin) == Some(1000)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rtt_excludes_hold_time() {
+        let peer = dummy_peer();
+
+        let mut hello = ControlPacket::from(Hello::new_unicast(1.into(), 400));
+        peer.set_transmit_timestamps(&mut hello);
+        let ControlPacket::Hello(hello) = hello else {
+            panic!("Packet is a Hello");
+        };
+        let origin = hello.timestamp().expect("Hello has a timestamp");
+
+        tokio::time::advance(Duration::from_millis(30)).await;
+
+        // The peer held our Hello for 20ms before sending its Hello and IHU, so the round trip
+        // time is 10ms.
+        let mut remote_hello = Hello::new_unicast(1.into(), 400);
+        remote_hello.set_timestamp(1_000_000);
+        peer.process_received_timestamps(&ControlPacket::from(remote_hello));
+        let mut ihu = Ihu::new(1.into(), 400, None);
+        ihu.set_timestamp(IhuTimestamp {
+            origin,
+            receive: 980_000,
+        });
+        peer.process_received_timestamps(&ControlPacket::from(ihu));
+
+        // The new sample is smoothed with the default link cost.
+        assert_eq!(
+            peer.inner.state.read().unwrap().link_cost,
+            (DEFAULT_LINK_COST * 9 + 10) / 10
+        );
+    }
+
+    #[tokio::test]
+    async fn loss_raises_cost_of_zero_rtt_link() {
+        let clean = dummy_peer();
+        let lossy = dummy_peer();
+        for peer in [&clean, &lossy] {
+            peer.inner.state.write().unwrap().link_cost = 0;
+        }
+        // The remote only receives half of our packets.
+        lossy.set_tx_cost(Metric::new(2 * ETX_NO_LOSS));
+
+        assert_eq!(clean.link_cost(), clean.inner.static_link_cost);
+        assert!(lossy.link_cost() >= clean.link_cost() + ETX_NO_LOSS);
+    }
+
+    #[test]
+    fn hello_history_without_loss() {
+        let mut history = HelloHistory::default();
+        assert_eq!(history.rx_factor(), None);
+
+        for seqno in 0..40 {
+            history.record(seqno.into());
+            assert_eq!(history.rx_factor(), Some(ETX_NO_LOSS));
+        }
+    }
+
+    #[test]
+    fn hello_history_with_loss() {
+        let mut history = HelloHistory::default();
+
+        for seqno in (0..32).step_by(2) {
+            history.record(seqno.into());
+        }
+
+        assert_eq!(history.rx_factor(), Some(2 * ETX_NO_LOSS));
+    }
+
+    #[test]
+    fn hello_history_seqno_wraps() {
+        let mut history = HelloHistory::default();
+
+        history.record(u16::MAX.into());
+        history.record(0.into());
+        history.record(2.into());
+
+        assert_eq!(history.rx_factor(), Some(ETX_NO_LOSS * 4 / 3));
+    }
+
+    #[test]
+    fn hello_history_resets_on_seqno_jump() {
+        let mut history = HelloHistory::default();
+
+        history.record(0.into());
+        history.record(10.into());
+        assert_eq!(history.rx_factor(), Some(ETX_NO_LOSS * 11 / 2));
+
+        history.record(1000.into());
+        assert_eq!(history.rx_factor(), Some(ETX_NO_LOSS));
+    }
+
+    #[test]
+    fn hello_history_seqno_behind() {
+        let mut history = HelloHistory::default();
+
+        for seqno in 0..10 {
+            history.record(seqno.into());
+        }
+        history.record(14.into());
+        // The peer increased its Hello interval, so the entries after seqno 11 are forgotten.
+        history.record(12.into());
+
+        assert_eq!(history.len, 13);
+        assert_eq!(history.rx_factor(), Some(ETX_NO_LOSS * 13 / 11));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::connection::Quic;
+use crate::connection::{self, Connection, Quic, WebSocket};
+use crate::crypto::{PublicKey, SecretKey};
 use crate::endpoint::{Endpoint, Protocol};
 use crate::peer::{Peer, PeerRef};
 use crate::router::Router;
@@ -8,17 +9,25 @@ use futures::StreamExt;
 use log::{debug, error, info, trace, warn};
 use network_interface::NetworkInterfaceConfig;
 use quinn::{MtuDiscoveryConfig, ServerConfig, TransportConfig};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
 use tokio::net::TcpStream;
 use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinHandle;
 use tokio::time::MissedTickBehavior;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+mod acl;
+pub use acl::{PeerAcl, PeerAclStats};
 
 /// Magic bytes to identify a multicast UDP packet used in link local peer discovery.
 const MYCELIUM_MULTICAST_DISCOVERY_MAGIC: &[u8; 8] = b"mycelium";
@@ -28,11 +37,30 @@ const PEER_DISCOVERY_BEACON_SIZE: usize = 8 + 2 + 40;
 const LL_PEER_DISCOVERY_GROUP: &str = "ff02::cafe";
 /// The time between sending consecutive link local discovery beacons.
 const LL_PEER_DISCOVERY_BEACON_INTERVAL: Duration = Duration::from_secs(60);
-/// The time between checking known peer liveness and trying to reconnect.
+/// The time between checking known peer liveness and trying to reconnect. The connect backoff of
+/// a peer is only checked at this interval, so a reconnect can be delayed by up to this amount.
 const PEER_CONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// The delay before reconnecting to a peer after the first failed connection attempt. The delay
+/// doubles with every consecutive failure.
+const MIN_CONNECT_BACKOFF: Duration = Duration::from_secs(5);
+/// The maximum delay between connection attempts to a peer.
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(300);
+/// The amount of time a connection must stay up before earlier failed connection attempts are
+/// forgotten. Connections which die sooner count as a failed attempt, so a peer which drops every
+/// connection right after it is set up is not reconnected in a tight loop.
+const STABLE_CONNECTION_DURATION: Duration = Duration::from_secs(60);
 /// The maximum amount of successive failures allowed when connecting to a local discovered peer,
 /// before it is forgotten.
 const MAX_FAILED_LOCAL_PEER_CONNECTION_ATTEMPTS: usize = 3;
+/// The maximum amount of time a remote gets to complete the authentication handshake on a new
+/// connection.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Context used to derive the key of the certificate from the node key.
+const CERTIFICATE_KEY_CONTEXT: &str = "mycelium certificate key v1";
+/// DER encoding of a PKCS#8 v1 Ed25519 private key, up to the 32 byte seed of the key.
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
 
 /// The PeerManager creates new peers by connecting to configured addresses, and setting up the
 /// connection. Once a connection is established, the created [`Peer`] is handed over to the
@@ -42,6 +70,33 @@ pub struct PeerManager {
     inner: Arc<Inner>,
 }
 
+/// Config for a [`PeerManager`].
+pub struct PeerManagerConfig {
+    /// Statically configured peers.
+    pub peers: Vec<Endpoint>,
+    /// Listen port for TCP connections.
+    pub tcp_listen_port: u16,
+    /// Listen port for Quic connections.
+    pub quic_listen_port: u16,
+    /// Listen port for TLS connections. If this is not set, no TLS listener is started.
+    pub tls_listen_port: Option<u16>,
+    /// Listen port for WebSocket connections. If this is not set, no WebSocket listener is
+    /// started.
+    pub ws_listen_port: Option<u16>,
+    /// Listen port for WebSocket connections secured with TLS. If this is not set, no secure
+    /// WebSocket listener is started.
+    pub wss_listen_port: Option<u16>,
+    /// HTTP proxy, as `host:port`, used to tunnel outbound WebSocket connections.
+    pub ws_proxy: Option<String>,
+    /// Path of a Unix domain socket to accept connections on. If this is not set, no Unix domain
+    /// socket listener is started.
+    pub unix_listen_path: Option<PathBuf>,
+    /// Udp port for link local peer discovery. If this is not set, peer discovery is disabled.
+    pub peer_discovery_port: Option<u16>,
+    /// Access control rules for inbound and discovered peers.
+    pub peer_acl: PeerAcl,
+}
+
 /// Details how the PeerManager learned about a remote.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -62,11 +117,35 @@ struct PeerInfo {
     connecting: bool,
     /// The [`PeerRef`] used to check liveliness.
     pr: PeerRef,
-    /// Amount of failed times we tried to connect to this peer. This is reset after a successful
-    /// connection.
+    /// Amount of failed times we tried to connect to this peer. This is reset after a connection
+    /// stayed up for [`STABLE_CONNECTION_DURATION`].
     connection_attempts: usize,
+    /// Details about the current and past connections to this peer.
+    health: ConnectionHealth,
     /// Keep track of the amount of bytes we've sent to and received from this peer.
     con_traffic: ConnectionTraffic,
+    /// The public key of the remote, as verified during the handshake of the last successful
+    /// connection.
+    remote_key: Option<PublicKey>,
+    /// Configured cost added to the link cost of connections to this peer.
+    cost: Option<u16>,
+    /// Amount of times a connection to this peer was rejected by the [`PeerAcl`].
+    acl_rejections: u64,
+}
+
+/// Details about the current and past connections to a peer.
+#[derive(Debug, Default)]
+struct ConnectionHealth {
+    /// The time before which we don't try to connect to the peer again.
+    retry_at: Option<tokio::time::Instant>,
+    /// The current delay between connection attempts.
+    backoff: Duration,
+    /// Description of the last error while connecting to the peer.
+    last_error: Option<String>,
+    /// Time of the last successful connection.
+    last_connected: Option<SystemTime>,
+    /// Time at which the current connection was set up.
+    connected_since: Option<tokio::time::Instant>,
 }
 
 /// Counters for the amount of traffic written to and received from a [`Peer`].
@@ -79,7 +158,7 @@ struct ConnectionTraffic {
 }
 
 /// General state about a connection to a [`Peer`].
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum ConnectionState {
     /// There is a working connection to the [`Peer`].
@@ -105,6 +184,22 @@ pub struct PeerStats {
     pub tx_bytes: u64,
     /// Amount of bytes received from this [`Peer`].
     pub rx_bytes: u64,
+    /// The [`PublicKey`] of the [`Peer`], if we ever completed a handshake with it. The remote
+    /// proved that it owns the associated secret key during this handshake.
+    pub remote_key: Option<PublicKey>,
+    /// The configured cost which is added to the link cost of this [`Peer`], if any.
+    pub cost: Option<u16>,
+    /// Description of the last error while connecting to this [`Peer`], if there was one.
+    pub last_error: Option<String>,
+    /// Time of the last successful connection to this [`Peer`], in seconds since the UNIX epoch.
+    pub last_connected: Option<u64>,
+    /// Amount of seconds the current connection to this [`Peer`] has been up, if it is alive.
+    pub uptime: Option<u64>,
+    /// Amount of seconds between the last failed connection attempt to this [`Peer`] and the
+    /// next one.
+    pub backoff: u64,
+    /// Amount of times a connection to this [`Peer`] was rejected by the [`PeerAcl`].
+    pub acl_rejections: u64,
 }
 
 impl PeerInfo {
@@ -117,10 +212,58 @@ impl PeerInfo {
     /// Return the amount of bytes written to this peer.
     #[inline]
     fn written(&self) -> u64 {
-        self.con_traffic.rx_bytes.load(Ordering::Relaxed)
+        self.con_traffic.tx_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Record a new connection to this peer.
+    fn connected(&mut self) {
+        self.health.retry_at = None;
+        self.health.backoff = Duration::ZERO;
+        self.health.last_connected = Some(SystemTime::now());
+        self.health.connected_since = Some(tokio::time::Instant::now());
+    }
+
+    /// Record a failed connection attempt, and back off before trying to connect again.
+    fn connection_failed(&mut self, error: String) {
+        self.connection_attempts += 1;
+        self.health.backoff = connect_backoff(self.connection_attempts);
+        self.health.retry_at = Some(tokio::time::Instant::now() + self.health.backoff);
+        self.health.last_error = Some(error);
+    }
+
+    /// Record that the connection to this peer died. If it was not up for long, this counts as a
+    /// failed connection attempt.
+    fn disconnected(&mut self) {
+        if let Some(since) = self.health.connected_since.take() {
+            let uptime = since.elapsed();
+            if uptime < STABLE_CONNECTION_DURATION {
+                self.connection_failed(format!("connection lost after {}s", uptime.as_secs()));
+            } else {
+                self.connection_attempts = 0;
+            }
+        }
+    }
+
+    /// Check if we can try to connect to this peer, i.e. we aren't backing off.
+    fn can_connect(&self) -> bool {
+        self.health
+            .retry_at
+            .map(|retry_at| retry_at <= tokio::time::Instant::now())
+            .unwrap_or(true)
     }
 }
 
+/// Get the delay before the next connection attempt, after the given amount of consecutive
+/// failed attempts. The delay grows exponentially up to [`MAX_CONNECT_BACKOFF`], and is randomized
+/// so peers which failed at the same time aren't all retried at the same time.
+fn connect_backoff(attempts: usize) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(16) as u32;
+    let backoff = MIN_CONNECT_BACKOFF
+        .saturating_mul(1 << exponent)
+        .min(MAX_CONNECT_BACKOFF);
+    backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
 /// Marker error to indicate a [`peer`](Endpoint) is already known.
 #[derive(Debug)]
 pub struct PeerExists;
@@ -136,24 +279,52 @@ struct Inner {
     /// Listen port for new peer connections
     tcp_listen_port: u16,
     quic_socket: quinn::Endpoint,
+    /// Acceptor for inbound TLS connections.
+    tls_acceptor: TlsAcceptor,
+    /// Certificate presented to remotes on inbound Quic and TLS connections. It is used as
+    /// channel binding in the handshake of these connections.
+    certificate: rustls::Certificate,
+    /// HTTP proxy used to tunnel outbound WebSocket connections.
+    ws_proxy: Option<String>,
+    /// Keys of the node, used to authenticate ourselves to remotes.
+    node_keypair: (SecretKey, PublicKey),
+    /// Access control rules for inbound and discovered peers.
+    acl: RwLock<PeerAcl>,
+    /// Amount of remotes rejected by the [`PeerAcl`] because of their address.
+    acl_rejected_by_address: AtomicU64,
+    /// Amount of remotes rejected by the [`PeerAcl`] because of their key.
+    acl_rejected_by_key: AtomicU64,
+    /// Counter to create unique endpoints for inbound connections which don't have a remote
+    /// address, i.e. Unix domain sockets and in memory connections.
+    local_connections: AtomicU64,
+    /// Listeners, and the tasks managing outbound and discovered peers.
+    tasks: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl PeerManager {
     pub fn new(
         router: Router,
-        static_peers_sockets: Vec<Endpoint>,
-        tcp_listen_port: u16,
-        quic_listen_port: u16,
-        peer_discovery_port: u16,
-        disable_peer_discovery: bool,
+        node_keypair: (SecretKey, PublicKey),
+        config: PeerManagerConfig,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let quic_socket = make_quic_endpoint(router.router_id(), quic_listen_port)?;
+        let (certificate_chain, private_key) =
+            make_certificate(&node_keypair.0, router.router_id())?;
+        let tls_acceptor = TlsAcceptor::from(Arc::new(
+            rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(certificate_chain.clone(), private_key.clone())?,
+        ));
+        let certificate = certificate_chain[0].clone();
+        let quic_socket =
+            make_quic_endpoint(certificate_chain, private_key, config.quic_listen_port)?;
 
         let peer_manager = PeerManager {
             inner: Arc::new(Inner {
                 router: Mutex::new(router),
                 peers: Mutex::new(
-                    static_peers_sockets
+                    config
+                        .peers
                         .into_iter()
                         // These peers are not alive, but we say they are because the reconnect
                         // loop will perform the actual check and figure out they are dead, then
@@ -166,41 +337,102 @@ impl PeerManager {
                                     connecting: false,
                                     pr: PeerRef::new(),
                                     connection_attempts: 0,
+                                    health: ConnectionHealth::default(),
                                     con_traffic: ConnectionTraffic {
                                         tx_bytes: Arc::new(AtomicU64::new(0)),
                                         rx_bytes: Arc::new(AtomicU64::new(0)),
                                     },
+                                    remote_key: None,
+                                    cost: None,
+                                    acl_rejections: 0,
                                 },
                             )
                         })
                         .collect(),
                 ),
-                tcp_listen_port,
+                tcp_listen_port: config.tcp_listen_port,
                 quic_socket,
+                tls_acceptor,
+                certificate,
+                ws_proxy: config.ws_proxy,
+                node_keypair,
+                acl: RwLock::new(config.peer_acl),
+                acl_rejected_by_address: AtomicU64::new(0),
+                acl_rejected_by_key: AtomicU64::new(0),
+                local_connections: AtomicU64::new(0),
+                tasks: Mutex::new(Vec::new()),
             }),
         };
 
+        let mut tasks = Vec::new();
         // Start listeners for inbound connections.
-        tokio::spawn(peer_manager.inner.clone().tcp_listener());
-        tokio::spawn(peer_manager.inner.clone().quic_listener());
+        tasks.push(tokio::spawn(peer_manager.inner.clone().tcp_listener()));
+        tasks.push(tokio::spawn(peer_manager.inner.clone().quic_listener()));
+        if let Some(tls_listen_port) = config.tls_listen_port {
+            tasks.push(tokio::spawn(
+                peer_manager.inner.clone().tls_listener(tls_listen_port),
+            ));
+        }
+        if let Some(ws_listen_port) = config.ws_listen_port {
+            tasks.push(tokio::spawn(
+                peer_manager
+                    .inner
+                    .clone()
+                    .ws_listener(ws_listen_port, false),
+            ));
+        }
+        if let Some(wss_listen_port) = config.wss_listen_port {
+            tasks.push(tokio::spawn(
+                peer_manager
+                    .inner
+                    .clone()
+                    .ws_listener(wss_listen_port, true),
+            ));
+        }
+        if let Some(unix_listen_path) = config.unix_listen_path {
+            #[cfg(target_family = "unix")]
+            tasks.push(tokio::spawn(
+                peer_manager.inner.clone().unix_listener(unix_listen_path),
+            ));
+            #[cfg(not(target_family = "unix"))]
+            warn!(
+                "Unix domain sockets are not supported on this platform, not listening on {}",
+                unix_listen_path.display()
+            );
+        }
 
         // Start (re)connecting to outbound/local peers
-        tokio::spawn(peer_manager.inner.clone().connect_to_peers());
+        tasks.push(tokio::spawn(peer_manager.inner.clone().connect_to_peers()));
 
         // Discover local peers, this does not actually connect to them. That is handle by the
         // connect_to_peers task.
-        if !disable_peer_discovery {
-            tokio::spawn(
+        if let Some(peer_discovery_port) = config.peer_discovery_port {
+            tasks.push(tokio::spawn(
                 peer_manager
                     .inner
                     .clone()
                     .local_discovery(peer_discovery_port),
-            );
+            ));
         }
 
+        *peer_manager.inner.tasks.lock().unwrap() = tasks;
+
         Ok(peer_manager)
     }
 
+    /// Stop accepting inbound connections, and stop connecting to configured and discovered
+    /// peers. Existing connections are not closed, this is done by shutting down the [`Router`].
+    pub async fn shutdown(&self) {
+        let tasks = std::mem::take(&mut *self.inner.tasks.lock().unwrap());
+        for task in &tasks {
+            task.abort();
+        }
+        for task in tasks {
+            // The only possible error is the cancellation we just requested.
+            let _ = task.await;
+        }
+    }
+
     /// Add a new peer to the system.
     ///
     /// The peer starts of as a dead peer, and connecting is handled in the reconnect loop.
@@ -220,10 +452,14 @@ impl PeerManager {
                 connecting: false,
                 pr: PeerRef::new(),
                 connection_attempts: 0,
+                health: ConnectionHealth::default(),
                 con_traffic: ConnectionTraffic {
                     tx_bytes: Arc::new(AtomicU64::new(0)),
                     rx_bytes: Arc::new(AtomicU64::new(0)),
                 },
+                remote_key: None,
+                cost: None,
+                acl_rejections: 0,
             },
         );
 
@@ -247,6 +483,27 @@ impl PeerManager {
         })
     }
 
+    /// Set the cost which is added to the link cost of a peer, or remove it by passing [`None`].
+    /// The cost is kept when the peer reconnects, and applied immediately if the peer is
+    /// currently connected.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no peer identified by the given [`Endpoint`].
+    pub fn set_peer_cost(
+        &self,
+        endpoint: &Endpoint,
+        cost: Option<u16>,
+    ) -> Result<(), PeerNotFound> {
+        let mut peer_map = self.inner.peers.lock().unwrap();
+        let pi = peer_map.get_mut(endpoint).ok_or(PeerNotFound)?;
+        pi.cost = cost;
+        if let Some(peer) = pi.pr.upgrade() {
+            peer.set_extra_link_cost(cost.unwrap_or(0));
+        }
+        Ok(())
+    }
+
     /// Get a view of all known peers and their stats.
     pub fn peers(&self) -> Vec<PeerStats> {
         let peer_map = self.inner.peers.lock().unwrap();
@@ -260,15 +517,84 @@ impl PeerManager {
                 ConnectionState::Dead
             };
             pi.push(PeerStats {
-                endpoint: *endpoint,
+                endpoint: endpoint.clone(),
                 pt: peer_info.pt.clone(),
                 connection_state,
                 tx_bytes: peer_info.written(),
                 rx_bytes: peer_info.read(),
+                remote_key: peer_info.remote_key,
+                cost: peer_info.cost,
+                last_error: peer_info.health.last_error.clone(),
+                last_connected: peer_info.health.last_connected.map(|time| {
+                    time.duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs()
+                }),
+                uptime: peer_info
+                    .health
+                    .connected_since
+                    .filter(|_| peer_info.pr.alive())
+                    .map(|since| since.elapsed().as_secs()),
+                backoff: peer_info.health.backoff.as_secs(),
+                acl_rejections: peer_info.acl_rejections,
             });
         }
         pi
     }
+
+    /// Add a new peer over an in memory connection, e.g. one side of a [`tokio::io::duplex`]. The
+    /// other side of the connection must be added to a different node.
+    ///
+    /// The peer is added as an inbound peer, so it is removed once the connection dies.
+    pub fn add_memory_peer(&self, con: DuplexStream) {
+        let id = self.inner.local_connections.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(self.inner.clone().accept_inbound(
+            con,
+            Endpoint::memory(format!("{id}")),
+            false,
+        ));
+    }
+
+    /// Get the current [`PeerAcl`].
+    pub fn acl(&self) -> PeerAcl {
+        self.inner.acl.read().unwrap().clone()
+    }
+
+    /// Replace the current [`PeerAcl`].
+    ///
+    /// Existing inbound and discovered peers which are no longer allowed by the new rules are
+    /// disconnected and removed.
+    pub fn set_acl(&self, acl: PeerAcl) {
+        let mut peer_map = self.inner.peers.lock().unwrap();
+        peer_map.retain(|endpoint, pi| {
+            if pi.pt == PeerType::Static {
+                return true;
+            }
+            // Local connections don't have an address to check.
+            let allowed = (matches!(endpoint.proto(), Protocol::Unix | Protocol::Memory)
+                || acl.allows_address(endpoint.address().ip()))
+                && pi
+                    .remote_key
+                    .map(|key| acl.allows_key(&key))
+                    .unwrap_or(true);
+            if !allowed {
+                info!("Removing peer {endpoint} as it is no longer allowed by the peer ACL");
+                if let Some(peer) = pi.pr.upgrade() {
+                    peer.died();
+                }
+            }
+            allowed
+        });
+        *self.inner.acl.write().unwrap() = acl;
+    }
+
+    /// Get the amount of remotes rejected by the [`PeerAcl`].
+    pub fn acl_stats(&self) -> PeerAclStats {
+        PeerAclStats {
+            rejected_by_address: self.inner.acl_rejected_by_address.load(Ordering::Relaxed),
+            rejected_by_key: self.inner.acl_rejected_by_key.load(Ordering::Relaxed),
+        }
+    }
 }
 
 impl Inner {
@@ -293,19 +619,29 @@ impl Inner {
                     if let Some(pi) = peers.get_mut(&endpoint) {
                         // Regardless of what happened, we are no longer connecting.
                         pi.connecting = false;
-                        if let Some(peer) = maybe_new_peer {
-                            // We did find a new Peer, insert into router and keep track of it
-                            // Use fully qualified call to aid compiler in type inference.
-                            pi.pr = Peer::refer(&peer);
-                            self.router.lock().unwrap().add_peer_interface(peer);
-                        } else {
-                            // Connection failed, add a failed attempt and forget about the peer if
-                            // needed.
-                            pi.connection_attempts += 1;
-                            if pi.pt == PeerType::LinkLocalDiscovery
-                                && pi.connection_attempts >= MAX_FAILED_LOCAL_PEER_CONNECTION_ATTEMPTS {
-                                info!("Forgetting about locally discovered peer {endpoint} after failing to connect to it");
-                                peers.remove(&endpoint);
+                        match maybe_new_peer {
+                            Ok((peer, remote_key)) => {
+                                info!("Connected to new peer {endpoint}");
+                                // We did find a new Peer, insert into router and keep track of it
+                                // Use fully qualified call to aid compiler in type inference.
+                                pi.pr = Peer::refer(&peer);
+                                pi.remote_key = Some(remote_key);
+                                pi.connected();
+                                if let Some(cost) = pi.cost {
+                                    peer.set_extra_link_cost(cost);
+                                }
+                                self.router.lock().unwrap().add_peer_interface(peer);
+                            }
+                            Err(e) => {
+                                // Connection failed, add a failed attempt and forget about the
+                                // peer if needed.
+                                error!("Couldn't connect to {endpoint}: {e}");
+                                pi.connection_failed(e);
+                                if pi.pt == PeerType::LinkLocalDiscovery
+                                    && pi.connection_attempts >= MAX_FAILED_LOCAL_PEER_CONNECTION_ATTEMPTS {
+                                    info!("Forgetting about locally discovered peer {endpoint} after failing to connect to it");
+                                    peers.remove(&endpoint);
+                                }
                             }
                         }
                     }
@@ -313,18 +649,22 @@ impl Inner {
                 _ = peer_check_interval.tick() => {
                     // Remove dead inbound peers
                     self.peers.lock().unwrap().retain(|_, v| v.pt != PeerType::Inbound || v.pr.alive());
-                    debug!("Looking for dead peers");
+                    trace!("Looking for dead peers");
                     // check if there is an entry for the peer in the router's peer list
                     for (endpoint, pi) in self.peers.lock().unwrap().iter_mut() {
                         if !pi.connecting && !pi.pr.alive() {
-                            debug!("Found dead peer {endpoint}");
                             if pi.pt == PeerType::Inbound {
-                                debug!("Refusing to reconnect to inbound peer");
+                                debug!("Refusing to reconnect to inbound peer {endpoint}");
+                                continue
+                            }
+                            pi.disconnected();
+                            if !pi.can_connect() {
                                 continue
                             }
+                            debug!("Found dead peer {endpoint}");
                             // Mark that we are connecting to the peer.
                             pi.connecting = true;
-                            connection_futures.push(self.clone().connect_peer(*endpoint, pi.con_traffic.clone()));
+                            connection_futures.push(self.clone().connect_peer(endpoint.clone(), pi.pt.clone(), pi.con_traffic.clone()));
                         }
                     }
                 }
@@ -336,72 +676,80 @@ impl Inner {
     async fn connect_peer(
         self: Arc<Self>,
         endpoint: Endpoint,
+        pt: PeerType,
         ct: ConnectionTraffic,
-    ) -> (Endpoint, Option<Peer>) {
+    ) -> (Endpoint, Result<(Peer, PublicKey), String>) {
         debug!("Connecting to {endpoint}");
         match endpoint.proto() {
-            Protocol::Tcp => self.connect_tcp_peer(endpoint, ct).await,
-            Protocol::Quic => self.connect_quic_peer(endpoint, ct).await,
+            Protocol::Tcp => self.connect_tcp_peer(endpoint, pt, ct).await,
+            Protocol::Quic => self.connect_quic_peer(endpoint, pt, ct).await,
+            Protocol::Tls => self.connect_tls_peer(endpoint, pt, ct).await,
+            Protocol::Ws | Protocol::Wss => self.connect_ws_peer(endpoint, pt, ct).await,
+            #[cfg(target_family = "unix")]
+            Protocol::Unix => self.connect_unix_peer(endpoint, pt, ct).await,
+            #[cfg(not(target_family = "unix"))]
+            Protocol::Unix => (
+                endpoint,
+                Err("Unix domain sockets are not supported on this platform".to_string()),
+            ),
+            Protocol::Memory => (
+                endpoint,
+                Err("in memory connections can only be added directly".to_string()),
+            ),
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    async fn connect_unix_peer(
+        self: Arc<Self>,
+        endpoint: Endpoint,
+        pt: PeerType,
+        ct: ConnectionTraffic,
+    ) -> (Endpoint, Result<(Peer, PublicKey), String>) {
+        match tokio::net::UnixStream::connect(endpoint.path().unwrap_or_default()).await {
+            Ok(stream) => {
+                debug!("Opened connection to {endpoint}");
+                let res = self.new_peer(stream, &endpoint, pt, ct, None).await;
+                (endpoint, res)
+            }
+            Err(e) => (endpoint, Err(e.to_string())),
         }
     }
 
     async fn connect_tcp_peer(
         self: Arc<Self>,
         endpoint: Endpoint,
+        pt: PeerType,
         ct: ConnectionTraffic,
-    ) -> (Endpoint, Option<Peer>) {
+    ) -> (Endpoint, Result<(Peer, PublicKey), String>) {
         match TcpStream::connect(endpoint.address()).await {
             Ok(peer_stream) => {
                 debug!("Opened connection to {endpoint}");
                 // Make sure Nagle's algorithm is disabeld as it can cause latency spikes.
                 if let Err(e) = peer_stream.set_nodelay(true) {
-                    error!("Couldn't disable Nagle's algorithm on stream {e}");
-                    return (endpoint, None);
+                    return (
+                        endpoint,
+                        Err(format!("couldn't disable Nagle's algorithm on stream: {e}")),
+                    );
                 }
 
-                // Scope the MutexGuard, if we don't do this the future won't be Send
-                let res = {
-                    let router = self.router.lock().unwrap();
-                    let router_data_tx = router.router_data_tx();
-                    let router_control_tx = router.router_control_tx();
-                    let dead_peer_sink = router.dead_peer_sink().clone();
-
-                    Peer::new(
-                        router_data_tx,
-                        router_control_tx,
-                        peer_stream,
-                        dead_peer_sink,
-                        ct.tx_bytes,
-                        ct.rx_bytes,
-                    )
-                };
-                match res {
-                    Ok(new_peer) => {
-                        info!("Connected to new peer {}", endpoint);
-                        (endpoint, Some(new_peer))
-                    }
-                    Err(e) => {
-                        error!("Failed to spawn peer {endpoint}: {e}");
-                        (endpoint, None)
-                    }
-                }
-            }
-            Err(e) => {
-                error!("Couldn't connect to {endpoint}: {e}");
-                (endpoint, None)
+                let res = self.new_peer(peer_stream, &endpoint, pt, ct, None).await;
+                (endpoint, res)
             }
+            Err(e) => (endpoint, Err(e.to_string())),
         }
     }
 
     async fn connect_quic_peer(
         self: Arc<Self>,
         endpoint: Endpoint,
+        pt: PeerType,
         ct: ConnectionTraffic,
-    ) -> (Endpoint, Option<Peer>) {
+    ) -> (Endpoint, Result<(Peer, PublicKey), String>) {
         let mut config = quinn::ClientConfig::new(Arc::new(
             rustls::ClientConfig::builder()
                 .with_safe_defaults()
-                .with_custom_certificate_verifier(SkipServerVerification::new())
+                .with_custom_certificate_verifier(PinnedByHandshake::new())
                 .with_no_client_auth(),
         ));
         // Todo: tweak transport config
@@ -426,83 +774,293 @@ impl Inner {
             Ok(connecting) => match connecting.await {
                 Ok(con) => match con.open_bi().await {
                     Ok((tx, rx)) => {
-                        let q_con = Quic::new(tx, rx, endpoint.address());
-                        let res = {
-                            let router = self.router.lock().unwrap();
-                            let router_data_tx = router.router_data_tx();
-                            let router_control_tx = router.router_control_tx();
-                            let dead_peer_sink = router.dead_peer_sink().clone();
-
-                            Peer::new(
-                                router_data_tx,
-                                router_control_tx,
-                                q_con,
-                                dead_peer_sink,
-                                ct.tx_bytes,
-                                ct.rx_bytes,
-                            )
+                        let Some(certificate) = con
+                            .peer_identity()
+                            .and_then(|identity| {
+                                identity.downcast::<Vec<rustls::Certificate>>().ok()
+                            })
+                            .and_then(|chain| chain.first().cloned())
+                        else {
+                            return (
+                                endpoint,
+                                Err("remote did not present a certificate".to_string()),
+                            );
                         };
-                        match res {
-                            Ok(new_peer) => {
-                                info!("Connected to new peer {}", endpoint);
-                                (endpoint, Some(new_peer))
-                            }
-                            Err(e) => {
-                                error!("Failed to spawn peer {endpoint}: {e}");
-                                (endpoint, None)
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("Couldn't open bidirectional quic stream to {endpoint}: {e}");
-                        (endpoint, None)
+                        let q_con = Quic::new(tx, rx, endpoint.address());
+                        let res = self
+                            .new_peer(q_con, &endpoint, pt, ct, Some(&certificate))
+                            .await;
+                        (endpoint, res)
                     }
+                    Err(e) => (
+                        endpoint,
+                        Err(format!("couldn't open bidirectional quic stream: {e}")),
+                    ),
                 },
-                Err(e) => {
-                    error!("Couldn't complete quic connection to {endpoint}: {e}");
-                    (endpoint, None)
-                }
+                Err(e) => (
+                    endpoint,
+                    Err(format!("couldn't complete quic connection: {e}")),
+                ),
             },
-            Err(e) => {
-                error!("Couldn't initiate connection to {endpoint}: {e}");
-                (endpoint, None)
+            Err(e) => (
+                endpoint,
+                Err(format!("couldn't initiate quic connection: {e}")),
+            ),
+        }
+    }
+
+    async fn connect_tls_peer(
+        self: Arc<Self>,
+        endpoint: Endpoint,
+        pt: PeerType,
+        ct: ConnectionTraffic,
+    ) -> (Endpoint, Result<(Peer, PublicKey), String>) {
+        let connector = TlsConnector::from(Arc::new(
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(PinnedByHandshake::new())
+                .with_no_client_auth(),
+        ));
+        let server_name = rustls::ServerName::try_from("dummy.mycelium")
+            .expect("Dummy server name is a valid DNS name; qed");
+
+        let stream = match TcpStream::connect(endpoint.address()).await {
+            Ok(stream) => stream,
+            Err(e) => return (endpoint, Err(e.to_string())),
+        };
+        // Make sure Nagle's algorithm is disabeld as it can cause latency spikes.
+        if let Err(e) = stream.set_nodelay(true) {
+            return (
+                endpoint,
+                Err(format!("couldn't disable Nagle's algorithm on stream: {e}")),
+            );
+        }
+
+        match tokio::time::timeout(HANDSHAKE_TIMEOUT, connector.connect(server_name, stream)).await
+        {
+            Ok(Ok(tls_stream)) => {
+                debug!("Opened TLS connection to {endpoint}");
+                let Some(certificate) = tls_stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|chain| chain.first().cloned())
+                else {
+                    return (
+                        endpoint,
+                        Err("remote did not present a certificate".to_string()),
+                    );
+                };
+                let res = self
+                    .new_peer(
+                        tokio_rustls::TlsStream::from(tls_stream),
+                        &endpoint,
+                        pt,
+                        ct,
+                        Some(&certificate),
+                    )
+                    .await;
+                (endpoint, res)
             }
+            Ok(Err(e)) => (
+                endpoint,
+                Err(format!("couldn't complete TLS connection: {e}")),
+            ),
+            Err(_) => (endpoint, Err("TLS connection timed out".to_string())),
         }
     }
 
-    async fn tcp_listener(self: Arc<Self>) {
-        // Take a copy of every channel here first so we avoid lock contention in the loop later.
-        let router_data_tx = self.router.lock().unwrap().router_data_tx();
-        let router_control_tx = self.router.lock().unwrap().router_control_tx();
-        let dead_peer_sink = self.router.lock().unwrap().dead_peer_sink().clone();
+    async fn connect_ws_peer(
+        self: Arc<Self>,
+        endpoint: Endpoint,
+        pt: PeerType,
+        ct: ConnectionTraffic,
+    ) -> (Endpoint, Result<(Peer, PublicKey), String>) {
+        let secure = endpoint.proto() == Protocol::Wss;
+        // If the endpoint uses a host name, it is sent in the request, and used as server name of
+        // the TLS connection, so proxies and load balancers serving multiple hosts can forward
+        // the connection.
+        let url = format!(
+            "{}://{}{}",
+            if secure { "wss" } else { "ws" },
+            endpoint.authority(),
+            endpoint.path().unwrap_or("/")
+        );
+        let connector = if secure {
+            // The certificate of the remote is not verified, since the remote proves its identity
+            // in the handshake afterwards. Unlike with Quic and TLS, the certificate is not bound
+            // to the handshake, since the remote might be behind a proxy which terminates TLS.
+            tokio_tungstenite::Connector::Rustls(Arc::new(
+                rustls::ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_custom_certificate_verifier(PinnedByHandshake::new())
+                    .with_no_client_auth(),
+            ))
+        } else {
+            tokio_tungstenite::Connector::Plain
+        };
+
+        // Host names are resolved now, so a changed address is picked up when reconnecting.
+        let connect = match self.ws_proxy {
+            Some(ref proxy) => TcpStream::connect(proxy.as_str()).await,
+            None => TcpStream::connect(endpoint.authority()).await,
+        };
+        let mut stream = match connect {
+            Ok(stream) => stream,
+            Err(e) => return (endpoint, Err(e.to_string())),
+        };
+        // Make sure Nagle's algorithm is disabeld as it can cause latency spikes.
+        if let Err(e) = stream.set_nodelay(true) {
+            return (
+                endpoint,
+                Err(format!("couldn't disable Nagle's algorithm on stream: {e}")),
+            );
+        }
+        // The address of the remote, or of the proxy if one is used.
+        let remote = match stream.peer_addr() {
+            Ok(remote) => remote,
+            Err(e) => return (endpoint, Err(e.to_string())),
+        };
+
+        let proxied = self.ws_proxy.is_some();
+        let authority = endpoint.authority();
+        let handshake = async move {
+            if proxied {
+                connection::http_connect(&mut stream, &authority)
+                    .await
+                    .map_err(tokio_tungstenite::tungstenite::Error::Io)?;
+            }
+            tokio_tungstenite::client_async_tls_with_config(url, stream, None, Some(connector))
+                .await
+        };
+        match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+            Ok(Ok((ws, _))) => {
+                debug!("Opened websocket connection to {endpoint}");
+                let con = WebSocket::new(ws, remote, secure);
+                let res = self.new_peer(con, &endpoint, pt, ct, None).await;
+                (endpoint, res)
+            }
+            Ok(Err(e)) => (
+                endpoint,
+                Err(format!("couldn't complete websocket connection: {e}")),
+            ),
+            Err(_) => (endpoint, Err("websocket connection timed out".to_string())),
+        }
+    }
+
+    /// Authenticate the remote on a newly established connection, and create a [`Peer`] for it
+    /// if the remote proves it owns the key it claims. Unless the remote is a static peer, the key
+    /// must also be allowed by the [`PeerAcl`].
+    ///
+    /// If the connection is secured with TLS, `certificate` is the certificate presented by the
+    /// server, which is then bound to the handshake.
+    ///
+    /// The returned [`PublicKey`] is the verified key of the remote. If the peer can't be created,
+    /// the reason is returned instead.
+    async fn new_peer<C: Connection + Unpin + Send + 'static>(
+        &self,
+        con: C,
+        endpoint: &Endpoint,
+        pt: PeerType,
+        ct: ConnectionTraffic,
+        certificate: Option<&rustls::Certificate>,
+    ) -> Result<(Peer, PublicKey), String> {
+        let channel_binding = certificate.map(|c| c.0.as_slice()).unwrap_or_default();
+        let (con, remote_key) = match tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            connection::handshake(
+                con,
+                &self.node_keypair.0,
+                self.node_keypair.1,
+                channel_binding,
+            ),
+        )
+        .await
+        {
+            Ok(Ok(res)) => res,
+            Ok(Err(e)) => return Err(format!("handshake failed: {e}")),
+            Err(_) => return Err("handshake timed out".to_string()),
+        };
+        debug!("Authenticated {endpoint} as {remote_key}");
+
+        if pt != PeerType::Static && !self.acl.read().unwrap().allows_key(&remote_key) {
+            self.acl_rejected_by_key.fetch_add(1, Ordering::Relaxed);
+            self.record_acl_rejection(endpoint);
+            return Err(format!("key {remote_key} is not allowed by the peer ACL"));
+        }
+
+        // Scope the MutexGuard, if we don't do this the future won't be Send
+        let res = {
+            let router = self.router.lock().unwrap();
+            let router_data_tx = router.router_data_tx();
+            let router_control_tx = router.router_control_tx();
+            let dead_peer_sink = router.dead_peer_sink().clone();
+
+            Peer::new(
+                router_data_tx,
+                router_control_tx,
+                con,
+                dead_peer_sink,
+                ct.tx_bytes,
+                ct.rx_bytes,
+            )
+        };
+        match res {
+            Ok(new_peer) => Ok((new_peer, remote_key)),
+            Err(e) => Err(format!("failed to spawn peer: {e}")),
+        }
+    }
 
+    /// Count a rejection by the [`PeerAcl`] for the peer with the given [`Endpoint`], if it is
+    /// known.
+    fn record_acl_rejection(&self, endpoint: &Endpoint) {
+        if let Some(pi) = self.peers.lock().unwrap().get_mut(endpoint) {
+            pi.acl_rejections += 1;
+        }
+    }
+
+    /// Authenticate an inbound connection and add it as a new [`Peer`]. If `tls` is set, the
+    /// connection is secured with TLS using our own certificate.
+    async fn accept_inbound<C: Connection + Unpin + Send + 'static>(
+        self: Arc<Self>,
+        con: C,
+        endpoint: Endpoint,
+        tls: bool,
+    ) {
+        let ct = ConnectionTraffic {
+            tx_bytes: Arc::new(AtomicU64::new(0)),
+            rx_bytes: Arc::new(AtomicU64::new(0)),
+        };
+        let certificate = tls.then_some(&self.certificate);
+        match self
+            .new_peer(con, &endpoint, PeerType::Inbound, ct.clone(), certificate)
+            .await
+        {
+            Ok(new_peer) => {
+                info!("Accepted new inbound peer {endpoint}");
+                self.add_peer(endpoint, PeerType::Inbound, ct, Some(new_peer));
+            }
+            Err(e) => info!("Rejected inbound peer {endpoint}: {e}"),
+        }
+    }
+
+    async fn tcp_listener(self: Arc<Self>) {
         match TcpListener::bind(("::", self.tcp_listen_port)).await {
             Ok(listener) => loop {
                 match listener.accept().await {
                     Ok((stream, remote)) => {
-                        let tx_bytes = Arc::new(AtomicU64::new(0));
-                        let rx_bytes = Arc::new(AtomicU64::new(0));
-                        let new_peer = match Peer::new(
-                            router_data_tx.clone(),
-                            router_control_tx.clone(),
+                        if !self.acl.read().unwrap().allows_address(remote.ip()) {
+                            self.acl_rejected_by_address.fetch_add(1, Ordering::Relaxed);
+                            debug!("Rejecting inbound connection from {remote} by peer ACL");
+                            continue;
+                        }
+                        // Authenticate the remote in a separate task, so a slow remote can't
+                        // block new connections.
+                        tokio::spawn(self.clone().accept_inbound(
                             stream,
-                            dead_peer_sink.clone(),
-                            tx_bytes.clone(),
-                            rx_bytes.clone(),
-                        ) {
-                            Ok(peer) => peer,
-                            Err(e) => {
-                                error!("Failed to spawn peer: {e}");
-                                continue;
-                            }
-                        };
-                        info!("Accepted new inbound peer {}", remote);
-                        self.add_peer(
                             Endpoint::new(Protocol::Tcp, remote),
-                            PeerType::Inbound,
-                            ConnectionTraffic { tx_bytes, rx_bytes },
-                            Some(new_peer),
-                        );
+                            false,
+                        ));
                     }
                     Err(e) => {
                         error!("Error accepting connection: {}", e);
@@ -516,11 +1074,6 @@ impl Inner {
     }
 
     async fn quic_listener(self: Arc<Self>) {
-        // Take a copy of every channel here first so we avoid lock contention in the loop later.
-        let router_data_tx = self.router.lock().unwrap().router_data_tx();
-        let router_control_tx = self.router.lock().unwrap().router_control_tx();
-        let dead_peer_sink = self.router.lock().unwrap().dead_peer_sink().clone();
-
         loop {
             let con = if let Some(con) = self.quic_socket.accept().await {
                 match con.await {
@@ -536,38 +1089,223 @@ impl Inner {
                 return;
             };
 
-            let q = match con.accept_bi().await {
-                Ok((tx, rx)) => Quic::new(tx, rx, con.remote_address()),
-                Err(e) => {
-                    error!("Failed to accept bidirectional quic stream: {e}");
-                    continue;
+            if !self
+                .acl
+                .read()
+                .unwrap()
+                .allows_address(con.remote_address().ip())
+            {
+                self.acl_rejected_by_address.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    "Rejecting inbound quic connection from {} by peer ACL",
+                    con.remote_address()
+                );
+                con.close(0_u8.into(), b"not allowed");
+                continue;
+            }
+
+            // Accept the stream and authenticate the remote in a separate task, so a slow remote
+            // can't block new connections.
+            tokio::spawn(self.clone().accept_quic_inbound(con));
+        }
+    }
+
+    /// Accept the bidirectional stream of an inbound Quic connection, then authenticate it and add
+    /// it as a new [`Peer`].
+    async fn accept_quic_inbound(self: Arc<Self>, con: quinn::Connection) {
+        let remote = con.remote_address();
+        let q = match tokio::time::timeout(HANDSHAKE_TIMEOUT, con.accept_bi()).await {
+            Ok(Ok((tx, rx))) => Quic::new(tx, rx, remote),
+            Ok(Err(e)) => {
+                debug!("Failed to accept bidirectional quic stream from {remote}: {e}");
+                return;
+            }
+            Err(_) => {
+                debug!("Remote {remote} did not open a quic stream in time");
+                con.close(0_u8.into(), b"timed out");
+                return;
+            }
+        };
+
+        self.accept_inbound(q, Endpoint::new(Protocol::Quic, remote), true)
+            .await
+    }
+
+    async fn tls_listener(self: Arc<Self>, tls_listen_port: u16) {
+        match TcpListener::bind(("::", tls_listen_port)).await {
+            Ok(listener) => loop {
+                match listener.accept().await {
+                    Ok((stream, remote)) => {
+                        if !self.acl.read().unwrap().allows_address(remote.ip()) {
+                            self.acl_rejected_by_address.fetch_add(1, Ordering::Relaxed);
+                            debug!("Rejecting inbound TLS connection from {remote} by peer ACL");
+                            continue;
+                        }
+                        // Complete the TLS handshake and authenticate the remote in a separate
+                        // task, so a slow remote can't block new connections.
+                        tokio::spawn(self.clone().accept_tls_inbound(stream, remote));
+                    }
+                    Err(e) => {
+                        error!("Error accepting TLS connection: {}", e);
+                    }
+                }
+            },
+            Err(e) => {
+                error!("Error starting TLS listener: {}", e);
+            }
+        }
+    }
+
+    /// Perform the TLS handshake of an inbound connection, then authenticate it and add it as a
+    /// new [`Peer`].
+    async fn accept_tls_inbound(self: Arc<Self>, stream: TcpStream, remote: SocketAddr) {
+        let tls_stream =
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, self.tls_acceptor.accept(stream)).await {
+                Ok(Ok(tls_stream)) => tls_stream,
+                Ok(Err(e)) => {
+                    debug!("Failed to accept TLS connection from {remote}: {e}");
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake of connection from {remote} timed out");
+                    return;
                 }
             };
 
-            let tx_bytes = Arc::new(AtomicU64::new(0));
-            let rx_bytes = Arc::new(AtomicU64::new(0));
-            let new_peer = match Peer::new(
-                router_data_tx.clone(),
-                router_control_tx.clone(),
-                q,
-                dead_peer_sink.clone(),
-                tx_bytes.clone(),
-                rx_bytes.clone(),
-            ) {
-                Ok(peer) => peer,
-                Err(e) => {
-                    error!("Failed to spawn peer: {e}");
-                    continue;
+        self.accept_inbound(
+            tokio_rustls::TlsStream::from(tls_stream),
+            Endpoint::new(Protocol::Tls, remote),
+            true,
+        )
+        .await
+    }
+
+    /// Accept connections on a Unix domain socket at the given path. If a stale socket exists at
+    /// this path, e.g. because a previous instance did not shut down cleanly, it is removed. If
+    /// another process is still listening on the socket, it is left alone and no connections are
+    /// accepted.
+    #[cfg(target_family = "unix")]
+    async fn unix_listener(self: Arc<Self>, path: PathBuf) {
+        if !remove_stale_socket(&path).await {
+            error!(
+                "Socket {} is in use by another process, not listening on it",
+                path.display()
+            );
+            return;
+        }
+
+        match tokio::net::UnixListener::bind(&path) {
+            Ok(listener) => loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        // Unix domain sockets are local, so the peer ACL address rules don't
+                        // apply. The connection does not have a remote address, so create a
+                        // unique endpoint for it.
+                        let id = self.local_connections.fetch_add(1, Ordering::Relaxed);
+                        let endpoint = Endpoint::unix(format!("{}#{id}", path.display()));
+                        // Authenticate the remote in a separate task, so a slow remote can't
+                        // block new connections.
+                        tokio::spawn(self.clone().accept_inbound(stream, endpoint, false));
+                    }
+                    Err(e) => {
+                        error!("Error accepting unix connection: {}", e);
+                    }
+                }
+            },
+            Err(e) => {
+                error!("Error starting unix listener on {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Accept WebSocket connections. If `secure` is set, connections are secured with TLS first.
+    /// Otherwise, this is intended to run behind a proxy or load balancer which terminates TLS.
+    async fn ws_listener(self: Arc<Self>, ws_listen_port: u16, secure: bool) {
+        match TcpListener::bind(("::", ws_listen_port)).await {
+            Ok(listener) => {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, remote)) => {
+                            if !self.acl.read().unwrap().allows_address(remote.ip()) {
+                                self.acl_rejected_by_address.fetch_add(1, Ordering::Relaxed);
+                                debug!("Rejecting inbound websocket connection from {remote} by peer ACL");
+                                continue;
+                            }
+                            // Upgrade and authenticate the remote in a separate task, so a slow
+                            // remote can't block new connections.
+                            tokio::spawn(self.clone().accept_ws_inbound(stream, remote, secure));
+                        }
+                        Err(e) => {
+                            error!("Error accepting websocket connection: {}", e);
+                        }
+                    }
                 }
-            };
-            info!("Accepted new inbound quic peer {}", con.remote_address());
-            self.add_peer(
-                Endpoint::new(Protocol::Quic, con.remote_address()),
-                PeerType::Inbound,
-                ConnectionTraffic { tx_bytes, rx_bytes },
-                Some(new_peer),
-            )
+            }
+            Err(e) => {
+                error!("Error starting websocket listener: {}", e);
+            }
+        }
+    }
+
+    /// Perform the TLS handshake of an inbound connection if `secure` is set, then upgrade it to
+    /// a WebSocket.
+    async fn accept_ws_inbound(
+        self: Arc<Self>,
+        stream: TcpStream,
+        remote: SocketAddr,
+        secure: bool,
+    ) {
+        if !secure {
+            return self.upgrade_ws_inbound(stream, remote, Protocol::Ws).await;
         }
+
+        let tls_stream =
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, self.tls_acceptor.accept(stream)).await {
+                Ok(Ok(tls_stream)) => tls_stream,
+                Ok(Err(e)) => {
+                    debug!("Failed to accept TLS connection from {remote}: {e}");
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake of connection from {remote} timed out");
+                    return;
+                }
+            };
+        self.upgrade_ws_inbound(
+            tokio_rustls::TlsStream::from(tls_stream),
+            remote,
+            Protocol::Wss,
+        )
+        .await
+    }
+
+    /// Perform the WebSocket upgrade of an inbound connection, then authenticate it and add it
+    /// as a new [`Peer`].
+    async fn upgrade_ws_inbound<S>(self: Arc<Self>, stream: S, remote: SocketAddr, proto: Protocol)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let ws =
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, tokio_tungstenite::accept_async(stream))
+                .await
+            {
+                Ok(Ok(ws)) => ws,
+                Ok(Err(e)) => {
+                    debug!("Failed to accept websocket connection from {remote}: {e}");
+                    return;
+                }
+                Err(_) => {
+                    debug!("Websocket upgrade of connection from {remote} timed out");
+                    return;
+                }
+            };
+
+        self.accept_inbound(
+            WebSocket::new(ws, remote, proto == Protocol::Wss),
+            Endpoint::new(proto, remote),
+            false,
+        )
+        .await
     }
 
     /// Add a new peer identifier we discovered.
@@ -576,47 +1314,63 @@ impl Inner {
         endpoint: Endpoint,
         discovery_type: PeerType,
         con_traffic: ConnectionTraffic,
-        peer: Option<Peer>,
+        peer: Option<(Peer, PublicKey)>,
     ) {
         let mut peers = self.peers.lock().unwrap();
         // Only if we don't know it yet.
-        if let Entry::Vacant(e) = peers.entry(endpoint) {
-            e.insert(PeerInfo {
+        if let Entry::Vacant(e) = peers.entry(endpoint.clone()) {
+            let pi = e.insert(PeerInfo {
                 pt: discovery_type,
                 connecting: false,
-                pr: if let Some(p) = &peer {
+                pr: if let Some((p, _)) = &peer {
                     p.refer()
                 } else {
                     PeerRef::new()
                 },
                 connection_attempts: 0,
+                health: ConnectionHealth::default(),
                 con_traffic,
+                remote_key: peer.as_ref().map(|(_, remote_key)| *remote_key),
+                cost: None,
+                acl_rejections: 0,
             });
-            if let Some(p) = peer {
+            if let Some((p, _)) = peer {
+                pi.connected();
                 self.router.lock().unwrap().add_peer_interface(p);
             }
             info!("Added new peer {endpoint}");
         } else if discovery_type == PeerType::Inbound {
             // We got an inbound peer with a duplicate entry. This is possible if the sending port
             // is the same as the previous one, which generally happens with our Quic setup. In
-            // this case, the old connection needs to be replaced.
+            // this case, the old connection needs to be replaced. A configured cost is kept.
+            let cost = peers.get(&endpoint).and_then(|pi| pi.cost);
+            if let (Some((p, _)), Some(cost)) = (&peer, cost) {
+                p.set_extra_link_cost(cost);
+            }
             let old_peer_info = peers.insert(
-                endpoint,
+                endpoint.clone(),
                 PeerInfo {
                     pt: discovery_type,
                     connecting: false,
-                    pr: if let Some(p) = &peer {
+                    pr: if let Some((p, _)) = &peer {
                         p.refer()
                     } else {
                         PeerRef::new()
                     },
                     connection_attempts: 0,
+                    health: ConnectionHealth::default(),
                     con_traffic,
+                    remote_key: peer.as_ref().map(|(_, remote_key)| *remote_key),
+                    cost,
+                    acl_rejections: 0,
                 },
             );
             // If we have a new peer notify insert the new one in the router, then notify it that
             // the old one is dead.
-            if let Some(p) = peer {
+            if let Some((p, _)) = peer {
+                if let Some(pi) = peers.get_mut(&endpoint) {
+                    pi.connected();
+                }
                 let router = self.router.lock().unwrap();
                 router.add_peer_interface(p);
                 if let Some(old_peer) = old_peer_info
@@ -792,8 +1546,26 @@ impl Inner {
         // Override the port. Care must be taken since link local IPv6 expects the
         // scope_id to be set.
         remote.set_port(port);
+        let endpoint = Endpoint::new(Protocol::Tcp, remote);
+        {
+            let acl = self.acl.read().unwrap();
+            if !acl.allows_address(remote.ip()) {
+                self.acl_rejected_by_address.fetch_add(1, Ordering::Relaxed);
+                self.record_acl_rejection(&endpoint);
+                trace!("Ignoring discovery beacon from {remote} by peer ACL");
+                return;
+            }
+            // The key in the beacon is not verified yet, but if it is denied there is no point
+            // in connecting. If the remote lies about its key, the handshake will fail.
+            if !acl.allows_key(&remote_rid.to_pubkey()) {
+                self.acl_rejected_by_key.fetch_add(1, Ordering::Relaxed);
+                self.record_acl_rejection(&endpoint);
+                trace!("Ignoring discovery beacon from {remote} with key denied by peer ACL");
+                return;
+            }
+        }
         self.add_peer(
-            Endpoint::new(Protocol::Tcp, remote),
+            endpoint,
             PeerType::LinkLocalDiscovery,
             ConnectionTraffic {
                 tx_bytes: Arc::new(AtomicU64::new(0)),
@@ -804,20 +1576,63 @@ impl Inner {
     }
 }
 
+/// Remove the Unix domain socket at `path` if it is stale, i.e. nothing accepts connections on it
+/// anymore. Returns false if another process is still listening on the socket.
+#[cfg(target_family = "unix")]
+async fn remove_stale_socket(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {}
+        // Binding fails and reports the problem if something else exists at the path.
+        _ => return true,
+    }
+    match tokio::net::UnixStream::connect(path).await {
+        Ok(_) => false,
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            if let Err(e) = std::fs::remove_file(path) {
+                error!("Could not remove stale socket {}: {e}", path.display());
+            }
+            true
+        }
+        Err(_) => true,
+    }
+}
+
+/// Generate the self signed certificate used by the Quic, TLS and secure WebSocket listeners.
+///
+/// The Ed25519 key of the certificate is derived from the secret key of the node, so a node
+/// always presents a certificate with the same key. Remotes don't verify the certificate on its
+/// own. Instead, for Quic and TLS connections it is bound to the handshake which is performed once
+/// the connection is set up, in which the node proves its identity.
+fn make_certificate(
+    node_secret_key: &SecretKey,
+    router_id: RouterId,
+) -> Result<(Vec<rustls::Certificate>, rustls::PrivateKey), Box<dyn std::error::Error>> {
+    let seed = blake3::derive_key(CERTIFICATE_KEY_CONTEXT, node_secret_key.as_bytes());
+    let mut key_der = ED25519_PKCS8_PREFIX.to_vec();
+    key_der.extend_from_slice(&seed);
+
+    let mut params = rcgen::CertificateParams::new(vec![format!("{router_id}")]);
+    params.alg = &rcgen::PKCS_ED25519;
+    params.key_pair = Some(rcgen::KeyPair::from_der(&key_der)?);
+    let cert = rcgen::Certificate::from_params(params)?;
+    let certificate_der = cert.serialize_der()?;
+    let private_key_der = cert.serialize_private_key_der();
+
+    Ok((
+        vec![rustls::Certificate(certificate_der)],
+        rustls::PrivateKey(private_key_der),
+    ))
+}
+
 /// Spawn a quic socket which can be used to both receive quic connections and initiate new quic
 /// connections to remotes.
 fn make_quic_endpoint(
-    router_id: RouterId,
+    certificate_chain: Vec<rustls::Certificate>,
+    private_key: rustls::PrivateKey,
     quic_listen_port: u16,
 ) -> Result<quinn::Endpoint, Box<dyn std::error::Error>> {
-    // Generate self signed certificate certificate.
-    // TODO: sign with router keys
-    let cert = rcgen::generate_simple_self_signed(vec![format!("{router_id}")])?;
-    let certificate_der = cert.serialize_der()?;
-    let private_key_der = cert.serialize_private_key_der();
-    let private_key = rustls::PrivateKey(private_key_der);
-    let certificate_chain = vec![rustls::Certificate(certificate_der)];
-
     let mut server_config = ServerConfig::with_single_cert(certificate_chain, private_key)?;
     // We can unwrap this since it's the only current instance.
     let transport_config = Arc::get_mut(&mut server_config.transport).unwrap();
@@ -850,16 +1665,20 @@ fn make_quic_endpoint(
     Ok(endpoint)
 }
 
-/// Dummy certificate verifier that treats any certificate as valid.
-struct SkipServerVerification;
+/// Certificate verifier which accepts any certificate when the TLS connection is set up.
+///
+/// The certificate is pinned afterwards: it is used as channel binding in the handshake, so the
+/// handshake only succeeds if the remote which proves its identity presented this certificate
+/// itself.
+struct PinnedByHandshake;
 
-impl SkipServerVerification {
+impl PinnedByHandshake {
     fn new() -> Arc<Self> {
         Arc::new(Self)
     }
 }
 
-impl rustls::client::ServerCertVerifier for SkipServerVerification {
+impl rustls::client::ServerCertVerifier for PinnedByHandshake {
     fn verify_server_cert(
         &self,
         _end_entity: &rustls::Certificate,
@@ -905,3 +1724,213 @@ impl fmt::Display for PeerNotFound {
 }
 
 impl std::error::Error for PeerNotFound {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::{
+        connect_backoff, make_certificate, ConnectionHealth, ConnectionTraffic, PeerInfo, PeerType,
+        PinnedByHandshake, MAX_CONNECT_BACKOFF, MIN_CONNECT_BACKOFF, STABLE_CONNECTION_DURATION,
+    };
+    use crate::connection;
+    use crate::crypto::{PublicKey, SecretKey};
+    use crate::peer::PeerRef;
+    use crate::router_id::RouterId;
+
+    fn peer_info() -> PeerInfo {
+        PeerInfo {
+            pt: PeerType::Static,
+            connecting: false,
+            pr: PeerRef::new(),
+            connection_attempts: 0,
+            health: ConnectionHealth::default(),
+            con_traffic: ConnectionTraffic {
+                tx_bytes: Arc::new(AtomicU64::new(0)),
+                rx_bytes: Arc::new(AtomicU64::new(0)),
+            },
+            remote_key: None,
+            cost: None,
+            acl_rejections: 0,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_up_to_cap() {
+        for attempts in 1..100 {
+            let backoff = connect_backoff(attempts);
+            assert!(backoff >= MIN_CONNECT_BACKOFF / 2);
+            assert!(backoff <= MAX_CONNECT_BACKOFF);
+        }
+        assert!(connect_backoff(1) <= MIN_CONNECT_BACKOFF);
+        assert!(connect_backoff(3) >= MIN_CONNECT_BACKOFF * 2);
+        assert!(connect_backoff(100) >= MAX_CONNECT_BACKOFF / 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_connections_back_off() {
+        let mut pi = peer_info();
+        assert!(pi.can_connect());
+
+        pi.connection_failed("connection refused".to_string());
+        assert_eq!(pi.connection_attempts, 1);
+        assert!(!pi.can_connect());
+        assert_eq!(pi.health.last_error.as_deref(), Some("connection refused"));
+        tokio::time::sleep(pi.health.backoff).await;
+        assert!(pi.can_connect());
+
+        pi.connected();
+        assert!(pi.can_connect());
+        assert_eq!(pi.health.backoff, Duration::ZERO);
+        assert!(pi.health.last_connected.is_some());
+
+        // A connection which dies quickly counts as a failure.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        pi.disconnected();
+        assert_eq!(pi.connection_attempts, 2);
+        assert!(!pi.can_connect());
+
+        // A stable connection resets the failures.
+        pi.connected();
+        tokio::time::sleep(STABLE_CONNECTION_DURATION).await;
+        pi.disconnected();
+        assert_eq!(pi.connection_attempts, 0);
+        assert!(pi.can_connect());
+    }
+
+    #[test]
+    fn certificate_key_is_derived_from_node_key() {
+        let sk = SecretKey::new();
+        let router_id = RouterId::new(PublicKey::from(&sk));
+
+        let (_, key1) = make_certificate(&sk, router_id).expect("Can create certificate");
+        let (_, key2) = make_certificate(&sk, router_id).expect("Can create certificate");
+        assert_eq!(key1, key2);
+
+        let (_, other_key) =
+            make_certificate(&SecretKey::new(), router_id).expect("Can create certificate");
+        assert_ne!(key1, other_key);
+    }
+
+    #[tokio::test]
+    async fn tls_roundtrip() {
+        let sk = SecretKey::new();
+        let (certificate_chain, private_key) =
+            make_certificate(&sk, RouterId::new(PublicKey::from(&sk)))
+                .expect("Can create certificate");
+        let acceptor = TlsAcceptor::from(Arc::new(
+            rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(certificate_chain, private_key)
+                .expect("Valid certificate"),
+        ));
+        let connector = TlsConnector::from(Arc::new(
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(PinnedByHandshake::new())
+                .with_no_client_auth(),
+        ));
+        let server_name =
+            rustls::ServerName::try_from("dummy.mycelium").expect("Valid server name");
+
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await.expect("Can accept TLS");
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.expect("Can read data");
+            stream.write_all(&buf).await.expect("Can write data");
+            stream.flush().await.expect("Can flush data");
+        });
+
+        let mut stream = connector
+            .connect(server_name, client)
+            .await
+            .expect("Can connect TLS");
+        stream.write_all(b"hello").await.expect("Can write data");
+        stream.flush().await.expect("Can flush data");
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.expect("Can read data");
+        assert_eq!(&buf, b"hello");
+        server.await.expect("Server finishes");
+    }
+
+    #[tokio::test]
+    async fn tls_certificate_binds_handshake() {
+        let server_sk = SecretKey::new();
+        let server_pk = PublicKey::from(&server_sk);
+        let client_sk = SecretKey::new();
+        let client_pk = PublicKey::from(&client_sk);
+        let (certificate_chain, private_key) =
+            make_certificate(&server_sk, RouterId::new(server_pk)).expect("Can create certificate");
+        let certificate = certificate_chain[0].clone();
+        let acceptor = TlsAcceptor::from(Arc::new(
+            rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(certificate_chain, private_key)
+                .expect("Valid certificate"),
+        ));
+        let connector = TlsConnector::from(Arc::new(
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(PinnedByHandshake::new())
+                .with_no_client_auth(),
+        ));
+        let server_name =
+            rustls::ServerName::try_from("dummy.mycelium").expect("Valid server name");
+
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let stream = acceptor.accept(server).await.expect("Can accept TLS");
+            // The server binds the handshake to its own certificate.
+            connection::handshake(stream, &server_sk, server_pk, &certificate.0).await
+        });
+
+        let stream = connector
+            .connect(server_name, client)
+            .await
+            .expect("Can connect TLS");
+        let peer_certificate = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|chain| chain.first().cloned())
+            .expect("Server presents a certificate");
+        let (_, remote) = connection::handshake(stream, &client_sk, client_pk, &peer_certificate.0)
+            .await
+            .expect("Handshake succeeds");
+        assert_eq!(remote, server_pk);
+        server
+            .await
+            .expect("Server finishes")
+            .expect("Handshake succeeds");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn only_stale_sockets_are_removed() {
+        let path = std::env::temp_dir().join(format!(
+            "mycelium-socket-test-{}.sock",
+            rand::random::<u64>()
+        ));
+
+        // Nothing at the path.
+        assert!(super::remove_stale_socket(&path).await);
+
+        // A socket which is still in use is kept.
+        let listener = tokio::net::UnixListener::bind(&path).expect("Can bind socket");
+        assert!(!super::remove_stale_socket(&path).await);
+        assert!(path.exists());
+
+        // Once the listener is gone, the socket is stale and removed.
+        drop(listener);
+        assert!(super::remove_stale_socket(&path).await);
+        assert!(!path.exists());
+    }
+}
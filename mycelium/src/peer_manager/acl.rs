@@ -0,0 +1,127 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{crypto::PublicKey, subnet::Subnet};
+
+/// Access control rules for remotes which connect to us, or which are found through link local
+/// discovery. Statically configured peers are never subjected to these rules.
+///
+/// A remote is rejected if its underlay address is part of a denied subnet, or if its public key
+/// is denied. If any allowed subnets are set, the remote must have an address in one of them.
+/// Similarly, if any allowed keys are set, the remote must use one of these keys. Deny rules take
+/// precedence over allow rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PeerAcl {
+    /// Underlay subnets remotes are allowed to connect from. If this is empty, all subnets are
+    /// allowed.
+    pub allowed_subnets: Vec<Subnet>,
+    /// Underlay subnets remotes are not allowed to connect from.
+    pub denied_subnets: Vec<Subnet>,
+    /// Keys remotes are allowed to use. If this is empty, all keys are allowed.
+    pub allowed_keys: Vec<PublicKey>,
+    /// Keys remotes are not allowed to use.
+    pub denied_keys: Vec<PublicKey>,
+}
+
+/// Amount of remotes rejected by the [`PeerAcl`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerAclStats {
+    /// Amount of remotes rejected because of their underlay address.
+    pub rejected_by_address: u64,
+    /// Amount of remotes rejected because of their public key.
+    pub rejected_by_key: u64,
+}
+
+impl PeerAcl {
+    /// Checks if a remote with the given underlay [`IpAddr`] is allowed.
+    pub fn allows_address(&self, ip: IpAddr) -> bool {
+        // Inbound IPv4 connections on a dual stack socket show up as IPv4 mapped IPv6 addresses.
+        let ip = match ip {
+            IpAddr::V6(ip6) => ip6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+
+        if self.denied_subnets.iter().any(|s| s.contains_ip(ip)) {
+            return false;
+        }
+
+        self.allowed_subnets.is_empty() || self.allowed_subnets.iter().any(|s| s.contains_ip(ip))
+    }
+
+    /// Checks if a remote using the given [`PublicKey`] is allowed.
+    pub fn allows_key(&self, key: &PublicKey) -> bool {
+        if self.denied_keys.contains(key) {
+            return false;
+        }
+
+        self.allowed_keys.is_empty() || self.allowed_keys.contains(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::PeerAcl;
+    use crate::crypto::{PublicKey, SecretKey};
+
+    #[test]
+    fn default_allows_everything() {
+        let acl = PeerAcl::default();
+
+        assert!(acl.allows_address(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+        assert!(acl.allows_address(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert!(acl.allows_key(&PublicKey::from(&SecretKey::new())));
+    }
+
+    #[test]
+    fn deny_takes_precedence() {
+        let acl = PeerAcl {
+            allowed_subnets: vec!["10.0.0.0/8".parse().unwrap()],
+            denied_subnets: vec!["10.1.0.0/16".parse().unwrap()],
+            ..Default::default()
+        };
+
+        assert!(acl.allows_address(IpAddr::V4(Ipv4Addr::new(10, 2, 0, 1))));
+        assert!(!acl.allows_address(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1))));
+        assert!(!acl.allows_address(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_match_ipv4_subnets() {
+        let acl = PeerAcl {
+            denied_subnets: vec!["192.0.2.0/24".parse().unwrap()],
+            ..Default::default()
+        };
+
+        assert!(!acl.allows_address(IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped())));
+        assert!(acl.allows_address(IpAddr::V6(Ipv4Addr::new(198, 51, 100, 1).to_ipv6_mapped())));
+    }
+
+    #[test]
+    fn key_rules() {
+        let allowed = PublicKey::from(&SecretKey::new());
+        let denied = PublicKey::from(&SecretKey::new());
+        let unknown = PublicKey::from(&SecretKey::new());
+
+        let acl = PeerAcl {
+            denied_keys: vec![denied],
+            ..Default::default()
+        };
+        assert!(acl.allows_key(&allowed));
+        assert!(!acl.allows_key(&denied));
+        assert!(acl.allows_key(&unknown));
+
+        let acl = PeerAcl {
+            allowed_keys: vec![allowed, denied],
+            denied_keys: vec![denied],
+            ..Default::default()
+        };
+        assert!(acl.allows_key(&allowed));
+        assert!(!acl.allows_key(&denied));
+        assert!(!acl.allows_key(&unknown));
+    }
+}
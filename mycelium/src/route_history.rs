@@ -0,0 +1,176 @@
+//! A bounded, in memory history of changes to the selected route of subnets.
+//!
+//! Every time the selected route of a subnet changes, the old and new route are recorded
+//! together with the reason for the change. This is mainly useful to debug flapping routes.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{metric::Metric, sequence_number::SeqNo, subnet::Subnet};
+
+/// Amount of changes kept for a single subnet. Once this is exceeded, the oldest change is
+/// removed.
+const MAX_CHANGES_PER_SUBNET: usize = 32;
+/// Amount of subnets for which changes are kept. Once this is exceeded, the history of the
+/// subnet which did not change for the longest time is removed.
+const MAX_SUBNETS: usize = 1024;
+
+/// The reason the selected route of a subnet changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RouteChangeReason {
+    /// An update was received which made a different route better, or announced the first
+    /// route for the subnet.
+    Update,
+    /// A retraction was received for the selected route.
+    Retraction,
+    /// An unfeasible update was received for the selected route.
+    Unfeasible,
+    /// The neighbour of the selected route died.
+    PeerDied,
+    /// The selected route expired.
+    Expired,
+}
+
+/// A single change of the selected route of a subnet.
+#[derive(Debug, Clone)]
+pub struct RouteChange {
+    /// Time at which the change happened.
+    pub time: SystemTime,
+    /// The subnet for which the selected route changed.
+    pub subnet: Subnet,
+    /// Identifier of the neighbour of the previously selected route, if there was one.
+    pub old_neighbour: Option<String>,
+    /// Metric of the previously selected route, including the link cost to the neighbour.
+    pub old_metric: Option<Metric>,
+    /// Identifier of the neighbour of the newly selected route, if there is one.
+    pub new_neighbour: Option<String>,
+    /// Metric of the newly selected route, including the link cost to the neighbour.
+    pub new_metric: Option<Metric>,
+    /// Seqno of the newly selected route, or of the previously selected route if there is no new
+    /// route.
+    pub seqno: SeqNo,
+    /// Why the selected route changed.
+    pub reason: RouteChangeReason,
+}
+
+/// History of [`RouteChange`]s for all subnets.
+#[derive(Default)]
+pub(crate) struct RouteHistory {
+    changes: HashMap<Subnet, VecDeque<RouteChange>>,
+}
+
+impl RouteHistory {
+    /// Create a new, empty `RouteHistory`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a new [`RouteChange`].
+    pub fn record(&mut self, change: RouteChange) {
+        if !self.changes.contains_key(&change.subnet) && self.changes.len() >= MAX_SUBNETS {
+            let oldest = self
+                .changes
+                .iter()
+                .min_by_key(|(_, changes)| changes.back().map(|c| c.time))
+                .map(|(subnet, _)| *subnet);
+            if let Some(oldest) = oldest {
+                self.changes.remove(&oldest);
+            }
+        }
+
+        let changes = self.changes.entry(change.subnet).or_default();
+        if changes.len() >= MAX_CHANGES_PER_SUBNET {
+            changes.pop_front();
+        }
+        changes.push_back(change);
+    }
+
+    /// Get all recorded changes, optionally only for the given [`Subnet`], ordered from oldest
+    /// to newest.
+    pub fn changes(&self, subnet: Option<Subnet>) -> Vec<RouteChange> {
+        let mut changes: Vec<_> = match subnet {
+            Some(subnet) => self
+                .changes
+                .get(&subnet)
+                .map(|changes| changes.iter().cloned().collect())
+                .unwrap_or_default(),
+            None => self.changes.values().flatten().cloned().collect(),
+        };
+        changes.sort_by_key(|change| change.time);
+        changes
+    }
+}
+
+impl fmt::Display for RouteChangeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Update => "update",
+            Self::Retraction => "retraction",
+            Self::Unfeasible => "unfeasible update",
+            Self::PeerDied => "peer died",
+            Self::Expired => "expired",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv6Addr,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{RouteChange, RouteChangeReason, RouteHistory, MAX_CHANGES_PER_SUBNET};
+    use crate::subnet::Subnet;
+
+    fn change(segment: u16, time: SystemTime) -> RouteChange {
+        RouteChange {
+            time,
+            subnet: Subnet::new(Ipv6Addr::new(0x400, segment, 0, 0, 0, 0, 0, 0).into(), 64)
+                .expect("Valid subnet definition"),
+            old_neighbour: None,
+            old_metric: None,
+            new_neighbour: Some("peer".to_string()),
+            new_metric: Some(10.into()),
+            seqno: 0.into(),
+            reason: RouteChangeReason::Update,
+        }
+    }
+
+    #[test]
+    fn history_is_bounded_per_subnet() {
+        let mut history = RouteHistory::new();
+        let start = SystemTime::UNIX_EPOCH;
+
+        for i in 0..MAX_CHANGES_PER_SUBNET as u64 + 5 {
+            history.record(change(1, start + Duration::from_secs(i)));
+        }
+
+        let changes = history.changes(Some(change(1, start).subnet));
+        assert_eq!(changes.len(), MAX_CHANGES_PER_SUBNET);
+        assert_eq!(changes[0].time, start + Duration::from_secs(5));
+    }
+
+    #[test]
+    fn changes_are_ordered_and_filtered() {
+        let mut history = RouteHistory::new();
+        let start = SystemTime::UNIX_EPOCH;
+
+        history.record(change(1, start + Duration::from_secs(2)));
+        history.record(change(2, start + Duration::from_secs(1)));
+        history.record(change(1, start + Duration::from_secs(3)));
+
+        let all = history.changes(None);
+        assert_eq!(all.len(), 3);
+        assert!(all.windows(2).all(|w| w[0].time <= w[1].time));
+
+        assert_eq!(history.changes(Some(change(2, start).subnet)).len(), 1);
+        assert!(history.changes(Some(change(3, start).subnet)).is_empty());
+    }
+}
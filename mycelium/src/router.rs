@@ -1,13 +1,17 @@
 use crate::{
     babel::{self, RouteRequest, SeqNoRequest},
     crypto::{PacketBuffer, PublicKey, SecretKey, SharedSecret},
-    filters::RouteUpdateFilter,
+    filters::{FilterChain, FilterConfig, FilterStats, RouteUpdateFilter},
     metric::Metric,
+    metrics::{DataPlaneCounters, DataPlaneMetrics},
     packet::{ControlPacket, DataPacket},
     peer::Peer,
+    route_history::{RouteChange, RouteChangeReason, RouteHistory},
     router_id::RouterId,
     routing_table::{RouteEntry, RouteExpirationType, RouteKey, RoutingTable},
+    seqno_cache::SeqnoCache,
     sequence_number::SeqNo,
+    snapshot::RouterSnapshot,
     source_table::{FeasibilityDistance, SourceKey, SourceTable},
     subnet::Subnet,
 };
@@ -20,51 +24,181 @@ use log::{debug, error, info, trace, warn};
 use std::{
     error::Error,
     net::IpAddr,
+    num::NonZeroU8,
+    path::PathBuf,
     sync::{Arc, Mutex, RwLock},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
-
-/// Time between HELLO messags, in seconds
-const HELLO_INTERVAL: u64 = 20;
-/// Time filled in in IHU packet
-const IHU_INTERVAL: Duration = Duration::from_secs(HELLO_INTERVAL * 3);
-/// Max time used in UPDATE packets. For local (static) routes this is the timeout they are
-/// advertised with.
-const UPDATE_INTERVAL: Duration = Duration::from_secs(HELLO_INTERVAL * 3);
-/// Time between route table dumps to peers.
-const ROUTE_PROPAGATION_INTERVAL: Duration = UPDATE_INTERVAL;
-/// Amount of seconds that can elapse before we consider a [`Peer`] as dead from the routers POV.
-/// Since IHU's are sent in response to HELLO packets, this MUST be greater than the
+use tokio::{
+    sync::{
+        mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        Notify,
+    },
+    task::JoinHandle,
+};
+
+/// Default time between HELLO messages.
+const HELLO_INTERVAL: Duration = Duration::from_secs(20);
+/// Default time filled in in IHU packet
+const IHU_INTERVAL: Duration = Duration::from_secs(HELLO_INTERVAL.as_secs() * 3);
+/// Default max time used in UPDATE packets. For local (static) routes this is the timeout they are
+/// advertised with. This is also the time between route table dumps to peers.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(HELLO_INTERVAL.as_secs() * 3);
+/// Default amount of time that can elapse before we consider a [`Peer`] as dead from the routers
+/// POV. Since IHU's are sent in response to HELLO packets, this MUST be greater than the
 /// [`HELLO_INTERVAL`].
 ///
 /// We allow missing 1 hello, + some latency, so 2 HELLO's + 3 seconds for latency.
-const DEAD_PEER_THRESHOLD: Duration = Duration::from_secs(HELLO_INTERVAL * 2 + 3);
-/// The duration between checks for dead peers in the router. This check only looks for peers where
-/// time since the last IHU exceeds DEAD_PEER_THRESHOLD.
+const DEAD_PEER_THRESHOLD: Duration = Duration::from_secs(HELLO_INTERVAL.as_secs() * 2 + 3);
+/// The maximum duration between checks for dead peers in the router. This check only looks for
+/// peers where time since the last IHU exceeds the dead peer threshold. If the hello interval is
+/// shorter, peers are checked once every hello interval instead.
 const DEAD_PEER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
-/// Amount of time to wait between consecutive seqno bumps of the local router seqno.
+/// Amount of Hellos a peer can miss, based on the interval it advertises in its Hellos, before it
+/// is considered dead.
+const MISSED_HELLOS_BEFORE_DEAD: u32 = 2;
+/// Time allowed for latency on top of the advertised Hello interval of a peer, before it is
+/// considered dead.
+const HELLO_LATENCY_MARGIN: Duration = Duration::from_secs(3);
+
+/// Default amount of time to wait between consecutive seqno bumps of the local router seqno.
 const SEQNO_BUMP_TIMEOUT: Duration = Duration::from_secs(4);
 
+/// Default hop count limit of seqno requests we send or forward, as recommended in
+/// [section 3.8.2.1 of the babel rfc](https://datatracker.ietf.org/doc/html/rfc8966#section-3.8.2.1).
+// SAFETY: value is not zero.
+const SEQNO_REQUEST_HOP_LIMIT: NonZeroU8 = unsafe { NonZeroU8::new_unchecked(64) };
+
 /// Metric change of more than 10 is considered a large change.
 const BIG_METRIC_CHANGE_TRESHOLD: Metric = Metric::new(10);
 
 /// The amount a metric of a route needs to improve before we will consider switching to it.
 const SIGNIFICANT_METRIC_IMPROVEMENT: Metric = Metric::new(10);
 
-/// Hold retracted routes for 1 minute before purging them from the [`RoutingTable`].
+/// By default, hold retracted routes for 1 minute before purging them from the [`RoutingTable`].
 const RETRACTED_ROUTE_HOLD_TIME: Duration = Duration::from_secs(60);
 
+/// Smallest interval which can be sent in a TLV, which expresses intervals in centiseconds.
+const MIN_TLV_INTERVAL: Duration = Duration::from_millis(10);
+/// Largest interval which can be sent in a TLV, which expresses intervals in centiseconds.
+const MAX_TLV_INTERVAL: Duration = Duration::from_millis(u16::MAX as u64 * 10);
+
 /// The interval specified in updates if the update won't be repeated.
 const INTERVAL_NOT_REPEATING: Duration = Duration::from_millis(0);
 
+/// Time between checks for seqno requests which need to be resent.
+const SEQNO_REQUEST_RETRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time between saving consecutive snapshots of the routing state, if enabled. Snapshots are also
+/// saved whenever the router seqno is bumped.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Timers and intervals used by a [`Router`].
+///
+/// The defaults work well for most networks. Lossy links, e.g. wireless links, can benefit from
+/// longer intervals, while networks in a data centre can use shorter intervals to detect failures
+/// faster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouterConfig {
+    /// Time between Hellos sent to every peer. This interval is advertised in the Hellos, so the
+    /// peer knows when to expect the next one.
+    pub hello_interval: Duration,
+    /// Interval advertised in IHU's.
+    pub ihu_interval: Duration,
+    /// Time between announcements of all routes to every peer. This interval is advertised in the
+    /// updates, and determines how long peers keep the routes.
+    pub update_interval: Duration,
+    /// Time without IHU from a peer, after which the peer is considered dead. This must be greater
+    /// than the `hello_interval`, since IHU's are sent in reply to Hellos.
+    pub dead_peer_threshold: Duration,
+    /// Minimum time between consecutive bumps of the router seqno.
+    pub seqno_bump_timeout: Duration,
+    /// Time a retracted route is kept before it is removed.
+    pub retracted_route_hold_time: Duration,
+    /// Maximum hop count of seqno requests we send or forward. Requests received with a higher
+    /// hop count are lowered to this value before they are forwarded, so they reach at most this
+    /// many routers past us. A limit of 1 means requests are only sent to direct neighbours, and
+    /// never forwarded.
+    pub seqno_request_hop_limit: NonZeroU8,
+}
+
+/// Error returned when the values of a [`RouterConfig`] are not consistent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidRouterConfig {
+    /// The named interval can't be expressed in a TLV.
+    IntervalOutOfRange(&'static str),
+    /// The dead peer threshold is not greater than the hello interval.
+    DeadPeerThresholdTooLow,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            hello_interval: HELLO_INTERVAL,
+            ihu_interval: IHU_INTERVAL,
+            update_interval: UPDATE_INTERVAL,
+            dead_peer_threshold: DEAD_PEER_THRESHOLD,
+            seqno_bump_timeout: SEQNO_BUMP_TIMEOUT,
+            retracted_route_hold_time: RETRACTED_ROUTE_HOLD_TIME,
+            seqno_request_hop_limit: SEQNO_REQUEST_HOP_LIMIT,
+        }
+    }
+}
+
+impl RouterConfig {
+    /// Check if the values in this `RouterConfig` are consistent.
+    pub fn validate(&self) -> Result<(), InvalidRouterConfig> {
+        for (name, interval) in [
+            ("hello interval", self.hello_interval),
+            ("IHU interval", self.ihu_interval),
+            ("update interval", self.update_interval),
+        ] {
+            if !(MIN_TLV_INTERVAL..=MAX_TLV_INTERVAL).contains(&interval) {
+                return Err(InvalidRouterConfig::IntervalOutOfRange(name));
+            }
+        }
+        if self.dead_peer_threshold <= self.hello_interval {
+            return Err(InvalidRouterConfig::DeadPeerThresholdTooLow);
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for InvalidRouterConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IntervalOutOfRange(name) => write!(
+                f,
+                "{name} must be between {MIN_TLV_INTERVAL:?} and {MAX_TLV_INTERVAL:?}"
+            ),
+            Self::DeadPeerThresholdTooLow => {
+                f.write_str("dead peer threshold must be greater than the hello interval")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidRouterConfig {}
+
+/// Filters applied to received route updates, before they are processed by the [`Router`].
+pub struct UpdateFilters {
+    /// Filters which can't be changed once the router is created.
+    pub fixed: Vec<Box<dyn RouteUpdateFilter + Send + Sync>>,
+    /// Filters configured by the operator, which can be replaced at runtime with
+    /// [`Router::set_update_filters`].
+    pub configured: Vec<FilterConfig>,
+}
+
 #[derive(Clone)]
 pub struct Router {
     inner_w: Arc<Mutex<WriteHandle<RouterInner, RouterOpLogEntry>>>,
     inner_r: ReadHandle<RouterInner>,
     peer_interfaces: Arc<RwLock<Vec<Peer>>>,
     source_table: Arc<RwLock<SourceTable>>,
+    /// Seqno requests we sent or forwarded, which are not answered yet.
+    seqno_cache: Arc<Mutex<SeqnoCache>>,
     // Router SeqNo and last time it was bumped
     router_seqno: Arc<RwLock<(SeqNo, Instant)>>,
     static_routes: Vec<Subnet>,
@@ -75,10 +209,22 @@ pub struct Router {
     node_tun: UnboundedSender<DataPacket>,
     node_tun_subnet: Subnet,
     update_filters: Arc<Vec<Box<dyn RouteUpdateFilter + Send + Sync>>>,
+    /// Filters configured by the operator, which can be changed at runtime.
+    configured_filters: Arc<RwLock<FilterChain>>,
+    /// History of changes to the selected routes.
+    route_history: Arc<Mutex<RouteHistory>>,
+    /// Counters of handled data packets.
+    data_counters: Arc<DataPlaneCounters>,
     /// Channel injected into peers, so they can notify the router if they exit.
     dead_peer_sink: mpsc::Sender<Peer>,
     /// Channel to notify the router of expired SourceKey's.
     expired_source_key_sink: mpsc::Sender<SourceKey>,
+    /// Notification to save a snapshot of the routing state immediately.
+    snapshot_notify: Arc<Notify>,
+    /// Background tasks of the router, which are stopped when the router is shut down.
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    /// Timers used by the router.
+    config: RouterConfig,
 }
 
 impl Router {
@@ -87,8 +233,12 @@ impl Router {
         node_tun_subnet: Subnet,
         static_routes: Vec<Subnet>,
         node_keypair: (SecretKey, PublicKey),
-        update_filters: Vec<Box<dyn RouteUpdateFilter + Send + Sync>>,
+        config: RouterConfig,
+        update_filters: UpdateFilters,
+        snapshot_path: Option<PathBuf>,
     ) -> Result<Self, Box<dyn Error>> {
+        config.validate()?;
+
         // Tx is passed onto each new peer instance. This enables peers to send control packets to the router.
         let (router_control_tx, router_control_rx) = mpsc::unbounded_channel();
         // Tx is passed onto each new peer instance. This enables peers to send data packets to the router.
@@ -100,14 +250,41 @@ impl Router {
         let router_inner = RouterInner::new(expired_route_entry_sink)?;
         let (inner_w, inner_r) = left_right::new_from_empty(router_inner);
 
-        let router_id = RouterId::new(node_keypair.1);
+        let snapshot = match snapshot_path.as_deref().map(RouterSnapshot::load) {
+            Some(Ok(Some(snapshot))) if snapshot.router_id.to_pubkey() == node_keypair.1 => {
+                info!("Restoring routing state from snapshot");
+                Some(snapshot)
+            }
+            Some(Ok(Some(_))) => {
+                warn!("Ignoring routing state snapshot of a different node key");
+                None
+            }
+            Some(Ok(None)) | None => None,
+            Some(Err(e)) => {
+                warn!("Failed to load routing state snapshot: {e}");
+                None
+            }
+        };
+
+        let mut source_table = SourceTable::new();
+        let (router_id, router_seqno) = if let Some(snapshot) = snapshot {
+            for (source_key, fd) in snapshot.feasibility_distances {
+                source_table.insert(source_key, fd, expired_source_key_sink.clone());
+            }
+            // We might have bumped the seqno after the snapshot was saved, so bump it again to
+            // make sure we never announce a lower seqno than before.
+            (snapshot.router_id, snapshot.seqno + 1)
+        } else {
+            (RouterId::new(node_keypair.1), SeqNo::new())
+        };
 
         let router = Router {
             inner_w: Arc::new(Mutex::new(inner_w)),
             inner_r,
             peer_interfaces: Arc::new(RwLock::new(Vec::new())),
-            source_table: Arc::new(RwLock::new(SourceTable::new())),
-            router_seqno: Arc::new(RwLock::new((SeqNo::new(), Instant::now()))),
+            source_table: Arc::new(RwLock::new(source_table)),
+            seqno_cache: Arc::new(Mutex::new(SeqnoCache::new())),
+            router_seqno: Arc::new(RwLock::new((router_seqno, Instant::now()))),
             static_routes,
             router_id,
             node_keypair,
@@ -117,34 +294,48 @@ impl Router {
             node_tun_subnet,
             dead_peer_sink,
             expired_source_key_sink,
-            update_filters: Arc::new(update_filters),
+            update_filters: Arc::new(update_filters.fixed),
+            configured_filters: Arc::new(RwLock::new(FilterChain::new(update_filters.configured))),
+            route_history: Arc::new(Mutex::new(RouteHistory::new())),
+            data_counters: Arc::new(DataPlaneCounters::default()),
+            snapshot_notify: Arc::new(Notify::new()),
+            tasks: Arc::new(Mutex::new(Vec::new())),
+            config,
         };
 
-        tokio::spawn(Router::start_periodic_hello_sender(router.clone()));
-        tokio::spawn(Router::handle_incoming_control_packet(
-            router.clone(),
-            router_control_rx,
-        ));
-        tokio::spawn(Router::handle_incoming_data_packet(
-            router.clone(),
-            router_data_rx,
-        ));
-        tokio::spawn(Router::propagate_static_routes(router.clone()));
-        tokio::spawn(Router::propagate_selected_routes(router.clone()));
-
-        tokio::spawn(Router::check_for_dead_peers(router.clone()));
-
-        tokio::spawn(Router::process_expired_source_keys(
-            router.clone(),
-            expired_source_key_stream,
-        ));
-
-        tokio::spawn(Router::process_expired_route_keys(
-            router.clone(),
-            expired_route_entry_stream,
-        ));
+        let mut tasks = vec![
+            tokio::spawn(Router::start_periodic_hello_sender(router.clone())),
+            tokio::spawn(Router::handle_incoming_control_packet(
+                router.clone(),
+                router_control_rx,
+            )),
+            tokio::spawn(Router::handle_incoming_data_packet(
+                router.clone(),
+                router_data_rx,
+            )),
+            tokio::spawn(Router::propagate_static_routes(router.clone())),
+            tokio::spawn(Router::propagate_selected_routes(router.clone())),
+            tokio::spawn(Router::check_for_dead_peers(router.clone())),
+            tokio::spawn(Router::process_expired_source_keys(
+                router.clone(),
+                expired_source_key_stream,
+            )),
+            tokio::spawn(Router::process_expired_route_keys(
+                router.clone(),
+                expired_route_entry_stream,
+            )),
+            tokio::spawn(Router::process_dead_peers(router.clone(), dead_peer_stream)),
+            tokio::spawn(Router::retry_seqno_requests(router.clone())),
+        ];
+
+        if let Some(snapshot_path) = snapshot_path {
+            tasks.push(tokio::spawn(Router::save_snapshots(
+                router.clone(),
+                snapshot_path,
+            )));
+        }
 
-        tokio::spawn(Router::process_dead_peers(router.clone(), dead_peer_stream));
+        *router.tasks.lock().unwrap() = tasks;
 
         Ok(router)
     }
@@ -161,6 +352,38 @@ impl Router {
         self.node_tun_subnet
     }
 
+    /// Get the filters configured by the operator, with the amount of updates they rejected.
+    pub fn update_filters(&self) -> Vec<FilterStats> {
+        self.configured_filters.read().unwrap().stats()
+    }
+
+    /// Replace the filters configured by the operator. Routes which were already accepted are
+    /// not removed, but they will expire if they are no longer refreshed.
+    pub fn set_update_filters(&self, filters: Vec<FilterConfig>) {
+        self.configured_filters.write().unwrap().replace(filters)
+    }
+
+    /// Get the recorded changes of selected routes, optionally only for the given [`Subnet`],
+    /// ordered from oldest to newest.
+    pub fn route_history(&self, subnet: Option<Subnet>) -> Vec<RouteChange> {
+        self.route_history.lock().unwrap().changes(subnet)
+    }
+
+    /// Get the counters of handled data packets.
+    pub(crate) fn data_counters(&self) -> &DataPlaneCounters {
+        &self.data_counters
+    }
+
+    /// Get the amount of data packets handled since the router started.
+    pub fn data_plane_metrics(&self) -> DataPlaneMetrics {
+        self.data_counters.metrics()
+    }
+
+    /// Get the subnets which are statically announced by this router.
+    pub fn static_routes(&self) -> &[Subnet] {
+        &self.static_routes
+    }
+
     pub fn node_tun(&self) -> UnboundedSender<DataPacket> {
         self.node_tun.clone()
     }
@@ -273,20 +496,86 @@ impl Router {
             .collect()
     }
 
+    /// Create a [`RouterSnapshot`] of the current routing state.
+    fn snapshot(&self) -> RouterSnapshot {
+        RouterSnapshot {
+            router_id: self.router_id,
+            seqno: self.router_seqno.read().unwrap().0,
+            feasibility_distances: self
+                .source_table
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(sk, fd)| (*sk, *fd))
+                .collect(),
+        }
+    }
+
+    /// Task which saves a snapshot of the routing state to the given path. A snapshot is saved
+    /// when the task starts, periodically, and whenever the router seqno changes.
+    async fn save_snapshots(self, path: PathBuf) {
+        loop {
+            trace!("Saving routing state snapshot");
+            if let Err(e) = self.snapshot().save(&path).await {
+                error!("Failed to save routing state snapshot to {path:?}: {e}");
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(SNAPSHOT_INTERVAL) => {}
+                _ = self.snapshot_notify.notified() => {}
+            }
+        }
+    }
+
+    /// Shut down the router.
+    ///
+    /// All background tasks of the router are stopped first, so no more updates are sent or
+    /// processed. Then the static routes are retracted on every peer, after which the connections
+    /// to the peers are closed. This returns once all connections are closed.
+    pub async fn shutdown(&self) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in &tasks {
+            task.abort();
+        }
+        for task in tasks {
+            // The only possible error is the cancellation we just requested.
+            let _ = task.await;
+        }
+
+        let peers = std::mem::take(&mut *self.peer_interfaces.write().unwrap());
+        for peer in &peers {
+            debug!(
+                "Retracting static routes on {} before closing the connection",
+                peer.connection_identifier()
+            );
+            self.retract_static_routes_to_peer(peer);
+            peer.died();
+        }
+        for peer in peers {
+            peer.closed().await;
+        }
+    }
+
     /// Task which periodically checks for dead peers in the Router.
     async fn check_for_dead_peers(self) {
+        let check_interval = DEAD_PEER_CHECK_INTERVAL.min(self.config.hello_interval);
         loop {
-            // check for dead peers every second
-            tokio::time::sleep(DEAD_PEER_CHECK_INTERVAL).await;
+            tokio::time::sleep(check_interval).await;
 
             trace!("Checking for dead peers");
 
             let dead_peers = {
-                // a peer is assumed dead when the peer's last sent ihu exceeds a threshold
+                // a peer is assumed dead when the peer's last sent ihu exceeds a threshold, or
+                // when it stopped sending the Hellos it announced.
                 let mut dead_peers = Vec::new();
                 for peer in self.peer_interfaces.read().unwrap().iter() {
-                    // check if the peer's last_received_ihu is greater than the threshold
-                    if peer.time_last_received_ihu().elapsed() > DEAD_PEER_THRESHOLD {
+                    let ihu_expired =
+                        peer.time_last_received_ihu().elapsed() > self.config.dead_peer_threshold;
+                    let hello_expired = peer.hello_interval().is_some_and(|interval| {
+                        peer.time_last_received_hello().elapsed()
+                            > interval * MISSED_HELLOS_BEFORE_DEAD + HELLO_LATENCY_MARGIN
+                    });
+                    if ihu_expired || hello_expired {
                         // peer is dead
                         info!("Peer {} is dead", peer.connection_identifier());
                         // Notify peer it's dead in case it's not aware of that yet.
@@ -323,12 +612,22 @@ impl Router {
             for (rk, _, re) in inner.routing_table.iter() {
                 if rk.neighbour() == &dead_peer {
                     subnets_to_select.push(rk.subnet());
+                    if re.selected() {
+                        let mut retracted = re.clone();
+                        retracted.update_metric(Metric::infinite());
+                        self.record_route_change(
+                            rk.subnet(),
+                            Some(re),
+                            Some(&retracted),
+                            RouteChangeReason::PeerDied,
+                        );
+                    }
                     inner_w.append(RouterOpLogEntry::UpdateRouteEntry(
                         rk,
                         re.seqno(),
                         Metric::infinite(),
                         re.source().router_id(),
-                        RETRACTED_ROUTE_HOLD_TIME,
+                        self.config.retracted_route_hold_time,
                     ));
                 }
             }
@@ -343,12 +642,12 @@ impl Router {
 
         // And run required route selection
         for subnet in subnets_to_select {
-            self.route_selection(subnet);
+            self.route_selection(subnet, RouteChangeReason::PeerDied);
         }
     }
 
     /// Run route selection for a given subnet
-    fn route_selection(&self, subnet: Subnet) {
+    fn route_selection(&self, subnet: Subnet, reason: RouteChangeReason) {
         debug!("Running route selection for {subnet}");
         let mut inner_w = self.inner_w.lock().unwrap();
 
@@ -394,6 +693,17 @@ impl Router {
             )));
             inner_w.publish();
 
+            self.record_route_change(
+                subnet,
+                if routes[0].selected() {
+                    Some(&routes[0])
+                } else {
+                    None
+                },
+                Some(new_selected),
+                reason,
+            );
+
             self.trigger_update(subnet);
         }
     }
@@ -439,7 +749,7 @@ impl Router {
                     entry.seqno(),
                     Metric::infinite(),
                     entry.source().router_id(),
-                    RETRACTED_ROUTE_HOLD_TIME,
+                    self.config.retracted_route_hold_time,
                 ));
             } else if entry.metric().is_infinite()
                 && matches!(expiration_type, RouteExpirationType::Remove)
@@ -462,14 +772,21 @@ impl Router {
                     .entries(subnet);
                 // Only inject selected route if we are simply retracting it, otherwise it is
                 // actually already removed.
-                if let Some(r) = self.find_best_route(
+                let new_selected = self.find_best_route(
                     &routes,
                     if matches!(expiration_type, RouteExpirationType::Retract) {
                         Some(&entry)
                     } else {
                         None
                     },
-                ) {
+                );
+                self.record_route_change(
+                    subnet,
+                    Some(&entry),
+                    new_selected,
+                    RouteChangeReason::Expired,
+                );
+                if let Some(r) = new_selected {
                     debug!("Rerun route selection after expiration event");
                     inner
                         .append(RouterOpLogEntry::SelectRoute(RouteKey::new(
@@ -521,24 +838,38 @@ impl Router {
     }
 
     /// Handle a received hello TLV
-    fn handle_incoming_hello(&self, _: babel::Hello, source_peer: Peer) {
-        // Upon receiving and Hello message from a peer, this node has to send a IHU back
-        // TODO: properly calculate RX cost, for now just set the link cost.
-        let ihu = ControlPacket::new_ihu(source_peer.link_cost().into(), IHU_INTERVAL, None);
-        if let Err(e) = source_peer.send_control_packet(ihu) {
+    fn handle_incoming_hello(&self, hello: babel::Hello, source_peer: Peer) {
+        source_peer.received_hello(hello.seqno(), hello.interval());
+        // Upon receiving and Hello message from a peer, this node has to send a IHU back, which
+        // informs the peer how well we receive it. If the peer sends timestamps, the IHU is sent
+        // together with our next Hello instead, so the peer can measure the round trip time.
+        if hello.timestamp().is_none() {
+            self.send_ihu(&source_peer);
+        }
+    }
+
+    /// Send an IHU to a peer, which informs the peer how well we receive it.
+    fn send_ihu(&self, peer: &Peer) {
+        let ihu = ControlPacket::new_ihu(peer.rx_cost(), self.config.ihu_interval, None);
+        if let Err(e) = peer.send_control_packet(ihu) {
             error!("Error sending IHU to peer: {e}");
         }
     }
 
     /// Handle a received IHU TLV
-    fn handle_incoming_ihu(&self, _: babel::Ihu, source_peer: Peer) {
-        // reset the IHU timer associated with the peer
-        // measure time between Hello and and IHU and set the link cost
-        let time_diff = tokio::time::Instant::now()
-            .duration_since(source_peer.time_last_received_hello())
-            .as_millis();
-
-        source_peer.set_link_cost(time_diff as u16);
+    fn handle_incoming_ihu(&self, ihu: babel::Ihu, source_peer: Peer) {
+        // The rx cost of the peer is the cost for us to send packets to it.
+        source_peer.set_tx_cost(ihu.rx_cost());
+
+        // If the peer sends timestamps, the round trip time is measured from them when the IHU
+        // is received. Otherwise, fall back to the time between sending our Hello and receiving
+        // the IHU, since the peer sends an IHU in reply to our Hello.
+        if !source_peer.timestamps_supported() {
+            let rtt = tokio::time::Instant::now()
+                .duration_since(source_peer.time_last_sent_hello())
+                .as_millis();
+            source_peer.set_link_cost(rtt.min(u16::MAX as u128 - 1) as u16);
+        }
 
         // set the last_received_ihu for this peer
         source_peer.set_time_last_received_ihu(tokio::time::Instant::now());
@@ -566,7 +897,7 @@ impl Router {
                     return;
                 }
                 babel::Update::new(
-                    advertised_update_interval(sre),
+                    advertised_update_interval(sre, self.config.update_interval),
                     sre.seqno(),
                     sre.metric() + Metric::from(sre.neighbour().link_cost()),
                     subnet,
@@ -583,9 +914,9 @@ impl Router {
                     "Advertising static route {static_route} in response to route request for {subnet}"
                 );
                 babel::Update::new(
-                    UPDATE_INTERVAL, // Static route is advertised with the default interval
+                    self.config.update_interval, // Static route is advertised with the default interval
                     self.router_seqno.read().unwrap().0, // Updates receive the seqno of the router
-                    Metric::from(0), // Static route has no further hop costs
+                    Metric::from(0),             // Static route has no further hop costs
                     *static_route,
                     self.router_id,
                 )
@@ -615,10 +946,6 @@ impl Router {
 
     /// Handle a received SeqNo request TLV.
     fn handle_incoming_seqno_request(&self, mut seqno_request: SeqNoRequest, source_peer: Peer) {
-        // According to the babel rfc, we shoudl maintain a table of recent SeqNo requests and
-        // periodically retry requests without reply. We will however not do this for now and rely
-        // on the fact that we have stable links in general.
-
         let inner = self
             .inner_r
             .enter()
@@ -643,7 +970,7 @@ impl Router {
                     seqno_request.prefix()
                 );
                 let update = babel::Update::new(
-                    advertised_update_interval(route_entry),
+                    advertised_update_interval(route_entry, self.config.update_interval),
                     route_entry.seqno(), // updates receive the seqno of the router
                     route_entry.metric() + Metric::from(source_peer.link_cost()),
                     // the cost of the route is the cost of the route + the cost of the link to the peer
@@ -676,7 +1003,7 @@ impl Router {
             && seqno_request.seqno().gt(&router_seqno)
             && self.static_routes.contains(&seqno_request.prefix())
         {
-            if last_seqno_bump.elapsed() >= SEQNO_BUMP_TIMEOUT {
+            if last_seqno_bump.elapsed() < self.config.seqno_bump_timeout {
                 trace!("Ignoring seqno bump request which happened too fast");
                 return;
             }
@@ -689,7 +1016,7 @@ impl Router {
             {
                 let mut router_seqno = self.router_seqno.write().unwrap();
                 // First check again if we should bump
-                if router_seqno.1.elapsed() >= SEQNO_BUMP_TIMEOUT {
+                if router_seqno.1.elapsed() < self.config.seqno_bump_timeout {
                     trace!("Ignoring seqno bump request which happened too fast");
                     return;
                 }
@@ -698,6 +1025,7 @@ impl Router {
                 // Set last modified time
                 router_seqno.1 = Instant::now();
             }
+            self.snapshot_notify.notify_one();
 
             self.propagate_static_route();
 
@@ -705,55 +1033,87 @@ impl Router {
         }
 
         // Otherwise, if the router-id from the request is not our own, we check the hop count
-        // field. If it is at least 2, we decrement it by 1, and forward the packet. To do so, we
-        // try to find a route to the subnet. First we check for a feasible route and send the
-        // packet there if the next hop is not the sender of this packet. Otherwise, we check for
-        // any route which might potentially be unfeasible, which also did not originate the
-        // packet.
-        if seqno_request.router_id() != self.router_id && seqno_request.hop_count() > 1 {
-            seqno_request.decrement_hop_count();
-
+        // field, after lowering it to our configured limit. If it is at least 2, we decrement it
+        // by 1, and forward the packet. To do so, we try to find a route to the subnet. First we
+        // check for a feasible route and send the packet there if the next hop is not the sender
+        // of this packet. Otherwise, we check for any route which might potentially be
+        // unfeasible, which also did not originate the packet.
+        //
+        // If we recently forwarded the same request, possibly received from a different
+        // neighbour, it is not forwarded again. The pending request is retried if needed.
+        if seqno_request.router_id() != self.router_id
+            && forward_hop_count(&mut seqno_request, self.config.seqno_request_hop_limit)
+        {
             let possible_routes = inner.routing_table.entries(seqno_request.prefix());
 
-            // First only consider feasible routes.
+            // First only consider feasible routes, and finally consider infeasible routes as well.
             let source_table = self.source_table.read().unwrap();
-            if let Some(re) = possible_routes.iter().find(|re| {
-                source_table.route_feasible(re)
-                    && re.neighbour() != &source_peer
-                    && re.neighbour().alive()
-                    && !re.metric().is_infinite()
-            }) {
+            let next_hop = possible_routes
+                .iter()
+                .find(|re| {
+                    source_table.route_feasible(re)
+                        && re.neighbour() != &source_peer
+                        && re.neighbour().alive()
+                        && !re.metric().is_infinite()
+                })
+                .or_else(|| {
+                    possible_routes.iter().find(|re| {
+                        re.neighbour() != &source_peer
+                            && re.neighbour().alive()
+                            && !re.metric().is_infinite()
+                    })
+                })
+                .map(|re| re.neighbour());
+
+            if let Some(next_hop) = next_hop {
+                let mut seqno_cache = self.seqno_cache.lock().unwrap();
+                if seqno_cache.is_duplicate(&seqno_request, Instant::now()) {
+                    debug!(
+                        "Not forwarding duplicate seqno request {} for {}",
+                        seqno_request.seqno(),
+                        seqno_request.prefix(),
+                    );
+                    return;
+                }
                 debug!(
                     "Forwarding seqno request {} for {} to {}",
                     seqno_request.seqno(),
                     seqno_request.prefix(),
-                    re.neighbour().connection_identifier()
+                    next_hop.connection_identifier()
                 );
-                if let Err(e) = re.neighbour().send_control_packet(seqno_request.into()) {
+                seqno_cache.insert(seqno_request.clone(), next_hop, Instant::now());
+                if let Err(e) = next_hop.send_control_packet(seqno_request.into()) {
                     error!(
                         "Failed to foward seqno request to {}: {e}",
-                        re.neighbour().connection_identifier(),
+                        next_hop.connection_identifier(),
                     );
                 }
-                return;
             }
+        }
+    }
 
-            // Finally consider infeasible routes as well.
-            if let Some(re) = possible_routes.iter().find(|re| {
-                re.neighbour() != &source_peer
-                    && re.neighbour().alive()
-                    && !re.metric().is_infinite()
-            }) {
+    /// Task which periodically resends seqno requests which are not answered yet.
+    async fn retry_seqno_requests(self) {
+        loop {
+            tokio::time::sleep(SEQNO_REQUEST_RETRY_CHECK_INTERVAL).await;
+
+            let retries = {
+                let mut seqno_cache = self.seqno_cache.lock().unwrap();
+                let retries = seqno_cache.retries(Instant::now());
+                trace!("{} seqno requests pending", seqno_cache.len());
+                retries
+            };
+            for (seqno_request, peer) in retries {
                 debug!(
-                    "Forwarding seqno request {} for {} to {}",
+                    "Resending seqno request {} for {} to {}",
                     seqno_request.seqno(),
                     seqno_request.prefix(),
-                    re.neighbour().connection_identifier()
+                    peer.connection_identifier()
                 );
-                if let Err(e) = re.neighbour().send_control_packet(seqno_request.into()) {
+                if let Err(e) = peer.send_control_packet(seqno_request.into()) {
                     error!(
-                        "Failed to foward seqno request to {}: {e}",
-                        re.neighbour().connection_identifier(),
+                        "Failed to resend seqno request to {}: {e}",
+                        peer.connection_identifier()
                     );
                 }
             }
@@ -805,6 +1165,14 @@ impl Router {
                 return;
             }
         }
+        if !self.configured_filters.read().unwrap().allow(
+            &update,
+            source_peer.connection_identifier(),
+            Instant::now(),
+        ) {
+            debug!("Update denied by configured filter");
+            return;
+        }
 
         let metric = update.metric();
         let router_id = update.router_id();
@@ -869,20 +1237,25 @@ impl Router {
                         )
                     });
 
+                let mut seqno_request = SeqNoRequest::new(
+                    fd.seqno() + 1,
+                    existing_entry.source().router_id(),
+                    update.subnet(),
+                );
+                seqno_request.limit_hop_count(self.config.seqno_request_hop_limit);
+                let mut seqno_cache = self.seqno_cache.lock().unwrap();
+                if seqno_cache.is_duplicate(&seqno_request, Instant::now()) {
+                    trace!("Seqno request for {} is already pending", update.subnet());
+                    return;
+                }
                 debug!(
                     "Sending seqno_request to {} for seqno {} of {}",
                     source_peer.connection_identifier(),
                     fd.seqno() + 1,
                     update.subnet(),
                 );
-                if let Err(e) = source_peer.send_control_packet(
-                    SeqNoRequest::new(
-                        fd.seqno() + 1,
-                        existing_entry.source().router_id(),
-                        update.subnet(),
-                    )
-                    .into(),
-                ) {
+                seqno_cache.insert(seqno_request.clone(), &source_peer, Instant::now());
+                if let Err(e) = source_peer.send_control_packet(seqno_request.into()) {
                     error!(
                         "Failed to send seqno request to {}: {e}",
                         source_peer.connection_identifier()
@@ -907,7 +1280,7 @@ impl Router {
                 seqno,
                 metric,
                 router_id,
-                route_hold_time(&update),
+                route_hold_time(&update, self.config.retracted_route_hold_time),
             ));
             // If the update is unfeasible the route must be unselected.
             if existing_entry.selected() && !update_feasible {
@@ -928,7 +1301,7 @@ impl Router {
                 metric,
                 seqno,
                 false,
-                route_hold_time(&update),
+                route_hold_time(&update, self.config.retracted_route_hold_time),
             );
             routing_table_entries.push(re.clone());
 
@@ -968,7 +1341,24 @@ impl Router {
         // What doesn't constitue a large change:
         // - small metric change
         // - seqno increase (unless it is requested by a peer)
-        // TODO: we don't memorize seqno requests for now so consider broadcasting this anyway
+        let seqno_request_satisfied = !metric.is_infinite()
+            && self
+                .seqno_cache
+                .lock()
+                .unwrap()
+                .satisfy(&SourceKey::new(subnet, router_id), seqno);
+        self.record_route_change(
+            subnet,
+            old_selected_route.as_ref(),
+            new_selected_route,
+            if metric.is_infinite() {
+                RouteChangeReason::Retraction
+            } else if !update_feasible {
+                RouteChangeReason::Unfeasible
+            } else {
+                RouteChangeReason::Update
+            },
+        );
         let trigger_update = match (&old_selected_route, new_selected_route) {
             (Some(old_route), Some(new_route)) => {
                 if new_route.neighbour() != old_route.neighbour() {
@@ -980,8 +1370,8 @@ impl Router {
                 }
                 // Router id changed.
                 new_route.source().router_id() != old_route.source().router_id()
-                // TODO: remove | seqno changed
-                    || new_route.seqno().gt(&old_route.seqno())
+                // Requested seqno increase
+                    || (seqno_request_satisfied && new_route.seqno().gt(&old_route.seqno()))
                     || new_route.metric().delta(&old_route.metric()) > BIG_METRIC_CHANGE_TRESHOLD
             }
             (None, Some(new_route)) => {
@@ -1007,6 +1397,40 @@ impl Router {
         }
     }
 
+    /// Record a change of the selected route for a [`Subnet`] in the route history. Nothing is
+    /// recorded if the selected route did not actually change, i.e. it still goes through the
+    /// same neighbour and it was not retracted or reinstated.
+    fn record_route_change(
+        &self,
+        subnet: Subnet,
+        old: Option<&RouteEntry>,
+        new: Option<&RouteEntry>,
+        reason: RouteChangeReason,
+    ) {
+        let seqno = match (old, new) {
+            (None, None) => return,
+            (Some(old), Some(new))
+                if old.neighbour() == new.neighbour()
+                    && old.metric().is_infinite() == new.metric().is_infinite() =>
+            {
+                return
+            }
+            (_, Some(re)) | (Some(re), None) => re.seqno(),
+        };
+
+        let total_metric = |re: &RouteEntry| re.metric() + Metric::from(re.neighbour().link_cost());
+        self.route_history.lock().unwrap().record(RouteChange {
+            time: SystemTime::now(),
+            subnet,
+            old_neighbour: old.map(|re| re.neighbour().connection_identifier().clone()),
+            old_metric: old.map(total_metric),
+            new_neighbour: new.map(|re| re.neighbour().connection_identifier().clone()),
+            new_metric: new.map(total_metric),
+            seqno,
+            reason,
+        });
+    }
+
     /// Trigger an update for the given [`Subnet`].
     fn trigger_update(&self, subnet: Subnet) {
         self.propagate_selected_route(subnet);
@@ -1023,8 +1447,6 @@ impl Router {
     }
 
     pub fn route_packet(&self, mut data_packet: DataPacket) {
-        let node_tun_subnet = self.node_tun_subnet();
-
         trace!(
             "Incoming data packet {} -> {}",
             data_packet.src_ip,
@@ -1037,9 +1459,17 @@ impl Router {
         }
         data_packet.hop_limit -= 1;
 
-        if node_tun_subnet.contains_ip(data_packet.dst_ip.into()) {
+        // Packets for any of our static routes, including extra subnets announced on behalf of
+        // hosts behind this node, are delivered to the TUN interface.
+        if self
+            .static_routes
+            .iter()
+            .any(|sr| sr.contains_ip(data_packet.dst_ip.into()))
+        {
             if let Err(e) = self.node_tun().send(data_packet) {
                 error!("Error sending data packet to TUN interface: {:?}", e);
+            } else {
+                self.data_counters.packet_routed();
             }
         } else {
             match self.select_best_route(IpAddr::V6(data_packet.dst_ip)) {
@@ -1050,6 +1480,8 @@ impl Router {
                             route_entry.neighbour().connection_identifier(),
                             e
                         );
+                    } else {
+                        self.data_counters.packet_routed();
                     }
                 }
                 None => {
@@ -1070,6 +1502,7 @@ impl Router {
     /// Handle a packet who's TTL is too low.
     fn time_exceeded(&self, data_packet: DataPacket) {
         trace!("Refusing to forward expired packet");
+        self.data_counters.ttl_exceeded();
         self.oob_icmp(
             Icmpv6Type::TimeExceeded(TimeExceededCode::HopLimitExceeded),
             data_packet,
@@ -1082,6 +1515,7 @@ impl Router {
             "Could not forward data packet, no route found for {}",
             data_packet.dst_ip
         );
+        self.data_counters.no_route();
 
         self.oob_icmp(
             Icmpv6Type::DestinationUnreachable(DestUnreachableCode::NoRoute),
@@ -1167,7 +1601,7 @@ impl Router {
     /// Task to propagete the static routes periodically
     async fn propagate_static_routes(self) {
         loop {
-            tokio::time::sleep(ROUTE_PROPAGATION_INTERVAL).await;
+            tokio::time::sleep(self.config.update_interval).await;
 
             trace!("Propagating static routes");
 
@@ -1180,7 +1614,7 @@ impl Router {
     /// Task to propagate selected routes periodically
     async fn propagate_selected_routes(self) {
         loop {
-            tokio::time::sleep(ROUTE_PROPAGATION_INTERVAL).await;
+            tokio::time::sleep(self.config.update_interval).await;
 
             trace!("Propagating selected routes");
 
@@ -1190,17 +1624,22 @@ impl Router {
 
     /// Task which periodically sends a Hello TLV to all known peers
     async fn start_periodic_hello_sender(self) {
-        let hello_interval = Duration::from_secs(HELLO_INTERVAL);
+        let hello_interval = self.config.hello_interval;
         loop {
             tokio::time::sleep(hello_interval).await;
 
             for peer in self.peer_interfaces.read().unwrap().iter() {
                 let hello = ControlPacket::new_hello(peer, hello_interval);
-                peer.set_time_last_received_hello(tokio::time::Instant::now());
+                peer.set_time_last_sent_hello(tokio::time::Instant::now());
 
                 if let Err(error) = peer.send_control_packet(hello) {
                     error!("Error sending hello to peer: {}", error);
                 }
+                // The IHU echoes the timestamp of the last Hello of the peer. Sending it right
+                // after our own Hello lets the peer subtract the time it took us to reply.
+                if peer.timestamps_supported() {
+                    self.send_ihu(peer);
+                }
             }
         }
     }
@@ -1212,11 +1651,30 @@ impl Router {
         }
     }
 
+    /// Retract the static routes on a single peer, by announcing them with an infinite metric.
+    ///
+    /// Unlike regular updates, this does not touch the source table, as the routes are no longer
+    /// announced by us afterwards.
+    fn retract_static_routes_to_peer(&self, peer: &Peer) {
+        for sr in self.static_routes.iter() {
+            let update = babel::Update::new(
+                self.config.update_interval,
+                self.router_seqno.read().unwrap().0,
+                Metric::infinite(),
+                *sr,
+                self.router_id,
+            );
+            if let Err(e) = peer.send_control_packet(ControlPacket::Update(update)) {
+                error!("Error sending retraction to peer: {:?}", e);
+            }
+        }
+    }
+
     /// Propagate the static routes to a single peer
     fn propagate_static_route_to_peer(&self, peer: &Peer) {
         for sr in self.static_routes.iter() {
             let update = babel::Update::new(
-                UPDATE_INTERVAL,
+                self.config.update_interval,
                 self.router_seqno.read().unwrap().0, // updates receive the seqno of the router
                 Metric::from(0),                     // Static route has no further hop costs
                 *sr,
@@ -1236,7 +1694,7 @@ impl Router {
             .lookup_selected(subnet.address())
         {
             let update = babel::Update::new(
-                advertised_update_interval(sre),
+                advertised_update_interval(sre, self.config.update_interval),
                 sre.seqno(),
                 sre.metric() + Metric::from(sre.neighbour().link_cost()),
                 sre.source().subnet(),
@@ -1248,7 +1706,7 @@ impl Router {
             // TODO: is this possible in the first place?
             info!("Retracting route for {subnet}");
             let update = babel::Update::new(
-                UPDATE_INTERVAL,
+                self.config.update_interval,
                 self.router_seqno.read().unwrap().0,
                 Metric::infinite(),
                 subnet,
@@ -1293,7 +1751,7 @@ impl Router {
                 continue;
             }
             let update = babel::Update::new(
-                advertised_update_interval(sre),
+                advertised_update_interval(sre, self.config.update_interval),
                 sre.seqno(),
                 // the cost of the route is the cost of the route + the cost of the link to the next-hop
                 sre.metric() + neigh_link_cost,
@@ -1454,14 +1912,14 @@ impl Clone for RouterInner {
 }
 
 /// Calculate the hold time for a [`RouteEntry`] from an [`Update`](babel::Update) .
-fn route_hold_time(update: &babel::Update) -> Duration {
+fn route_hold_time(update: &babel::Update, retracted_route_hold_time: Duration) -> Duration {
     // According to https://datatracker.ietf.org/doc/html/rfc8966#section-appendix.b a good value
     // would be 3.5 times the update inteval.
     // In case of a retracted route: in general this should not be added to the routing table, so
     // the only reason this is called is because a route was retracted through an update. Even if
     // the peer won't send this again, hold the route for some time so it can get flushed properly.
     if update.metric().is_infinite() {
-        RETRACTED_ROUTE_HOLD_TIME
+        retracted_route_hold_time
     } else {
         // Route expiry time -> 3.5 times advertised Update interval.
         Duration::from_millis((update.interval().as_millis() * 7 / 2) as u64)
@@ -1469,7 +1927,7 @@ fn route_hold_time(update: &babel::Update) -> Duration {
 }
 
 /// Calculates the interval to use when announcing updates on (selected) routes.
-fn advertised_update_interval(sre: &RouteEntry) -> Duration {
+fn advertised_update_interval(sre: &RouteEntry, update_interval: Duration) -> Duration {
     // We actually just need to set the value of the update interval, since that is the upper bound
     // on when we will advertise the route again.
     // One caveat is an expired route. If an entry is expired, it means that it will change state
@@ -1478,14 +1936,28 @@ fn advertised_update_interval(sre: &RouteEntry) -> Duration {
     if sre.metric().is_infinite() && sre.expires().as_nanos() == 0 {
         INTERVAL_NOT_REPEATING
     } else {
-        UPDATE_INTERVAL
+        update_interval
+    }
+}
+
+/// Prepares a received [`SeqNoRequest`] to be forwarded. The hop count is first lowered to
+/// `hop_limit`, so peers can't make requests travel further through the network than our own
+/// requests. Afterwards it is decremented, if that is allowed. Returns false if the request must
+/// not be forwarded.
+fn forward_hop_count(seqno_request: &mut SeqNoRequest, hop_limit: NonZeroU8) -> bool {
+    seqno_request.limit_hop_count(hop_limit);
+    if seqno_request.hop_count() <= 1 {
+        return false;
     }
+    seqno_request.decrement_hop_count();
+    true
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
         net::{IpAddr, Ipv6Addr},
+        num::NonZeroU8,
         sync::{atomic::AtomicU64, Arc},
         time::Duration,
     };
@@ -1493,8 +1965,14 @@ mod tests {
     use tokio::sync::mpsc;
 
     use crate::{
-        babel::Update, crypto::PublicKey, metric::Metric, peer::Peer, router_id::RouterId,
-        sequence_number::SeqNo, source_table::SourceKey, subnet::Subnet,
+        babel::{SeqNoRequest, Update},
+        crypto::PublicKey,
+        metric::Metric,
+        peer::Peer,
+        router_id::RouterId,
+        sequence_number::SeqNo,
+        source_table::SourceKey,
+        subnet::Subnet,
     };
 
     #[test]
@@ -1512,19 +1990,19 @@ mod tests {
         let update = Update::new(Duration::from_secs(60), seqno, metric, subnet, router_id);
         assert_eq!(
             Duration::from_millis(210_000),
-            super::route_hold_time(&update)
+            super::route_hold_time(&update, super::RETRACTED_ROUTE_HOLD_TIME)
         );
         let update = Update::new(Duration::from_secs(1), seqno, metric, subnet, router_id);
         assert_eq!(
             Duration::from_millis(3_500),
-            super::route_hold_time(&update)
+            super::route_hold_time(&update, super::RETRACTED_ROUTE_HOLD_TIME)
         );
         // Since update is expressed in centiseconds, we lose precision and
         // Duration::from_milis(478) is equal to Duration::from_millis(470);
         let update = Update::new(Duration::from_millis(478), seqno, metric, subnet, router_id);
         assert_eq!(
             Duration::from_millis(1_645),
-            super::route_hold_time(&update)
+            super::route_hold_time(&update, super::RETRACTED_ROUTE_HOLD_TIME)
         );
 
         // Retractions are also held for some time
@@ -1537,7 +2015,74 @@ mod tests {
         );
         assert_eq!(
             super::RETRACTED_ROUTE_HOLD_TIME,
-            super::route_hold_time(&update)
+            super::route_hold_time(&update, super::RETRACTED_ROUTE_HOLD_TIME)
+        );
+    }
+
+    #[test]
+    fn forward_hop_count() {
+        let subnet = Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
+            .expect("Valid subnet definition");
+        let router_id = RouterId::new(PublicKey::from([0; 32]));
+        let limit = NonZeroU8::new(8).unwrap();
+
+        // Requests with a high hop count are limited before they are forwarded.
+        let mut snr = SeqNoRequest::new(SeqNo::new(), router_id, subnet);
+        assert!(super::forward_hop_count(&mut snr, limit));
+        assert_eq!(snr.hop_count(), 7);
+
+        // Requests within the limit are only decremented.
+        assert!(super::forward_hop_count(&mut snr, limit));
+        assert_eq!(snr.hop_count(), 6);
+
+        // Requests which already used all their hops are not forwarded.
+        let mut snr = SeqNoRequest::new(SeqNo::new(), router_id, subnet);
+        assert!(!super::forward_hop_count(
+            &mut snr,
+            NonZeroU8::new(1).unwrap()
+        ));
+        assert_eq!(snr.hop_count(), 1);
+    }
+
+    #[test]
+    fn validate_router_config() {
+        use super::{InvalidRouterConfig, RouterConfig};
+
+        assert_eq!(RouterConfig::default().validate(), Ok(()));
+
+        let config = RouterConfig {
+            hello_interval: Duration::from_secs(1),
+            dead_peer_threshold: Duration::from_secs(3),
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+
+        let config = RouterConfig {
+            hello_interval: Duration::from_secs(30),
+            dead_peer_threshold: Duration::from_secs(30),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(InvalidRouterConfig::DeadPeerThresholdTooLow)
+        );
+
+        let config = RouterConfig {
+            update_interval: Duration::from_secs(1000),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(InvalidRouterConfig::IntervalOutOfRange("update interval"))
+        );
+
+        let config = RouterConfig {
+            hello_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(InvalidRouterConfig::IntervalOutOfRange("hello interval"))
         );
     }
 
@@ -1576,7 +2121,7 @@ mod tests {
         );
         // We can't match exactly here since everything takes a non instant amount of time to do,
         // but basically verify that the calculated interval is within expected parameters.
-        let advertised_interval = super::advertised_update_interval(&re);
+        let advertised_interval = super::advertised_update_interval(&re, super::UPDATE_INTERVAL);
         assert_eq!(advertised_interval, super::UPDATE_INTERVAL);
 
         // Expired route with finite metric
@@ -1589,7 +2134,7 @@ mod tests {
             selected,
             expiration,
         );
-        let advertised_interval = super::advertised_update_interval(&re);
+        let advertised_interval = super::advertised_update_interval(&re, super::UPDATE_INTERVAL);
         assert_eq!(advertised_interval, super::UPDATE_INTERVAL);
 
         // Expired route with infinite metric
@@ -1601,7 +2146,7 @@ mod tests {
             selected,
             expiration,
         );
-        let advertised_interval = super::advertised_update_interval(&re);
+        let advertised_interval = super::advertised_update_interval(&re, super::UPDATE_INTERVAL);
         assert_eq!(advertised_interval, super::INTERVAL_NOT_REPEATING);
 
         // Check that the interval is properly capped
@@ -1609,7 +2154,7 @@ mod tests {
         let re = super::RouteEntry::new(source, neighbor, metric, seqno, selected, expiration);
         // We can't match exactly here since everything takes a non instant amount of time to do,
         // but basically verify that the calculated interval is within expected parameters.
-        let advertised_interval = super::advertised_update_interval(&re);
+        let advertised_interval = super::advertised_update_interval(&re, super::UPDATE_INTERVAL);
         assert_eq!(advertised_interval, super::UPDATE_INTERVAL);
     }
 }
@@ -0,0 +1,238 @@
+//! Cache of pending [`SeqNoRequest`]s, as described in
+//! [section 3.8.2 of the babel rfc](https://datatracker.ietf.org/doc/html/rfc8966#section-3.8.2).
+//!
+//! Every seqno request we send or forward is remembered until it is satisfied by an update, or
+//! until it has been resent a few times without reply. This allows us to retransmit requests
+//! which might have been lost, and to avoid forwarding the same request multiple times if it
+//! reaches us from multiple neighbours.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    babel::SeqNoRequest,
+    peer::{Peer, PeerRef},
+    sequence_number::SeqNo,
+    source_table::SourceKey,
+};
+
+/// Time to wait for a reply before a request is resent for the first time. This is doubled
+/// after every retry.
+const SEQNO_REQUEST_RETRY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Amount of times a request is resent before we give up on it.
+const MAX_SEQNO_REQUEST_RETRIES: u8 = 2;
+/// A request for a seqno which is not higher than the seqno of a request sent less than this
+/// time ago is considered a duplicate.
+const SEQNO_REQUEST_DUPLICATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// A seqno request which was sent, but for which no update was received yet.
+struct PendingSeqNoRequest {
+    /// The request as it was last sent.
+    request: SeqNoRequest,
+    /// The neighbour the request was sent to.
+    target: PeerRef,
+    /// Time at which the request was last sent.
+    sent: Instant,
+    /// Amount of times the request has been resent.
+    retries: u8,
+}
+
+/// A cache of pending [`SeqNoRequest`]s.
+#[derive(Default)]
+pub struct SeqnoCache {
+    requests: HashMap<SourceKey, PendingSeqNoRequest>,
+}
+
+impl SeqnoCache {
+    /// Create a new, empty `SeqnoCache`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks if an equivalent request was sent recently. This is the case if a request for the
+    /// same source, with a seqno which is at least as high as the seqno in the given request,
+    /// was sent within the duplicate window.
+    pub fn is_duplicate(&self, request: &SeqNoRequest, now: Instant) -> bool {
+        self.requests
+            .get(&SourceKey::new(request.prefix(), request.router_id()))
+            .map(|pending| {
+                !pending.request.seqno().lt(&request.seqno())
+                    && now.duration_since(pending.sent) < SEQNO_REQUEST_DUPLICATE_WINDOW
+            })
+            .unwrap_or(false)
+    }
+
+    /// Remember that the given request was sent to the target [`Peer`].
+    pub fn insert(&mut self, request: SeqNoRequest, target: &Peer, now: Instant) {
+        self.requests.insert(
+            SourceKey::new(request.prefix(), request.router_id()),
+            PendingSeqNoRequest {
+                request,
+                target: target.refer(),
+                sent: now,
+                retries: 0,
+            },
+        );
+    }
+
+    /// Notify the cache that an update with the given [`SeqNo`] was received for a source. If
+    /// this satisfies a pending request, the request is removed and true is returned.
+    pub fn satisfy(&mut self, source: &SourceKey, seqno: SeqNo) -> bool {
+        match self.requests.get(source) {
+            Some(pending) if !seqno.lt(&pending.request.seqno()) => {
+                self.requests.remove(source);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Get all requests which need to be resent, together with the [`Peer`] to send them to.
+    ///
+    /// Requests which have been resent too often, or for which the target is no longer
+    /// available, are removed.
+    pub fn retries(&mut self, now: Instant) -> Vec<(SeqNoRequest, Peer)> {
+        let mut retries = Vec::new();
+
+        self.requests.retain(|_, pending| {
+            let timeout = SEQNO_REQUEST_RETRY_TIMEOUT * 2u32.pow(pending.retries as u32);
+            if now.duration_since(pending.sent) < timeout {
+                return true;
+            }
+            if pending.retries >= MAX_SEQNO_REQUEST_RETRIES {
+                return false;
+            }
+            let target = match pending.target.upgrade() {
+                Some(target) if target.alive() => target,
+                _ => return false,
+            };
+
+            pending.retries += 1;
+            pending.sent = now;
+            retries.push((pending.request.clone(), target));
+
+            true
+        });
+
+        retries
+    }
+
+    /// Amount of pending requests in the cache.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv6Addr},
+        sync::{atomic::AtomicU64, Arc},
+        time::{Duration, Instant},
+    };
+
+    use tokio::sync::mpsc;
+
+    use super::SeqnoCache;
+    use crate::{
+        babel::SeqNoRequest, peer::Peer, router_id::RouterId, source_table::SourceKey,
+        subnet::Subnet,
+    };
+
+    fn dummy_peer() -> (Peer, tokio::io::DuplexStream) {
+        let (router_data_tx, _router_data_rx) = mpsc::channel(1);
+        let (router_control_tx, _router_control_rx) = mpsc::unbounded_channel();
+        let (dead_peer_sink, _dead_peer_stream) = mpsc::channel(1);
+        let (con1, con2) = tokio::io::duplex(1500);
+        let peer = Peer::new(
+            router_data_tx,
+            router_control_tx,
+            con1,
+            dead_peer_sink,
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(AtomicU64::new(0)),
+        )
+        .expect("Can create a dummy peer");
+        (peer, con2)
+    }
+
+    fn request(seqno: u16) -> SeqNoRequest {
+        SeqNoRequest::new(
+            seqno.into(),
+            RouterId::from([0; RouterId::BYTE_SIZE]),
+            Subnet::new(IpAddr::V6(Ipv6Addr::new(0x400, 0, 0, 0, 0, 0, 0, 0)), 64)
+                .expect("Valid subnet definition"),
+        )
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_detected() {
+        let (peer, _con) = dummy_peer();
+        let mut cache = SeqnoCache::new();
+        let now = Instant::now();
+
+        assert!(!cache.is_duplicate(&request(5), now));
+
+        cache.insert(request(5), &peer, now);
+
+        assert!(cache.is_duplicate(&request(4), now));
+        assert!(cache.is_duplicate(&request(5), now));
+        assert!(!cache.is_duplicate(&request(6), now));
+        assert!(!cache.is_duplicate(&request(5), now + Duration::from_secs(10)));
+    }
+
+    #[tokio::test]
+    async fn updates_satisfy_requests() {
+        let (peer, _con) = dummy_peer();
+        let mut cache = SeqnoCache::new();
+        let req = request(5);
+        let source = SourceKey::new(req.prefix(), req.router_id());
+
+        cache.insert(req, &peer, Instant::now());
+
+        assert!(!cache.satisfy(&source, 4.into()));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.satisfy(&source, 6.into()));
+        assert_eq!(cache.len(), 0);
+        assert!(!cache.satisfy(&source, 6.into()));
+    }
+
+    #[tokio::test]
+    async fn requests_are_retried() {
+        let (peer, _con) = dummy_peer();
+        let mut cache = SeqnoCache::new();
+        let now = Instant::now();
+
+        cache.insert(request(5), &peer, now);
+
+        assert!(cache.retries(now + Duration::from_secs(1)).is_empty());
+
+        let retries = cache.retries(now + Duration::from_secs(2));
+        assert_eq!(retries.len(), 1);
+        assert_eq!(retries[0].0, request(5));
+        assert_eq!(retries[0].1, peer);
+
+        // Timeout doubles after every retry.
+        assert!(cache.retries(now + Duration::from_secs(5)).is_empty());
+        assert_eq!(cache.retries(now + Duration::from_secs(6)).len(), 1);
+
+        // Give up after the max amount of retries.
+        assert!(cache.retries(now + Duration::from_secs(20)).is_empty());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn requests_to_dead_peers_are_dropped() {
+        let (peer, _con) = dummy_peer();
+        let mut cache = SeqnoCache::new();
+        let now = Instant::now();
+
+        cache.insert(request(5), &peer, now);
+        peer.died();
+
+        assert!(cache.retries(now + Duration::from_secs(2)).is_empty());
+        assert_eq!(cache.len(), 0);
+    }
+}
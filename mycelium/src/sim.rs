@@ -0,0 +1,577 @@
+//! An in-process simulation of a network of routers.
+//!
+//! A [`Network`] consists of a set of [`Router`]s which are connected by in-memory links. Every
+//! link has a configurable latency and packet loss, and can be failed, cut and restored at
+//! runtime. Since everything runs in the same process on tokio timers, a simulation is meant to
+//! be run on a paused clock (e.g. `#[tokio::test(start_paused = true)]`), in which case hours of
+//! protocol time pass in milliseconds.
+//!
+//! This module is only available in tests, or when the `test-support` feature is enabled.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{SinkExt, StreamExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{
+    io::{DuplexStream, ReadHalf, WriteHalf},
+    sync::mpsc,
+    task::JoinHandle,
+    time::Instant,
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::{
+    crypto::{PublicKey, SecretKey},
+    data::DataPacket,
+    packet::{self, Packet},
+    peer::Peer,
+    router::{Router, RouterConfig, UpdateFilters},
+    subnet::Subnet,
+};
+
+/// Size of the buffer of the in-memory streams used for a link.
+const LINK_BUFFER_SIZE: usize = 64 * 1024;
+/// Interval at which [`Network::wait_converged`] checks the state of the network.
+const CONVERGENCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Properties of a simulated link between 2 nodes.
+#[derive(Debug, Clone, Copy)]
+pub struct LinkConfig {
+    /// Time it takes for a packet to cross the link, in either direction.
+    pub latency: Duration,
+    /// Chance for a packet to be dropped, between 0 (no loss) and 1 (all packets are lost).
+    pub loss: f64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(10),
+            loss: 0.0,
+        }
+    }
+}
+
+/// A node in a simulated [`Network`].
+pub struct SimNode {
+    router: Router,
+    subnet: Subnet,
+    /// Packets routed to the node itself. This is never read, but must be kept so the router
+    /// does not see a closed channel.
+    _tun_rx: mpsc::UnboundedReceiver<DataPacket>,
+}
+
+impl SimNode {
+    fn new(config: RouterConfig) -> Self {
+        let secret_key = SecretKey::new();
+        let public_key = PublicKey::from(&secret_key);
+        let subnet = Subnet::new(
+            Subnet::new(public_key.address().into(), 64)
+                .expect("64 is a valid IPv6 prefix size; qed")
+                .network(),
+            64,
+        )
+        .expect("64 is a valid IPv6 prefix size; qed");
+        let (tun_tx, _tun_rx) = mpsc::unbounded_channel();
+
+        let router = Router::new(
+            tun_tx,
+            subnet,
+            vec![subnet],
+            (secret_key, public_key),
+            config,
+            UpdateFilters {
+                fixed: crate::builtin_update_filters(),
+                configured: vec![],
+            },
+            None,
+        )
+        .expect("Can create a router with a valid config and without snapshot");
+
+        Self {
+            router,
+            subnet,
+            _tun_rx,
+        }
+    }
+
+    /// The [`Router`] of this node.
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
+    /// The subnet announced by this node.
+    pub fn subnet(&self) -> Subnet {
+        self.subnet
+    }
+
+    /// Create a new [`Peer`] for the router of this node on the given connection.
+    fn new_peer(&self, con: DuplexStream) -> Peer {
+        Peer::new(
+            self.router.router_data_tx(),
+            self.router.router_control_tx(),
+            con,
+            self.router.dead_peer_sink().clone(),
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(AtomicU64::new(0)),
+        )
+        .expect("In memory connections have an identifier and link cost")
+    }
+}
+
+/// A link between 2 nodes in a simulated [`Network`].
+struct Link {
+    nodes: (usize, usize),
+    config: LinkConfig,
+    /// Indicates if packets can cross the link. If this is false, all packets are silently
+    /// dropped.
+    up: Arc<AtomicBool>,
+    /// The peers in the routers of both nodes, if the link is currently connected.
+    peers: Option<(Peer, Peer)>,
+    /// Tasks moving packets across the link.
+    relays: Vec<JoinHandle<()>>,
+}
+
+impl Link {
+    /// The node on the other side of the link, if `node` is on this link and `peer` is the
+    /// current peer used by `node` for this link.
+    fn remote(&self, node: usize, peer: &Peer) -> Option<usize> {
+        let (a, b) = self.nodes;
+        let (peer_a, peer_b) = self.peers.as_ref()?;
+        if node == a && peer == peer_a {
+            Some(b)
+        } else if node == b && peer == peer_b {
+            Some(a)
+        } else {
+            None
+        }
+    }
+
+    /// Set up a new connection for the link, and add the peers to the routers of both nodes.
+    fn open(&mut self, nodes: &[SimNode], rng: &mut StdRng) {
+        let (a, b) = self.nodes;
+        let (con_a, relay_a) = tokio::io::duplex(LINK_BUFFER_SIZE);
+        let (con_b, relay_b) = tokio::io::duplex(LINK_BUFFER_SIZE);
+
+        let peer_a = nodes[a].new_peer(con_a);
+        let peer_b = nodes[b].new_peer(con_b);
+
+        let (relay_a_read, relay_a_write) = tokio::io::split(relay_a);
+        let (relay_b_read, relay_b_write) = tokio::io::split(relay_b);
+        self.relays = [
+            relay(relay_a_read, relay_b_write, self, rng.gen()),
+            relay(relay_b_read, relay_a_write, self, rng.gen()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        nodes[a].router.add_peer_interface(peer_a.clone());
+        nodes[b].router.add_peer_interface(peer_b.clone());
+        self.peers = Some((peer_a, peer_b));
+    }
+
+    /// Stop moving packets across the link, closing the connection between the nodes.
+    fn close(&mut self) {
+        for relay in self.relays.drain(..) {
+            relay.abort();
+        }
+        if let Some((peer_a, peer_b)) = self.peers.take() {
+            peer_a.died();
+            peer_b.died();
+        }
+    }
+}
+
+/// The path taken by packets from one node to another, according to the selected routes of the
+/// nodes along the way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Path {
+    /// The destination is reached through the given nodes, including source and destination.
+    Complete(Vec<usize>),
+    /// The last node of the given nodes does not have a usable route to the destination.
+    Broken(Vec<usize>),
+    /// Packets loop through the given nodes. The last node is the first node which is visited
+    /// twice.
+    Loop(Vec<usize>),
+}
+
+/// A simulated network of routers.
+pub struct Network {
+    nodes: Vec<SimNode>,
+    links: Vec<Link>,
+    rng: StdRng,
+}
+
+impl Network {
+    /// Create a new network of `size` nodes, without any links. Packet loss on links is decided
+    /// by a random generator seeded with `seed`.
+    pub fn new(size: usize, seed: u64) -> Self {
+        Self::with_router_config(size, seed, RouterConfig::default())
+    }
+
+    /// Create a new network of `size` nodes, which use the given timers, without any links.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the [`RouterConfig`] is not valid.
+    pub fn with_router_config(size: usize, seed: u64, config: RouterConfig) -> Self {
+        Self {
+            nodes: (0..size).map(|_| SimNode::new(config)).collect(),
+            links: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// The nodes in the network.
+    pub fn nodes(&self) -> &[SimNode] {
+        &self.nodes
+    }
+
+    /// Connect node `a` and node `b` with a new link. Returns the index of the link.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either node does not exist, or if the configured loss is not between 0 and 1.
+    pub fn connect(&mut self, a: usize, b: usize, config: LinkConfig) -> usize {
+        assert!(a < self.nodes.len() && b < self.nodes.len(), "unknown node");
+        assert!((0.0..=1.0).contains(&config.loss), "invalid link loss");
+
+        let mut link = Link {
+            nodes: (a, b),
+            config,
+            up: Arc::new(AtomicBool::new(true)),
+            peers: None,
+            relays: Vec::new(),
+        };
+        link.open(&self.nodes, &mut self.rng);
+        self.links.push(link);
+        self.links.len() - 1
+    }
+
+    /// Silently drop all packets on a link, without closing the connection. The nodes need to
+    /// notice the failure themselves.
+    pub fn fail_link(&mut self, link: usize) {
+        self.links[link].up.store(false, Ordering::Relaxed);
+    }
+
+    /// Close the connection on a link. Both nodes are immediately notified.
+    pub fn cut_link(&mut self, link: usize) {
+        let link = &mut self.links[link];
+        link.up.store(false, Ordering::Relaxed);
+        link.close();
+    }
+
+    /// Restore a failed or cut link. This always sets up a new connection between the nodes.
+    pub fn restore_link(&mut self, link: usize) {
+        let Self { nodes, links, rng } = self;
+        let link = &mut links[link];
+        link.close();
+        link.up = Arc::new(AtomicBool::new(true));
+        link.open(nodes, rng);
+    }
+
+    /// Fail all links between nodes in `group` and nodes outside of it.
+    pub fn partition(&mut self, group: &[usize]) {
+        for idx in 0..self.links.len() {
+            let (a, b) = self.links[idx].nodes;
+            if group.contains(&a) != group.contains(&b) {
+                self.fail_link(idx);
+            }
+        }
+    }
+
+    /// Restore all links which are currently failed or cut.
+    pub fn heal(&mut self) {
+        for idx in 0..self.links.len() {
+            if !self.links[idx].up.load(Ordering::Relaxed) {
+                self.restore_link(idx);
+            }
+        }
+    }
+
+    /// Let the network run for the given duration.
+    pub async fn settle(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    /// Wait until the network is [converged](Self::converged), checking every second. Returns
+    /// false if the network did not converge within `timeout`.
+    pub async fn wait_converged(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.converged() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(CONVERGENCE_CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Check if the network is converged. This is the case if every node has a loop free path
+    /// to every node it can reach over links which are up, and no usable route to any node it
+    /// can't reach.
+    pub fn converged(&self) -> bool {
+        (0..self.nodes.len()).all(|from| {
+            let reachable = self.reachable(from);
+            (0..self.nodes.len())
+                .filter(|to| *to != from)
+                .all(|to| match self.path(from, to) {
+                    Path::Complete(_) => reachable.contains(&to),
+                    Path::Broken(ref hops) => !reachable.contains(&to) && hops.len() == 1,
+                    Path::Loop(_) => false,
+                })
+        })
+    }
+
+    /// Find a routing loop in the network. If there is one, the path which loops is returned.
+    pub fn find_loop(&self) -> Option<Vec<usize>> {
+        for from in 0..self.nodes.len() {
+            for to in (0..self.nodes.len()).filter(|to| *to != from) {
+                if let Path::Loop(hops) = self.path(from, to) {
+                    return Some(hops);
+                }
+            }
+        }
+        None
+    }
+
+    /// Follow the selected routes from node `from` to node `to`.
+    pub fn path(&self, from: usize, to: usize) -> Path {
+        let destination = self.nodes[to].subnet;
+        let mut hops = vec![from];
+        let mut current = from;
+        while current != to {
+            let Some(next) = self.next_hop(current, destination) else {
+                return Path::Broken(hops);
+            };
+            let looped = hops.contains(&next);
+            hops.push(next);
+            if looped {
+                return Path::Loop(hops);
+            }
+            current = next;
+        }
+        Path::Complete(hops)
+    }
+
+    /// The node used as next hop by `node` to reach `destination`, if it has a usable route.
+    fn next_hop(&self, node: usize, destination: Subnet) -> Option<usize> {
+        let route = self.nodes[node]
+            .router
+            .load_selected_routes()
+            .into_iter()
+            .find(|re| re.source().subnet() == destination && !re.metric().is_infinite())?;
+        self.links
+            .iter()
+            .find_map(|link| link.remote(node, route.neighbour()))
+    }
+
+    /// All nodes which can be reached from `node` over links which are up.
+    fn reachable(&self, node: usize) -> HashSet<usize> {
+        let mut seen = HashSet::from([node]);
+        let mut queue = VecDeque::from([node]);
+        while let Some(current) = queue.pop_front() {
+            for link in &self.links {
+                if !link.up.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let next = match link.nodes {
+                    (a, b) if a == current => b,
+                    (a, b) if b == current => a,
+                    _ => continue,
+                };
+                if seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        for link in &mut self.links {
+            link.close();
+        }
+    }
+}
+
+/// Move packets in one direction across a link, applying the loss and latency of the link.
+fn relay(
+    from: ReadHalf<DuplexStream>,
+    to: WriteHalf<DuplexStream>,
+    link: &Link,
+    seed: u64,
+) -> [JoinHandle<()>; 2] {
+    let config = link.config;
+    let up = link.up.clone();
+    let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, Packet)>();
+
+    let receiver = tokio::spawn(async move {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut frames = FramedRead::new(from, packet::Codec::new());
+        while let Some(Ok(packet)) = frames.next().await {
+            if !up.load(Ordering::Relaxed) || rng.gen_bool(config.loss) {
+                continue;
+            }
+            if tx.send((Instant::now() + config.latency, packet)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let sender = tokio::spawn(async move {
+        let mut sink = FramedWrite::new(to, packet::Codec::new());
+        while let Some((deliver_at, packet)) = rx.recv().await {
+            tokio::time::sleep_until(deliver_at).await;
+            if sink.send(packet).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    [receiver, sender]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LinkConfig, Network, Path};
+    use crate::router::RouterConfig;
+
+    /// Upper bound for the time it takes the network to converge after a change.
+    const CONVERGENCE_TIMEOUT: Duration = Duration::from_secs(300);
+
+    fn line(size: usize) -> Network {
+        let mut network = Network::new(size, 0);
+        for node in 1..size {
+            network.connect(node - 1, node, LinkConfig::default());
+        }
+        network
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn line_converges() {
+        let network = line(5);
+
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+        assert_eq!(network.path(0, 4), Path::Complete(vec![0, 1, 2, 3, 4]));
+        assert_eq!(network.path(4, 0), Path::Complete(vec![4, 3, 2, 1, 0]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lossy_ring_converges() {
+        let mut network = Network::new(6, 1);
+        for node in 0..6 {
+            network.connect(
+                node,
+                (node + 1) % 6,
+                LinkConfig {
+                    latency: Duration::from_millis(50),
+                    loss: 0.05,
+                },
+            );
+        }
+
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+        assert_eq!(network.find_loop(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cut_link_is_routed_around() {
+        let mut network = Network::new(4, 2);
+        for node in 0..4 {
+            network.connect(node, (node + 1) % 4, LinkConfig::default());
+        }
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+
+        network.cut_link(0);
+
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+        assert_eq!(network.path(0, 1), Path::Complete(vec![0, 3, 2, 1]));
+        assert_eq!(network.find_loop(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_link_is_detected() {
+        let mut network = line(3);
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+
+        // Nothing is reported to the nodes, they need to notice the lack of traffic.
+        network.fail_link(1);
+
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+        assert_eq!(network.path(0, 2), Path::Broken(vec![0]));
+        assert_eq!(network.find_loop(), None);
+
+        network.restore_link(1);
+
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+        assert_eq!(network.path(0, 2), Path::Complete(vec![0, 1, 2]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn short_timers_detect_failures_faster() {
+        let config = RouterConfig {
+            hello_interval: Duration::from_secs(1),
+            dead_peer_threshold: Duration::from_secs(3),
+            ..Default::default()
+        };
+        let mut network = Network::with_router_config(3, 4, config);
+        network.connect(0, 1, LinkConfig::default());
+        network.connect(1, 2, LinkConfig::default());
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+
+        network.fail_link(1);
+
+        // With the default timers, it takes at least 43 seconds to notice the failure.
+        assert!(network.wait_converged(Duration::from_secs(10)).await);
+        assert_eq!(network.path(0, 2), Path::Broken(vec![0]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn partition_and_heal() {
+        let mut network = Network::new(6, 3);
+        // 2 triangles, connected by 2 links.
+        for (a, b) in [
+            (0, 1),
+            (1, 2),
+            (2, 0),
+            (3, 4),
+            (4, 5),
+            (5, 3),
+            (0, 3),
+            (2, 5),
+        ] {
+            network.connect(a, b, LinkConfig::default());
+        }
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+
+        network.partition(&[0, 1, 2]);
+
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+        for from in 0..3 {
+            for to in 3..6 {
+                assert_eq!(network.path(from, to), Path::Broken(vec![from]));
+                assert_eq!(network.path(to, from), Path::Broken(vec![to]));
+            }
+        }
+
+        network.heal();
+
+        assert!(network.wait_converged(CONVERGENCE_TIMEOUT).await);
+        assert_eq!(network.find_loop(), None);
+    }
+}
@@ -0,0 +1,257 @@
+//! Persistent snapshot of the routing state, which allows a node to restart without starting
+//! from a clean slate.
+//!
+//! The snapshot contains our [`RouterId`], the [`SeqNo`] we announce our static routes with, and
+//! the [`FeasibilityDistance`] of all entries in the [`SourceTable`](crate::source_table::SourceTable).
+//! Reusing the [`RouterId`] after a restart means our routes are recognized by other nodes as
+//! the same source. Since they remember the seqno we previously announced, we must make sure
+//! to never announce a lower one after the restart.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    metric::Metric,
+    router_id::RouterId,
+    sequence_number::SeqNo,
+    source_table::{FeasibilityDistance, SourceKey},
+    subnet::Subnet,
+};
+
+/// Magic bytes at the start of a snapshot file.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"mysn";
+/// Version of the snapshot format.
+const SNAPSHOT_VERSION: u8 = 1;
+/// Address family marker of an IPv4 subnet.
+const FAMILY_IPV4: u8 = 4;
+/// Address family marker of an IPv6 subnet.
+const FAMILY_IPV6: u8 = 6;
+
+/// The routing state of a [`Router`](crate::router::Router) which is persisted across restarts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouterSnapshot {
+    /// The [`RouterId`] in use by the router.
+    pub router_id: RouterId,
+    /// The [`SeqNo`] our static routes are announced with.
+    pub seqno: SeqNo,
+    /// All feasibility distances in the source table.
+    pub feasibility_distances: Vec<(SourceKey, FeasibilityDistance)>,
+}
+
+impl RouterSnapshot {
+    /// Load a `RouterSnapshot` from the given path. If there is no file at the path, `None` is
+    /// returned.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        Self::from_bytes(&data).map(Some)
+    }
+
+    /// Save this `RouterSnapshot` to the given path. The snapshot is first written to a temporary
+    /// file, which then replaces the existing file. As a result, there is always a complete
+    /// snapshot at the path, even if the process is interrupted while saving.
+    pub async fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        file.write_all(&self.to_bytes()).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        tokio::fs::rename(&tmp_path, path).await
+    }
+
+    /// Encode this `RouterSnapshot`.
+    fn to_bytes(&self) -> BytesMut {
+        let mut buf = BytesMut::new();
+
+        buf.put_slice(SNAPSHOT_MAGIC);
+        buf.put_u8(SNAPSHOT_VERSION);
+        buf.put_slice(&self.router_id.as_bytes());
+        buf.put_u16(self.seqno.into());
+        buf.put_u32(self.feasibility_distances.len() as u32);
+        for (source_key, fd) in &self.feasibility_distances {
+            let subnet = source_key.subnet();
+            match subnet.address() {
+                IpAddr::V4(ip) => {
+                    buf.put_u8(FAMILY_IPV4);
+                    buf.put_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    buf.put_u8(FAMILY_IPV6);
+                    buf.put_slice(&ip.octets());
+                }
+            }
+            buf.put_u8(subnet.prefix_len());
+            buf.put_slice(&source_key.router_id().as_bytes());
+            buf.put_u16(fd.metric().into());
+            buf.put_u16(fd.seqno().into());
+        }
+
+        buf
+    }
+
+    /// Decode a `RouterSnapshot`.
+    fn from_bytes(mut src: &[u8]) -> io::Result<Self> {
+        if src.remaining() < SNAPSHOT_MAGIC.len() + 1
+            || &src[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC
+        {
+            return Err(invalid_data("not a router snapshot"));
+        }
+        src.advance(SNAPSHOT_MAGIC.len());
+        if src.get_u8() != SNAPSHOT_VERSION {
+            return Err(invalid_data("unsupported router snapshot version"));
+        }
+
+        let router_id = read_router_id(&mut src)?;
+        ensure_remaining(&src, 6)?;
+        let seqno = src.get_u16().into();
+        let count = src.get_u32();
+
+        let mut feasibility_distances = Vec::new();
+        for _ in 0..count {
+            ensure_remaining(&src, 1)?;
+            let address = match src.get_u8() {
+                FAMILY_IPV4 => {
+                    ensure_remaining(&src, 4)?;
+                    let mut raw_ip = [0; 4];
+                    src.copy_to_slice(&mut raw_ip);
+                    IpAddr::V4(Ipv4Addr::from(raw_ip))
+                }
+                FAMILY_IPV6 => {
+                    ensure_remaining(&src, 16)?;
+                    let mut raw_ip = [0; 16];
+                    src.copy_to_slice(&mut raw_ip);
+                    IpAddr::V6(Ipv6Addr::from(raw_ip))
+                }
+                _ => return Err(invalid_data("unknown address family in router snapshot")),
+            };
+            ensure_remaining(&src, 1)?;
+            let subnet = Subnet::new(address, src.get_u8())
+                .map_err(|_| invalid_data("invalid prefix length in router snapshot"))?;
+            let source_router_id = read_router_id(&mut src)?;
+            ensure_remaining(&src, 4)?;
+            let metric = Metric::from(src.get_u16());
+            let fd_seqno = SeqNo::from(src.get_u16());
+
+            feasibility_distances.push((
+                SourceKey::new(subnet, source_router_id),
+                FeasibilityDistance::new(metric, fd_seqno),
+            ));
+        }
+
+        Ok(Self {
+            router_id,
+            seqno,
+            feasibility_distances,
+        })
+    }
+}
+
+/// Read a [`RouterId`] from the buffer.
+fn read_router_id(src: &mut &[u8]) -> io::Result<RouterId> {
+    ensure_remaining(src, RouterId::BYTE_SIZE)?;
+    let mut raw = [0; RouterId::BYTE_SIZE];
+    src.copy_to_slice(&mut raw);
+    Ok(RouterId::from(raw))
+}
+
+/// Ensure at least `len` bytes are left in the buffer.
+fn ensure_remaining(src: &&[u8], len: usize) -> io::Result<()> {
+    if src.remaining() < len {
+        Err(invalid_data("router snapshot is truncated"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Create an [`io::Error`] indicating a malformed snapshot.
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::RouterSnapshot;
+    use crate::{
+        crypto::{PublicKey, SecretKey},
+        router_id::RouterId,
+        source_table::{FeasibilityDistance, SourceKey},
+        subnet::Subnet,
+    };
+
+    fn snapshot() -> RouterSnapshot {
+        RouterSnapshot {
+            router_id: RouterId::new(PublicKey::from(&SecretKey::new())),
+            seqno: 65000.into(),
+            feasibility_distances: vec![
+                (
+                    SourceKey::new(
+                        Subnet::new(Ipv6Addr::new(0x400, 1, 2, 3, 0, 0, 0, 0).into(), 64)
+                            .expect("64 is a valid IPv6 prefix size; qed"),
+                        RouterId::new(PublicKey::from(&SecretKey::new())),
+                    ),
+                    FeasibilityDistance::new(25.into(), 3.into()),
+                ),
+                (
+                    SourceKey::new(
+                        Subnet::new(Ipv4Addr::new(10, 0, 0, 0).into(), 8)
+                            .expect("8 is a valid IPv4 prefix size; qed"),
+                        RouterId::new(PublicKey::from(&SecretKey::new())),
+                    ),
+                    FeasibilityDistance::new(1000.into(), 65535.into()),
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let snapshot = snapshot();
+
+        let decoded =
+            RouterSnapshot::from_bytes(&snapshot.to_bytes()).expect("Can decode encoded snapshot");
+
+        assert_eq!(decoded, snapshot);
+    }
+
+    #[test]
+    fn rejects_invalid_data() {
+        let encoded = snapshot().to_bytes();
+
+        assert!(RouterSnapshot::from_bytes(&encoded[..encoded.len() - 1]).is_err());
+        assert!(RouterSnapshot::from_bytes(&encoded[1..]).is_err());
+        assert!(RouterSnapshot::from_bytes(&[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let path = std::env::temp_dir().join(format!(
+            "mycelium-snapshot-test-{}.bin",
+            rand::random::<u64>()
+        ));
+        assert_eq!(
+            RouterSnapshot::load(&path).expect("Missing file is not an error"),
+            None
+        );
+
+        let snapshot = snapshot();
+        snapshot.save(&path).await.expect("Can save snapshot");
+        let loaded = RouterSnapshot::load(&path).expect("Can load saved snapshot");
+        std::fs::remove_file(&path).expect("Can remove snapshot file");
+
+        assert_eq!(loaded, Some(snapshot));
+    }
+}
@@ -18,7 +18,7 @@ pub struct SourceKey {
     router_id: RouterId,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct FeasibilityDistance {
     metric: Metric,
     seqno: SeqNo,
@@ -125,6 +125,11 @@ impl SourceTable {
         self.table.get(key).map(|(_, v)| v)
     }
 
+    /// Iterate over all entries in the `SourceTable`.
+    pub fn iter(&self) -> impl Iterator<Item = (&SourceKey, &FeasibilityDistance)> {
+        self.table.iter().map(|(k, (_, fd))| (k, fd))
+    }
+
     /// Indicates if an update is feasible in the context of the current `SoureTable`.
     pub fn is_update_feasible(&self, update: &babel::Update) -> bool {
         // Before an update is accepted it should be checked against the feasbility condition
@@ -9,9 +9,10 @@
 //! might not be optimal for other uses.
 
 use core::fmt;
-use std::net::IpAddr;
+use std::{net::IpAddr, str::FromStr};
 
 use ipnet::IpNet;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 
 /// Representation of a subnet. A subnet can be either IPv4 or IPv6.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -25,6 +26,11 @@ pub struct Subnet {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PrefixLenError;
 
+/// An error returned when parsing a [`Subnet`] from a string which is not in the form of
+/// `address/prefix_len`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubnetParseError;
+
 impl Subnet {
     /// Create a new `Subnet` from the given [`IpAddr`] and prefix length.
     pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Subnet, PrefixLenError> {
@@ -89,6 +95,36 @@ impl fmt::Display for Subnet {
     }
 }
 
+impl FromStr for Subnet {
+    type Err = SubnetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            inner: IpNet::from_str(s).map_err(|_| SubnetParseError)?,
+        })
+    }
+}
+
+impl Serialize for Subnet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
 impl fmt::Display for PrefixLenError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str("Invalid prefix length for this address")
@@ -96,3 +132,11 @@ impl fmt::Display for PrefixLenError {
 }
 
 impl std::error::Error for PrefixLenError {}
+
+impl fmt::Display for SubnetParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Invalid subnet, expected an address and prefix length like 10.0.0.0/8")
+    }
+}
+
+impl std::error::Error for SubnetParseError {}
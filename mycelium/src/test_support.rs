@@ -0,0 +1,95 @@
+//! Helpers to set up [`Node`]s and fixtures in tests, shared by the tests of this crate and of
+//! crates depending on it.
+//!
+//! This module is only available in tests, or when the `test-support` feature is enabled.
+
+use std::time::Duration;
+
+#[cfg(feature = "message")]
+use crate::message::{MessageId, ReceivedMessage};
+use crate::{crypto::SecretKey, peer_manager::PeerAcl, Config, Node};
+
+/// Size of the buffer of the in-memory connection between 2 nodes.
+const MEMORY_PEER_BUFFER_SIZE: usize = 1500;
+
+/// A [`Config`] for a node with the given key, which does not create a TUN interface, has no
+/// peers and listens on random ports.
+pub fn node_config(node_key: SecretKey) -> Config {
+    Config {
+        node_key,
+        peers: vec![],
+        no_tun: true,
+        tcp_listen_port: 0,
+        quic_listen_port: 0,
+        tls_listen_port: None,
+        ws_listen_port: None,
+        wss_listen_port: None,
+        ws_proxy: None,
+        unix_listen_path: None,
+        peer_discovery_port: None,
+        tun_name: String::new(),
+        peer_acl: PeerAcl::default(),
+        state_dir: None,
+        persist_messages: false,
+        #[cfg(feature = "message")]
+        inbox_limits: crate::message::InboxLimits::default(),
+        extra_subnets: vec![],
+        update_filters: vec![],
+        router_config: Default::default(),
+    }
+}
+
+/// Create a node from [`node_config`].
+pub async fn node(node_key: SecretKey) -> Node {
+    Node::new(node_config(node_key))
+        .await
+        .expect("Can create node")
+}
+
+/// Connect 2 nodes over an in-memory connection. The handshake happens in the background.
+pub fn connect(a: &Node, b: &Node) {
+    let (con1, con2) = tokio::io::duplex(MEMORY_PEER_BUFFER_SIZE);
+    a.add_memory_peer(con1);
+    b.add_memory_peer(con2);
+}
+
+/// Create two nodes which are connected to each other, and wait until they have a route to
+/// each other.
+pub async fn connected_nodes() -> (Node, Node) {
+    let (node1, node2) = (node(SecretKey::new()).await, node(SecretKey::new()).await);
+    connect(&node1, &node2);
+
+    let has_route = |node: &Node, target: &Node| {
+        node.selected_routes().iter().any(|re| {
+            re.source().subnet() == target.info().node_subnet && !re.metric().is_infinite()
+        })
+    };
+    for _ in 0..50 {
+        if has_route(&node1, &node2) && has_route(&node2, &node1) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(has_route(&node1, &node2) && has_route(&node2, &node1));
+
+    (node1, node2)
+}
+
+/// A received message with the given topic and data, from a fixed sender in the global subnet.
+#[cfg(feature = "message")]
+pub fn received_message(topic: &[u8], data: &[u8]) -> ReceivedMessage {
+    let (src_pk, dst_pk) = (
+        crate::crypto::PublicKey::from([1; 32]),
+        crate::crypto::PublicKey::from([2; 32]),
+    );
+    ReceivedMessage {
+        id: MessageId::new(),
+        is_reply: false,
+        src_ip: src_pk.address().into(),
+        src_pk,
+        dst_ip: dst_pk.address().into(),
+        dst_pk,
+        topic: topic.to_vec(),
+        data: data.to_vec(),
+    }
+}
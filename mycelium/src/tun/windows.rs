@@ -93,6 +93,10 @@ pub async fn new(
                 }
             }
         }
+        // Also stop the ingress path, which is blocked waiting for a packet.
+        if let Err(e) = tx_session.shutdown() {
+            error!("Could not shut down TUN session: {e}");
+        }
         info!("Stop writing to tun interface");
     });
 
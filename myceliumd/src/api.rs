@@ -1,21 +1,27 @@
-use std::{net::SocketAddr, str::FromStr, sync::Arc};
+use std::{fmt, net::SocketAddr, str::FromStr, sync::Arc, time::UNIX_EPOCH};
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
-    routing::{delete, get},
+    routing::{delete, get, put},
     Json, Router,
 };
 use log::{debug, error};
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use tokio::sync::Mutex;
 
 use mycelium::{
     endpoint::Endpoint,
-    peer_manager::{PeerExists, PeerNotFound, PeerStats},
+    filters::{FilterConfig, FilterStats},
+    peer_manager::{PeerAcl, PeerAclStats, PeerExists, PeerNotFound, PeerStats},
+    route_history::RouteChangeReason,
+    subnet::Subnet,
 };
 
-mod message;
+use crate::delivery::TopicRouter;
+
+pub mod message;
+mod metrics;
 
 /// Http API server handle. The server is spawned in a background task. If this handle is dropped,
 /// the server is terminated.
@@ -30,24 +36,43 @@ pub struct Http {
 struct HttpServerState {
     /// Access to the (`node`)(mycelium::Node) state.
     node: Arc<Mutex<mycelium::Node>>,
+    /// Delivery of messages to local applications.
+    topic_router: Arc<TopicRouter>,
 }
 
 impl Http {
-    /// Spawns a new HTTP API server on the provided listening address.
-    pub fn spawn(node: mycelium::Node, listen_addr: SocketAddr) -> Self {
-        let server_state = HttpServerState {
-            node: Arc::new(Mutex::new(node)),
-        };
+    /// Spawns a new HTTP API server on the provided listening address. If `enable_metrics` is
+    /// set, metrics are exposed in the Prometheus text format on `/metrics`.
+    pub fn spawn(
+        node: Arc<Mutex<mycelium::Node>>,
+        topic_router: Arc<TopicRouter>,
+        listen_addr: SocketAddr,
+        enable_metrics: bool,
+    ) -> Self {
+        let server_state = HttpServerState { node, topic_router };
         let admin_routes = Router::new()
             .route("/admin", get(get_info))
             .route("/admin/peers", get(get_peers).post(add_peer))
-            .route("/admin/peers/:endpoint", delete(delete_peer))
+            .route(
+                "/admin/peers/:endpoint",
+                delete(delete_peer).patch(update_peer),
+            )
+            .route("/admin/peers/acl", get(get_peer_acl).put(set_peer_acl))
+            .route("/admin/peers/acl/stats", get(get_peer_acl_stats))
             .route("/admin/routes/selected", get(get_selected_routes))
             .route("/admin/routes/fallback", get(get_fallback_routes))
+            .route("/admin/routes/history", get(get_route_history))
+            .route(
+                "/admin/filters",
+                get(get_update_filters).put(set_update_filters),
+            )
             .with_state(server_state.clone());
-        let app = Router::new()
+        let mut app = Router::new()
             .nest("/api/v1", admin_routes)
-            .nest("/api/v1", message::message_router_v1(server_state));
+            .nest("/api/v1", message::message_router_v1(server_state.clone()));
+        if enable_metrics {
+            app = app.merge(metrics::metrics_router(server_state));
+        }
 
         let (_cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
 
@@ -85,6 +110,16 @@ async fn get_peers(State(state): State<HttpServerState>) -> Json<Vec<PeerStats>>
 pub struct AddPeer {
     /// The endpoint used to connect to the peer
     pub endpoint: String,
+    /// Optional cost added to the link cost of the peer
+    #[serde(default)]
+    pub cost: Option<u16>,
+}
+
+/// Payload of an update_peer request
+#[derive(Deserialize)]
+pub struct UpdatePeer {
+    /// Cost added to the link cost of the peer, or null to remove it
+    pub cost: Option<u16>,
 }
 
 /// Add a new peer to the system
@@ -98,8 +133,15 @@ async fn add_peer(
         Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string())),
     };
 
-    match state.node.lock().await.add_peer(endpoint) {
-        Ok(()) => Ok(StatusCode::NO_CONTENT),
+    let node = state.node.lock().await;
+    match node.add_peer(endpoint.clone()) {
+        Ok(()) => {
+            if payload.cost.is_some() {
+                // The peer was just added while we hold the lock, so it always exists.
+                let _ = node.set_peer_cost(&endpoint, payload.cost);
+            }
+            Ok(StatusCode::NO_CONTENT)
+        }
         Err(PeerExists) => Err((
             StatusCode::CONFLICT,
             "A peer identified by that endpoint already exists".to_string(),
@@ -107,6 +149,32 @@ async fn add_peer(
     }
 }
 
+/// Update the settings of an existing peer
+async fn update_peer(
+    State(state): State<HttpServerState>,
+    Path(endpoint): Path<String>,
+    Json(payload): Json<UpdatePeer>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    debug!("Attempting to update peer {}", endpoint);
+    let endpoint = match Endpoint::from_str(&endpoint) {
+        Ok(endpoint) => endpoint,
+        Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string())),
+    };
+
+    match state
+        .node
+        .lock()
+        .await
+        .set_peer_cost(&endpoint, payload.cost)
+    {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(PeerNotFound) => Err((
+            StatusCode::NOT_FOUND,
+            "A peer identified by that endpoint does not exist".to_string(),
+        )),
+    }
+}
+
 /// remove an existing peer from the system
 async fn delete_peer(
     State(state): State<HttpServerState>,
@@ -127,6 +195,44 @@ async fn delete_peer(
     }
 }
 
+/// Get the current peer access control rules.
+async fn get_peer_acl(State(state): State<HttpServerState>) -> Json<PeerAcl> {
+    debug!("Fetching peer ACL");
+    Json(state.node.lock().await.peer_acl())
+}
+
+/// Replace the peer access control rules.
+async fn set_peer_acl(
+    State(state): State<HttpServerState>,
+    Json(acl): Json<PeerAcl>,
+) -> StatusCode {
+    debug!("Updating peer ACL");
+    state.node.lock().await.set_peer_acl(acl);
+    StatusCode::NO_CONTENT
+}
+
+/// Get the amount of remotes rejected by the peer access control rules.
+async fn get_peer_acl_stats(State(state): State<HttpServerState>) -> Json<PeerAclStats> {
+    debug!("Fetching peer ACL stats");
+    Json(state.node.lock().await.peer_acl_stats())
+}
+
+/// Get the configured route update filters.
+async fn get_update_filters(State(state): State<HttpServerState>) -> Json<Vec<FilterStats>> {
+    debug!("Fetching update filters");
+    Json(state.node.lock().await.update_filters())
+}
+
+/// Replace the configured route update filters.
+async fn set_update_filters(
+    State(state): State<HttpServerState>,
+    Json(filters): Json<Vec<FilterConfig>>,
+) -> StatusCode {
+    debug!("Updating update filters");
+    state.node.lock().await.set_update_filters(filters);
+    StatusCode::NO_CONTENT
+}
+
 /// Alias to a [`Metric`](crate::metric::Metric) for serialization in the API.
 pub enum Metric {
     /// Finite metric
@@ -199,18 +305,95 @@ async fn get_fallback_routes(State(state): State<HttpServerState>) -> Json<Vec<R
     Json(routes)
 }
 
+/// A change of the selected route of a subnet.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteChange {
+    /// Time of the change, in seconds since the unix epoch.
+    pub time: u64,
+    /// The subnet for which the selected route changed.
+    pub subnet: String,
+    /// Next hop of the previously selected route, if there was one.
+    pub old_next_hop: Option<String>,
+    /// Metric of the previously selected route, if there was one.
+    pub old_metric: Option<Metric>,
+    /// Next hop of the newly selected route, if there is one.
+    pub new_next_hop: Option<String>,
+    /// Metric of the newly selected route, if there is one.
+    pub new_metric: Option<Metric>,
+    /// Sequence number of the newly selected route, or of the previously selected route if
+    /// there is no new route.
+    pub seqno: u16,
+    /// Why the selected route changed.
+    pub reason: RouteChangeReason,
+}
+
+/// Query parameters for the route history.
+#[derive(Deserialize)]
+struct RouteHistoryQuery {
+    /// Only return changes for this subnet.
+    subnet: Option<Subnet>,
+}
+
+/// List the recorded changes of selected routes, from oldest to newest.
+async fn get_route_history(
+    State(state): State<HttpServerState>,
+    Query(query): Query<RouteHistoryQuery>,
+) -> Json<Vec<RouteChange>> {
+    debug!("Loading route history");
+    let changes = state
+        .node
+        .lock()
+        .await
+        .route_history(query.subnet)
+        .into_iter()
+        .map(|rc| RouteChange {
+            time: rc
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            subnet: rc.subnet.to_string(),
+            old_next_hop: rc.old_neighbour,
+            old_metric: rc.old_metric.map(|m| {
+                if m.is_infinite() {
+                    Metric::Infinite
+                } else {
+                    Metric::Value(m.into())
+                }
+            }),
+            new_next_hop: rc.new_neighbour,
+            new_metric: rc.new_metric.map(|m| {
+                if m.is_infinite() {
+                    Metric::Infinite
+                } else {
+                    Metric::Value(m.into())
+                }
+            }),
+            seqno: rc.seqno.into(),
+            reason: rc.reason,
+        })
+        .collect();
+
+    Json(changes)
+}
+
 /// General info about a node.
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Info {
     /// The overlay subnet in use by the node.
     pub node_subnet: String,
+    /// Extra subnets announced by the node.
+    pub extra_subnets: Vec<String>,
 }
 
 /// Get general info about the node.
 async fn get_info(State(state): State<HttpServerState>) -> Json<Info> {
+    let info = state.node.lock().await.info();
     Json(Info {
-        node_subnet: state.node.lock().await.info().node_subnet.to_string(),
+        node_subnet: info.node_subnet.to_string(),
+        extra_subnets: info.extra_subnets.iter().map(ToString::to_string).collect(),
     })
 }
 
@@ -226,6 +409,35 @@ impl Serialize for Metric {
     }
 }
 
+impl<'de> Deserialize<'de> for Metric {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawMetric {
+            Value(u16),
+            Text(String),
+        }
+
+        match RawMetric::deserialize(deserializer)? {
+            RawMetric::Value(v) => Ok(Self::Value(v)),
+            RawMetric::Text(s) if s == "infinite" => Ok(Self::Infinite),
+            RawMetric::Text(s) => Err(D::Error::custom(format!("invalid metric {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Infinite => f.write_str("infinite"),
+            Self::Value(v) => write!(f, "{v}"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -243,4 +455,16 @@ mod tests {
 
         assert_eq!("\"infinite\"", s);
     }
+
+    #[test]
+    fn metric_deserialization() {
+        let metric: super::Metric = serde_json::from_str("10").expect("can decode finite metric");
+        assert!(matches!(metric, super::Metric::Value(10)));
+
+        let metric: super::Metric =
+            serde_json::from_str("\"infinite\"").expect("can decode infinite metric");
+        assert!(matches!(metric, super::Metric::Infinite));
+
+        assert!(serde_json::from_str::<super::Metric>("\"finite\"").is_err());
+    }
 }
@@ -1,20 +1,29 @@
-use std::{net::IpAddr, ops::Deref, time::Duration};
+use std::{convert::Infallible, io, net::IpAddr, ops::Deref, time::Duration};
 
 use axum::{
+    body::Body,
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
     Json, Router,
 };
+use futures::{stream::BoxStream, Stream, StreamExt};
 use log::debug;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_util::io::StreamReader;
 
 use mycelium::{
     crypto::PublicKey,
-    message::{MessageId, MessageInfo},
+    message::{MessageId, MessageInfo, MessageStateChange, ReceivedMessage},
 };
 
 use super::HttpServerState;
+use crate::delivery::TopicRouteStats;
 
 /// Default amount of time to try and send a message if it is not explicitly specified.
 const DEFAULT_MESSAGE_TRY_DURATION: Duration = Duration::from_secs(60 * 5);
@@ -25,6 +34,14 @@ pub fn message_router_v1(server_state: HttpServerState) -> Router {
         .route("/messages", get(get_message).post(push_message))
         .route("/messages/status/:id", get(message_status))
         .route("/messages/reply/:id", post(reply_message))
+        .route("/messages/inbox", get(inbox_stats))
+        .route(
+            "/messages/stream",
+            get(get_message_stream).post(push_message_stream),
+        )
+        .route("/messages/stream/reply/:id", post(reply_message_stream))
+        .route("/messages/routes", get(topic_route_stats))
+        .route("/messages/subscribe", get(subscribe))
         .with_state(server_state)
 }
 
@@ -63,6 +80,24 @@ pub struct MessageReceiveInfo {
     pub payload: Vec<u8>,
 }
 
+impl From<ReceivedMessage> for MessageReceiveInfo {
+    fn from(m: ReceivedMessage) -> Self {
+        Self {
+            id: m.id,
+            src_ip: m.src_ip,
+            src_pk: m.src_pk,
+            dst_ip: m.dst_ip,
+            dst_pk: m.dst_pk,
+            topic: if m.topic.is_empty() {
+                None
+            } else {
+                Some(m.topic)
+            },
+            payload: m.data,
+        }
+    }
+}
+
 impl MessageDestination {
     /// Get the IP address of the destination.
     fn ip(self) -> IpAddr {
@@ -106,16 +141,16 @@ async fn get_message(
         query.timeout_secs()
     );
 
+    // Don't hold the node lock while waiting, as that blocks other requests, shutdown and
+    // reloading the config.
+    let message_stack = state.node.lock().await.message_stack();
+
     // A timeout of 0 seconds essentially means get a message if there is one, and return
     // immediatly if there isn't. This is the result of the implementation of Timeout, which does a
     // poll of the internal future first, before polling the delay.
     tokio::time::timeout(
         Duration::from_secs(query.timeout_secs()),
-        state
-            .node
-            .lock()
-            .await
-            .get_message(!query.peek(), query.topic),
+        message_stack.message(!query.peek(), query.topic),
     )
     .await
     .or(Err(StatusCode::NO_CONTENT))
@@ -269,6 +304,353 @@ async fn message_status(
         .map(Json)
 }
 
+#[derive(Deserialize)]
+struct PushMessageStreamQuery {
+    /// Receiver of the message, either an IP address or a hex encoded public key.
+    dst: String,
+    /// Optional topic of the message, base64 encoded.
+    #[serde(default)]
+    #[serde(with = "base64::optional_binary")]
+    topic: Option<Vec<u8>>,
+}
+
+impl PushMessageStreamQuery {
+    /// Get the IP address of the destination, if it is valid.
+    fn dst_ip(&self) -> Option<IpAddr> {
+        self.dst
+            .parse()
+            .ok()
+            .or_else(|| Some(IpAddr::V6(self.dst.parse::<PublicKey>().ok()?.address())))
+    }
+}
+
+/// Turn a request body into a reader for a message, after checking its announced size. The
+/// returned receiver resolves once the message stack is done reading the body.
+fn body_reader(
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<
+    (
+        impl tokio::io::AsyncRead + Send + Unpin + 'static,
+        u64,
+        tokio::sync::oneshot::Receiver<()>,
+    ),
+    StatusCode,
+> {
+    let len = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse().ok())
+        .ok_or(StatusCode::LENGTH_REQUIRED)?;
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+    let stream = body.into_data_stream().map(move |data| {
+        // Dropped together with the reader.
+        let _ = &done_tx;
+        data.map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    });
+
+    Ok((StreamReader::new(stream), len, done_rx))
+}
+
+async fn push_message_stream(
+    State(state): State<HttpServerState>,
+    Query(query): Query<PushMessageStreamQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, Json<MessageIdReply>), StatusCode> {
+    let dst = query.dst_ip().ok_or(StatusCode::BAD_REQUEST)?;
+    let (reader, len, done) = body_reader(&headers, body)?;
+    debug!("Pushing new message stream of {len} bytes to message stack for target {dst}");
+
+    let id = state
+        .node
+        .lock()
+        .await
+        .push_message_stream(dst, reader, len, query.topic, DEFAULT_MESSAGE_TRY_DURATION)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    // The body can only be read while the request is in progress, so only reply once all data
+    // is read, or sending stopped.
+    let _ = done.await;
+
+    Ok((StatusCode::CREATED, Json(MessageIdReply { id })))
+}
+
+async fn reply_message_stream(
+    State(state): State<HttpServerState>,
+    Path(id): Path<MessageId>,
+    Query(query): Query<PushMessageStreamQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> StatusCode {
+    let Some(dst) = query.dst_ip() else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let (reader, len, done) = match body_reader(&headers, body) {
+        Ok(body) => body,
+        Err(status) => return status,
+    };
+    debug!(
+        "Pushing new reply stream to {} of {len} bytes to message stack for target {dst}",
+        id.as_hex(),
+    );
+
+    state.node.lock().await.reply_message_stream(
+        id,
+        dst,
+        reader,
+        len,
+        DEFAULT_MESSAGE_TRY_DURATION,
+    );
+
+    // See push_message_stream.
+    let _ = done.await;
+
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Deserialize)]
+struct GetMessageStreamQuery {
+    timeout: Option<u64>,
+    /// Optional filter for start of the message, base64 encoded.
+    #[serde(default)]
+    #[serde(with = "base64::optional_binary")]
+    topic: Option<Vec<u8>>,
+}
+
+async fn get_message_stream(
+    State(state): State<HttpServerState>,
+    Query(query): Query<GetMessageStreamQuery>,
+) -> Result<Response, StatusCode> {
+    let timeout = query.timeout.unwrap_or(0);
+    debug!("Attempt to get message stream, timeout {timeout} seconds");
+
+    // See get_message for why the node lock is released first, and for the meaning of a timeout
+    // of 0 seconds.
+    let message_stack = state.node.lock().await.message_stack();
+    let reader = tokio::time::timeout(
+        Duration::from_secs(timeout),
+        message_stack.message_stream(query.topic),
+    )
+    .await
+    .or(Err(StatusCode::NO_CONTENT))?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_LENGTH, reader.len.into());
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/octet-stream"),
+    );
+    let fields = [
+        ("x-message-id", reader.id.as_hex()),
+        ("x-message-src-ip", reader.src_ip.to_string()),
+        ("x-message-src-pk", reader.src_pk.to_string()),
+        ("x-message-dst-ip", reader.dst_ip.to_string()),
+        ("x-message-dst-pk", reader.dst_pk.to_string()),
+    ];
+    for (name, value) in fields {
+        headers.insert(
+            name,
+            value
+                .try_into()
+                .expect("Hex and IP addresses are valid header values; qed"),
+        );
+    }
+    if !reader.topic.is_empty() {
+        headers.insert(
+            "x-message-topic",
+            base64::encode(&reader.topic)
+                .try_into()
+                .expect("Base64 is a valid header value; qed"),
+        );
+    }
+
+    // If the message turns out to be invalid while it is being sent, the response is aborted.
+    Ok((headers, Body::from_stream(reader)).into_response())
+}
+
+/// Usage of the inbox of received messages.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InboxStatsResponse {
+    messages: usize,
+    pending_messages: usize,
+    bytes: usize,
+    senders: Vec<SenderUsage>,
+    topics: Vec<TopicUsage>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenderUsage {
+    ip: IpAddr,
+    pk: PublicKey,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicUsage {
+    #[serde(with = "base64::binary")]
+    topic: Vec<u8>,
+    messages: usize,
+}
+
+async fn inbox_stats(State(state): State<HttpServerState>) -> Json<InboxStatsResponse> {
+    debug!("Fetching inbox stats");
+
+    let stats = state.node.lock().await.inbox_stats();
+
+    Json(InboxStatsResponse {
+        messages: stats.messages,
+        pending_messages: stats.pending_messages,
+        bytes: stats.bytes,
+        senders: stats
+            .senders
+            .into_iter()
+            .map(|usage| SenderUsage {
+                ip: usage.ip,
+                pk: usage.pk,
+                bytes: usage.bytes,
+            })
+            .collect(),
+        topics: stats
+            .topics
+            .into_iter()
+            .map(|usage| TopicUsage {
+                topic: usage.topic,
+                messages: usage.messages,
+            })
+            .collect(),
+    })
+}
+
+async fn topic_route_stats(State(state): State<HttpServerState>) -> Json<Vec<TopicRouteStats>> {
+    debug!("Fetching topic route stats");
+
+    Json(state.topic_router.stats())
+}
+
+/// Kind of events to stream to a subscriber.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum SubscriptionMode {
+    /// Stream messages as they are received.
+    #[default]
+    Inbound,
+    /// Stream state changes of messages we sent.
+    Outbound,
+}
+
+#[derive(Deserialize)]
+struct SubscribeQuery {
+    #[serde(default)]
+    mode: SubscriptionMode,
+    /// Only stream messages with exactly this topic, base64 encoded.
+    #[serde(default)]
+    #[serde(with = "base64::optional_binary")]
+    topic: Option<Vec<u8>>,
+    /// Only stream messages of which the topic starts with this prefix, base64 encoded.
+    #[serde(default)]
+    #[serde(with = "base64::optional_binary")]
+    topic_prefix: Option<Vec<u8>>,
+}
+
+impl SubscribeQuery {
+    /// Check if a received message matches the topic filters of the query.
+    fn matches(&self, message: &ReceivedMessage) -> bool {
+        self.topic.as_ref().map_or(true, |t| &message.topic == t)
+            && self
+                .topic_prefix
+                .as_ref()
+                .map_or(true, |p| message.topic.starts_with(p))
+    }
+}
+
+/// State of a sent message, as streamed to a subscriber.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageStatusEvent {
+    id: MessageId,
+    #[serde(flatten)]
+    info: MessageInfo,
+}
+
+impl From<MessageStateChange> for MessageStatusEvent {
+    fn from(change: MessageStateChange) -> Self {
+        Self {
+            id: change.id,
+            info: change.info,
+        }
+    }
+}
+
+async fn subscribe(
+    State(state): State<HttpServerState>,
+    Query(query): Query<SubscribeQuery>,
+) -> Sse<BoxStream<'static, Result<Event, Infallible>>> {
+    debug!("Subscribing to message events");
+
+    let message_stack = state.node.lock().await.message_stack();
+
+    let events = match query.mode {
+        SubscriptionMode::Inbound => {
+            subscription_events(message_stack.subscribe_received(), move |message| {
+                if !query.matches(&message) {
+                    return None;
+                }
+                Some(
+                    Event::default()
+                        .event("message")
+                        .json_data(MessageReceiveInfo::from(message))
+                        .expect("Message can be serialized; qed"),
+                )
+            })
+            .boxed()
+        }
+        SubscriptionMode::Outbound => {
+            subscription_events(message_stack.subscribe_state_changes(), |change| {
+                Some(
+                    Event::default()
+                        .event("status")
+                        .json_data(MessageStatusEvent::from(change))
+                        .expect("Message status can be serialized; qed"),
+                )
+            })
+            .boxed()
+        }
+    };
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Turn a subscription into a stream of server sent events. Items for which `to_event` returns
+/// [`Option::None`] are skipped. If the subscriber falls behind, a `lagged` event with the amount
+/// of skipped items is sent.
+fn subscription_events<T, F>(
+    rx: broadcast::Receiver<T>,
+    to_event: F,
+) -> impl Stream<Item = Result<Event, Infallible>>
+where
+    T: Clone + Send + 'static,
+    F: FnMut(T) -> Option<Event> + Send + 'static,
+{
+    futures::stream::unfold((rx, to_event), |(mut rx, mut to_event)| async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(item) => match to_event(item) {
+                    Some(event) => event,
+                    None => continue,
+                },
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    Event::default().event("lagged").data(skipped.to_string())
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), (rx, to_event)));
+        }
+    })
+}
+
 /// Module to implement base64 decoding and encoding
 /// Sourced from https://users.rust-lang.org/t/serialize-a-vec-u8-to-json-as-base64/57781, with some
 /// addaptions to work with the new version of the base64 crate
@@ -281,6 +663,13 @@ mod base64 {
         GeneralPurposeConfig::new(),
     );
 
+    /// Encode data as base64.
+    pub fn encode(data: &[u8]) -> String {
+        use base64::Engine;
+
+        B64ENGINE.encode(data)
+    }
+
     pub mod binary {
         use super::B64ENGINE;
         use base64::Engine;
@@ -327,3 +716,70 @@ mod base64 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::extract::{Query, State};
+    use mycelium::{
+        crypto::SecretKey,
+        test_support::{node, received_message},
+    };
+    use tokio::sync::Mutex;
+
+    use super::{get_message, GetMessageQuery, HttpServerState, SubscribeQuery, SubscriptionMode};
+    use crate::delivery::TopicRouter;
+
+    fn query(topic: Option<&[u8]>, topic_prefix: Option<&[u8]>) -> SubscribeQuery {
+        SubscribeQuery {
+            mode: SubscriptionMode::Inbound,
+            topic: topic.map(<[u8]>::to_vec),
+            topic_prefix: topic_prefix.map(<[u8]>::to_vec),
+        }
+    }
+
+    #[test]
+    fn subscribe_query_matches() {
+        let (chat, room) = (
+            received_message(b"chat", &[]),
+            received_message(b"chat/room", &[]),
+        );
+
+        assert!(query(None, None).matches(&chat));
+        assert!(query(None, None).matches(&room));
+
+        assert!(query(Some(b"chat"), None).matches(&chat));
+        assert!(!query(Some(b"chat"), None).matches(&room));
+
+        assert!(!query(None, Some(b"chat/")).matches(&chat));
+        assert!(query(None, Some(b"chat/")).matches(&room));
+
+        // Both filters must match.
+        assert!(query(Some(b"chat/room"), Some(b"chat/")).matches(&room));
+        assert!(!query(Some(b"chat"), Some(b"chat/")).matches(&chat));
+    }
+
+    #[tokio::test]
+    async fn get_message_releases_node_lock() {
+        let node = node(SecretKey::new()).await;
+        let state = HttpServerState {
+            topic_router: Arc::new(TopicRouter::spawn(node.message_stack(), vec![])),
+            node: Arc::new(Mutex::new(node)),
+        };
+
+        let request = tokio::spawn(get_message(
+            State(state.clone()),
+            Query(GetMessageQuery {
+                peek: None,
+                timeout: Some(60),
+                topic: None,
+            }),
+        ));
+        // Give the request time to start waiting for a message.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!request.is_finished());
+        assert!(state.node.try_lock().is_ok());
+        request.abort();
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt::Write;
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use log::debug;
+
+use mycelium::peer_manager::{ConnectionState, PeerType};
+
+use super::HttpServerState;
+
+/// Content type of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// All peer types, so every combination is exported, even if there are no such peers.
+const PEER_TYPES: [PeerType; 3] = [
+    PeerType::Static,
+    PeerType::LinkLocalDiscovery,
+    PeerType::Inbound,
+];
+
+/// All connection states, so every combination is exported, even if there are no such peers.
+const CONNECTION_STATES: [ConnectionState; 3] = [
+    ConnectionState::Alive,
+    ConnectionState::Connecting,
+    ConnectionState::Dead,
+];
+
+/// Return a router which has the metrics endpoint and its handler mounted.
+pub fn metrics_router(server_state: HttpServerState) -> Router {
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(server_state)
+}
+
+/// Render the metrics of the node in the Prometheus text format.
+async fn get_metrics(State(state): State<HttpServerState>) -> impl IntoResponse {
+    debug!("Rendering metrics");
+    let (peers, selected_routes, fallback_routes, data_plane, messages) = {
+        let node = state.node.lock().await;
+        (
+            node.peer_info(),
+            node.selected_routes().len(),
+            node.fallback_routes().len(),
+            node.data_plane_metrics(),
+            node.message_metrics(),
+        )
+    };
+
+    let mut out = String::new();
+
+    metric_header(&mut out, "mycelium_peers", "gauge", "Amount of peers.");
+    for pt in &PEER_TYPES {
+        for cs in &CONNECTION_STATES {
+            let count = peers
+                .iter()
+                .filter(|p| &p.pt == pt && &p.connection_state == cs)
+                .count();
+            let _ = writeln!(
+                out,
+                "mycelium_peers{{type=\"{}\",state=\"{}\"}} {count}",
+                peer_type_label(pt),
+                connection_state_label(cs),
+            );
+        }
+    }
+
+    metric_header(
+        &mut out,
+        "mycelium_peer_tx_bytes_total",
+        "counter",
+        "Amount of bytes transmitted to a peer.",
+    );
+    for peer in &peers {
+        let _ = writeln!(
+            out,
+            "mycelium_peer_tx_bytes_total{{protocol=\"{}\",address=\"{}\"}} {}",
+            peer.endpoint.proto(),
+            escape_label(&peer.endpoint.authority()),
+            peer.tx_bytes,
+        );
+    }
+    metric_header(
+        &mut out,
+        "mycelium_peer_rx_bytes_total",
+        "counter",
+        "Amount of bytes received from a peer.",
+    );
+    for peer in &peers {
+        let _ = writeln!(
+            out,
+            "mycelium_peer_rx_bytes_total{{protocol=\"{}\",address=\"{}\"}} {}",
+            peer.endpoint.proto(),
+            escape_label(&peer.endpoint.authority()),
+            peer.rx_bytes,
+        );
+    }
+
+    metric_header(&mut out, "mycelium_routes", "gauge", "Amount of routes.");
+    let _ = writeln!(
+        out,
+        "mycelium_routes{{kind=\"selected\"}} {selected_routes}"
+    );
+    let _ = writeln!(
+        out,
+        "mycelium_routes{{kind=\"fallback\"}} {fallback_routes}"
+    );
+
+    counter(
+        &mut out,
+        "mycelium_packets_routed_total",
+        "Data packets delivered locally or forwarded to a peer.",
+        data_plane.packets_routed,
+    );
+    counter(
+        &mut out,
+        "mycelium_packets_no_route_total",
+        "Data packets for which no route was found.",
+        data_plane.no_route,
+    );
+    counter(
+        &mut out,
+        "mycelium_packets_ttl_exceeded_total",
+        "Data packets dropped because their hop limit expired.",
+        data_plane.ttl_exceeded,
+    );
+    counter(
+        &mut out,
+        "mycelium_packet_decryption_failures_total",
+        "Data packets which could not be decrypted.",
+        data_plane.decryption_failures,
+    );
+
+    counter(
+        &mut out,
+        "mycelium_messages_sent_total",
+        "Messages pushed to be sent.",
+        messages.sent,
+    );
+    counter(
+        &mut out,
+        "mycelium_messages_received_total",
+        "Messages fully received.",
+        messages.received,
+    );
+    counter(
+        &mut out,
+        "mycelium_messages_delivered_total",
+        "Sent messages confirmed to be received.",
+        messages.delivered,
+    );
+    counter(
+        &mut out,
+        "mycelium_messages_read_total",
+        "Sent messages confirmed to be read.",
+        messages.read,
+    );
+    counter(
+        &mut out,
+        "mycelium_messages_aborted_total",
+        "Sent messages aborted because they were not received in time.",
+        messages.aborted,
+    );
+    counter(
+        &mut out,
+        "mycelium_messages_rejected_total",
+        "Sent messages refused by the receiver.",
+        messages.rejected,
+    );
+    counter(
+        &mut out,
+        "mycelium_inbox_rejected_total",
+        "Received messages refused because the inbox is full.",
+        messages.inbox_rejected,
+    );
+    counter(
+        &mut out,
+        "mycelium_inbox_dropped_total",
+        "Unread messages removed to make room for new messages.",
+        messages.inbox_dropped,
+    );
+
+    ([(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], out)
+}
+
+/// Write the HELP and TYPE lines of a metric.
+fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Write a counter without labels.
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    metric_header(out, name, "counter", help);
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn peer_type_label(pt: &PeerType) -> &'static str {
+    match pt {
+        PeerType::Static => "static",
+        PeerType::LinkLocalDiscovery => "linkLocalDiscovery",
+        PeerType::Inbound => "inbound",
+    }
+}
+
+fn connection_state_label(cs: &ConnectionState) -> &'static str {
+    match cs {
+        ConnectionState::Alive => "alive",
+        ConnectionState::Connecting => "connecting",
+        ConnectionState::Dead => "dead",
+    }
+}
+
+/// Escape a label value as required by the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn label_escaping() {
+        assert_eq!(super::escape_label("[::1]:9651"), "[::1]:9651");
+        assert_eq!(super::escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
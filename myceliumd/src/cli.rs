@@ -1,7 +1,9 @@
 mod inspect;
 #[cfg(feature = "message")]
 mod message;
+mod routes;
 
 pub use inspect::inspect;
 #[cfg(feature = "message")]
 pub use message::{recv_msg, send_msg};
+pub use routes::route_history;
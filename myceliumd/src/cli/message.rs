@@ -2,7 +2,7 @@ use std::{
     io::Write,
     mem,
     net::{IpAddr, SocketAddr},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use base64::{
@@ -18,6 +18,7 @@ use mycelium::{
     subnet::Subnet,
 };
 use serde::{Serialize, Serializer};
+use tokio_util::io::ReaderStream;
 
 enum Payload {
     Readable(String),
@@ -120,46 +121,80 @@ pub async fn send_msg(
         }
     };
 
-    // Load msg, files have prio.
-    let msg = if let Some(path) = msg_path {
-        match tokio::fs::read(&path).await {
-            Err(e) => {
-                error!("Could not read file at {:?}: {e}", path);
-                return Err(e.into());
+    let client = reqwest::Client::new();
+    let request = match msg_path {
+        // Files are streamed to the node, so they don't need to be loaded in memory. Waiting for a
+        // reply is only possible for regular messages.
+        Some(path) if !wait => {
+            let (file, len) = match open_msg_file(&path).await {
+                Err(e) => {
+                    error!("Could not read file at {:?}: {e}", path);
+                    return Err(e.into());
+                }
+                Ok(file) => file,
+            };
+
+            let mut url = format!("http://{server_addr}/api/v1/messages/stream");
+            if let Some(reply_to) = reply_to {
+                url.push_str(&format!("/reply/{reply_to}"));
+            }
+            let mut query = vec![(
+                "dst",
+                match destination {
+                    MessageDestination::Ip(ip) => ip.to_string(),
+                    MessageDestination::Pk(pk) => pk.to_string(),
+                },
+            )];
+            if let Some(topic) = topic {
+                query.push(("topic", encode_base64(topic.as_bytes())));
             }
-            Ok(data) => data,
+
+            client
+                .post(url)
+                .query(&query)
+                .header(reqwest::header::CONTENT_LENGTH, len)
+                .body(reqwest::Body::wrap_stream(ReaderStream::new(file)))
         }
-    } else if let Some(msg) = msg {
-        msg.into_bytes()
-    } else {
-        error!("Message is a required argument if `--msg-path` is not provided");
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Message is a required argument if `--msg-path` is not provided",
-        )
-        .into());
-    };
+        msg_path => {
+            // Load msg, files have prio.
+            let msg = if let Some(path) = msg_path {
+                match tokio::fs::read(&path).await {
+                    Err(e) => {
+                        error!("Could not read file at {:?}: {e}", path);
+                        return Err(e.into());
+                    }
+                    Ok(data) => data,
+                }
+            } else if let Some(msg) = msg {
+                msg.into_bytes()
+            } else {
+                error!("Message is a required argument if `--msg-path` is not provided");
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Message is a required argument if `--msg-path` is not provided",
+                )
+                .into());
+            };
 
-    let mut url = format!("http://{server_addr}/api/v1/messages");
-    if let Some(reply_to) = reply_to {
-        url.push_str(&format!("/reply/{reply_to}"));
-    }
-    if wait {
-        // A year should be sufficient to wait
-        let reply_timeout = timeout.unwrap_or(60 * 60 * 24 * 365);
-        url.push_str(&format!("?reply_timeout={reply_timeout}"));
-    }
+            let mut url = format!("http://{server_addr}/api/v1/messages");
+            if let Some(reply_to) = reply_to {
+                url.push_str(&format!("/reply/{reply_to}"));
+            }
+            if wait {
+                // A year should be sufficient to wait
+                let reply_timeout = timeout.unwrap_or(60 * 60 * 24 * 365);
+                url.push_str(&format!("?reply_timeout={reply_timeout}"));
+            }
 
-    match reqwest::Client::new()
-        .post(url)
-        .json(&MessageSendInfo {
-            dst: destination,
-            topic: topic.map(String::into_bytes),
-            payload: msg,
-        })
-        .send()
-        .await
-    {
+            client.post(url).json(&MessageSendInfo {
+                dst: destination,
+                topic: topic.map(String::into_bytes),
+                payload: msg,
+            })
+        }
+    };
+
+    match request.send().await {
         Err(e) => {
             error!("Failed to send request: {e}");
             return Err(e.into());
@@ -213,6 +248,13 @@ pub async fn send_msg(
     Ok(())
 }
 
+/// Open a file to send as message, and get its size.
+async fn open_msg_file(path: &Path) -> std::io::Result<(tokio::fs::File, u64)> {
+    let file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    Ok((file, len))
+}
+
 const STATUSCODE_NO_CONTENT: u16 = 204;
 
 pub async fn recv_msg(
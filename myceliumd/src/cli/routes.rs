@@ -0,0 +1,70 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use log::{debug, error};
+use mycelium::subnet::Subnet;
+
+use crate::api::RouteChange;
+
+/// Print the recorded changes of selected routes, optionally only for the given subnet.
+pub async fn route_history(
+    subnet: Option<Subnet>,
+    json: bool,
+    server_addr: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut url = format!("http://{server_addr}/api/v1/admin/routes/history");
+    if let Some(subnet) = subnet {
+        url.push_str(&format!("?subnet={subnet}"));
+    }
+
+    let changes = match reqwest::get(url).await {
+        Err(e) => {
+            error!("Failed to load route history: {e}");
+            return Err(e.into());
+        }
+        Ok(resp) => {
+            debug!("Received route history response");
+            match resp.json::<Vec<RouteChange>>().await {
+                Err(e) => {
+                    error!("Failed to load response json: {e}");
+                    return Err(e.into());
+                }
+                Ok(changes) => changes,
+            }
+        }
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changes)?);
+        return Ok(());
+    }
+
+    for change in changes {
+        let time = format_age(change.time);
+        let old = match (change.old_next_hop, change.old_metric) {
+            (Some(next_hop), Some(metric)) => format!("{next_hop} (metric {metric})"),
+            _ => "none".to_string(),
+        };
+        let new = match (change.new_next_hop, change.new_metric) {
+            (Some(next_hop), Some(metric)) => format!("{next_hop} (metric {metric})"),
+            _ => "none".to_string(),
+        };
+        println!(
+            "{time} {}: {old} -> {new}, seqno {}, reason: {}",
+            change.subnet, change.seqno, change.reason
+        );
+    }
+
+    Ok(())
+}
+
+/// Format a unix timestamp as the time elapsed since then.
+fn format_age(timestamp: u64) -> String {
+    let elapsed = (UNIX_EPOCH + Duration::from_secs(timestamp))
+        .elapsed()
+        .unwrap_or_default()
+        .as_secs();
+    format!("{}s ago", elapsed)
+}
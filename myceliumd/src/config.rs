@@ -0,0 +1,638 @@
+//! Loading of node arguments from a TOML configuration file.
+//!
+//! Every key in the file has the same name as the long form of the matching CLI flag. Values set
+//! on the CLI take precedence over values set in the file.
+//!
+//! Static peers can also be listed in a separate peers file, with one endpoint per line. The
+//! peers and the log level are reloaded from both files on SIGHUP, see [`Reloadable`]. Both files
+//! can set a cost for a static peer, which is added to the link cost of the connection to it.
+
+use std::{
+    fmt, io, net::SocketAddr, num::NonZeroU8, path::Path, path::PathBuf, str::FromStr,
+    time::Duration,
+};
+
+use log::LevelFilter;
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use mycelium::{
+    crypto::PublicKey,
+    endpoint::{Endpoint, EndpointParseError},
+    filters::FilterConfig,
+    message::OverflowPolicy,
+    subnet::Subnet,
+};
+
+use crate::{delivery::TopicRoute, Cli};
+
+/// The contents of a configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigFile {
+    pub key_file: Option<PathBuf>,
+    pub debug: bool,
+    pub silent: bool,
+    #[serde(deserialize_with = "deserialize_static_peers")]
+    pub peers: Vec<StaticPeer>,
+    pub peers_file: Option<PathBuf>,
+    pub tcp_listen_port: Option<u16>,
+    pub quic_listen_port: Option<u16>,
+    pub tls_listen_port: Option<u16>,
+    pub ws_listen_port: Option<u16>,
+    pub wss_listen_port: Option<u16>,
+    pub ws_proxy: Option<String>,
+    pub unix_listen_path: Option<PathBuf>,
+    pub peer_discovery_port: Option<u16>,
+    pub disable_peer_discovery: bool,
+    pub api_addr: Option<SocketAddr>,
+    pub enable_metrics: bool,
+    pub no_tun: bool,
+    pub tun_name: Option<String>,
+    pub allowed_peer_subnets: Vec<Subnet>,
+    pub denied_peer_subnets: Vec<Subnet>,
+    pub allowed_peer_keys: Vec<PublicKey>,
+    pub denied_peer_keys: Vec<PublicKey>,
+    pub state_dir: Option<PathBuf>,
+    pub persist_messages: bool,
+    pub inbox_max_bytes: Option<usize>,
+    pub inbox_max_bytes_per_sender: Option<usize>,
+    pub inbox_max_topic_messages: Option<usize>,
+    pub inbox_max_pending_messages: Option<usize>,
+    pub inbox_overflow: Option<OverflowPolicy>,
+    pub extra_subnets: Option<u8>,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub hello_interval: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub ihu_interval: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub update_interval: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub dead_peer_threshold: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub seqno_bump_timeout: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub retracted_route_hold_time: Option<Duration>,
+    pub seqno_request_hop_limit: Option<NonZeroU8>,
+    /// Filters for received route updates. Filters set with the CLI flags are added to these.
+    pub filters: Vec<FilterConfig>,
+    /// Local applications to deliver received messages to, based on their topic.
+    pub topic_routes: Vec<TopicRoute>,
+}
+
+/// An error while loading a [`ConfigFile`].
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file is not a valid configuration file.
+    Parse(toml::de::Error),
+    /// The amount of extra subnets in the file is too high.
+    TooManyExtraSubnets(u8),
+    /// A line in a peers file is not a valid peer.
+    InvalidPeer { line: usize, err: PeerParseError },
+}
+
+/// A statically configured peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticPeer {
+    /// The endpoint used to connect to the peer.
+    pub endpoint: Endpoint,
+    /// Cost added to the link cost of the connection to the peer.
+    pub cost: Option<u16>,
+}
+
+/// An error while parsing a [`StaticPeer`] from a line in a peers file.
+#[derive(Debug)]
+pub enum PeerParseError {
+    /// The endpoint of the peer is not valid.
+    Endpoint(EndpointParseError),
+    /// The part after the endpoint is not a valid `cost=<cost>` setting.
+    InvalidCost(String),
+}
+
+/// Settings which are reloaded on SIGHUP, without restarting the node.
+///
+/// Values set on the CLI are remembered, so they keep overriding the values in the config file
+/// after it is reloaded.
+pub struct Reloadable {
+    config_file: Option<PathBuf>,
+    cli_peers: Vec<Endpoint>,
+    cli_peers_file: Option<PathBuf>,
+    cli_debug: Option<bool>,
+    cli_silent: Option<bool>,
+}
+
+/// The current value of the [`Reloadable`] settings.
+pub struct ReloadedSettings {
+    /// All configured static peers, without duplicate endpoints.
+    pub peers: Vec<StaticPeer>,
+    /// Log level of the node.
+    pub log_level: LevelFilter,
+}
+
+impl ConfigFile {
+    /// Load a configuration file from the given path.
+    pub async fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .map_err(ConfigError::Io)?;
+        contents.parse()
+    }
+
+    /// Fill all values of the [`Cli`] which were not set on the command line with the values of
+    /// this file. The configured update filters are returned.
+    pub fn merge_into(self, cli: &mut Cli) -> Vec<FilterConfig> {
+        cli.key_file = cli.key_file.take().or(self.key_file);
+        // If the log level is set on the CLI, the file is ignored, so setting `silent` in the file
+        // does not silence `--debug`.
+        if cli_flag(cli.debug, cli.no_debug).is_none()
+            && cli_flag(cli.silent, cli.no_silent).is_none()
+        {
+            cli.debug = self.debug;
+            cli.silent = self.silent;
+        }
+
+        let args = &mut cli.node_args;
+        if args.static_peers.is_empty() {
+            args.static_peers = self.peers.into_iter().map(|peer| peer.endpoint).collect();
+        }
+        args.peers_file = args.peers_file.take().or(self.peers_file);
+        args.tcp_listen_port = args.tcp_listen_port.or(self.tcp_listen_port);
+        args.quic_listen_port = args.quic_listen_port.or(self.quic_listen_port);
+        args.tls_listen_port = args.tls_listen_port.or(self.tls_listen_port);
+        args.ws_listen_port = args.ws_listen_port.or(self.ws_listen_port);
+        args.wss_listen_port = args.wss_listen_port.or(self.wss_listen_port);
+        args.ws_proxy = args.ws_proxy.take().or(self.ws_proxy);
+        args.unix_listen_path = args.unix_listen_path.take().or(self.unix_listen_path);
+        args.peer_discovery_port = args.peer_discovery_port.or(self.peer_discovery_port);
+        args.disable_peer_discovery =
+            cli_flag(args.disable_peer_discovery, args.enable_peer_discovery)
+                .unwrap_or(self.disable_peer_discovery);
+        args.api_addr = args.api_addr.or(self.api_addr);
+        args.enable_metrics =
+            cli_flag(args.enable_metrics, args.disable_metrics).unwrap_or(self.enable_metrics);
+        args.no_tun = cli_flag(args.no_tun, args.tun).unwrap_or(self.no_tun);
+        args.tun_name = args.tun_name.take().or(self.tun_name);
+        if args.allowed_peer_subnets.is_empty() {
+            args.allowed_peer_subnets = self.allowed_peer_subnets;
+        }
+        if args.denied_peer_subnets.is_empty() {
+            args.denied_peer_subnets = self.denied_peer_subnets;
+        }
+        if args.allowed_peer_keys.is_empty() {
+            args.allowed_peer_keys = self.allowed_peer_keys;
+        }
+        if args.denied_peer_keys.is_empty() {
+            args.denied_peer_keys = self.denied_peer_keys;
+        }
+        args.state_dir = args.state_dir.take().or(self.state_dir);
+        args.persist_messages = cli_flag(args.persist_messages, args.no_persist_messages)
+            .unwrap_or(self.persist_messages);
+        args.inbox_max_bytes = args.inbox_max_bytes.or(self.inbox_max_bytes);
+        args.inbox_max_bytes_per_sender = args
+            .inbox_max_bytes_per_sender
+            .or(self.inbox_max_bytes_per_sender);
+        args.inbox_max_topic_messages = args
+            .inbox_max_topic_messages
+            .or(self.inbox_max_topic_messages);
+        args.inbox_max_pending_messages = args
+            .inbox_max_pending_messages
+            .or(self.inbox_max_pending_messages);
+        args.inbox_overflow = args.inbox_overflow.or(self.inbox_overflow);
+        args.extra_subnets = args.extra_subnets.or(self.extra_subnets);
+        args.hello_interval = args.hello_interval.or(self.hello_interval);
+        args.ihu_interval = args.ihu_interval.or(self.ihu_interval);
+        args.update_interval = args.update_interval.or(self.update_interval);
+        args.dead_peer_threshold = args.dead_peer_threshold.or(self.dead_peer_threshold);
+        args.seqno_bump_timeout = args.seqno_bump_timeout.or(self.seqno_bump_timeout);
+        args.retracted_route_hold_time = args
+            .retracted_route_hold_time
+            .or(self.retracted_route_hold_time);
+        args.seqno_request_hop_limit = args
+            .seqno_request_hop_limit
+            .or(self.seqno_request_hop_limit);
+
+        self.filters
+    }
+}
+
+impl Reloadable {
+    /// Remember the reloadable values set on the CLI. This must be called before a config file is
+    /// merged into the [`Cli`].
+    pub fn new(cli: &Cli) -> Self {
+        Self {
+            config_file: cli.config_file.clone(),
+            cli_peers: cli.node_args.static_peers.clone(),
+            cli_peers_file: cli.node_args.peers_file.clone(),
+            cli_debug: cli_flag(cli.debug, cli.no_debug),
+            cli_silent: cli_flag(cli.silent, cli.no_silent),
+        }
+    }
+
+    /// Reload the config file, if one is set, and compute the new settings.
+    pub async fn reload(&self) -> Result<ReloadedSettings, ConfigError> {
+        let config = match self.config_file {
+            Some(ref path) => ConfigFile::load(path).await?,
+            None => ConfigFile::default(),
+        };
+        self.settings(&config).await
+    }
+
+    /// Compute the settings from the CLI and the given config file. If a peers file is set, the
+    /// peers in it are loaded as well.
+    pub async fn settings(&self, config: &ConfigFile) -> Result<ReloadedSettings, ConfigError> {
+        let mut peers = if self.cli_peers.is_empty() {
+            config.peers.clone()
+        } else {
+            self.cli_peers
+                .iter()
+                .cloned()
+                .map(StaticPeer::from)
+                .collect()
+        };
+        if let Some(path) = self.cli_peers_file.as_ref().or(config.peers_file.as_ref()) {
+            peers.extend(load_peers_file(path).await?);
+        }
+        let mut unique: Vec<StaticPeer> = Vec::with_capacity(peers.len());
+        for peer in peers {
+            if !unique.iter().any(|p| p.endpoint == peer.endpoint) {
+                unique.push(peer);
+            }
+        }
+
+        Ok(ReloadedSettings {
+            peers: unique,
+            log_level: if self.cli_debug.is_some() || self.cli_silent.is_some() {
+                log_level(
+                    self.cli_debug.unwrap_or(false),
+                    self.cli_silent.unwrap_or(false),
+                )
+            } else {
+                log_level(config.debug, config.silent)
+            },
+        })
+    }
+}
+
+/// Get the value of a boolean flag which can be negated on the CLI, from the flag setting it and
+/// the flag clearing it. `None` is returned if neither is set, in which case the value from the
+/// config file applies.
+fn cli_flag(set: bool, unset: bool) -> Option<bool> {
+    if set {
+        Some(true)
+    } else if unset {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Get the log level for the given flags. Silent takes precedence over debug.
+pub fn log_level(debug: bool, silent: bool) -> LevelFilter {
+    if silent {
+        LevelFilter::Error
+    } else if debug {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    }
+}
+
+/// Load a file with one peer endpoint per line, optionally followed by `cost=<cost>`. Empty lines
+/// and lines starting with `#` are ignored.
+pub async fn load_peers_file(path: &Path) -> Result<Vec<StaticPeer>, ConfigError> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(ConfigError::Io)?;
+    parse_peers(&contents)
+}
+
+fn parse_peers(contents: &str) -> Result<Vec<StaticPeer>, ConfigError> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, peer)| {
+            StaticPeer::from_str(peer).map_err(|err| ConfigError::InvalidPeer { line, err })
+        })
+        .collect()
+}
+
+impl From<Endpoint> for StaticPeer {
+    fn from(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            cost: None,
+        }
+    }
+}
+
+impl FromStr for StaticPeer {
+    type Err = PeerParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let endpoint = parts
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(PeerParseError::Endpoint)?;
+        let cost = match parts.next() {
+            None => None,
+            Some(setting) => Some(
+                setting
+                    .strip_prefix("cost=")
+                    .and_then(|cost| cost.parse().ok())
+                    .ok_or_else(|| PeerParseError::InvalidCost(setting.to_string()))?,
+            ),
+        };
+        if let Some(extra) = parts.next() {
+            return Err(PeerParseError::InvalidCost(extra.to_string()));
+        }
+
+        Ok(Self { endpoint, cost })
+    }
+}
+
+impl FromStr for ConfigFile {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: Self = toml::from_str(s).map_err(ConfigError::Parse)?;
+        match config.extra_subnets {
+            Some(amount) if amount > mycelium::crypto::MAX_DELEGATED_SUBNETS => {
+                Err(ConfigError::TooManyExtraSubnets(amount))
+            }
+            _ => Ok(config),
+        }
+    }
+}
+
+/// A peer in a config file, either only an endpoint or a table with an endpoint and a cost.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PeerDefinition {
+    Endpoint(String),
+    #[serde(rename_all = "kebab-case")]
+    WithCost {
+        endpoint: String,
+        cost: Option<u16>,
+    },
+}
+
+/// Deserialize a list of peers with endpoints in the same format as they are passed on the CLI,
+/// e.g. `tcp://[::1]:9651` or `{ endpoint = "tcp://[::1]:9651", cost = 100 }`.
+fn deserialize_static_peers<'de, D>(deserializer: D) -> Result<Vec<StaticPeer>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<PeerDefinition>::deserialize(deserializer)?
+        .into_iter()
+        .map(|peer| {
+            let (endpoint, cost) = match peer {
+                PeerDefinition::Endpoint(endpoint) => (endpoint, None),
+                PeerDefinition::WithCost { endpoint, cost } => (endpoint, cost),
+            };
+            Ok(StaticPeer {
+                endpoint: Endpoint::from_str(&endpoint).map_err(D::Error::custom)?,
+                cost,
+            })
+        })
+        .collect()
+}
+
+/// Deserialize an optional duration from an amount of seconds, which can have a fractional part.
+fn deserialize_seconds<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<f64>::deserialize(deserializer)?
+        .map(|seconds| Duration::try_from_secs_f64(seconds).map_err(D::Error::custom))
+        .transpose()
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "could not read config file: {e}"),
+            Self::Parse(e) => write!(f, "invalid config file: {e}"),
+            Self::TooManyExtraSubnets(amount) => write!(
+                f,
+                "invalid config file: at most {} extra subnets can be announced, got {amount}",
+                mycelium::crypto::MAX_DELEGATED_SUBNETS
+            ),
+            Self::InvalidPeer { line, err } => {
+                write!(f, "invalid peer on line {line} of peers file: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl fmt::Display for PeerParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Endpoint(e) => e.fmt(f),
+            Self::InvalidCost(setting) => {
+                write!(f, "expected cost=<cost> after the endpoint, got {setting}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PeerParseError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use super::{parse_peers, ConfigFile, Reloadable};
+    use crate::Cli;
+    use mycelium::{filters::FilterConfig, message::OverflowPolicy};
+
+    const CONFIG: &str = r#"
+        peers = ["tcp://127.0.0.1:9651", { endpoint = "quic://[::1]:9651", cost = 100 }]
+        tcp-listen-port = 9000
+        tun-name = "mycelium"
+        no-tun = true
+        hello-interval = 5
+        dead-peer-threshold = 12.5
+        inbox-max-bytes = 1048576
+        inbox-overflow = "drop-oldest"
+
+        [[filters]]
+        type = "maxMetric"
+        metric = 100
+
+        [[topic-routes]]
+        topic = "example"
+        command = ["cat"]
+        retry-delay = 0.5
+    "#;
+
+    #[test]
+    fn parse_config_file() {
+        let config: ConfigFile = CONFIG.parse().expect("Valid config file");
+
+        assert_eq!(config.peers.len(), 2);
+        assert_eq!(config.peers[0].cost, None);
+        assert_eq!(config.peers[1].cost, Some(100));
+        assert_eq!(config.tcp_listen_port, Some(9000));
+        assert_eq!(config.tun_name.as_deref(), Some("mycelium"));
+        assert!(config.no_tun);
+        assert_eq!(config.hello_interval, Some(Duration::from_secs(5)));
+        assert_eq!(
+            config.dead_peer_threshold,
+            Some(Duration::from_millis(12_500))
+        );
+        assert_eq!(config.update_interval, None);
+        assert_eq!(config.inbox_max_bytes, Some(1_048_576));
+        assert_eq!(config.inbox_overflow, Some(OverflowPolicy::DropOldest));
+        assert_eq!(
+            config.filters,
+            vec![FilterConfig::MaxMetric { metric: 100 }]
+        );
+        assert_eq!(config.topic_routes.len(), 1);
+        assert_eq!(config.topic_routes[0].topic, "example");
+        assert_eq!(
+            config.topic_routes[0].retry_delay,
+            Duration::from_millis(500)
+        );
+
+        assert!("unknown-key = 1".parse::<ConfigFile>().is_err());
+        assert!("peers = [\"udp://[::1]:9651\"]"
+            .parse::<ConfigFile>()
+            .is_err());
+        assert!(
+            "peers = [{ endpoint = \"tcp://[::1]:9651\", cost = 70000 }]"
+                .parse::<ConfigFile>()
+                .is_err()
+        );
+        assert!("extra-subnets = 17".parse::<ConfigFile>().is_err());
+        assert!("hello-interval = -1".parse::<ConfigFile>().is_err());
+    }
+
+    #[test]
+    fn cli_overrides_config_file() {
+        let config: ConfigFile = CONFIG.parse().expect("Valid config file");
+        let mut cli = Cli::parse_from([
+            "mycelium",
+            "--tcp-listen-port",
+            "9100",
+            "--peers",
+            "tcp://127.0.0.2:9651",
+            "--max-update-metric",
+            "50",
+            "--hello-interval",
+            "2",
+        ]);
+
+        let filters = config.merge_into(&mut cli);
+
+        assert_eq!(cli.node_args.tcp_listen_port, Some(9100));
+        assert_eq!(cli.node_args.static_peers.len(), 1);
+        assert_eq!(cli.node_args.tun_name.as_deref(), Some("mycelium"));
+        assert!(cli.node_args.no_tun);
+        assert_eq!(cli.node_args.hello_interval, Some(Duration::from_secs(2)));
+        assert_eq!(
+            cli.node_args.dead_peer_threshold,
+            Some(Duration::from_millis(12_500))
+        );
+        assert_eq!(filters, vec![FilterConfig::MaxMetric { metric: 100 }]);
+    }
+
+    #[test]
+    fn cli_clears_config_file_flags() {
+        let config: ConfigFile = "no-tun = true\nenable-metrics = true\ndebug = true\n"
+            .parse()
+            .expect("Valid config file");
+        let mut cli = Cli::parse_from(["mycelium", "--tun", "--no-debug", "--silent"]);
+
+        config.merge_into(&mut cli);
+
+        assert!(!cli.node_args.no_tun);
+        assert!(cli.node_args.enable_metrics);
+        assert!(!cli.debug);
+        assert!(cli.silent);
+
+        // The last of a flag and its negation wins.
+        let cli = Cli::parse_from(["mycelium", "--tun", "--no-tun"]);
+        assert!(cli.node_args.no_tun);
+        assert!(!cli.node_args.tun);
+    }
+
+    #[test]
+    fn peers_file() {
+        let peers = parse_peers(
+            "# Public nodes\n\ntcp://127.0.0.1:9651\n  quic://[::1]:9651  cost=50 \n# tcp://127.0.0.2:9651\n",
+        )
+        .expect("Valid peers file");
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].cost, None);
+        assert_eq!(peers[1].cost, Some(50));
+
+        assert!(matches!(
+            parse_peers("tcp://127.0.0.1:9651\nnot a peer\n"),
+            Err(super::ConfigError::InvalidPeer { line: 2, .. })
+        ));
+        for invalid in [
+            "tcp://127.0.0.1:9651 50",
+            "tcp://127.0.0.1:9651 cost=-1",
+            "tcp://127.0.0.1:9651 cost=50 cost=60",
+        ] {
+            assert!(matches!(
+                parse_peers(invalid),
+                Err(super::ConfigError::InvalidPeer {
+                    line: 1,
+                    err: super::PeerParseError::InvalidCost(_)
+                })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn reloaded_settings() {
+        let config: ConfigFile = CONFIG.parse().expect("Valid config file");
+
+        let reloadable = Reloadable::new(&Cli::parse_from(["mycelium"]));
+        let settings = reloadable.settings(&config).await.expect("No peers file");
+        assert_eq!(settings.peers.len(), 2);
+        assert_eq!(settings.peers[1].cost, Some(100));
+        assert_eq!(settings.log_level, log::LevelFilter::Info);
+
+        let reloadable = Reloadable::new(&Cli::parse_from([
+            "mycelium",
+            "--debug",
+            "--peers",
+            "tcp://127.0.0.1:9651",
+            "tcp://127.0.0.1:9651",
+        ]));
+        let settings = reloadable.settings(&config).await.expect("No peers file");
+        assert_eq!(settings.peers.len(), 1);
+        assert_eq!(settings.log_level, log::LevelFilter::Debug);
+    }
+
+    #[tokio::test]
+    async fn cli_log_level_overrides_config_file() {
+        let config: ConfigFile = "silent = true".parse().expect("Valid config file");
+
+        let reloadable = Reloadable::new(&Cli::parse_from(["mycelium"]));
+        let settings = reloadable.settings(&config).await.expect("No peers file");
+        assert_eq!(settings.log_level, log::LevelFilter::Error);
+
+        let mut cli = Cli::parse_from(["mycelium", "--debug"]);
+        let reloadable = Reloadable::new(&cli);
+        let settings = reloadable.settings(&config).await.expect("No peers file");
+        assert_eq!(settings.log_level, log::LevelFilter::Debug);
+
+        config.merge_into(&mut cli);
+        assert_eq!(
+            super::log_level(cli.debug, cli.silent),
+            log::LevelFilter::Debug
+        );
+    }
+}
@@ -0,0 +1,734 @@
+//! Delivery of received messages to local applications, based on the topic of the message.
+//!
+//! Every [`TopicRoute`] passes the messages with its topic in the inbox to a Unix socket, the stdin
+//! of a command, or an HTTP webhook, as they come in. A message is only removed from the inbox
+//! once it is delivered. Data written back by the application is sent to the sender of the
+//! message as reply. Failed deliveries are retried a few times, after which the message is
+//! dropped.
+//!
+//! Messages with the topic of a route can still be read through the HTTP API while they wait for
+//! delivery, e.g. by readers which don't filter on a topic. A message which is taken from the
+//! inbox that way is not delivered by the route.
+
+use std::{
+    fmt,
+    future::Future,
+    io,
+    path::PathBuf,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinHandle,
+};
+
+use mycelium::message::{MessageStack, ReceivedMessage};
+
+use crate::api::message::MessageReceiveInfo;
+
+/// Default amount of times a failed delivery is retried.
+const DEFAULT_RETRIES: u32 = 3;
+/// Default time to wait before the first retry of a failed delivery. This doubles on every retry.
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Maximum amount of times a failed delivery can be retried.
+const MAX_RETRIES: u32 = 100;
+/// Maximum time to wait before a retry of a failed delivery.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 5);
+/// Default time a single delivery can take, including the time to write the reply.
+const DEFAULT_DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Amount of time to try and send a reply to a delivered message.
+const REPLY_TRY_DURATION: Duration = Duration::from_secs(60 * 5);
+
+/// Automatic delivery of messages with a given topic.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "TopicRouteDefinition")]
+pub struct TopicRoute {
+    /// Topic of the delivered messages.
+    pub topic: String,
+    /// Where the messages are delivered.
+    pub target: DeliveryTarget,
+    /// Amount of times a failed delivery is retried.
+    pub retries: u32,
+    /// Time to wait before the first retry of a failed delivery.
+    pub retry_delay: Duration,
+    /// Maximum time a single delivery can take.
+    pub timeout: Duration,
+}
+
+/// A local application which receives messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryTarget {
+    /// Connect to a Unix domain socket, and write the message as JSON document. The socket is
+    /// closed for writing afterwards, and everything read until the other side closes the
+    /// connection is the reply.
+    Socket(PathBuf),
+    /// Run a command with the message data on stdin, and information about the message in
+    /// environment variables. Everything written to stdout is the reply. The command must exit
+    /// successfully.
+    Command(Vec<String>),
+    /// POST the message as JSON document to a URL. The body of a successful response is the
+    /// reply.
+    Webhook(String),
+}
+
+/// A topic route as it is written in the config file.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TopicRouteDefinition {
+    topic: String,
+    socket: Option<PathBuf>,
+    command: Option<Vec<String>>,
+    webhook: Option<String>,
+    retries: Option<u32>,
+    retry_delay: Option<f64>,
+    timeout: Option<f64>,
+}
+
+/// Error returned when a [`TopicRoute`] is not valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidTopicRoute {
+    /// The topic is longer than the maximum topic length.
+    TopicTooLarge,
+    /// None or more than one of `socket`, `command` and `webhook` are set.
+    Target,
+    /// The command to run is empty.
+    EmptyCommand,
+    /// A duration is negative or too large.
+    Duration(String),
+    /// The webhook is not a valid `http://` URL. Webhooks over https are not supported, as the
+    /// HTTP client is built without TLS.
+    WebhookUrl,
+    /// More retries than [`MAX_RETRIES`] are configured.
+    TooManyRetries,
+    /// The retry delay is larger than [`MAX_RETRY_DELAY`].
+    RetryDelayTooLarge,
+}
+
+/// Reason a single delivery of a message failed.
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// Communicating with the application failed.
+    Io(io::Error),
+    /// The command exited unsuccessfully.
+    Command(std::process::ExitStatus),
+    /// The webhook request failed.
+    Webhook(reqwest::Error),
+    /// The application did not finish in time.
+    Timeout,
+}
+
+/// Delivery statistics of a [`TopicRoute`].
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicRouteStats {
+    /// Topic of the route.
+    pub topic: String,
+    /// Amount of messages which were delivered.
+    pub delivered: u64,
+    /// Amount of replies sent for delivered messages.
+    pub replies: u64,
+    /// Amount of deliveries which failed, including retries.
+    pub failed_attempts: u64,
+    /// Amount of messages which were dropped because all retries failed.
+    pub dropped: u64,
+    /// The error of the last failed delivery, if any.
+    pub last_error: Option<String>,
+}
+
+/// Handle to the tasks delivering messages for all configured [`TopicRoute`]s. The tasks are
+/// stopped when this is dropped.
+pub struct TopicRouter {
+    stats: Vec<Arc<Mutex<TopicRouteStats>>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TopicRouter {
+    /// Start delivering messages for the given routes. Received messages with the topic of a
+    /// route are kept in the inbox until they are delivered or dropped.
+    pub fn spawn(message_stack: MessageStack, routes: Vec<TopicRoute>) -> Self {
+        let mut stats = Vec::with_capacity(routes.len());
+        let mut tasks = Vec::with_capacity(routes.len());
+        for route in routes {
+            let route_stats = Arc::new(Mutex::new(TopicRouteStats {
+                topic: route.topic.clone(),
+                ..Default::default()
+            }));
+            stats.push(route_stats.clone());
+            let target = route.target.clone();
+            tasks.push(tokio::spawn(run_route(
+                message_stack.clone(),
+                route,
+                route_stats,
+                move |msg| {
+                    let target = target.clone();
+                    async move { target.deliver(&msg).await }
+                },
+            )));
+        }
+
+        Self { stats, tasks }
+    }
+
+    /// Get the delivery statistics of all routes.
+    pub fn stats(&self) -> Vec<TopicRouteStats> {
+        self.stats
+            .iter()
+            .map(|stats| stats.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+impl Drop for TopicRouter {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Deliver all messages for a route, one at a time, with the given delivery function. Messages
+/// are only taken from the inbox once they are delivered, so they are kept if the node stops
+/// during a delivery.
+async fn run_route<F, Fut>(
+    message_stack: MessageStack,
+    route: TopicRoute,
+    stats: Arc<Mutex<TopicRouteStats>>,
+    deliver: F,
+) where
+    F: Fn(ReceivedMessage) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, DeliveryError>>,
+{
+    debug!(
+        "Delivering messages with topic {} to {}",
+        route.topic, route.target
+    );
+    loop {
+        let msg = message_stack
+            .peek_message(Some(route.topic.as_bytes().to_vec()))
+            .await;
+
+        let mut retry_delay = route.retry_delay;
+        let mut attempt = 0;
+        let reply = loop {
+            let err = match tokio::time::timeout(route.timeout, deliver(msg.clone())).await {
+                Ok(Ok(reply)) => break Some(reply),
+                Ok(Err(e)) => e,
+                Err(_) => DeliveryError::Timeout,
+            };
+            warn!(
+                "Failed to deliver message {} to {}: {err}",
+                msg.id.as_hex(),
+                route.target
+            );
+            {
+                let mut stats = stats.lock().unwrap();
+                stats.failed_attempts += 1;
+                stats.last_error = Some(err.to_string());
+            }
+            if attempt == route.retries {
+                break None;
+            }
+            attempt += 1;
+            tokio::time::sleep(retry_delay).await;
+            retry_delay = retry_delay.saturating_mul(2).min(MAX_RETRY_DELAY);
+        };
+
+        let Some(reply) = reply else {
+            error!(
+                "Dropping message {} after {} failed deliveries to {}",
+                msg.id.as_hex(),
+                route.retries + 1,
+                route.target
+            );
+            message_stack.discard_message(msg.id);
+            stats.lock().unwrap().dropped += 1;
+            continue;
+        };
+
+        debug!("Delivered message {} to {}", msg.id.as_hex(), route.target);
+        message_stack.take_message(msg.id);
+        let mut route_stats = stats.lock().unwrap();
+        route_stats.delivered += 1;
+        if !reply.is_empty() {
+            let reply_id =
+                message_stack.reply_message(msg.id, msg.src_ip, reply, REPLY_TRY_DURATION);
+            debug!(
+                "Sending reply {} to message {}",
+                reply_id.as_hex(),
+                msg.id.as_hex()
+            );
+            route_stats.replies += 1;
+        }
+    }
+}
+
+impl DeliveryTarget {
+    /// Deliver a message to the target, and return the reply. If the application did not reply,
+    /// the reply is empty.
+    pub async fn deliver(&self, msg: &ReceivedMessage) -> Result<Vec<u8>, DeliveryError> {
+        match self {
+            Self::Socket(path) => deliver_socket(path, msg).await,
+            Self::Command(command) => deliver_command(command, msg).await,
+            Self::Webhook(url) => deliver_webhook(url, msg).await,
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+async fn deliver_socket(
+    path: &std::path::Path,
+    msg: &ReceivedMessage,
+) -> Result<Vec<u8>, DeliveryError> {
+    let mut stream = tokio::net::UnixStream::connect(path).await?;
+    let body = serde_json::to_vec(&MessageReceiveInfo::from(msg.clone()))
+        .expect("Message info can be serialized; qed");
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    Ok(reply)
+}
+
+#[cfg(not(target_family = "unix"))]
+async fn deliver_socket(
+    _: &std::path::Path,
+    _: &ReceivedMessage,
+) -> Result<Vec<u8>, DeliveryError> {
+    Err(DeliveryError::Io(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix domain sockets are not supported on this platform",
+    )))
+}
+
+async fn deliver_command(
+    command: &[String],
+    msg: &ReceivedMessage,
+) -> Result<Vec<u8>, DeliveryError> {
+    let mut child = tokio::process::Command::new(&command[0])
+        .args(&command[1..])
+        .env("MYCELIUM_MESSAGE_ID", msg.id.as_hex())
+        .env("MYCELIUM_MESSAGE_SRC_IP", msg.src_ip.to_string())
+        .env("MYCELIUM_MESSAGE_SRC_PK", msg.src_pk.to_string())
+        .env(
+            "MYCELIUM_MESSAGE_TOPIC",
+            &*String::from_utf8_lossy(&msg.topic),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        // The command is stopped if the delivery times out.
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .expect("Stdin of the command is piped; qed");
+    let write = async move {
+        // The command is not required to read the message.
+        match stdin.write_all(&msg.data).await {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+            _ => Ok(()),
+        }
+    };
+    let (written, output) = tokio::join!(write, child.wait_with_output());
+    written?;
+    let output = output?;
+    if !output.status.success() {
+        return Err(DeliveryError::Command(output.status));
+    }
+
+    Ok(output.stdout)
+}
+
+async fn deliver_webhook(url: &str, msg: &ReceivedMessage) -> Result<Vec<u8>, DeliveryError> {
+    let response = reqwest::Client::new()
+        .post(url)
+        .json(&MessageReceiveInfo::from(msg.clone()))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+impl TryFrom<TopicRouteDefinition> for TopicRoute {
+    type Error = InvalidTopicRoute;
+
+    fn try_from(def: TopicRouteDefinition) -> Result<Self, Self::Error> {
+        if def.topic.len() > 255 {
+            return Err(InvalidTopicRoute::TopicTooLarge);
+        }
+        let target = match (def.socket, def.command, def.webhook) {
+            (Some(path), None, None) => DeliveryTarget::Socket(path),
+            (None, Some(command), None) => {
+                if command.is_empty() {
+                    return Err(InvalidTopicRoute::EmptyCommand);
+                }
+                DeliveryTarget::Command(command)
+            }
+            (None, None, Some(url)) => {
+                if !reqwest::Url::parse(&url).is_ok_and(|url| url.scheme() == "http") {
+                    return Err(InvalidTopicRoute::WebhookUrl);
+                }
+                DeliveryTarget::Webhook(url)
+            }
+            _ => return Err(InvalidTopicRoute::Target),
+        };
+        let seconds = |value: Option<f64>, default| {
+            value.map_or(Ok(default), |seconds| {
+                Duration::try_from_secs_f64(seconds)
+                    .map_err(|e| InvalidTopicRoute::Duration(e.to_string()))
+            })
+        };
+
+        let retries = def.retries.unwrap_or(DEFAULT_RETRIES);
+        if retries > MAX_RETRIES {
+            return Err(InvalidTopicRoute::TooManyRetries);
+        }
+        let retry_delay = seconds(def.retry_delay, DEFAULT_RETRY_DELAY)?;
+        if retry_delay > MAX_RETRY_DELAY {
+            return Err(InvalidTopicRoute::RetryDelayTooLarge);
+        }
+
+        Ok(Self {
+            topic: def.topic,
+            target,
+            retries,
+            retry_delay,
+            timeout: seconds(def.timeout, DEFAULT_DELIVERY_TIMEOUT)?,
+        })
+    }
+}
+
+impl fmt::Display for DeliveryTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Socket(path) => write!(f, "socket {}", path.display()),
+            Self::Command(command) => write!(f, "command {}", command.join(" ")),
+            Self::Webhook(url) => write!(f, "webhook {url}"),
+        }
+    }
+}
+
+impl fmt::Display for InvalidTopicRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TopicTooLarge => f.write_str("topic can be at most 255 bytes"),
+            Self::Target => f.write_str("exactly one of socket, command or webhook must be set"),
+            Self::EmptyCommand => f.write_str("command can't be empty"),
+            Self::WebhookUrl => {
+                f.write_str("webhook must be a valid http:// URL, https is not supported")
+            }
+            Self::Duration(e) => write!(f, "invalid duration: {e}"),
+            Self::TooManyRetries => write!(f, "retries can be at most {MAX_RETRIES}"),
+            Self::RetryDelayTooLarge => write!(
+                f,
+                "retry-delay can be at most {} seconds",
+                MAX_RETRY_DELAY.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidTopicRoute {}
+
+impl From<io::Error> for DeliveryError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for DeliveryError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Webhook(e)
+    }
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => e.fmt(f),
+            Self::Command(status) => write!(f, "command failed: {status}"),
+            Self::Webhook(e) => write!(f, "webhook request failed: {e}"),
+            Self::Timeout => f.write_str("delivery timed out"),
+        }
+    }
+}
+
+impl std::error::Error for DeliveryError {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::IpAddr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{routing::post, Json, Router};
+    use mycelium::{
+        crypto::{PublicKey, SecretKey},
+        message::{MessageId, ReceivedMessage},
+        test_support::{connect, node, received_message},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::Instant,
+    };
+
+    use super::{
+        run_route, DeliveryError, DeliveryTarget, InvalidTopicRoute, TopicRoute, TopicRouteStats,
+    };
+    use crate::api::message::MessageReceiveInfo;
+
+    #[test]
+    fn parse_topic_route() {
+        let route: TopicRoute =
+            toml::from_str("topic = \"example\"\nwebhook = \"http://127.0.0.1:8080\"\nretries = 1")
+                .expect("Valid topic route");
+        assert_eq!(
+            route.target,
+            DeliveryTarget::Webhook("http://127.0.0.1:8080".into())
+        );
+        assert_eq!(route.retries, 1);
+
+        for (invalid, err) in [
+            ("topic = \"example\"", InvalidTopicRoute::Target),
+            (
+                "topic = \"example\"\nsocket = \"/tmp/a\"\nwebhook = \"http://[::1]\"",
+                InvalidTopicRoute::Target,
+            ),
+            (
+                "topic = \"example\"\ncommand = []",
+                InvalidTopicRoute::EmptyCommand,
+            ),
+            (
+                "topic = \"example\"\nwebhook = \"https://127.0.0.1:8080\"",
+                InvalidTopicRoute::WebhookUrl,
+            ),
+            (
+                "topic = \"example\"\nwebhook = \"127.0.0.1:8080\"",
+                InvalidTopicRoute::WebhookUrl,
+            ),
+            (
+                "topic = \"example\"\ncommand = [\"true\"]\nretries = 1000",
+                InvalidTopicRoute::TooManyRetries,
+            ),
+            (
+                "topic = \"example\"\ncommand = [\"true\"]\nretry-delay = 3600",
+                InvalidTopicRoute::RetryDelayTooLarge,
+            ),
+        ] {
+            let e = toml::from_str::<TopicRoute>(invalid).unwrap_err();
+            assert!(e.to_string().contains(&err.to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_to_command() {
+        let target = DeliveryTarget::Command(vec![
+            "sh".into(),
+            "-c".into(),
+            "printf \"$MYCELIUM_MESSAGE_TOPIC:\"; cat".into(),
+        ]);
+        let reply = target
+            .deliver(&received_message(b"example", b"data"))
+            .await
+            .unwrap();
+        assert_eq!(reply, b"example:data");
+
+        let target = DeliveryTarget::Command(vec!["false".into()]);
+        assert!(matches!(
+            target.deliver(&received_message(b"example", b"data")).await,
+            Err(DeliveryError::Command(_))
+        ));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn deliver_to_socket() {
+        let path = std::env::temp_dir().join(format!(
+            "mycelium-delivery-{}.sock",
+            MessageId::new().as_hex()
+        ));
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut body = Vec::new();
+            stream.read_to_end(&mut body).await.unwrap();
+            let info: MessageReceiveInfo = serde_json::from_slice(&body).unwrap();
+            stream.write_all(&info.payload).await.unwrap();
+        });
+
+        let reply = DeliveryTarget::Socket(path.clone())
+            .deliver(&received_message(b"example", b"data"))
+            .await
+            .unwrap();
+        server.await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(reply, b"data");
+    }
+
+    #[tokio::test]
+    async fn deliver_to_webhook() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(|Json(info): Json<MessageReceiveInfo>| async move { info.payload }),
+            )
+            .route(
+                "/fail",
+                post(|| async { axum::http::StatusCode::INTERNAL_SERVER_ERROR }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let reply = DeliveryTarget::Webhook(format!("http://{addr}/hook"))
+            .deliver(&received_message(b"example", b"data"))
+            .await
+            .unwrap();
+        assert_eq!(reply, b"data");
+
+        assert!(matches!(
+            DeliveryTarget::Webhook(format!("http://{addr}/fail"))
+                .deliver(&received_message(b"example", b"data"))
+                .await,
+            Err(DeliveryError::Webhook(_))
+        ));
+    }
+
+    fn route(topic: &str, retries: u32) -> TopicRoute {
+        TopicRoute {
+            topic: topic.into(),
+            // The target is not used, messages are passed to the delivery function of the test.
+            target: DeliveryTarget::Command(vec!["true".into()]),
+            retries,
+            retry_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_delivery() {
+        let receiver_key = SecretKey::new();
+        let receiver_ip = IpAddr::V6(PublicKey::from(&receiver_key).address());
+        let receiver = node(receiver_key).await;
+        let sender = node(SecretKey::new()).await;
+        connect(&sender, &receiver);
+        let stats = Arc::new(Mutex::new(TopicRouteStats::default()));
+
+        // The first 2 deliveries fail, the third one replies with the message data.
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let deliver = {
+            let attempts = attempts.clone();
+            move |msg: ReceivedMessage| {
+                let attempts = attempts.clone();
+                async move {
+                    let attempt = {
+                        let mut attempts = attempts.lock().unwrap();
+                        attempts.push(Instant::now());
+                        attempts.len()
+                    };
+                    if attempt < 3 {
+                        Err(DeliveryError::Timeout)
+                    } else {
+                        Ok([b"reply:", &msg.data[..]].concat())
+                    }
+                }
+            }
+        };
+        let task = tokio::spawn(run_route(
+            receiver.message_stack(),
+            route("example", 3),
+            stats.clone(),
+            deliver,
+        ));
+
+        let (id, reply) = sender
+            .push_message(
+                receiver_ip,
+                b"data".to_vec(),
+                Some(b"example".to_vec()),
+                Duration::from_secs(60),
+                true,
+            )
+            .expect("Can push message");
+        let mut reply = reply.expect("Reply is subscribed");
+        tokio::time::timeout(Duration::from_secs(60), reply.changed())
+            .await
+            .expect("Reply is received")
+            .expect("Reply subscriber is kept");
+        let reply = reply.borrow().clone().expect("Reply is set");
+        assert!(reply.is_reply);
+        assert_eq!(reply.data, b"reply:data");
+
+        // Retries are delayed by the retry delay, which doubles every time.
+        let attempts = attempts.lock().unwrap().clone();
+        assert_eq!(attempts.len(), 3);
+        assert!(attempts[1] - attempts[0] >= Duration::from_secs(1));
+        assert!(attempts[2] - attempts[1] >= Duration::from_secs(2));
+        {
+            let stats = stats.lock().unwrap();
+            assert_eq!(stats.delivered, 1);
+            assert_eq!(stats.replies, 1);
+            assert_eq!(stats.failed_attempts, 2);
+            assert_eq!(stats.dropped, 0);
+            assert_eq!(
+                stats.last_error.as_deref(),
+                Some(&*DeliveryError::Timeout.to_string())
+            );
+        }
+        // The delivered message is taken from the inbox.
+        assert!(receiver.message_stack().discard_message(id).is_none());
+        task.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drop_undeliverable_message() {
+        let receiver_key = SecretKey::new();
+        let receiver_ip = IpAddr::V6(PublicKey::from(&receiver_key).address());
+        let receiver = node(receiver_key).await;
+        let sender = node(SecretKey::new()).await;
+        connect(&sender, &receiver);
+        let stats = Arc::new(Mutex::new(TopicRouteStats::default()));
+
+        let task = tokio::spawn(run_route(
+            receiver.message_stack(),
+            route("example", 1),
+            stats.clone(),
+            |_| async { Err(DeliveryError::Timeout) },
+        ));
+        let (id, _) = sender
+            .push_message(
+                receiver_ip,
+                b"data".to_vec(),
+                Some(b"example".to_vec()),
+                Duration::from_secs(60),
+                false,
+            )
+            .expect("Can push message");
+
+        for _ in 0..60 {
+            if stats.lock().unwrap().dropped > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        {
+            let stats = stats.lock().unwrap();
+            assert_eq!(stats.dropped, 1);
+            assert_eq!(stats.failed_attempts, 2);
+            assert_eq!(stats.delivered, 0);
+        }
+        // The dropped message is removed from the inbox.
+        assert!(receiver.message_stack().discard_message(id).is_none());
+        task.abort();
+    }
+}
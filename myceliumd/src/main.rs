@@ -1,23 +1,34 @@
 use clap::{Args, Parser, Subcommand};
 use crypto::PublicKey;
-use log::{debug, error, warn, LevelFilter};
+use log::{debug, error, info, warn, LevelFilter};
 use mycelium::endpoint::Endpoint;
+use mycelium::filters::FilterConfig;
+use mycelium::message::{InboxLimits, OverflowPolicy};
+use mycelium::peer_manager::{PeerAcl, PeerType};
+use mycelium::router::RouterConfig;
+use mycelium::subnet::Subnet;
 use mycelium::{crypto, Node};
 use std::io;
 use std::net::Ipv4Addr;
 use std::path::Path;
+use std::sync::Arc;
 use std::{
     error::Error,
     net::{IpAddr, SocketAddr},
+    num::NonZeroU8,
     path::PathBuf,
+    time::Duration,
 };
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 #[cfg(target_family = "unix")]
 use tokio::signal::{self, unix::SignalKind};
+use tokio::sync::Mutex;
 
 mod api;
 mod cli;
+mod config;
+mod delivery;
 
 /// The default port on the underlay to listen on for incoming TCP connections.
 const DEFAULT_TCP_LISTEN_PORT: u16 = 9651;
@@ -50,10 +61,27 @@ struct Cli {
     #[arg(short = 'd', long = "debug", default_value_t = false)]
     debug: bool,
 
+    /// Disable debug logging, even if it is enabled in the config file.
+    #[arg(long = "no-debug", default_value_t = false, overrides_with = "debug")]
+    no_debug: bool,
+
     /// Disable all logs except error logs.
     #[arg(long = "silent", default_value_t = false)]
     silent: bool,
 
+    /// Don't disable any logs, even if `silent` is set in the config file.
+    #[arg(long = "no-silent", default_value_t = false, overrides_with = "silent")]
+    no_silent: bool,
+
+    /// Path to a TOML configuration file.
+    ///
+    /// The file can set all node arguments, using the long name of the flag as key, as well as
+    /// the `filters` for received route updates and the `topic-routes` for received messages.
+    /// Flags set on the command line override values in the file. On SIGHUP, the peers and the log
+    /// level are reloaded from this file.
+    #[arg(short = 'c', long = "config", global = true)]
+    config_file: Option<PathBuf>,
+
     #[clap(flatten)]
     node_args: NodeArguments,
 
@@ -74,6 +102,12 @@ pub enum Command {
         key: Option<String>,
     },
 
+    /// Actions on the routes of a running node
+    Routes {
+        #[command(subcommand)]
+        command: RoutesCommand,
+    },
+
     #[cfg(feature = "message")]
     /// Actions on the message subsystem
     Message {
@@ -82,6 +116,19 @@ pub enum Command {
     },
 }
 
+#[derive(Debug, Subcommand)]
+pub enum RoutesCommand {
+    /// Show the recorded changes of selected routes, from oldest to newest.
+    History {
+        /// Only show changes for this subnet.
+        #[arg(long = "subnet")]
+        subnet: Option<Subnet>,
+        /// Output in json format.
+        #[arg(long = "json")]
+        json: bool,
+    },
+}
+
 #[derive(Debug, Subcommand)]
 pub enum MessageCommand {
     Send {
@@ -96,7 +143,8 @@ pub enum MessageCommand {
         /// for a chosen topic.
         #[arg(short = 't', long = "topic")]
         topic: Option<String>,
-        /// Optional file to use as message body.
+        /// Optional file to use as message body. Unless `--wait` is set, the file is streamed to
+        /// the node instead of being loaded in memory as a whole.
         #[arg(long = "msg-path")]
         msg_path: Option<PathBuf>,
         /// Optional message ID to reply to.
@@ -131,17 +179,61 @@ pub struct NodeArguments {
     #[arg(long = "peers", num_args = 1..)]
     static_peers: Vec<Endpoint>,
 
-    /// Port to listen on for tcp connections.
-    #[arg(short = 't', long = "tcp-listen-port", default_value_t = DEFAULT_TCP_LISTEN_PORT)]
-    tcp_listen_port: u16,
+    /// File with peers to connect to, one endpoint per line.
+    ///
+    /// An endpoint can be followed by `cost=<cost>`, which is added to the link cost of the peer.
+    /// Empty lines and lines starting with `#` are ignored. The peers in this file are added to
+    /// the other configured peers, and are reloaded on SIGHUP.
+    #[arg(long = "peers-file")]
+    peers_file: Option<PathBuf>,
+
+    /// Port to listen on for tcp connections. Default [9651].
+    #[arg(short = 't', long = "tcp-listen-port")]
+    tcp_listen_port: Option<u16>,
+
+    /// Port to listen on for quic connections. Default [9651].
+    #[arg(short = 'q', long = "quic-listen-port")]
+    quic_listen_port: Option<u16>,
 
-    /// Port to listen on for quic connections.
-    #[arg(short = 'q', long = "quic-listen-port", default_value_t = DEFAULT_QUIC_LISTEN_PORT)]
-    quic_listen_port: u16,
+    /// Port to listen on for TLS connections.
+    ///
+    /// If this is not set, no TLS connections are accepted. Connecting to TLS peers with a
+    /// `tls://` endpoint does not require this.
+    #[arg(long = "tls-listen-port")]
+    tls_listen_port: Option<u16>,
+
+    /// Port to listen on for WebSocket connections.
+    ///
+    /// If this is not set, no WebSocket connections are accepted. The WebSocket listener does not
+    /// use TLS, so it is intended to be exposed through a proxy or load balancer which terminates
+    /// TLS.
+    #[arg(long = "ws-listen-port")]
+    ws_listen_port: Option<u16>,
+
+    /// Port to listen on for WebSocket connections secured with TLS.
+    ///
+    /// If this is not set, no secure WebSocket connections are accepted.
+    #[arg(long = "wss-listen-port")]
+    wss_listen_port: Option<u16>,
+
+    /// HTTP proxy used for outbound WebSocket connections, as `host:port`.
+    ///
+    /// The connection to `ws://` and `wss://` peers is tunneled through the proxy with a CONNECT
+    /// request.
+    #[arg(long = "ws-proxy")]
+    ws_proxy: Option<String>,
+
+    /// Path of a Unix domain socket to accept connections on.
+    ///
+    /// This allows connecting multiple nodes on the same host without using the network, by
+    /// using a `unix://` endpoint with the same path. A stale socket at this path, which no
+    /// process is listening on anymore, is removed.
+    #[arg(long = "unix-listen-path")]
+    unix_listen_path: Option<PathBuf>,
 
-    /// Port to use for link local peer discovery. This uses the UDP protocol.
-    #[arg(long = "peer-discovery-port", default_value_t = DEFAULT_PEER_DISCOVERY_PORT)]
-    peer_discovery_port: u16,
+    /// Port to use for link local peer discovery. This uses the UDP protocol. Default [9650].
+    #[arg(long = "peer-discovery-port")]
+    peer_discovery_port: Option<u16>,
 
     /// Disable peer discovery.
     ///
@@ -151,9 +243,29 @@ pub struct NodeArguments {
     #[arg(long = "disable-peer-discovery", default_value_t = false)]
     disable_peer_discovery: bool,
 
-    /// Address of the HTTP API server.
-    #[arg(long = "api-addr", default_value_t = DEFAULT_HTTP_API_SERVER_ADDRESS)]
-    api_addr: SocketAddr,
+    /// Enable peer discovery, even if it is disabled in the config file.
+    #[arg(
+        long = "enable-peer-discovery",
+        default_value_t = false,
+        overrides_with = "disable_peer_discovery"
+    )]
+    enable_peer_discovery: bool,
+
+    /// Address of the HTTP API server. Default [127.0.0.1:8989].
+    #[arg(long = "api-addr")]
+    api_addr: Option<SocketAddr>,
+
+    /// Expose metrics in the Prometheus text format on `/metrics` of the HTTP API server.
+    #[arg(long = "enable-metrics", default_value_t = false)]
+    enable_metrics: bool,
+
+    /// Don't expose metrics, even if they are enabled in the config file.
+    #[arg(
+        long = "disable-metrics",
+        default_value_t = false,
+        overrides_with = "enable_metrics"
+    )]
+    disable_metrics: bool,
 
     /// Run without creating a TUN interface.
     ///
@@ -163,31 +275,189 @@ pub struct NodeArguments {
     #[arg(long = "no-tun", default_value_t = false)]
     no_tun: bool,
 
+    /// Create a TUN interface, even if `no-tun` is set in the config file.
+    #[arg(long = "tun", default_value_t = false, overrides_with = "no_tun")]
+    tun: bool,
+
     /// Name to use for the TUN interface, if one is created.
     ///
     /// Setting this only matters if a TUN interface is actually created, i.e. if the `--no-tun`
     /// flag is **not** set. The name set here must be valid for the current platform, e.g. on OSX,
-    /// the name must start with `utun` and be followed by digits.
-    #[arg(long = "tun-name", default_value = TUN_NAME)]
-    tun_name: String,
+    /// the name must start with `utun` and be followed by digits. Default [tun0], or [utun3] on
+    /// OSX.
+    #[arg(long = "tun-name")]
+    tun_name: Option<String>,
+
+    /// Only accept inbound and discovered peers with an underlay address in one of these subnets.
+    ///
+    /// If this is not set, peers from all subnets are accepted, unless they are explicitly denied.
+    /// Statically configured peers are not affected.
+    #[arg(long = "allowed-peer-subnets", num_args = 1..)]
+    allowed_peer_subnets: Vec<Subnet>,
+
+    /// Reject inbound and discovered peers with an underlay address in one of these subnets.
+    #[arg(long = "denied-peer-subnets", num_args = 1..)]
+    denied_peer_subnets: Vec<Subnet>,
+
+    /// Only accept inbound and discovered peers using one of these hex encoded public keys.
+    ///
+    /// If this is not set, all keys are accepted, unless they are explicitly denied. Statically
+    /// configured peers are not affected.
+    #[arg(long = "allowed-peer-keys", num_args = 1..)]
+    allowed_peer_keys: Vec<PublicKey>,
+
+    /// Reject inbound and discovered peers using one of these hex encoded public keys.
+    #[arg(long = "denied-peer-keys", num_args = 1..)]
+    denied_peer_keys: Vec<PublicKey>,
+
+    /// Directory to keep state in across restarts.
+    ///
+    /// If this is set, a snapshot of the routing state is saved here, which allows the node to
+    /// reconverge faster after a restart.
+    #[arg(long = "state-dir")]
+    state_dir: Option<PathBuf>,
+
+    /// Keep received messages which have not been read yet, and messages which are still being
+    /// sent, in the state directory.
+    ///
+    /// After a restart, unread messages are available again, and sending messages is resumed if
+    /// their deadline did not pass yet. This requires a state directory.
+    #[arg(long = "persist-messages")]
+    persist_messages: bool,
+
+    /// Don't keep messages in the state directory, even if this is enabled in the config file.
+    #[arg(long = "no-persist-messages", overrides_with = "persist_messages")]
+    no_persist_messages: bool,
+
+    /// Maximum total size of received messages which are not read yet, in bytes. Default
+    /// [268435456].
+    ///
+    /// Messages which are still being received count with their announced size.
+    #[arg(long = "inbox-max-bytes")]
+    inbox_max_bytes: Option<usize>,
+
+    /// Maximum total size of received messages from a single sender which are not read yet, in
+    /// bytes. Messages from all IPs in the subnet of the sender count together. Default
+    /// [67108864].
+    #[arg(long = "inbox-max-bytes-per-sender")]
+    inbox_max_bytes_per_sender: Option<usize>,
+
+    /// Maximum amount of received messages with the same topic which are not read yet. Default
+    /// [10000].
+    #[arg(long = "inbox-max-topic-messages")]
+    inbox_max_topic_messages: Option<usize>,
+
+    /// Maximum amount of messages which are being received at the same time. Default [256].
+    #[arg(long = "inbox-max-pending-messages")]
+    inbox_max_pending_messages: Option<usize>,
+
+    /// What to do with a new message if the inbox is full: "reject" or "drop-oldest". Default
+    /// [reject].
+    ///
+    /// Rejected messages are refused, and the sender stops sending them. With "drop-oldest", the
+    /// oldest unread messages are removed to make room for the new message.
+    #[arg(long = "inbox-overflow")]
+    inbox_overflow: Option<OverflowPolicy>,
+
+    /// Amount of extra /64 subnets to announce, in addition to the node subnet.
+    ///
+    /// These subnets are derived from the node key, so other nodes can verify they are owned by
+    /// this node. Traffic for them is delivered to the TUN interface, from where it can be routed
+    /// to e.g. containers behind this node. The announced subnets are listed in the node info.
+    #[arg(
+        long = "extra-subnets",
+        value_parser = clap::value_parser!(u8).range(..=crypto::MAX_DELEGATED_SUBNETS as i64)
+    )]
+    extra_subnets: Option<u8>,
+
+    /// Time between Hellos sent to peers, in seconds. Default [20].
+    ///
+    /// This interval is advertised to peers, which consider the node dead if it misses 2 Hellos.
+    /// Shorter intervals detect link failures faster, at the cost of more traffic.
+    #[arg(long = "hello-interval", value_parser = parse_seconds)]
+    hello_interval: Option<Duration>,
+
+    /// Interval advertised in IHU's, in seconds. Default [60].
+    #[arg(long = "ihu-interval", value_parser = parse_seconds)]
+    ihu_interval: Option<Duration>,
+
+    /// Time between announcements of all routes to peers, in seconds. Default [60].
+    ///
+    /// Peers remove routes which are not announced again within 3.5 times this interval.
+    #[arg(long = "update-interval", value_parser = parse_seconds)]
+    update_interval: Option<Duration>,
+
+    /// Time without reply to our Hellos after which a peer is considered dead, in seconds.
+    /// Default [43].
+    ///
+    /// This must be greater than the hello interval.
+    #[arg(long = "dead-peer-threshold", value_parser = parse_seconds)]
+    dead_peer_threshold: Option<Duration>,
+
+    /// Minimum time between consecutive increases of the router seqno, in seconds. Default [4].
+    #[arg(long = "seqno-bump-timeout", value_parser = parse_seconds)]
+    seqno_bump_timeout: Option<Duration>,
+
+    /// Time a retracted route is kept before it is removed, in seconds. Default [60].
+    #[arg(long = "retracted-route-hold-time", value_parser = parse_seconds)]
+    retracted_route_hold_time: Option<Duration>,
+
+    /// Maximum amount of hops seqno requests sent or forwarded by this node can travel. Default
+    /// [64].
+    ///
+    /// Requests from peers with a higher hop count are lowered to this value before they are
+    /// forwarded. A value of 1 means requests are only sent to direct peers.
+    #[arg(long = "seqno-request-hop-limit")]
+    seqno_request_hop_limit: Option<NonZeroU8>,
+
+    /// Reject route updates for routes originated by routers using one of these hex encoded
+    /// public keys.
+    #[arg(long = "deny-update-routers", num_args = 1..)]
+    deny_update_routers: Vec<PublicKey>,
+
+    /// Reject route updates with a metric higher than this value.
+    #[arg(long = "max-update-metric")]
+    max_update_metric: Option<u16>,
+
+    /// Limit the amount of route updates accepted from a single peer per second.
+    #[arg(long = "update-rate-limit")]
+    update_rate_limit: Option<u32>,
+
+    /// Amount of route updates a single peer can send at once when `--update-rate-limit` is set.
+    ///
+    /// Defaults to 10 seconds worth of updates.
+    #[arg(long = "update-rate-burst")]
+    update_rate_burst: Option<u32>,
+
+    /// Reject route updates for subnets which are part of one of these subnets.
+    #[arg(long = "deny-update-subnets", num_args = 1..)]
+    deny_update_subnets: Vec<Subnet>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+
+    let reloadable = config::Reloadable::new(&cli);
+    let (mut update_filters, topic_routes) = match cli.config_file.clone() {
+        Some(path) => {
+            let mut config = config::ConfigFile::load(&path).await?;
+            let topic_routes = std::mem::take(&mut config.topic_routes);
+            (config.merge_into(&mut cli), topic_routes)
+        }
+        None => (Vec::new(), Vec::new()),
+    };
+    let api_addr = cli
+        .node_args
+        .api_addr
+        .unwrap_or(DEFAULT_HTTP_API_SERVER_ADDRESS);
 
+    // The module filter allows all levels which can be configured, the actual level is set as
+    // max level, so it can be changed on SIGHUP.
     pretty_env_logger::formatted_timed_builder()
-        .filter_module(
-            "mycelium",
-            if cli.silent {
-                LevelFilter::Error
-            } else if cli.debug {
-                LevelFilter::Debug
-            } else {
-                LevelFilter::Info
-            },
-        )
+        .filter_module("mycelium", LevelFilter::Debug)
         .init();
+    log::set_max_level(config::log_level(cli.debug, cli.silent));
 
     let key_path = if let Some(path) = cli.key_file {
         path
@@ -224,6 +494,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
                 return Ok(());
             }
+            Command::Routes { command } => match command {
+                RoutesCommand::History { subnet, json } => {
+                    return cli::route_history(subnet, json, api_addr).await
+                }
+            },
             #[cfg(feature = "message")]
             Command::Message { command } => match command {
                 MessageCommand::Send {
@@ -243,7 +518,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         reply_to,
                         topic,
                         msg_path,
-                        cli.node_args.api_addr,
+                        api_addr,
                     )
                     .await
                 }
@@ -252,10 +527,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     topic,
                     msg_path,
                     raw,
-                } => {
-                    return cli::recv_msg(timeout, topic, msg_path, raw, cli.node_args.api_addr)
-                        .await
-                }
+                } => return cli::recv_msg(timeout, topic, msg_path, raw, api_addr).await,
             },
         }
     }
@@ -269,23 +541,161 @@ async fn main() -> Result<(), Box<dyn Error>> {
         secret_key
     };
 
+    // Static peers are resolved the same way as when they are reloaded, which also loads the
+    // peers file.
+    let static_peers = reloadable.reload().await?.peers;
+
+    let extra_subnets = (0..cli.node_args.extra_subnets.unwrap_or(0))
+        .filter_map(|index| PublicKey::from(&node_secret_key).delegated_subnet(index))
+        .collect();
+
+    if !cli.node_args.deny_update_routers.is_empty() {
+        update_filters.push(FilterConfig::DenyRouters {
+            keys: cli.node_args.deny_update_routers,
+        });
+    }
+    if let Some(metric) = cli.node_args.max_update_metric {
+        update_filters.push(FilterConfig::MaxMetric { metric });
+    }
+    if let Some(updates_per_second) = cli.node_args.update_rate_limit {
+        update_filters.push(FilterConfig::RateLimit {
+            updates_per_second,
+            burst: cli
+                .node_args
+                .update_rate_burst
+                .unwrap_or(updates_per_second.saturating_mul(10)),
+        });
+    }
+    if !cli.node_args.deny_update_subnets.is_empty() {
+        update_filters.push(FilterConfig::DenySubnets {
+            subnets: cli.node_args.deny_update_subnets,
+        });
+    }
+
+    let default_timers = RouterConfig::default();
+    let router_config = RouterConfig {
+        hello_interval: cli
+            .node_args
+            .hello_interval
+            .unwrap_or(default_timers.hello_interval),
+        ihu_interval: cli
+            .node_args
+            .ihu_interval
+            .unwrap_or(default_timers.ihu_interval),
+        update_interval: cli
+            .node_args
+            .update_interval
+            .unwrap_or(default_timers.update_interval),
+        dead_peer_threshold: cli
+            .node_args
+            .dead_peer_threshold
+            .unwrap_or(default_timers.dead_peer_threshold),
+        seqno_bump_timeout: cli
+            .node_args
+            .seqno_bump_timeout
+            .unwrap_or(default_timers.seqno_bump_timeout),
+        retracted_route_hold_time: cli
+            .node_args
+            .retracted_route_hold_time
+            .unwrap_or(default_timers.retracted_route_hold_time),
+        seqno_request_hop_limit: cli
+            .node_args
+            .seqno_request_hop_limit
+            .unwrap_or(default_timers.seqno_request_hop_limit),
+    };
+
+    let default_inbox_limits = InboxLimits::default();
+    let inbox_limits = InboxLimits {
+        max_bytes: cli
+            .node_args
+            .inbox_max_bytes
+            .unwrap_or(default_inbox_limits.max_bytes),
+        max_bytes_per_sender: cli
+            .node_args
+            .inbox_max_bytes_per_sender
+            .unwrap_or(default_inbox_limits.max_bytes_per_sender),
+        max_topic_messages: cli
+            .node_args
+            .inbox_max_topic_messages
+            .unwrap_or(default_inbox_limits.max_topic_messages),
+        max_pending_messages: cli
+            .node_args
+            .inbox_max_pending_messages
+            .unwrap_or(default_inbox_limits.max_pending_messages),
+        overflow: cli
+            .node_args
+            .inbox_overflow
+            .unwrap_or(default_inbox_limits.overflow),
+    };
+
     let config = mycelium::Config {
         node_key: node_secret_key,
-        peers: cli.node_args.static_peers,
+        peers: static_peers
+            .iter()
+            .map(|peer| peer.endpoint.clone())
+            .collect(),
         no_tun: cli.node_args.no_tun,
-        tcp_listen_port: cli.node_args.tcp_listen_port,
-        quic_listen_port: cli.node_args.quic_listen_port,
+        tcp_listen_port: cli
+            .node_args
+            .tcp_listen_port
+            .unwrap_or(DEFAULT_TCP_LISTEN_PORT),
+        quic_listen_port: cli
+            .node_args
+            .quic_listen_port
+            .unwrap_or(DEFAULT_QUIC_LISTEN_PORT),
+        tls_listen_port: cli.node_args.tls_listen_port,
+        ws_listen_port: cli.node_args.ws_listen_port,
+        wss_listen_port: cli.node_args.wss_listen_port,
+        ws_proxy: cli.node_args.ws_proxy,
+        unix_listen_path: cli.node_args.unix_listen_path,
         peer_discovery_port: if cli.node_args.disable_peer_discovery {
             None
         } else {
-            Some(cli.node_args.peer_discovery_port)
+            Some(
+                cli.node_args
+                    .peer_discovery_port
+                    .unwrap_or(DEFAULT_PEER_DISCOVERY_PORT),
+            )
         },
-        tun_name: cli.node_args.tun_name,
+        tun_name: cli
+            .node_args
+            .tun_name
+            .unwrap_or_else(|| TUN_NAME.to_string()),
+        peer_acl: PeerAcl {
+            allowed_subnets: cli.node_args.allowed_peer_subnets,
+            denied_subnets: cli.node_args.denied_peer_subnets,
+            allowed_keys: cli.node_args.allowed_peer_keys,
+            denied_keys: cli.node_args.denied_peer_keys,
+        },
+        state_dir: cli.node_args.state_dir,
+        persist_messages: cli.node_args.persist_messages,
+        inbox_limits,
+        extra_subnets,
+        update_filters,
+        router_config,
     };
 
     let node = Node::new(config).await?;
-
-    let _api = api::Http::spawn(node, cli.node_args.api_addr);
+    for peer in static_peers.iter().filter(|peer| peer.cost.is_some()) {
+        // All static peers were just added to the node.
+        let _ = node.set_peer_cost(&peer.endpoint, peer.cost);
+    }
+    // Peers from the CLI and config files, as of the last (re)load. Only these peers are removed
+    // if they are no longer configured, static peers added through the API are kept.
+    #[cfg(target_family = "unix")]
+    let mut configured_peers = static_peers;
+    let topic_router = Arc::new(delivery::TopicRouter::spawn(
+        node.message_stack(),
+        topic_routes,
+    ));
+    let node = Arc::new(Mutex::new(node));
+
+    let _api = api::Http::spawn(
+        node.clone(),
+        topic_router,
+        api_addr,
+        cli.node_args.enable_metrics,
+    );
 
     // TODO: put in dedicated file so we can only rely on certain signals on unix platforms
     #[cfg(target_family = "unix")]
@@ -294,10 +704,15 @@ async fn main() -> Result<(), Box<dyn Error>> {
             signal::unix::signal(SignalKind::interrupt()).expect("Can install SIGINT handler");
         let mut sigterm =
             signal::unix::signal(SignalKind::terminate()).expect("Can install SIGTERM handler");
-
-        tokio::select! {
-            _ = sigint.recv() => { }
-            _ = sigterm.recv() => { }
+        let mut sighup =
+            signal::unix::signal(SignalKind::hangup()).expect("Can install SIGHUP handler");
+
+        loop {
+            tokio::select! {
+                _ = sigint.recv() => break,
+                _ = sigterm.recv() => break,
+                _ = sighup.recv() => reload(&node, &reloadable, &mut configured_peers).await,
+            }
         }
     }
     #[cfg(not(target_family = "unix"))]
@@ -307,9 +722,92 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
+    // Retract our routes and close the connections, so peers don't have to wait until they
+    // notice we are gone.
+    node.lock().await.shutdown().await;
+
     Ok(())
 }
 
+/// Parse a duration from an amount of seconds, which can have a fractional part.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds = value.parse::<f64>().map_err(|e| e.to_string())?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+/// Reload the static peers and the log level. Peers which were configured during the previous
+/// load but no longer are, are removed. New peers are added, and peer costs which changed in the
+/// file since the previous load are applied. Static peers added through the API, costs set through
+/// the API, connections to peers which are still configured, and the TUN interface are not
+/// affected.
+#[cfg(target_family = "unix")]
+async fn reload(
+    node: &Mutex<Node>,
+    reloadable: &config::Reloadable,
+    configured_peers: &mut Vec<config::StaticPeer>,
+) {
+    info!("Reloading configuration");
+    let settings = match reloadable.reload().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to reload configuration: {e}");
+            return;
+        }
+    };
+
+    if settings.log_level != log::max_level() {
+        info!("Setting log level to {}", settings.log_level);
+        log::set_max_level(settings.log_level);
+    }
+
+    let node = node.lock().await;
+    let current: Vec<_> = node
+        .peer_info()
+        .into_iter()
+        .filter(|peer| peer.pt == PeerType::Static)
+        .map(|peer| (peer.endpoint, peer.cost))
+        .collect();
+
+    for endpoint in configured_peers
+        .iter()
+        .map(|peer| &peer.endpoint)
+        .filter(|ep| !settings.peers.iter().any(|peer| peer.endpoint == **ep))
+        .filter(|ep| current.iter().any(|(current, _)| current == *ep))
+    {
+        match node.remove_peer(endpoint.clone()) {
+            Ok(()) => info!("Removed peer {endpoint}"),
+            Err(e) => warn!("Failed to remove peer {endpoint}: {e}"),
+        }
+    }
+    for peer in &settings.peers {
+        let (current_cost, added) = match current.iter().find(|(ep, _)| *ep == peer.endpoint) {
+            Some((_, cost)) => (*cost, false),
+            None => {
+                match node.add_peer(peer.endpoint.clone()) {
+                    Ok(()) => info!("Added peer {}", peer.endpoint),
+                    Err(e) => {
+                        warn!("Failed to add peer {}: {e}", peer.endpoint);
+                        continue;
+                    }
+                }
+                (None, true)
+            }
+        };
+        // Only apply costs which changed in the file, so costs set through the API are kept.
+        let previous_cost = configured_peers
+            .iter()
+            .find(|previous| previous.endpoint == peer.endpoint)
+            .map(|previous| previous.cost);
+        if (added || previous_cost != Some(peer.cost)) && peer.cost != current_cost {
+            match node.set_peer_cost(&peer.endpoint, peer.cost) {
+                Ok(()) => info!("Set cost of peer {} to {:?}", peer.endpoint, peer.cost),
+                Err(e) => warn!("Failed to set cost of peer {}: {e}", peer.endpoint),
+            }
+        }
+    }
+    *configured_peers = settings.peers;
+}
+
 async fn load_key_file(path: &Path) -> Result<crypto::SecretKey, io::Error> {
     let mut file = File::open(path).await?;
     let mut secret_bytes = [0u8; 32];